@@ -0,0 +1,448 @@
+//! Windowed statistics of sensor measurements.
+//!
+//! Measurements are grouped by sensor, and by fixed time windows aligned to the UNIX epoch,
+//! e.g. every hour on the hour, or every day at midnight UTC. For every window, the
+//! `Statistics` hold the sample count, minimum, maximum, mean, standard deviation, and the
+//! timestamps of the first and last samples.
+//!
+//! Statistics have a text form, published next to the raw readings:
+//!
+//! ```text
+//! stats/<window>/<type>-<id> <start> <count> <min> <max> <mean> <stddev> <first> <last> <unit>
+//! ```
+//!
+//! e.g. `stats/1h/ph-01 2017-11-18T23:00:00Z 60 6.9 7.1 7.01 0.05 2017-11-18T23:00:30Z
+//! 2017-11-18T23:59:30Z pH`.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use errors::*;
+use measurement::{parse_topic, Measurement, SensorId, SensorType};
+use utilities::atof;
+
+use chrono::{DateTime, TimeZone, Utc};
+
+/// Prefix of the topics for statistics.
+pub const STATS_PREFIX: &str = "stats/";
+
+/// Length of an aggregation window.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Window {
+    #[serde(rename = "1m")]
+    Minute,
+    #[serde(rename = "15m")]
+    FifteenMinutes,
+    #[serde(rename = "1h")]
+    Hour,
+    #[serde(rename = "1d")]
+    Day,
+}
+
+impl Window {
+    /// The length of the window, in seconds.
+    pub fn seconds(&self) -> i64 {
+        match *self {
+            Window::Minute => 60,
+            Window::FifteenMinutes => 15 * 60,
+            Window::Hour => 60 * 60,
+            Window::Day => 24 * 60 * 60,
+        }
+    }
+
+    /// The name of the window, e.g. `15m`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Window::Minute => "1m",
+            Window::FifteenMinutes => "15m",
+            Window::Hour => "1h",
+            Window::Day => "1d",
+        }
+    }
+
+    /// The start of the window that holds `timestamp`.
+    pub fn start(&self, timestamp: &DateTime<Utc>) -> DateTime<Utc> {
+        let secs = timestamp.timestamp();
+        let len = self.seconds();
+        Utc.timestamp(secs - ((secs % len) + len) % len, 0)
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl ::std::str::FromStr for Window {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Window> {
+        match s {
+            "1m" => Ok(Window::Minute),
+            "15m" => Ok(Window::FifteenMinutes),
+            "1h" => Ok(Window::Hour),
+            "1d" => Ok(Window::Day),
+            _ => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
+}
+
+/// Statistics of the measurements taken by a sensor during a window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statistics {
+    pub sensor_type: SensorType,
+    pub sensor_id: SensorId,
+    pub window: Window,
+    /// The start of the window.
+    pub start: DateTime<Utc>,
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// The population standard deviation.
+    pub stddev: f64,
+    /// When the first sample in the window was taken.
+    pub first: DateTime<Utc>,
+    /// When the last sample in the window was taken.
+    pub last: DateTime<Utc>,
+}
+
+impl Statistics {
+    /// The topic for the statistics, `stats/<window>/<type>-<id>`.
+    pub fn topic(&self) -> String {
+        format!(
+            "{}{}/{}-{}",
+            STATS_PREFIX, self.window, self.sensor_type, self.sensor_id
+        )
+    }
+
+    /// Parse the text form of the statistics.
+    pub fn parse(s: &str) -> Result<Statistics> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 10 || !fields[0].starts_with(STATS_PREFIX) {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        let (window, sensor) = match fields[0][STATS_PREFIX.len()..].find('/') {
+            Some(idx) => fields[0][STATS_PREFIX.len()..].split_at(idx),
+            None => return Err(ErrorKind::ResponseParse.into()),
+        };
+        let window = window.parse::<Window>()?;
+        let (sensor_type, sensor_id) = parse_topic(&sensor[1..])?;
+        if fields[9] != sensor_type.unit() {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        let timestamp = |s: &str| -> Result<DateTime<Utc>> {
+            Ok(s.parse::<DateTime<Utc>>()
+                .context(ErrorKind::ResponseParse)?)
+        };
+        let value = |s: &str| -> Result<f64> { Ok(atof(s).context(ErrorKind::ResponseParse)?) };
+        Ok(Statistics {
+            sensor_type,
+            sensor_id,
+            window,
+            start: timestamp(fields[1])?,
+            count: fields[2].parse::<u64>().context(ErrorKind::ResponseParse)?,
+            min: value(fields[3])?,
+            max: value(fields[4])?,
+            mean: value(fields[5])?,
+            stddev: value(fields[6])?,
+            first: timestamp(fields[7])?,
+            last: timestamp(fields[8])?,
+        })
+    }
+}
+
+impl fmt::Display for Statistics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:?} {} {} {} {} {} {:?} {:?} {}",
+            self.topic(),
+            self.start,
+            self.count,
+            self.min,
+            self.max,
+            self.mean,
+            self.stddev,
+            self.first,
+            self.last,
+            self.sensor_type.unit()
+        )
+    }
+}
+
+// Running statistics for a window, using Welford's method for the variance.
+#[derive(Clone, Debug)]
+struct Accumulator {
+    start: DateTime<Utc>,
+    count: u64,
+    min: f64,
+    max: f64,
+    mean: f64,
+    m2: f64,
+    first: DateTime<Utc>,
+    last: DateTime<Utc>,
+}
+
+impl Accumulator {
+    fn new(start: DateTime<Utc>, m: &Measurement) -> Accumulator {
+        let value = f64::from(m.value);
+        Accumulator {
+            start,
+            count: 1,
+            min: value,
+            max: value,
+            mean: value,
+            m2: 0.0,
+            first: m.timestamp,
+            last: m.timestamp,
+        }
+    }
+
+    fn push(&mut self, m: &Measurement) {
+        let value = f64::from(m.value);
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if m.timestamp < self.first {
+            self.first = m.timestamp;
+        }
+        if m.timestamp > self.last {
+            self.last = m.timestamp;
+        }
+    }
+
+    fn statistics(&self, key: &(SensorType, SensorId, Window)) -> Statistics {
+        Statistics {
+            sensor_type: key.0,
+            sensor_id: key.1,
+            window: key.2,
+            start: self.start,
+            count: self.count,
+            min: self.min,
+            max: self.max,
+            mean: self.mean,
+            stddev: (self.m2 / self.count as f64).sqrt(),
+            first: self.first,
+            last: self.last,
+        }
+    }
+}
+
+/// Computes statistics over a stream of measurements, for every sensor and window.
+///
+/// A window is closed, and its statistics returned, once a measurement for a later window
+/// arrives, or once the time is past its end. Measurements are expected in chronological
+/// order; late measurements, for windows that are already closed, are ignored, even after
+/// their windows have been flushed.
+#[derive(Clone, Debug)]
+pub struct Aggregator {
+    windows: Vec<Window>,
+    open: BTreeMap<(SensorType, SensorId, Window), Accumulator>,
+    // The start of the last closed window of every sensor, and window length.
+    closed: BTreeMap<(SensorType, SensorId, Window), DateTime<Utc>>,
+}
+
+impl Aggregator {
+    /// Create a new aggregator for the given windows.
+    pub fn new(windows: &[Window]) -> Aggregator {
+        Aggregator {
+            windows: windows.to_vec(),
+            open: BTreeMap::new(),
+            closed: BTreeMap::new(),
+        }
+    }
+
+    /// The windows computed by the aggregator.
+    pub fn windows(&self) -> &[Window] {
+        &self.windows
+    }
+
+    /// Add a measurement, returning the statistics of any windows it closes.
+    pub fn push(&mut self, m: &Measurement) -> Vec<Statistics> {
+        let mut closed = Vec::new();
+        for window in &self.windows {
+            let key = (m.sensor_type, m.sensor_id, *window);
+            let start = window.start(&m.timestamp);
+            if self.closed.get(&key).map_or(false, |last| start <= *last) {
+                debug!("ignoring late measurement for {}", m.topic());
+                continue;
+            }
+            if let Some(acc) = self.open.get_mut(&key) {
+                if acc.start == start {
+                    acc.push(m);
+                    continue;
+                } else if acc.start > start {
+                    debug!("ignoring late measurement for {}", m.topic());
+                    continue;
+                }
+                closed.push(acc.statistics(&key));
+                let _last = self.closed.insert(key, acc.start);
+            }
+            let _prev = self.open.insert(key, Accumulator::new(start, m));
+        }
+        closed
+    }
+
+    /// Close every window that ends at, or before, `now`, returning their statistics.
+    pub fn flush(&mut self, now: &DateTime<Utc>) -> Vec<Statistics> {
+        let ended: Vec<(SensorType, SensorId, Window)> = self
+            .open
+            .iter()
+            .filter(|&(key, acc)| acc.start.timestamp() + key.2.seconds() <= now.timestamp())
+            .map(|(key, _)| *key)
+            .collect();
+        let mut closed = Vec::with_capacity(ended.len());
+        for key in ended {
+            if let Some(acc) = self.open.remove(&key) {
+                closed.push(acc.statistics(&key));
+                let _last = self.closed.insert(key, acc.start);
+            }
+        }
+        closed
+    }
+
+    /// Close every window, returning their statistics.
+    pub fn finish(&mut self) -> Vec<Statistics> {
+        let open = ::std::mem::replace(&mut self.open, BTreeMap::new());
+        for (key, acc) in &open {
+            let _last = self.closed.insert(*key, acc.start);
+        }
+        open.iter().map(|(key, acc)| acc.statistics(key)).collect()
+    }
+}
+
+/// Compute the statistics of stored measurements, e.g. from a `MeasurementLog`, ordered by
+/// sensor, and by window start.
+pub fn aggregate(measurements: &[Measurement], window: Window) -> Vec<Statistics> {
+    let mut sorted = measurements.to_vec();
+    sorted.sort_by_key(|m| m.timestamp);
+    let mut aggregator = Aggregator::new(&[window]);
+    let mut statistics = Vec::new();
+    for m in &sorted {
+        statistics.extend(aggregator.push(m));
+    }
+    statistics.extend(aggregator.finish());
+    statistics.sort_by(|a, b| {
+        (a.sensor_type, a.sensor_id, a.start).cmp(&(b.sensor_type, b.sensor_id, b.start))
+    });
+    statistics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.ymd(2017, 11, 18).and_hms(0, 0, 0) + ::chrono::Duration::seconds(secs)
+    }
+
+    fn ph(secs: i64, value: f32) -> Measurement {
+        Measurement::new(at(secs), SensorType::Ph, "01".parse().unwrap(), value)
+    }
+
+    #[test]
+    fn align_windows_to_the_epoch() {
+        let t = Utc.ymd(2017, 11, 18).and_hms(23, 40, 5);
+        assert_eq!(
+            Utc.ymd(2017, 11, 18).and_hms(23, 40, 0),
+            Window::Minute.start(&t)
+        );
+        assert_eq!(
+            Utc.ymd(2017, 11, 18).and_hms(23, 30, 0),
+            Window::FifteenMinutes.start(&t)
+        );
+        assert_eq!(
+            Utc.ymd(2017, 11, 18).and_hms(23, 0, 0),
+            Window::Hour.start(&t)
+        );
+        assert_eq!(
+            Utc.ymd(2017, 11, 18).and_hms(0, 0, 0),
+            Window::Day.start(&t)
+        );
+    }
+
+    #[test]
+    fn compute_statistics_for_closed_windows() {
+        let mut aggregator = Aggregator::new(&[Window::Minute, Window::Hour]);
+        assert!(aggregator.push(&ph(0, 2.0)).is_empty());
+        assert!(aggregator.push(&ph(20, 4.0)).is_empty());
+        assert!(aggregator.push(&ph(40, 4.0)).is_empty());
+        assert!(aggregator.push(&ph(50, 4.0)).is_empty());
+
+        let closed = aggregator.push(&ph(70, 5.0));
+        assert_eq!(1, closed.len());
+        let stats = &closed[0];
+        assert_eq!(Window::Minute, stats.window);
+        assert_eq!(at(0), stats.start);
+        assert_eq!(4, stats.count);
+        assert_eq!(2.0, stats.min);
+        assert_eq!(4.0, stats.max);
+        assert_eq!(3.5, stats.mean);
+        assert!((stats.stddev - 0.866_025).abs() < 1e-6);
+        assert_eq!(at(0), stats.first);
+        assert_eq!(at(50), stats.last);
+
+        // Late measurements are ignored by closed windows, and kept by open ones.
+        assert!(aggregator.push(&ph(30, 100.0)).is_empty());
+
+        let flushed = aggregator.flush(&at(120));
+        assert_eq!(1, flushed.len());
+        assert_eq!(at(60), flushed[0].start);
+        assert_eq!(1, flushed[0].count);
+
+        // Flushed windows are closed too.
+        assert!(aggregator.push(&ph(90, 1.0)).is_empty());
+        assert!(aggregator.flush(&at(180)).is_empty());
+
+        let finished = aggregator.finish();
+        assert_eq!(1, finished.len());
+        assert_eq!(Window::Hour, finished[0].window);
+        assert_eq!(7, finished[0].count);
+        assert_eq!(100.0, finished[0].max);
+    }
+
+    #[test]
+    fn aggregate_stored_measurements() {
+        let mut measurements: Vec<Measurement> = (0..48).map(|h| ph(h * 3600, h as f32)).collect();
+        measurements.reverse();
+        let daily = aggregate(&measurements, Window::Day);
+        assert_eq!(2, daily.len());
+        assert_eq!(at(0), daily[0].start);
+        assert_eq!(24, daily[0].count);
+        assert_eq!(0.0, daily[0].min);
+        assert_eq!(23.0, daily[0].max);
+        assert_eq!(11.5, daily[0].mean);
+        assert_eq!(at(47 * 3600), daily[1].last);
+    }
+
+    #[test]
+    fn format_and_parse_statistics() {
+        let mut aggregator = Aggregator::new(&[Window::FifteenMinutes]);
+        let _closed = aggregator.push(&ph(0, 7.0));
+        let _closed = aggregator.push(&ph(30, 7.5));
+        let stats = aggregator.finish().remove(0);
+        let text = stats.to_string();
+        assert_eq!(
+            "stats/15m/ph-01 2017-11-18T00:00:00Z 2 7 7.5 7.25 0.25 \
+             2017-11-18T00:00:00Z 2017-11-18T00:00:30Z pH",
+            text
+        );
+        assert_eq!(stats, Statistics::parse(&text).unwrap());
+
+        let invalid = [
+            "",
+            "ph-01 2017-11-18T00:00:00Z 7 pH",
+            "stats/2h/ph-01 2017-11-18T00:00:00Z 2 7 7.5 7.25 0.25 \
+             2017-11-18T00:00:00Z 2017-11-18T00:00:30Z pH",
+            "stats/15m/ph-01 2017-11-18T00:00:00Z 2 7 7.5 7.25 0.25 \
+             2017-11-18T00:00:00Z 2017-11-18T00:00:30Z celsius",
+        ];
+        for s in invalid.iter() {
+            assert!(Statistics::parse(s).is_err());
+        }
+    }
+}
@@ -0,0 +1,595 @@
+//! Threshold alarms for sensor measurements.
+//!
+//! An `AlarmRule` watches the measurements of one sensor, and is violated when a value is
+//! below its `low` limit, above its `high` limit, or changing faster than its `rate` limit,
+//! in units per hour, rising or falling. The rate of change is the least-squares slope of
+//! the values taken over the last `rate_window` seconds, so that a single noisy reading does
+//! not violate it. For example, in `toml`:
+//!
+//! ```toml
+//! [[alarms]]
+//! name = "ph-range"
+//! sensor = "ph-0123456789abcdef"
+//! low = 6.5
+//! high = 8.5
+//! hysteresis = 0.1
+//! duration = 300
+//! severity = "critical"
+//! ```
+//!
+//! An alarm is raised once its rule has been violated for at least `duration` seconds, and
+//! cleared once the values are back within the limits by more than the `hysteresis`, and the
+//! rate of change is back below its limit by more than the `rate_hysteresis`, so that
+//! readings around a limit do not raise, and clear, the alarm over and over.
+//!
+//! Every raise, and clear, is an `AlarmEvent`, published on the `alarm` topic:
+//!
+//! ```text
+//! alarm <raise|clear> <rule> <severity> <sensor> <timestamp> <value>
+//! ```
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use errors::*;
+use measurement::{parse_topic, Measurement, SensorId, SensorType};
+use utilities::atof;
+
+use chrono::{DateTime, Utc};
+
+/// Topic of the published alarm events.
+pub const ALARM_TOPIC: &str = "alarm";
+
+/// Time over which the rate of change is computed, unless given, in seconds.
+pub const DEFAULT_RATE_WINDOW: u64 = 3600;
+
+/// How serious an alarm is.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    #[serde(rename = "info")]
+    Info,
+    #[serde(rename = "warning")]
+    Warning,
+    #[serde(rename = "critical")]
+    Critical,
+}
+
+impl Default for Severity {
+    fn default() -> Severity {
+        Severity::Warning
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Severity::Info => write!(f, "info"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+impl FromStr for Severity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Severity> {
+        match s {
+            "info" => Ok(Severity::Info),
+            "warning" => Ok(Severity::Warning),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
+}
+
+/// A rule for raising alarms on the measurements of a sensor.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AlarmRule {
+    /// Name of the rule, without whitespace.
+    pub name: String,
+    /// Topic of the sensor measurements, e.g. `ph-0123456789abcdef`.
+    pub sensor: String,
+    /// Lowest acceptable value.
+    #[serde(default)]
+    pub low: Option<f64>,
+    /// Highest acceptable value.
+    #[serde(default)]
+    pub high: Option<f64>,
+    /// Highest acceptable rate of change, rising or falling, in units per hour.
+    #[serde(default)]
+    pub rate: Option<f64>,
+    /// Time over which the rate of change is computed, in seconds. Defaults to
+    /// `DEFAULT_RATE_WINDOW`.
+    #[serde(default)]
+    pub rate_window: Option<u64>,
+    /// How far within the limits values must be for the alarm to clear.
+    #[serde(default)]
+    pub hysteresis: f64,
+    /// How far below the `rate` limit, in units per hour, the rate of change must be for the
+    /// alarm to clear.
+    #[serde(default)]
+    pub rate_hysteresis: f64,
+    /// How long, in seconds, the rule must be violated before the alarm is raised.
+    #[serde(default)]
+    pub duration: u64,
+    #[serde(default)]
+    pub severity: Severity,
+}
+
+// The outcome of evaluating a rule on a value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Evaluation {
+    Violated,
+    Clear,
+    // Within the hysteresis band, or not known yet.
+    Hold,
+}
+
+impl AlarmRule {
+    /// Check that the rule is usable.
+    pub fn validate(&self) -> Result<()> {
+        let valid_name = !self.name.is_empty() && !self.name.contains(char::is_whitespace);
+        let has_limit = self.low.is_some() || self.high.is_some() || self.rate.is_some();
+        let valid_limits = match (self.low, self.high) {
+            (Some(low), Some(high)) => low < high,
+            _ => true,
+        };
+        if !valid_name
+            || !has_limit
+            || !valid_limits
+            || self.hysteresis < 0.0
+            || self.rate_hysteresis < 0.0
+            || self.rate_window == Some(0)
+            || parse_topic(&self.sensor).is_err()
+        {
+            return Err(ErrorKind::InvalidAlarmRule(self.name.clone()).into());
+        }
+        Ok(())
+    }
+
+    fn evaluate(&self, value: f64, rate: Option<f64>) -> Evaluation {
+        let h = self.hysteresis;
+        let mut violated = false;
+        let mut clear = true;
+        if let Some(low) = self.low {
+            violated |= value < low;
+            clear &= value >= low + h;
+        }
+        if let Some(high) = self.high {
+            violated |= value > high;
+            clear &= value <= high - h;
+        }
+        if let Some(limit) = self.rate {
+            match rate {
+                Some(rate) => {
+                    violated |= rate.abs() > limit;
+                    clear &= rate.abs() <= limit - self.rate_hysteresis;
+                }
+                None => clear = false,
+            }
+        }
+        if violated {
+            Evaluation::Violated
+        } else if clear {
+            Evaluation::Clear
+        } else {
+            Evaluation::Hold
+        }
+    }
+}
+
+/// An alarm that has been raised, and not cleared yet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ActiveAlarm {
+    pub rule: String,
+    pub severity: Severity,
+    pub sensor: String,
+    /// When the alarm was raised.
+    pub since: DateTime<Utc>,
+    /// The value that raised the alarm.
+    pub value: f64,
+}
+
+impl ActiveAlarm {
+    /// Parse the text form of an active alarm, `<rule> <severity> <sensor> <since> <value>`.
+    pub fn parse(s: &str) -> Result<ActiveAlarm> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        Ok(ActiveAlarm {
+            rule: fields[0].to_string(),
+            severity: fields[1].parse()?,
+            sensor: fields[2].to_string(),
+            since: fields[3]
+                .parse::<DateTime<Utc>>()
+                .context(ErrorKind::ResponseParse)?,
+            value: atof(fields[4]).context(ErrorKind::ResponseParse)?,
+        })
+    }
+}
+
+impl fmt::Display for ActiveAlarm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {:?} {}",
+            self.rule, self.severity, self.sensor, self.since, self.value
+        )
+    }
+}
+
+/// Whether an alarm was raised, or cleared.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AlarmEventKind {
+    Raise,
+    Clear,
+}
+
+/// An alarm being raised, or cleared.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlarmEvent {
+    pub kind: AlarmEventKind,
+    pub rule: String,
+    pub severity: Severity,
+    pub sensor: String,
+    pub timestamp: DateTime<Utc>,
+    pub value: f64,
+}
+
+impl AlarmEvent {
+    /// Parse a published alarm event.
+    pub fn parse(s: &str) -> Result<AlarmEvent> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 7 || fields[0] != ALARM_TOPIC {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        let kind = match fields[1] {
+            "raise" => AlarmEventKind::Raise,
+            "clear" => AlarmEventKind::Clear,
+            _ => return Err(ErrorKind::ResponseParse.into()),
+        };
+        let alarm = ActiveAlarm::parse(&fields[2..].join(" "))?;
+        Ok(AlarmEvent {
+            kind,
+            rule: alarm.rule,
+            severity: alarm.severity,
+            sensor: alarm.sensor,
+            timestamp: alarm.since,
+            value: alarm.value,
+        })
+    }
+}
+
+impl fmt::Display for AlarmEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.kind {
+            AlarmEventKind::Raise => "raise",
+            AlarmEventKind::Clear => "clear",
+        };
+        write!(
+            f,
+            "{} {} {} {} {} {:?} {}",
+            ALARM_TOPIC, kind, self.rule, self.severity, self.sensor, self.timestamp, self.value
+        )
+    }
+}
+
+// A rule, and what it knows of its sensor.
+#[derive(Clone, Debug)]
+struct WatchedRule {
+    rule: AlarmRule,
+    sensor_type: SensorType,
+    sensor_id: SensorId,
+    // The values within the rate window, oldest first.
+    window: VecDeque<(DateTime<Utc>, f64)>,
+    violated_since: Option<DateTime<Utc>>,
+    active: Option<ActiveAlarm>,
+}
+
+impl WatchedRule {
+    fn event(&self, kind: AlarmEventKind, m: &Measurement) -> AlarmEvent {
+        AlarmEvent {
+            kind,
+            rule: self.rule.name.clone(),
+            severity: self.rule.severity,
+            sensor: self.rule.sensor.clone(),
+            timestamp: m.timestamp,
+            value: f64::from(m.value),
+        }
+    }
+
+    // The rate of change of the values in the window, after adding `value`. Values that are
+    // not newer than the window are left out, and have no rate.
+    fn rate(&mut self, timestamp: DateTime<Utc>, value: f64) -> Option<f64> {
+        if self.window.back().map_or(false, |&(t, _)| timestamp <= t) {
+            return None;
+        }
+        self.window.push_back((timestamp, value));
+        let window = self.rule.rate_window.unwrap_or(DEFAULT_RATE_WINDOW);
+        let start = timestamp - ::chrono::Duration::seconds(window as i64);
+        while self.window.front().map_or(false, |&(t, _)| t < start) {
+            let _old = self.window.pop_front();
+        }
+        slope(&self.window)
+    }
+
+    fn push(&mut self, m: &Measurement) -> Option<AlarmEvent> {
+        let value = f64::from(m.value);
+        let rate = self.rate(m.timestamp, value);
+
+        match self.rule.evaluate(value, rate) {
+            Evaluation::Violated if self.active.is_none() => {
+                let since = *self.violated_since.get_or_insert(m.timestamp);
+                if (m.timestamp - since).num_seconds() < self.rule.duration as i64 {
+                    return None;
+                }
+                self.violated_since = None;
+                self.active = Some(ActiveAlarm {
+                    rule: self.rule.name.clone(),
+                    severity: self.rule.severity,
+                    sensor: self.rule.sensor.clone(),
+                    since: m.timestamp,
+                    value,
+                });
+                Some(self.event(AlarmEventKind::Raise, m))
+            }
+            Evaluation::Violated => None,
+            Evaluation::Clear => {
+                self.violated_since = None;
+                self.active
+                    .take()
+                    .map(|_| self.event(AlarmEventKind::Clear, m))
+            }
+            Evaluation::Hold => {
+                // The minimum duration is for uninterrupted violations.
+                self.violated_since = None;
+                None
+            }
+        }
+    }
+}
+
+// The least-squares slope of the values, in units per hour. There is none with fewer than two
+// values.
+fn slope(values: &VecDeque<(DateTime<Utc>, f64)>) -> Option<f64> {
+    let first = values.front()?.0;
+    let hours = |t: DateTime<Utc>| (t - first).num_milliseconds() as f64 / 3_600_000.0;
+    let n = values.len() as f64;
+    let mean_t = values.iter().map(|&(t, _)| hours(t)).sum::<f64>() / n;
+    let mean_v = values.iter().map(|&(_, v)| v).sum::<f64>() / n;
+    let (mut covariance, mut variance) = (0.0, 0.0);
+    for &(t, v) in values {
+        let dt = hours(t) - mean_t;
+        covariance += dt * (v - mean_v);
+        variance += dt * dt;
+    }
+    if variance > 0.0 {
+        Some(covariance / variance)
+    } else {
+        None
+    }
+}
+
+/// Evaluates alarm rules on measurements, and keeps the table of active alarms.
+#[derive(Clone, Debug, Default)]
+pub struct AlarmEngine {
+    rules: Vec<WatchedRule>,
+}
+
+impl AlarmEngine {
+    /// Create a new engine for the given rules.
+    pub fn new(rules: &[AlarmRule]) -> Result<AlarmEngine> {
+        let mut engine = AlarmEngine::default();
+        for rule in rules {
+            let _add = engine.add_rule(rule.clone())?;
+        }
+        Ok(engine)
+    }
+
+    /// Add a rule to the engine. Rule names must be unique.
+    pub fn add_rule(&mut self, rule: AlarmRule) -> Result<()> {
+        let _valid = rule.validate()?;
+        if self.rules.iter().any(|w| w.rule.name == rule.name) {
+            return Err(ErrorKind::InvalidAlarmRule(rule.name).into());
+        }
+        let (sensor_type, sensor_id) = parse_topic(&rule.sensor)?;
+        self.rules.push(WatchedRule {
+            rule,
+            sensor_type,
+            sensor_id,
+            window: VecDeque::new(),
+            violated_since: None,
+            active: None,
+        });
+        Ok(())
+    }
+
+    /// The rules of the engine.
+    pub fn rules(&self) -> Vec<&AlarmRule> {
+        self.rules.iter().map(|w| &w.rule).collect()
+    }
+
+    /// Evaluate a measurement, returning the alarms it raises, or clears.
+    pub fn push(&mut self, m: &Measurement) -> Vec<AlarmEvent> {
+        self.rules
+            .iter_mut()
+            .filter(|w| w.sensor_type == m.sensor_type && w.sensor_id == m.sensor_id)
+            .filter_map(|w| w.push(m))
+            .collect()
+    }
+
+    /// The alarms that are currently raised.
+    pub fn active(&self) -> Vec<ActiveAlarm> {
+        self.rules.iter().filter_map(|w| w.active.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.ymd(2017, 11, 18).and_hms(0, 0, 0) + Duration::seconds(secs)
+    }
+
+    fn ph(secs: i64, value: f32) -> Measurement {
+        Measurement::new(at(secs), SensorType::Ph, "01".parse().unwrap(), value)
+    }
+
+    fn ph_range() -> AlarmRule {
+        AlarmRule {
+            name: "ph-range".to_string(),
+            sensor: "ph-01".to_string(),
+            low: Some(6.5),
+            high: Some(8.5),
+            hysteresis: 0.25,
+            severity: Severity::Critical,
+            ..AlarmRule::default()
+        }
+    }
+
+    #[test]
+    fn raise_and_clear_alarms_with_hysteresis() {
+        let mut engine = AlarmEngine::new(&[ph_range()]).unwrap();
+        assert!(engine.push(&ph(0, 7.0)).is_empty());
+
+        let events = engine.push(&ph(10, 6.0));
+        assert_eq!(1, events.len());
+        assert_eq!(AlarmEventKind::Raise, events[0].kind);
+        assert_eq!(Severity::Critical, events[0].severity);
+        assert_eq!(1, engine.active().len());
+        assert_eq!(at(10), engine.active()[0].since);
+
+        // Still violated, and within the hysteresis band.
+        assert!(engine.push(&ph(20, 6.2)).is_empty());
+        assert!(engine.push(&ph(30, 6.6)).is_empty());
+        assert_eq!(1, engine.active().len());
+
+        let events = engine.push(&ph(40, 6.8));
+        assert_eq!(1, events.len());
+        assert_eq!(AlarmEventKind::Clear, events[0].kind);
+        assert!(engine.active().is_empty());
+
+        // Measurements of other sensors are not evaluated.
+        let other = Measurement::new(at(50), SensorType::Ph, "02".parse().unwrap(), 1.0);
+        assert!(engine.push(&other).is_empty());
+    }
+
+    #[test]
+    fn raise_alarms_after_the_minimum_duration() {
+        let mut rule = ph_range();
+        rule.duration = 60;
+        let mut engine = AlarmEngine::new(&[rule]).unwrap();
+        assert!(engine.push(&ph(0, 9.0)).is_empty());
+        assert!(engine.push(&ph(30, 9.0)).is_empty());
+        // Interrupted violations start over.
+        assert!(engine.push(&ph(40, 8.3)).is_empty());
+        assert!(engine.push(&ph(50, 9.0)).is_empty());
+        assert!(engine.push(&ph(100, 9.0)).is_empty());
+        let events = engine.push(&ph(110, 9.1));
+        assert_eq!(1, events.len());
+        assert_eq!(at(110), events[0].timestamp);
+    }
+
+    #[test]
+    fn raise_alarms_on_rate_of_change() {
+        let rule = AlarmRule {
+            name: "temperature-rising".to_string(),
+            sensor: "temperature-01".to_string(),
+            rate: Some(2.0),
+            ..AlarmRule::default()
+        };
+        let mut engine = AlarmEngine::new(&[rule]).unwrap();
+        let temperature = |secs: i64, value: f32| {
+            Measurement::new(
+                at(secs),
+                SensorType::Temperature,
+                "01".parse().unwrap(),
+                value,
+            )
+        };
+        assert!(engine.push(&temperature(0, 20.0)).is_empty());
+        // 1 °C per hour.
+        assert!(engine.push(&temperature(1800, 20.5)).is_empty());
+        // 2.5 °C per hour, over the last hour.
+        let events = engine.push(&temperature(3600, 22.5));
+        assert_eq!(AlarmEventKind::Raise, events[0].kind);
+        assert_eq!(Severity::Warning, events[0].severity);
+        let events = engine.push(&temperature(7200, 22.5));
+        assert_eq!(AlarmEventKind::Clear, events[0].kind);
+    }
+
+    #[test]
+    fn compute_the_rate_of_change_over_its_window() {
+        let rule = AlarmRule {
+            name: "temperature-rising".to_string(),
+            sensor: "temperature-01".to_string(),
+            rate: Some(2.0),
+            rate_window: Some(3600),
+            rate_hysteresis: 0.5,
+            ..AlarmRule::default()
+        };
+        let mut engine = AlarmEngine::new(&[rule]).unwrap();
+        // A reading every 10 minutes, with a noisy one at 30 minutes.
+        let values = [
+            20.0, 20.0, 20.0, 20.6, 20.1, 21.0, 21.6, 22.2, 22.4, 22.6, 22.8, 22.8, 22.8,
+        ];
+        let events: Vec<(i64, AlarmEventKind)> = values
+            .iter()
+            .enumerate()
+            .flat_map(|(i, value)| {
+                let secs = 600 * i as i64;
+                let m = Measurement::new(
+                    at(secs),
+                    SensorType::Temperature,
+                    "01".parse().unwrap(),
+                    *value,
+                );
+                engine.push(&m).into_iter().map(move |e| (secs, e.kind))
+            })
+            .collect();
+        // About 2.2 °C per hour over the last hour, and cleared below 1.5 °C per hour.
+        assert_eq!(
+            vec![(4200, AlarmEventKind::Raise), (7200, AlarmEventKind::Clear)],
+            events
+        );
+    }
+
+    #[test]
+    fn reject_invalid_alarm_rules() {
+        let mut no_limits = ph_range();
+        no_limits.low = None;
+        no_limits.high = None;
+        let mut bad_name = ph_range();
+        bad_name.name = "ph range".to_string();
+        let mut bad_sensor = ph_range();
+        bad_sensor.sensor = "ph".to_string();
+        let mut bad_limits = ph_range();
+        bad_limits.low = Some(9.0);
+        for rule in &[no_limits, bad_name, bad_sensor, bad_limits] {
+            assert!(AlarmEngine::new(&[rule.clone()]).is_err());
+        }
+        // Rule names are unique.
+        assert!(AlarmEngine::new(&[ph_range(), ph_range()]).is_err());
+    }
+
+    #[test]
+    fn format_and_parse_alarm_events() {
+        let mut engine = AlarmEngine::new(&[ph_range()]).unwrap();
+        let event = engine.push(&ph(10, 6.0)).remove(0);
+        let text = event.to_string();
+        assert_eq!(
+            "alarm raise ph-range critical ph-01 2017-11-18T00:00:10Z 6",
+            text
+        );
+        assert_eq!(event, AlarmEvent::parse(&text).unwrap());
+        assert!(AlarmEvent::parse("alarm raise ph-range critical ph-01").is_err());
+        assert!(
+            AlarmEvent::parse("alarm ring ph-range critical ph-01 2017-11-18T00:00:10Z 6").is_err()
+        );
+    }
+}
@@ -5,6 +5,7 @@ use super::requests::*;
 
 use common_ezo::EzoChipAPI;
 use errors::*;
+use network::session::{OwnedRequest, SessionHeartbeat, SessionLock, SessionState, SessionUnlock};
 use network::{Endpoint, ReplyStatus, SocketRequest};
 
 use zmq::Socket;
@@ -16,6 +17,41 @@ network_socket! {
     "Socket that makes requests to the Conductivity sensor socket."
 }
 
+impl ConductivityRequester {
+    /// Send a request string, as understood by its responder, and return the debug form of
+    /// the typed reply. Requests that are not understood yield `ErrorKind::RequestParse`.
+    pub fn request(&self, req_str: &str) -> Result<String> {
+        request_typed_reply!(self, req_str, [
+            CalibrationDry,
+            CalibrationHigh,
+            CalibrationLow,
+            CalibrationOnePoint,
+            CalibrationState,
+            CompensationGet,
+            CompensationSet,
+            OutputDisableConductivity,
+            OutputDisableSalinity,
+            OutputDisableSpecificGravity,
+            OutputDisableTds,
+            OutputEnableConductivity,
+            OutputEnableSalinity,
+            OutputEnableSpecificGravity,
+            OutputEnableTds,
+            OutputState,
+            OwnedRequest,
+            ProbeTypeOne,
+            ProbeTypePointOne,
+            ProbeTypeTen,
+            ProbeTypeState,
+            Reading,
+            SessionHeartbeat,
+            SessionLock,
+            SessionState,
+            SessionUnlock,
+        ])
+    }
+}
+
 impl EzoChipAPI for ConductivityRequester {
     type SensorError = Error;
     type SensorReply = ReplyStatus;
@@ -2,10 +2,12 @@
 use super::super::device::ConductivitySensor;
 use super::super::ConductivityAPI;
 use super::replies::*;
+use super::requests::*;
 
 use common_ezo::EzoChipAPI;
 use errors::*;
-use network::{Endpoint, ReplyStatus};
+use network::session::CalibrationLock;
+use network::{Endpoint, ReplyStatus, SocketReply, SocketRequest};
 
 use zmq::Socket;
 
@@ -14,7 +16,55 @@ use zmq::Socket;
 network_sensor_socket! {
     ConductivityResponder,
     ConductivitySensor,
-    "Socket that responds to Conductivity sensor commands."
+    "Socket that responds to Conductivity sensor commands.",
+    session
+}
+
+// Requests that change the calibration of the sensor, and need its session lock.
+fn changes_calibration(req_str: &str) -> bool {
+    <CalibrationClear as SocketRequest>::from_str(req_str).is_ok()
+        || <CalibrationDry as SocketRequest>::from_str(req_str).is_ok()
+        || <CalibrationHigh as SocketRequest>::from_str(req_str).is_ok()
+        || <CalibrationLow as SocketRequest>::from_str(req_str).is_ok()
+        || <CalibrationOnePoint as SocketRequest>::from_str(req_str).is_ok()
+        || <Factory as SocketRequest>::from_str(req_str).is_ok()
+        || <Import as SocketRequest>::from_str(req_str).is_ok()
+}
+
+impl ConductivityResponder {
+    /// Evaluate a request, as sent by a `ConductivityRequester`, and return the text form of
+    /// its reply. Requests that are not understood yield `ErrorKind::RequestParse`.
+    ///
+    /// Requests that change the calibration are only answered for the holder of the session
+    /// lock, see `benita::ezo::network::session`.
+    pub fn respond(&self, req_str: &str) -> Result<String> {
+        self.session
+            .respond(req_str, changes_calibration, |req_str| {
+                respond_to_requests!(self, req_str, {
+                    CalibrationDry => self.set_calibration_dry(),
+                    CalibrationHigh(value) => self.set_calibration_high(value),
+                    CalibrationLow(value) => self.set_calibration_low(value),
+                    CalibrationOnePoint(value) => self.set_calibration_single(value),
+                    CalibrationState => self.get_calibration_status(),
+                    CompensationGet => self.get_compensation(),
+                    CompensationSet(value) => self.set_compensation(value),
+                    OutputDisableConductivity => self.set_output_conductivity_off(),
+                    OutputDisableSalinity => self.set_output_salinity_off(),
+                    OutputDisableSpecificGravity => self.set_output_specific_gravity_off(),
+                    OutputDisableTds => self.set_output_tds_off(),
+                    OutputEnableConductivity => self.set_output_conductivity_on(),
+                    OutputEnableSalinity => self.set_output_salinity_on(),
+                    OutputEnableSpecificGravity => self.set_output_specific_gravity_on(),
+                    OutputEnableTds => self.set_output_tds_on(),
+                    OutputState => self.get_output_params(),
+                    ProbeTypeOne => self.set_probe_type_one(),
+                    ProbeTypePointOne => self.set_probe_type_point_one(),
+                    ProbeTypeTen => self.set_probe_type_ten(),
+                    ProbeTypeState => self.get_probe_type_status(),
+                    Reading => self.get_reading(),
+                })
+            })
+    }
 }
 
 impl EzoChipAPI for ConductivityResponder {
@@ -1,13 +1,27 @@
 //! Configuration settings for sensors and network sockets, using `toml`.
 //!
 //! `benita` sets up sensors and network configurations using `toml` and `serde`.
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use aggregate::Window;
+use alarm::AlarmRule;
 use errors::*;
-use toml;
+use schedule::Schedule;
+use store::LogPolicy;
+use validate::{self, Validator};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use toml::value::Table;
+use toml::{self, Value};
 
 /// Socket connection type. Can be `Bind` or `Connect`.
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 pub enum ConnectionType {
     #[serde(rename = "bind")]
     Bind,
@@ -28,21 +42,25 @@ impl Default for ConnectionType {
 }
 
 /// Configuration settings for network sockets.
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
-pub struct SocketConfig<'a> {
-    pub url: &'a str,
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SocketConfig {
+    pub url: String,
     #[serde(default)]
     pub socket_connection: ConnectionType,
 }
 
-impl<'a> SocketConfig<'a> {
+impl SocketConfig {
     pub fn from_str(config_str: &str) -> Result<SocketConfig> {
         Ok(toml::from_str(config_str).context(ErrorKind::ConfigParse)?)
     }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<SocketConfig> {
+        SocketConfig::from_str(&read_file(path)?)
+    }
 }
 
 /// Configuration settings for I2C sensors.
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
 pub struct SensorConfig {
     pub path: PathBuf,
     pub address: u16,
@@ -57,43 +75,728 @@ impl SensorConfig {
     pub fn from_str(config_str: &str) -> Result<SensorConfig> {
         Ok(toml::from_str(config_str).context(ErrorKind::ConfigParse)?)
     }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<SensorConfig> {
+        SensorConfig::from_str(&read_file(path)?)
+    }
 }
 
 /// Configuration settings for the calibrated sensors service.
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
-pub struct SensorServiceConfig<'a> {
-    pub pub_url: &'a str,
-    pub channel: &'a str,
-    pub rep_ec_url: &'a str,
-    pub rep_ph_url: &'a str,
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SensorServiceConfig {
+    pub pub_url: String,
+    pub channel: String,
+    pub rep_ec_url: String,
+    pub rep_ph_url: String,
 }
 
-impl<'a> SensorServiceConfig<'a> {
+impl SensorServiceConfig {
     pub fn from_str(config_str: &str) -> Result<SensorServiceConfig> {
         Ok(toml::from_str(config_str).context(ErrorKind::ConfigParse)?)
     }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<SensorServiceConfig> {
+        SensorServiceConfig::from_str(&read_file(path)?)
+    }
 }
 
 /// Configuration settings for networked proxies.
-#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
-pub struct ProxyConfig<'a> {
-    pub backend_url: &'a str,
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ProxyConfig {
+    pub backend_url: String,
     #[serde(default)]
     pub backend_connection: ConnectionType,
-    pub frontend_url: &'a str,
+    pub frontend_url: String,
     #[serde(default)]
     pub frontend_connection: ConnectionType,
 }
 
-impl<'a> ProxyConfig<'a> {
+impl ProxyConfig {
     pub fn from_str(config_str: &str) -> Result<ProxyConfig> {
         Ok(toml::from_str(config_str).context(ErrorKind::ConfigParse)?)
     }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<ProxyConfig> {
+        ProxyConfig::from_str(&read_file(path)?)
+    }
+}
+
+/// Layered sources of a configuration: its defaults, then a file, then environment
+/// variables, then command-line flags. Every layer overrides the settings of the layers
+/// before it.
+///
+/// Environment variables are named after a prefix, and the key of the setting, in upper
+/// case. For instance, `BENITA_SENSOR_PH_ADDRESS` overrides the `address` of the layers
+/// merged with the `BENITA_SENSOR_PH` prefix. Keys of nested tables are joined by `_` in
+/// variable names, and by `.` in `set`, e.g. `storage.path`. Tables in arrays are named
+/// by their index, or by their `name`, e.g. `BENITA_SENSORS_PH1_ADDRESS`, or
+/// `sensors.0.address`, for the `[[sensors]]` named `ph1`.
+///
+/// Settings without a value in an earlier layer are added to the innermost table that the
+/// variable, or key, names. Their values are integers, floats, booleans, or arrays, when
+/// they parse as such, and strings otherwise.
+///
+/// In strict mode, the strings, and files, merged may only hold settings that have a value
+/// in an earlier layer, e.g. in the defaults.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigLayers {
+    table: Table,
+    strict: bool,
+}
+
+impl ConfigLayers {
+    /// Start from the settings of `defaults`.
+    pub fn new<T: Serialize>(defaults: &T) -> Result<ConfigLayers> {
+        match Value::try_from(defaults).context(ErrorKind::ConfigParse)? {
+            Value::Table(table) => Ok(ConfigLayers {
+                table,
+                strict: false,
+            }),
+            _ => Err(ErrorKind::ConfigParse.into()),
+        }
+    }
+
+    /// Reject unknown settings in the strings, and files, merged from now on.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Override with the settings of a `toml` string.
+    pub fn merge_str(&mut self, config_str: &str) -> Result<()> {
+        self.merge_source(config_str, None)
+    }
+
+    /// Override with the settings of a `toml` file.
+    pub fn merge_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        self.merge_source(&read_file(path)?, Some(path))
+    }
+
+    fn merge_source(&mut self, config_str: &str, file: Option<&Path>) -> Result<()> {
+        let layer: Table = toml::from_str(config_str).context(ErrorKind::ConfigParse)?;
+        if self.strict {
+            let mut validator = Validator::new(config_str, file);
+            validator.check_layer_keys(&layer, &self.table);
+            validator.result()?;
+        }
+        merge_tables(&mut self.table, layer);
+        Ok(())
+    }
+
+    /// Override with the environment variables named after `prefix`.
+    pub fn merge_env(&mut self, prefix: &str) -> Result<()> {
+        self.merge_vars(prefix, env::vars())
+    }
+
+    /// Override with the `(name, value)` variables named after `prefix`. The others are
+    /// ignored.
+    pub fn merge_vars<I>(&mut self, prefix: &str, vars: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let prefix = format!("{}_", prefix);
+        for (name, raw) in vars {
+            if !name.starts_with(&prefix) {
+                continue;
+            }
+            if set_var(&mut self.table, &name[prefix.len()..], &raw).is_none() {
+                return Err(ErrorKind::ConfigValue(name).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Override a single setting, e.g. with the value of a command-line flag.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let keys: Vec<&str> = key.split('.').collect();
+        match set_value(&mut self.table, &keys, value) {
+            Some(()) => Ok(()),
+            None => Err(ErrorKind::ConfigValue(key.to_string()).into()),
+        }
+    }
+
+    /// The effective configuration, with every layer merged.
+    pub fn build<T: DeserializeOwned>(&self) -> Result<T> {
+        let config = Value::Table(self.table.clone())
+            .try_into()
+            .context(ErrorKind::ConfigParse)?;
+        Ok(config)
+    }
+
+    /// The `toml` form of the settings of every layer merged, e.g. to print those of
+    /// configurations that are not `Serialize`.
+    pub fn to_toml(&self) -> Result<String> {
+        // As a `Value`, so that the settings of every table come before its nested tables.
+        to_toml(&Value::Table(self.table.clone()))
+    }
+}
+
+/// The `toml` form of a configuration, e.g. to print the effective configuration built by
+/// `ConfigLayers`.
+pub fn to_toml<T: Serialize>(config: &T) -> Result<String> {
+    Ok(toml::to_string(config).context(ErrorKind::ConfigParse)?)
+}
+
+// The contents of a configuration file.
+fn read_file<P: AsRef<Path>>(path: P) -> Result<String> {
+    let path = path.as_ref();
+    let mut config_str = String::new();
+    let _read = File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut config_str))
+        .context(ErrorKind::ConfigRead(path.display().to_string()))?;
+    Ok(config_str)
+}
+
+// Merge the settings of `layer` into `base`, table by table.
+fn merge_tables(base: &mut Table, layer: Table) {
+    for (key, value) in layer {
+        let merged = match (base.remove(&key), value) {
+            (Some(Value::Table(mut nested)), Value::Table(layer)) => {
+                merge_tables(&mut nested, layer);
+                Value::Table(nested)
+            }
+            (_, value) => value,
+        };
+        base.insert(key, merged);
+    }
+}
+
+// The name of a key in variable names.
+fn var_name(key: &str) -> String {
+    key.to_uppercase().replace('-', "_")
+}
+
+// Set the setting named by `name`, the variable names of its keys joined by `_`, to `raw`.
+// Returns `None` if the value is not valid, or `name` refers to a table in an array that
+// does not exist.
+fn set_var(table: &mut Table, name: &str, raw: &str) -> Option<()> {
+    // Longer keys first, so that `REP_URL` is not taken for a `URL` setting in `REP`.
+    let mut keys: Vec<String> = table.keys().cloned().collect();
+    keys.sort_by(|a, b| b.len().cmp(&a.len()));
+    for key in keys {
+        let key_name = var_name(&key);
+        if name == key_name {
+            let typed = typed_value(table.get(&key), raw)?;
+            table.insert(key, typed);
+            return Some(());
+        }
+        if !name.starts_with(&format!("{}_", key_name)) {
+            continue;
+        }
+        let rest = &name[key_name.len() + 1..];
+        match table.get_mut(&key) {
+            Some(&mut Value::Table(ref mut nested)) => return set_var(nested, rest, raw),
+            Some(&mut Value::Array(ref mut items)) => {
+                let (nested, label) =
+                    find_item(items, |label| rest.starts_with(&format!("{}_", label)))?;
+                return set_var(nested, &rest[label.len() + 1..], raw);
+            }
+            _ => {}
+        }
+    }
+    let typed = typed_value(None, raw)?;
+    table.insert(name.to_lowercase(), typed);
+    Some(())
+}
+
+// The first table in `items` with a label, its index or the variable name of its `name`,
+// that `matches`, along with that label.
+fn find_item<F>(items: &mut [Value], matches: F) -> Option<(&mut Table, String)>
+where
+    F: Fn(&str) -> bool,
+{
+    for (index, item) in items.iter_mut().enumerate() {
+        let nested = match *item {
+            Value::Table(ref mut nested) => nested,
+            _ => continue,
+        };
+        let mut labels = vec![index.to_string()];
+        if let Some(&Value::String(ref name)) = nested.get("name") {
+            labels.push(var_name(name));
+        }
+        if let Some(label) = labels.into_iter().find(|label| matches(label)) {
+            return Some((nested, label));
+        }
+    }
+    None
+}
+
+// Set the setting at `keys` to `raw`. Returns `None` if the value is not valid.
+fn set_value(table: &mut Table, keys: &[&str], raw: &str) -> Option<()> {
+    let key = keys[0].to_string();
+    if keys.len() > 1 {
+        let nested = table
+            .entry(key)
+            .or_insert_with(|| Value::Table(Table::new()));
+        return match *nested {
+            Value::Table(ref mut nested) => set_value(nested, &keys[1..], raw),
+            Value::Array(ref mut items) if keys.len() > 2 => {
+                let label = var_name(keys[1]);
+                let (nested, _) = find_item(items, |l| l == label)?;
+                set_value(nested, &keys[2..], raw)
+            }
+            _ => None,
+        };
+    }
+    let typed = typed_value(table.get(&key), raw)?;
+    table.insert(key, typed);
+    Some(())
+}
+
+// The value of `raw`, typed as the `current` value of its setting. Settings without a
+// value take integers, floats, booleans, and arrays as such, and anything else as a string.
+fn typed_value(current: Option<&Value>, raw: &str) -> Option<Value> {
+    match current {
+        Some(&Value::Integer(_)) => raw.parse().ok().map(Value::Integer),
+        Some(&Value::Float(_)) => raw.parse().ok().map(Value::Float),
+        Some(&Value::Boolean(_)) => raw.parse().ok().map(Value::Boolean),
+        Some(&Value::Array(_)) => toml_value(raw),
+        Some(&Value::Table(_)) => None,
+        Some(_) => Some(Value::String(raw.to_string())),
+        None => match toml_value(raw) {
+            Some(value @ Value::Integer(_))
+            | Some(value @ Value::Float(_))
+            | Some(value @ Value::Boolean(_))
+            | Some(value @ Value::Array(_)) => Some(value),
+            _ => Some(Value::String(raw.to_string())),
+        },
+    }
+}
+
+// The value of `raw`, as written in `toml`.
+fn toml_value(raw: &str) -> Option<Value> {
+    toml::from_str::<Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+}
+
+/// Kind of EZO sensor.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum SensorKind {
+    #[serde(rename = "conductivity")]
+    Conductivity,
+    #[serde(rename = "ph")]
+    Ph,
+    #[serde(rename = "temperature")]
+    Temperature,
+}
+
+/// Configuration settings for a sensor sampled by the publisher service.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct PublishedSensorConfig<'a> {
+    pub kind: SensorKind,
+    pub path: PathBuf,
+    pub address: u16,
+    pub topic: &'a str,
+    /// Sampling interval, in milliseconds.
+    pub interval: u64,
+    /// Put the sensor to sleep between samples.
+    #[serde(default)]
+    pub sleep: bool,
+}
+
+/// Configuration settings for the local storage of measurements.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Directory that holds the measurement log.
+    pub path: PathBuf,
+    /// Maximum size of a log segment, in bytes.
+    #[serde(default)]
+    pub max_segment_size: Option<u64>,
+    /// Maximum time covered by a log segment, in seconds.
+    #[serde(default)]
+    pub max_segment_age: Option<u64>,
+    /// Maximum number of log segments to keep.
+    #[serde(default)]
+    pub max_segments: Option<usize>,
+    /// How long to keep measurements, in seconds.
+    #[serde(default)]
+    pub retention: Option<u64>,
+}
+
+impl StorageConfig {
+    pub fn from_str(config_str: &str) -> Result<StorageConfig> {
+        Ok(toml::from_str(config_str).context(ErrorKind::ConfigParse)?)
+    }
+
+    /// The log policy for these settings. Unset limits take their default values.
+    pub fn policy(&self) -> LogPolicy {
+        let default = LogPolicy::default();
+        LogPolicy {
+            max_segment_size: self.max_segment_size.unwrap_or(default.max_segment_size),
+            max_segment_age: self
+                .max_segment_age
+                .map(Duration::from_secs)
+                .or(default.max_segment_age),
+            max_segments: self.max_segments.or(default.max_segments),
+            retention: self
+                .retention
+                .map(Duration::from_secs)
+                .or(default.retention),
+        }
+    }
+}
+
+/// Configuration settings for the publisher service.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct PublisherConfig<'a> {
+    pub pub_url: &'a str,
+    #[serde(borrow)]
+    pub sensors: Vec<PublishedSensorConfig<'a>>,
+    /// Store every sample locally, as well as publishing it.
+    #[serde(default)]
+    pub storage: Option<StorageConfig>,
+    /// Publish the statistics of every sensor for these windows, e.g. `["1h", "1d"]`.
+    #[serde(default)]
+    pub aggregate: Vec<Window>,
+    /// Publish alarms on the `alarm` topic when these rules are violated.
+    #[serde(default)]
+    pub alarms: Vec<AlarmRule>,
+    /// Answer `alarms`, and `history`, requests on a `REP` socket bound to this URL.
+    #[serde(default)]
+    pub rep_url: Option<&'a str>,
+}
+
+impl<'a> PublisherConfig<'a> {
+    pub fn from_str(config_str: &str) -> Result<PublisherConfig> {
+        Ok(toml::from_str(config_str).context(ErrorKind::ConfigParse)?)
+    }
+}
+
+/// Configuration settings for a sensor exposed by the HTTP gateway, or the MQTT bridge.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct GatewaySensorConfig<'a> {
+    /// Name of the sensor in the gateway's paths, e.g. `ph1` in `/sensors/ph1/reading`, or
+    /// in the bridge's topics, e.g. `benita/ph1/command`.
+    pub name: &'a str,
+    pub kind: SensorKind,
+    /// URL of the sensor's command server.
+    pub url: &'a str,
+    /// Topic the sensor's readings are published on, `<type>-<id>`. Needed to announce the
+    /// sensor to Home Assistant.
+    #[serde(default)]
+    pub topic: Option<&'a str>,
+}
+
+/// Configuration settings for the HTTP gateway.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct GatewayConfig<'a> {
+    /// Address of the HTTP server, e.g. `0.0.0.0:8080`.
+    pub http_addr: &'a str,
+    #[serde(borrow)]
+    pub sensors: Vec<GatewaySensorConfig<'a>>,
+    /// URL of a publisher, whose readings are kept as the latest values of the metrics.
+    #[serde(default)]
+    pub sub_url: Option<&'a str>,
+}
+
+impl<'a> GatewayConfig<'a> {
+    pub fn from_str(config_str: &str) -> Result<GatewayConfig> {
+        Ok(toml::from_str(config_str).context(ErrorKind::ConfigParse)?)
+    }
+}
+
+/// Configuration settings for the MQTT bridge.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+pub struct MqttConfig<'a> {
+    /// Address of the MQTT broker, e.g. `localhost:1883`.
+    pub broker_addr: &'a str,
+    pub client_id: &'a str,
+    #[serde(default)]
+    pub username: Option<&'a str>,
+    #[serde(default)]
+    pub password: Option<&'a str>,
+    /// Keep alive interval, in seconds. Defaults to 60.
+    #[serde(default)]
+    pub keep_alive: Option<u16>,
+    /// Prefix of every topic used by the bridge. Defaults to `benita`.
+    #[serde(default)]
+    pub topic_prefix: Option<&'a str>,
+    /// QoS of the publications, `0` or `1`.
+    #[serde(default)]
+    pub qos: u8,
+    /// Whether the broker should retain the latest measurement of every sensor.
+    #[serde(default)]
+    pub retain: bool,
+    /// URL of a publisher, whose measurements are republished to MQTT.
+    #[serde(default)]
+    pub sub_url: Option<&'a str>,
+    /// Prefix of the Home Assistant discovery topics, e.g. `homeassistant`. When set, the
+    /// sensors with a `topic` are announced to Home Assistant.
+    #[serde(default)]
+    pub discovery_prefix: Option<&'a str>,
+    /// Command servers, whose requests are accepted on MQTT command topics.
+    #[serde(borrow, default)]
+    pub sensors: Vec<GatewaySensorConfig<'a>>,
+}
+
+impl<'a> MqttConfig<'a> {
+    pub fn from_str(config_str: &str) -> Result<MqttConfig> {
+        Ok(toml::from_str(config_str).context(ErrorKind::ConfigParse)?)
+    }
+}
+
+/// Where the InfluxDB sink writes its lines.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", deny_unknown_fields)]
+pub enum InfluxDestination {
+    /// A file, rotated when it grows past `max_bytes`, keeping `max_files` rotated files.
+    #[serde(rename = "file")]
+    File {
+        path: PathBuf,
+        #[serde(default)]
+        max_bytes: Option<u64>,
+        #[serde(default)]
+        max_files: Option<usize>,
+    },
+    /// A UDP listener, e.g. `localhost:8089`.
+    #[serde(rename = "udp")]
+    Udp { addr: String },
+    /// An HTTP write endpoint, e.g. `http://localhost:8086/write?db=benita`.
+    #[serde(rename = "http")]
+    Http { url: String },
+}
+
+/// Configuration settings for the InfluxDB sink.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct InfluxConfig<'a> {
+    /// URL of a publisher, whose measurements are written.
+    pub sub_url: &'a str,
+    /// Name of the InfluxDB measurement. Defaults to `benita`.
+    #[serde(default)]
+    pub measurement: Option<&'a str>,
+    /// Value of the `site` tag of every point.
+    #[serde(default)]
+    pub site: Option<&'a str>,
+    /// Firmware version of every sensor, by sensor id, for the `firmware` tag.
+    #[serde(default)]
+    pub firmware: BTreeMap<String, String>,
+    /// Number of lines written at once. Defaults to 100.
+    #[serde(default)]
+    pub batch_size: Option<usize>,
+    /// Longest time lines wait to be written, in seconds. Defaults to 10.
+    #[serde(default)]
+    pub flush_interval: Option<u64>,
+    /// Number of times a failed write is retried. Defaults to 3.
+    #[serde(default)]
+    pub retries: Option<u32>,
+    pub destination: InfluxDestination,
+}
+
+impl<'a> InfluxConfig<'a> {
+    pub fn from_str(config_str: &str) -> Result<InfluxConfig> {
+        Ok(toml::from_str(config_str).context(ErrorKind::ConfigParse)?)
+    }
+}
+
+/// Configuration settings for an I2C bus of a sensor node.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct BusConfig {
+    /// Name the node's sensors refer to the bus by, e.g. `i2c1`.
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// Settings applied to a node's sensor when it is brought up.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SensorOptions {
+    /// Put the sensor to sleep after every sample, with `true`, or never, with `false`. By
+    /// default, it sleeps when it is idle long enough between samples.
+    #[serde(default)]
+    pub sleep: Option<bool>,
+    /// Compensation temperature, in °C, of conductivity, and pH, sensors.
+    #[serde(default)]
+    pub compensation: Option<f64>,
+    /// Output parameters of conductivity sensors, e.g. `["EC", "TDS"]`. The others are
+    /// turned off.
+    #[serde(default)]
+    pub output: Option<Vec<String>>,
+    /// Scale of temperature sensors, `celsius`, `fahrenheit`, or `kelvin`.
+    #[serde(default)]
+    pub scale: Option<String>,
+}
+
+/// When a node's sensor is sampled, either on an `interval`, or on a `cron` expression.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleConfig {
+    /// Sampling interval, in milliseconds.
+    #[serde(default)]
+    pub interval: Option<u64>,
+    /// Sample on the wall-clock multiples of the interval, e.g. on the minute.
+    #[serde(default)]
+    pub align: bool,
+    /// Cron-like expression, in UTC, e.g. `0 */15 * * * *`.
+    #[serde(default)]
+    pub cron: Option<String>,
+}
+
+impl ScheduleConfig {
+    /// The schedule described by these settings.
+    pub fn schedule(&self) -> Result<Schedule> {
+        match (self.interval, self.cron.as_ref()) {
+            (Some(interval), None) if self.align => {
+                Ok(Schedule::Aligned(Duration::from_millis(interval)))
+            }
+            (Some(interval), None) => Ok(Schedule::Every(Duration::from_millis(interval))),
+            (None, Some(cron)) if !self.align => Ok(Schedule::Cron(cron.parse()?)),
+            (None, Some(_)) => {
+                let reason = "`align` applies to intervals only".to_string();
+                Err(ErrorKind::ConfigValue(reason).into())
+            }
+            (Some(_), Some(_)) => {
+                let reason = "schedule has both an interval, and a cron expression".to_string();
+                Err(ErrorKind::ConfigValue(reason).into())
+            }
+            (None, None) => {
+                let reason = "schedule has neither an interval, nor a cron expression".to_string();
+                Err(ErrorKind::ConfigValue(reason).into())
+            }
+        }
+    }
+}
+
+/// Configuration settings for a sensor of a node.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NodeSensorConfig {
+    /// Name of the sensor, unique in the node, e.g. `ph1`.
+    pub name: String,
+    pub kind: SensorKind,
+    /// Name of the bus the sensor is on.
+    pub bus: String,
+    pub address: u16,
+    /// Topic the sensor's readings are published on, `<type>-<id>`. Sensors without one are
+    /// not sampled.
+    #[serde(default)]
+    pub topic: Option<String>,
+    /// URL the sensor's responder is bound to, if any.
+    #[serde(default)]
+    pub rep_url: Option<String>,
+    /// Sampling schedule. Sensors are sampled every 10 seconds, unless given.
+    #[serde(default)]
+    pub schedule: Option<ScheduleConfig>,
+    #[serde(default)]
+    pub options: SensorOptions,
+}
+
+/// Configuration settings for the publisher of a node.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct NodePublisherConfig {
+    /// URL the `PUB` socket is bound to.
+    pub url: String,
+    /// Answer `alarms`, and `history`, requests on a `REP` socket bound to this URL.
+    #[serde(default)]
+    pub rep_url: Option<String>,
+    /// Publish the statistics of every sensor for these windows, e.g. `["1h", "1d"]`.
+    #[serde(default)]
+    pub aggregate: Vec<Window>,
+    /// Reload the configuration of the node on `reload` requests to the `rep_url`.
+    #[serde(default)]
+    pub reload: bool,
+}
+
+/// An exporter of the measurements published by a node.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(tag = "kind", deny_unknown_fields)]
+pub enum ExporterConfig {
+    /// Write InfluxDB line protocol, as the `InfluxSink` does.
+    #[serde(rename = "influx")]
+    Influx {
+        #[serde(default)]
+        measurement: Option<String>,
+        #[serde(default)]
+        site: Option<String>,
+        #[serde(default)]
+        firmware: BTreeMap<String, String>,
+        #[serde(default)]
+        batch_size: Option<usize>,
+        #[serde(default)]
+        flush_interval: Option<u64>,
+        #[serde(default)]
+        retries: Option<u32>,
+        destination: InfluxDestination,
+    },
+    /// Append rows to a file, as the `Exporter` does.
+    #[serde(rename = "file")]
+    File {
+        path: PathBuf,
+        /// `csv`, or `jsonl`. Defaults to `csv`.
+        #[serde(default)]
+        format: Option<String>,
+        /// `utc`, `local`, or an offset like `-06:00`. Defaults to `utc`.
+        #[serde(default)]
+        time_zone: Option<String>,
+        #[serde(default)]
+        precision: Option<usize>,
+    },
+}
+
+/// Configuration settings for a whole sensor node: its buses, and sensors, with their
+/// responders, and the publisher, storage, alarms, and exporters of their measurements.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NodeConfig {
+    /// Name of the node, e.g. `tank-a`.
+    pub name: String,
+    #[serde(default)]
+    pub buses: Vec<BusConfig>,
+    #[serde(default)]
+    pub sensors: Vec<NodeSensorConfig>,
+    #[serde(default)]
+    pub publisher: Option<NodePublisherConfig>,
+    /// Store every sample locally, as well as publishing it.
+    #[serde(default)]
+    pub storage: Option<StorageConfig>,
+    /// Publish alarms on the `alarm` topic when these rules are violated.
+    #[serde(default)]
+    pub alarms: Vec<AlarmRule>,
+    #[serde(default)]
+    pub exporters: Vec<ExporterConfig>,
+}
+
+impl NodeConfig {
+    /// Parse a node configuration, ignoring, with a warning, the keys it does not know.
+    pub fn from_str(config_str: &str) -> Result<NodeConfig> {
+        validate::lenient_node_config(config_str)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<NodeConfig> {
+        NodeConfig::from_str(&read_file(path)?)
+    }
+
+    /// Parse the configuration, rejecting unknown keys, and settings that make no sense.
+    /// See `validate`.
+    pub fn from_str_strict(config_str: &str) -> Result<NodeConfig> {
+        validate::node_config(config_str, None)
+    }
+
+    /// Read the configuration from a file, rejecting unknown keys, and settings that make
+    /// no sense. See `validate`.
+    pub fn from_file_strict<P: AsRef<Path>>(path: P) -> Result<NodeConfig> {
+        let path = path.as_ref();
+        validate::node_config(&read_file(path)?, Some(path))
+    }
+
+    /// The bus with the given name.
+    pub fn bus(&self, name: &str) -> Option<&BusConfig> {
+        self.buses.iter().find(|b| b.name == name)
+    }
+
+    /// The sensor with the given name.
+    pub fn sensor(&self, name: &str) -> Option<&NodeSensorConfig> {
+        self.sensors.iter().find(|s| s.name == name)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use alarm::Severity;
 
     // SensorServiceConfig for valid use cases
     #[test]
@@ -110,10 +813,10 @@ mod tests {
         assert_eq!(
             config,
             SensorServiceConfig {
-                pub_url: "ipc://tmp/benita.temp.ipc",
-                channel: "01234-id",
-                rep_ec_url: "ipc://tmp/benita.ec.ipc",
-                rep_ph_url: "ipc://tmp/benita.ph.ipc",
+                pub_url: "ipc://tmp/benita.temp.ipc".to_string(),
+                channel: "01234-id".to_string(),
+                rep_ec_url: "ipc://tmp/benita.ec.ipc".to_string(),
+                rep_ph_url: "ipc://tmp/benita.ph.ipc".to_string(),
             }
         );
 
@@ -130,10 +833,10 @@ mod tests {
         assert_eq!(
             config,
             SensorServiceConfig {
-                pub_url: "ipc://tmp/benita.temp.ipc",
-                channel: "01234-id",
-                rep_ec_url: "ipc://tmp/benita.ec.ipc",
-                rep_ph_url: "ipc://tmp/benita.ph.ipc",
+                pub_url: "ipc://tmp/benita.temp.ipc".to_string(),
+                channel: "01234-id".to_string(),
+                rep_ec_url: "ipc://tmp/benita.ec.ipc".to_string(),
+                rep_ph_url: "ipc://tmp/benita.ph.ipc".to_string(),
             }
         );
     }
@@ -216,7 +919,7 @@ mod tests {
         assert_eq!(
             config,
             SocketConfig {
-                url: "ipc://temp.ipc",
+                url: "ipc://temp.ipc".to_string(),
                 socket_connection: ConnectionType::Connect,
             }
         );
@@ -230,7 +933,7 @@ mod tests {
         assert_eq!(
             config,
             SocketConfig {
-                url: "ipc://temp.ipc",
+                url: "ipc://temp.ipc".to_string(),
                 socket_connection: ConnectionType::Connect,
             }
         );
@@ -267,8 +970,8 @@ mod tests {
         assert_eq!(
             config,
             ProxyConfig {
-                backend_url: "ipc://temp.ipc",
-                frontend_url: "tcp://127.0.0.1:5558",
+                backend_url: "ipc://temp.ipc".to_string(),
+                frontend_url: "tcp://127.0.0.1:5558".to_string(),
                 backend_connection: ConnectionType::Connect,
                 frontend_connection: ConnectionType::Connect,
             }
@@ -287,8 +990,8 @@ mod tests {
         assert_eq!(
             config,
             ProxyConfig {
-                backend_url: "ipc://temp.ipc",
-                frontend_url: "tcp://127.0.0.1:5558",
+                backend_url: "ipc://temp.ipc".to_string(),
+                frontend_url: "tcp://127.0.0.1:5558".to_string(),
                 backend_connection: ConnectionType::Bind,
                 frontend_connection: ConnectionType::Connect,
             }
@@ -313,4 +1016,659 @@ mod tests {
         let config: Result<ProxyConfig> = ProxyConfig::from_str(config_str);
         assert!(config.is_err());
     }
+
+    // ConfigLayers overriding defaults with a file, variables, and flags
+    #[test]
+    fn layers_override_defaults_with_file_vars_and_flags() {
+        let defaults = SensorConfig::new("/dev/i2c-1", 99);
+        let mut layers = ConfigLayers::new(&defaults).unwrap();
+        assert_eq!(defaults, layers.build().unwrap());
+
+        layers.merge_str(r#"path = "/dev/i2c-0""#).unwrap();
+        let config: SensorConfig = layers.build().unwrap();
+        assert_eq!(SensorConfig::new("/dev/i2c-0", 99), config);
+
+        let vars = vec![("BENITA_SENSOR_PH_ADDRESS".to_string(), "100".to_string())];
+        layers.merge_vars("BENITA_SENSOR_PH", vars.clone()).unwrap();
+        let config: SensorConfig = layers.build().unwrap();
+        assert_eq!(SensorConfig::new("/dev/i2c-0", 100), config);
+
+        // Variables of other prefixes are ignored
+        layers.merge_vars("BENITA_SENSOR_EC", vars).unwrap();
+        let config: SensorConfig = layers.build().unwrap();
+        assert_eq!(SensorConfig::new("/dev/i2c-0", 100), config);
+
+        layers.set("address", "101").unwrap();
+        let config: SensorConfig = layers.build().unwrap();
+        assert_eq!(SensorConfig::new("/dev/i2c-0", 101), config);
+
+        assert_eq!(
+            "path = \"/dev/i2c-0\"\naddress = 101\n",
+            to_toml(&config).unwrap()
+        );
+    }
+
+    // ConfigLayers overriding the tables in arrays, and adding settings
+    #[test]
+    fn layers_override_tables_in_arrays_and_add_missing_settings() {
+        let mut layers = ConfigLayers::default();
+        layers
+            .merge_str(
+                r#"
+                name = "tank-a"
+
+                [[buses]]
+                name = "i2c1"
+                path = "/dev/i2c-1"
+
+                [[sensors]]
+                name = "ph1"
+                kind = "ph"
+                bus = "i2c1"
+                address = 99
+
+                [[sensors]]
+                name = "ec-1"
+                kind = "conductivity"
+                bus = "i2c1"
+                address = 100
+                rep_url = "tcp://*:5558"
+
+                [publisher]
+                url = "tcp://*:5557"
+                "#,
+            )
+            .unwrap();
+        let vars = vec![
+            ("BENITA_SENSORS_PH1_ADDRESS".to_string(), "102".to_string()),
+            ("BENITA_SENSORS_1_REP_URL".to_string(), "tcp://*:5560".to_string()),
+            ("BENITA_SENSORS_EC_1_TOPIC".to_string(), "ec-01".to_string()),
+            ("BENITA_PUBLISHER_REP_URL".to_string(), "tcp://*:5559".to_string()),
+            ("BENITA_PUBLISHER_AGGREGATE".to_string(), r#"["1h"]"#.to_string()),
+        ];
+        layers.merge_vars("BENITA", vars).unwrap();
+        layers.set("sensors.ph1.options.compensation", "25.5").unwrap();
+        layers.set("sensors.1.schedule.interval", "60000").unwrap();
+        let config: NodeConfig = layers.build().unwrap();
+
+        assert_eq!(102, config.sensors[0].address);
+        assert_eq!(Some(25.5), config.sensors[0].options.compensation);
+        let sensor = &config.sensors[1];
+        assert_eq!(Some("tcp://*:5560".to_string()), sensor.rep_url);
+        assert_eq!(Some("ec-01".to_string()), sensor.topic);
+        assert_eq!(Some(60_000), sensor.schedule.as_ref().unwrap().interval);
+        let publisher = config.publisher.unwrap();
+        assert_eq!(Some("tcp://*:5559".to_string()), publisher.rep_url);
+        assert_eq!(vec![Window::Hour], publisher.aggregate);
+        assert!(layers.to_toml().unwrap().contains("rep_url = \"tcp://*:5559\""));
+    }
+
+    // ConfigLayers for invalid use cases
+    #[test]
+    fn layers_with_invalid_values_yield_err() {
+        let mut layers = ConfigLayers::new(&ProxyConfig::default()).unwrap();
+        // Values that are not of the type of their setting
+        let vars = vec![(
+            "BENITA_PROXY_BACKEND_CONNECTION".to_string(),
+            "both".to_string(),
+        )];
+        layers.merge_vars("BENITA_PROXY", vars).unwrap();
+        assert!(layers.build::<ProxyConfig>().is_err());
+
+        let mut layers = ConfigLayers::new(&SensorConfig::default()).unwrap();
+        assert!(layers.set("address", "x63").is_err());
+        assert!(layers.set("address.bus", "1").is_err());
+        // Settings that are not known to the configuration are ignored
+        layers.set("extra", "unseen").unwrap();
+        assert!(layers.build::<SensorConfig>().is_ok());
+
+        // Tables in arrays that do not exist yield error
+        let mut layers = ConfigLayers::default();
+        layers.merge_str("[[sensors]]\nname = \"ph1\"").unwrap();
+        let vars = vec![("BENITA_SENSORS_EC1_ADDRESS".to_string(), "100".to_string())];
+        assert_eq!(
+            ErrorKind::ConfigValue("BENITA_SENSORS_EC1_ADDRESS".to_string()),
+            layers.merge_vars("BENITA", vars).unwrap_err().kind()
+        );
+        assert!(layers.set("sensors.1.address", "100").is_err());
+
+        // Missing files yield error
+        assert!(layers.merge_file("/nonexistent/benita.toml").is_err());
+        assert!(ProxyConfig::from_file("/nonexistent/benita.toml").is_err());
+
+        // Unknown keys yield error in strict mode
+        let mut layers = ConfigLayers::new(&ProxyConfig::default()).unwrap();
+        layers.set_strict(true);
+        assert!(layers
+            .merge_str(r#"frontend_url = "tcp://127.0.0.1:5558""#)
+            .is_ok());
+        let err = layers
+            .merge_str(r#"frontent_url = "tcp://127.0.0.1:5558""#)
+            .unwrap_err();
+        assert_eq!(
+            ErrorKind::ConfigInvalid(
+                "line 1: frontent_url: unknown key `frontent_url`, did you mean `frontend_url`?"
+                    .to_string()
+            ),
+            err.kind()
+        );
+    }
+
+    // PublisherConfig for valid use cases
+    #[test]
+    fn reads_and_parses_publisher_config_toml() {
+        let config_str = r#"
+            pub_url = "tcp://*:5557"
+
+            [[sensors]]
+            kind = "temperature"
+            path = "/dev/i2c-1"
+            address = 101
+            topic = "temperature-0123456789abcdef"
+            interval = 10000
+            sleep = true
+
+            [[sensors]]
+            kind = "ph"
+            path = "/dev/i2c-1"
+            address = 99
+            topic = "ph-0123456789abcdef"
+            interval = 60000
+            "#;
+
+        let config = PublisherConfig::from_str(config_str).unwrap();
+        assert_eq!(
+            config,
+            PublisherConfig {
+                pub_url: "tcp://*:5557",
+                sensors: vec![
+                    PublishedSensorConfig {
+                        kind: SensorKind::Temperature,
+                        path: PathBuf::from("/dev/i2c-1"),
+                        address: 101,
+                        topic: "temperature-0123456789abcdef",
+                        interval: 10_000,
+                        sleep: true,
+                    },
+                    PublishedSensorConfig {
+                        kind: SensorKind::Ph,
+                        path: PathBuf::from("/dev/i2c-1"),
+                        address: 99,
+                        topic: "ph-0123456789abcdef",
+                        interval: 60_000,
+                        sleep: false,
+                    },
+                ],
+                storage: None,
+                aggregate: vec![],
+                alarms: vec![],
+                rep_url: None,
+            }
+        );
+
+        let config_str = r#"
+            pub_url = "tcp://*:5557"
+            sensors = []
+            aggregate = ["15m", "1d"]
+            "#;
+
+        let config = PublisherConfig::from_str(config_str).unwrap();
+        assert_eq!(vec![Window::FifteenMinutes, Window::Day], config.aggregate);
+    }
+
+    // AlarmRule for valid use cases
+    #[test]
+    fn reads_and_parses_alarm_config_toml() {
+        let config_str = r#"
+            pub_url = "tcp://*:5557"
+            rep_url = "tcp://*:5558"
+            sensors = []
+
+            [[alarms]]
+            name = "ph-range"
+            sensor = "ph-0123456789abcdef"
+            low = 6.5
+            high = 8.5
+            hysteresis = 0.1
+            duration = 300
+            severity = "critical"
+
+            [[alarms]]
+            name = "temperature-rising"
+            sensor = "temperature-0123456789abcdef"
+            rate = 2.0
+            rate_window = 1800
+            rate_hysteresis = 0.5
+            "#;
+
+        let config = PublisherConfig::from_str(config_str).unwrap();
+        assert_eq!(Some("tcp://*:5558"), config.rep_url);
+        assert_eq!(2, config.alarms.len());
+        assert_eq!(
+            config.alarms[0],
+            AlarmRule {
+                name: "ph-range".to_string(),
+                sensor: "ph-0123456789abcdef".to_string(),
+                low: Some(6.5),
+                high: Some(8.5),
+                rate: None,
+                rate_window: None,
+                hysteresis: 0.1,
+                rate_hysteresis: 0.0,
+                duration: 300,
+                severity: Severity::Critical,
+            }
+        );
+        assert_eq!(Severity::Warning, config.alarms[1].severity);
+        assert_eq!(Some(2.0), config.alarms[1].rate);
+        assert_eq!(Some(1800), config.alarms[1].rate_window);
+        assert_eq!(0.5, config.alarms[1].rate_hysteresis);
+    }
+
+    // StorageConfig for valid use cases
+    #[test]
+    fn reads_and_parses_storage_config_toml() {
+        let config_str = r#"
+            pub_url = "tcp://*:5557"
+            sensors = []
+
+            [storage]
+            path = "/var/lib/benita"
+            max_segments = 30
+            retention = 2592000
+            "#;
+
+        let config = PublisherConfig::from_str(config_str).unwrap();
+        let storage = config.storage.unwrap();
+        assert_eq!(PathBuf::from("/var/lib/benita"), storage.path);
+        assert_eq!(
+            storage.policy(),
+            LogPolicy {
+                max_segment_size: LogPolicy::default().max_segment_size,
+                max_segment_age: LogPolicy::default().max_segment_age,
+                max_segments: Some(30),
+                retention: Some(Duration::from_secs(2_592_000)),
+            }
+        );
+
+        // Storage settings need a path
+        let config_str = r#"
+            retention = 2592000
+            "#;
+
+        let config: Result<StorageConfig> = StorageConfig::from_str(config_str);
+        assert!(config.is_err());
+    }
+
+    // GatewayConfig for valid use cases
+    #[test]
+    fn reads_and_parses_gateway_config_toml() {
+        let config_str = r#"
+            http_addr = "0.0.0.0:8080"
+
+            [[sensors]]
+            name = "ph1"
+            kind = "ph"
+            url = "tcp://192.168.16.123:5558"
+            "#;
+
+        let config = GatewayConfig::from_str(config_str).unwrap();
+        assert_eq!(
+            config,
+            GatewayConfig {
+                http_addr: "0.0.0.0:8080",
+                sensors: vec![GatewaySensorConfig {
+                    name: "ph1",
+                    kind: SensorKind::Ph,
+                    url: "tcp://192.168.16.123:5558",
+                    topic: None,
+                }],
+                sub_url: None,
+            }
+        );
+
+        // Sensors need a url
+        let config_str = r#"
+            http_addr = "0.0.0.0:8080"
+
+            [[sensors]]
+            name = "ph1"
+            kind = "ph"
+            "#;
+
+        let config: Result<GatewayConfig> = GatewayConfig::from_str(config_str);
+        assert!(config.is_err());
+    }
+
+    // MqttConfig for valid use cases
+    #[test]
+    fn reads_and_parses_mqtt_config_toml() {
+        let config_str = r#"
+            broker_addr = "localhost:1883"
+            client_id = "benita-node"
+            qos = 1
+            retain = true
+            sub_url = "tcp://192.168.16.123:5556"
+            discovery_prefix = "homeassistant"
+
+            [[sensors]]
+            name = "ph1"
+            kind = "ph"
+            url = "tcp://192.168.16.123:5558"
+            topic = "ph-0123456789abcdef"
+            "#;
+
+        let config = MqttConfig::from_str(config_str).unwrap();
+        assert_eq!(
+            config,
+            MqttConfig {
+                broker_addr: "localhost:1883",
+                client_id: "benita-node",
+                qos: 1,
+                retain: true,
+                sub_url: Some("tcp://192.168.16.123:5556"),
+                discovery_prefix: Some("homeassistant"),
+                sensors: vec![GatewaySensorConfig {
+                    name: "ph1",
+                    kind: SensorKind::Ph,
+                    url: "tcp://192.168.16.123:5558",
+                    topic: Some("ph-0123456789abcdef"),
+                }],
+                ..Default::default()
+            }
+        );
+
+        // The broker address is required
+        let config_str = r#"
+            client_id = "benita-node"
+            "#;
+
+        let config: Result<MqttConfig> = MqttConfig::from_str(config_str);
+        assert!(config.is_err());
+    }
+
+    // InfluxConfig for valid use cases
+    #[test]
+    fn reads_and_parses_influx_config_toml() {
+        let config_str = r#"
+            sub_url = "tcp://192.168.16.123:5556"
+            site = "tank-a"
+            batch_size = 500
+
+            [firmware]
+            0123456789abcdef = "2.10"
+
+            [destination]
+            kind = "http"
+            url = "http://localhost:8086/write?db=benita"
+            "#;
+
+        let config = InfluxConfig::from_str(config_str).unwrap();
+        let mut firmware = BTreeMap::new();
+        let _firmware = firmware.insert("0123456789abcdef".to_string(), "2.10".to_string());
+        assert_eq!(
+            config,
+            InfluxConfig {
+                sub_url: "tcp://192.168.16.123:5556",
+                measurement: None,
+                site: Some("tank-a"),
+                firmware,
+                batch_size: Some(500),
+                flush_interval: None,
+                retries: None,
+                destination: InfluxDestination::Http {
+                    url: "http://localhost:8086/write?db=benita".to_string(),
+                },
+            }
+        );
+
+        let config_str = r#"
+            sub_url = "tcp://192.168.16.123:5556"
+
+            [destination]
+            kind = "file"
+            path = "/var/lib/benita/points.lp"
+            max_files = 3
+            "#;
+
+        let config = InfluxConfig::from_str(config_str).unwrap();
+        assert_eq!(
+            config.destination,
+            InfluxDestination::File {
+                path: PathBuf::from("/var/lib/benita/points.lp"),
+                max_bytes: None,
+                max_files: Some(3),
+            }
+        );
+
+        // Unknown destinations yield error
+        let config_str = r#"
+            sub_url = "tcp://192.168.16.123:5556"
+
+            [destination]
+            kind = "tcp"
+            addr = "localhost:8094"
+            "#;
+
+        let config: Result<InfluxConfig> = InfluxConfig::from_str(config_str);
+        assert!(config.is_err());
+    }
+
+    // PublisherConfig for invalid use cases
+    #[test]
+    fn reads_and_parses_invalid_publisher_config_toml_yielding_err() {
+        // Files with no known fields yield error
+        let config_str = r#""#;
+
+        let config: Result<PublisherConfig> = PublisherConfig::from_str(config_str);
+        assert!(config.is_err());
+
+        // Unknown sensor kinds yield error
+        let config_str = r#"
+            pub_url = "tcp://*:5557"
+
+            [[sensors]]
+            kind = "rgb"
+            path = "/dev/i2c-1"
+            address = 112
+            topic = "rgb-0123456789abcdef"
+            interval = 10000
+            "#;
+
+        let config: Result<PublisherConfig> = PublisherConfig::from_str(config_str);
+        assert!(config.is_err());
+
+        // Unknown aggregation windows yield error
+        let config_str = r#"
+            pub_url = "tcp://*:5557"
+            sensors = []
+            aggregate = ["1w"]
+            "#;
+
+        let config: Result<PublisherConfig> = PublisherConfig::from_str(config_str);
+        assert!(config.is_err());
+
+        // Unknown alarm severities yield error
+        let config_str = r#"
+            pub_url = "tcp://*:5557"
+            sensors = []
+
+            [[alarms]]
+            name = "ph-range"
+            sensor = "ph-0123456789abcdef"
+            low = 6.5
+            severity = "fatal"
+            "#;
+
+        let config: Result<PublisherConfig> = PublisherConfig::from_str(config_str);
+        assert!(config.is_err());
+    }
+
+    // NodeConfig for valid use cases
+    #[test]
+    fn reads_and_parses_node_config_toml() {
+        let config_str = r#"
+            name = "tank-a"
+
+            [[buses]]
+            name = "i2c1"
+            path = "/dev/i2c-1"
+
+            [[sensors]]
+            name = "ph1"
+            kind = "ph"
+            bus = "i2c1"
+            address = 99
+            topic = "ph-0123456789abcdef"
+            rep_url = "tcp://*:5558"
+            schedule = { interval = 5000 }
+            options = { compensation = 25.0, sleep = true }
+
+            [[sensors]]
+            name = "ec1"
+            kind = "conductivity"
+            bus = "i2c1"
+            address = 100
+            options = { output = ["EC", "TDS"] }
+
+            [publisher]
+            url = "tcp://*:5557"
+            aggregate = ["1h"]
+
+            [storage]
+            path = "/var/lib/benita"
+
+            [[alarms]]
+            name = "ph-range"
+            sensor = "ph-0123456789abcdef"
+            low = 6.5
+            high = 8.5
+
+            [[exporters]]
+            kind = "file"
+            path = "/var/lib/benita/measurements.csv"
+
+            [[exporters]]
+            kind = "influx"
+            site = "tank-a"
+
+            [exporters.destination]
+            kind = "udp"
+            addr = "localhost:8089"
+            "#;
+
+        let config = NodeConfig::from_str(config_str).unwrap();
+        assert_eq!("tank-a", config.name);
+        assert_eq!(
+            Some(&BusConfig {
+                name: "i2c1".to_string(),
+                path: PathBuf::from("/dev/i2c-1"),
+            }),
+            config.bus("i2c1")
+        );
+        let ph = config.sensor("ph1").unwrap();
+        assert_eq!(SensorKind::Ph, ph.kind);
+        assert_eq!(Some("tcp://*:5558".to_string()), ph.rep_url);
+        let schedule = ScheduleConfig {
+            interval: Some(5000),
+            ..ScheduleConfig::default()
+        };
+        assert_eq!(Some(schedule), ph.schedule);
+        assert_eq!(Some(25.0), ph.options.compensation);
+        assert_eq!(Some(true), ph.options.sleep);
+        let ec = config.sensor("ec1").unwrap();
+        assert_eq!(None, ec.topic);
+        assert_eq!(
+            Some(vec!["EC".to_string(), "TDS".to_string()]),
+            ec.options.output
+        );
+        assert_eq!(vec![Window::Hour], config.publisher.unwrap().aggregate);
+        assert_eq!(1, config.alarms.len());
+        assert_eq!(
+            ExporterConfig::File {
+                path: PathBuf::from("/var/lib/benita/measurements.csv"),
+                format: None,
+                time_zone: None,
+                precision: None,
+            },
+            config.exporters[0]
+        );
+        match config.exporters[1] {
+            ExporterConfig::Influx {
+                ref site,
+                ref destination,
+                ..
+            } => {
+                assert_eq!(Some("tank-a".to_string()), *site);
+                assert_eq!(
+                    InfluxDestination::Udp {
+                        addr: "localhost:8089".to_string(),
+                    },
+                    *destination
+                );
+            }
+            _ => panic!("not an influx exporter"),
+        }
+
+        // Nodes may be described by their name alone.
+        let config = NodeConfig::from_str(r#"name = "spare""#).unwrap();
+        assert!(config.sensors.is_empty());
+        assert_eq!(None, config.publisher);
+    }
+
+    // NodeConfig for invalid use cases
+    #[test]
+    fn reads_and_parses_invalid_node_config_toml_yielding_err() {
+        // Nodes need a name
+        let config: Result<NodeConfig> = NodeConfig::from_str(r#""#);
+        assert!(config.is_err());
+
+        // Sensors need a bus
+        let config_str = r#"
+            name = "tank-a"
+
+            [[sensors]]
+            name = "ph1"
+            kind = "ph"
+            address = 99
+            "#;
+
+        let config: Result<NodeConfig> = NodeConfig::from_str(config_str);
+        assert!(config.is_err());
+
+        // Unknown exporters yield error
+        let config_str = r#"
+            name = "tank-a"
+
+            [[exporters]]
+            kind = "graphite"
+            "#;
+
+        let config: Result<NodeConfig> = NodeConfig::from_str(config_str);
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn schedules_of_node_sensors() {
+        let schedule = |config_str: &str| {
+            let config: ScheduleConfig = toml::from_str(config_str).unwrap();
+            config.schedule()
+        };
+        assert_eq!(
+            Schedule::Every(Duration::from_millis(10_000)),
+            schedule("interval = 10000").unwrap()
+        );
+        assert_eq!(
+            Schedule::Aligned(Duration::from_millis(60_000)),
+            schedule("interval = 60000\nalign = true").unwrap()
+        );
+        assert_eq!(
+            Schedule::Cron("0 */15 * * * *".parse().unwrap()),
+            schedule(r#"cron = "0 */15 * * * *""#).unwrap()
+        );
+        assert!(schedule("").is_err());
+        assert!(schedule(r#"cron = "*/15 * * *""#).is_err());
+        assert!(schedule("interval = 10000\ncron = \"* * * * *\"").is_err());
+        assert!(schedule("cron = \"* * * * *\"\nalign = true").is_err());
+    }
 }
@@ -54,6 +54,40 @@ pub enum ErrorKind {
     RunTime(String),
     #[fail(display = "unable to setup proxy")]
     ProxyCreate,
+    #[fail(display = "invalid message for the broker protocol")]
+    BrokerProtocol,
+    #[fail(display = "the requested service is unknown to the broker")]
+    UnknownService,
+    #[fail(display = "the requested service did not reply in time")]
+    ServiceTimeout,
+    #[fail(display = "invalid measurement record")]
+    InvalidRecord,
+    #[fail(display = "could not access the measurement log")]
+    LogStorage,
+    #[fail(display = "invalid alarm rule: {}", _0)]
+    InvalidAlarmRule(String),
+    #[fail(display = "the requested resource does not exist: {}", _0)]
+    UnknownResource(String),
+    #[fail(display = "invalid message for the MQTT protocol")]
+    MqttProtocol,
+    #[fail(display = "could not write to InfluxDB: {}", _0)]
+    InfluxWrite(String),
+    #[fail(display = "invalid export option: {}", _0)]
+    InvalidExportOption(String),
+    #[fail(display = "could not write the exported measurements")]
+    ExportWrite,
+    #[fail(display = "could not read configuration file: {}", _0)]
+    ConfigRead(String),
+    #[fail(display = "invalid configuration value: {}", _0)]
+    ConfigValue(String),
+    #[fail(display = "invalid configuration:\n{}", _0)]
+    ConfigInvalid(String),
+    #[fail(display = "invalid sensor state change: {}", _0)]
+    InvalidStateChange(String),
+    #[fail(display = "locked by {}", _0)]
+    SessionLocked(String),
+    #[fail(display = "calibration needs a session lock")]
+    SessionRequired,
 }
 
 impl Fail for Error {
@@ -0,0 +1,557 @@
+//! Export of measurements as CSV, or JSON lines.
+//!
+//! Every row holds the readings taken by a sensor at the same time, so that the outputs of
+//! a conductivity sensor are split into the `EC`, `TDS`, `S`, and `SG` columns, e.g.
+//!
+//! ```text
+//! timestamp,sensor,type,EC,TDS
+//! 2017-11-18T23:40:05Z,0123456789abcdef,conductivity,1.5,320
+//! ```
+//!
+//! or, as JSON lines,
+//!
+//! ```text
+//! {"timestamp":"2017-11-18T23:40:05Z","sensor":"0123456789abcdef","type":"conductivity","EC":1.5,"TDS":320}
+//! ```
+//!
+//! Measurements are exported from the measurement log, with `export_range`, or as they are
+//! published, with `Exporter::follow`.
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use errors::*;
+use measurement::{Measurement, SensorId, SensorType};
+use network::subscriber::{Subscriber, STOP_CHECK_INTERVAL};
+use store::LogReader;
+
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use serde_json;
+
+/// Format of the exported rows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Comma-separated values, with a header row.
+    Csv,
+    /// A JSON object per line.
+    JsonLines,
+}
+
+impl Default for Format {
+    fn default() -> Format {
+        Format::Csv
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "jsonl" | "json-lines" => Ok(Format::JsonLines),
+            _ => Err(ErrorKind::InvalidExportOption(format!("unknown format {:?}", s)).into()),
+        }
+    }
+}
+
+/// A column of the exported rows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Column {
+    /// When the readings were taken.
+    Timestamp,
+    /// The id of the sensor.
+    Sensor,
+    /// The kind of sensor, i.e. `conductivity`, `ph`, or `temperature`.
+    Type,
+    /// The readings of a sensor type, e.g. `TDS`.
+    Field(SensorType),
+}
+
+impl Column {
+    /// The timestamp, sensor, and type columns, followed by a column for every output of
+    /// the sensors. Conductivity outputs are given as the text form of the chip's
+    /// `OutputStringStatus`, e.g. `EC,TDS`.
+    pub fn defaults(output_params: &str) -> Result<Vec<Column>> {
+        let mut columns = vec![Column::Timestamp, Column::Sensor, Column::Type];
+        for param in output_params.split(',').filter(|p| !p.trim().is_empty()) {
+            let sensor_type = SensorType::from_output_param(param).context(
+                ErrorKind::InvalidExportOption(format!("unknown output parameter {:?}", param)),
+            )?;
+            columns.push(Column::Field(sensor_type));
+        }
+        columns.push(Column::Field(SensorType::Ph));
+        columns.push(Column::Field(SensorType::Temperature));
+        Ok(columns)
+    }
+
+    /// Parse a comma-separated list of columns, e.g. `timestamp,sensor,EC`.
+    pub fn parse_list(s: &str) -> Result<Vec<Column>> {
+        s.split(',').map(|c| c.trim().parse()).collect()
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Column::Timestamp => "timestamp",
+            Column::Sensor => "sensor",
+            Column::Type => "type",
+            Column::Field(SensorType::Conductivity) => "EC",
+            Column::Field(SensorType::TotalDissolvedSolids) => "TDS",
+            Column::Field(SensorType::Salinity) => "S",
+            Column::Field(SensorType::SpecificGravity) => "SG",
+            Column::Field(SensorType::Ph) => "pH",
+            Column::Field(sensor_type) => sensor_type.name(),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Column {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Column> {
+        match s {
+            "timestamp" => Ok(Column::Timestamp),
+            "sensor" => Ok(Column::Sensor),
+            "type" => Ok(Column::Type),
+            "pH" => Ok(Column::Field(SensorType::Ph)),
+            "temperature" => Ok(Column::Field(SensorType::Temperature)),
+            _ => match SensorType::from_output_param(s) {
+                Ok(sensor_type) => Ok(Column::Field(sensor_type)),
+                Err(_) => {
+                    Err(ErrorKind::InvalidExportOption(format!("unknown column {:?}", s)).into())
+                }
+            },
+        }
+    }
+}
+
+/// Time zone of the exported timestamps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeZone {
+    Utc,
+    /// The time zone of the machine doing the export.
+    Local,
+    /// A fixed offset from UTC, e.g. `-06:00`.
+    Fixed(FixedOffset),
+}
+
+impl TimeZone {
+    /// The RFC 3339 form of `timestamp`, in this time zone.
+    pub fn format(&self, timestamp: &DateTime<Utc>) -> String {
+        match *self {
+            TimeZone::Utc => timestamp.format("%Y-%m-%dT%H:%M:%S%.fZ").to_string(),
+            TimeZone::Local => timestamp
+                .with_timezone(&Local)
+                .format("%Y-%m-%dT%H:%M:%S%.f%:z")
+                .to_string(),
+            TimeZone::Fixed(offset) => timestamp
+                .with_timezone(&offset)
+                .format("%Y-%m-%dT%H:%M:%S%.f%:z")
+                .to_string(),
+        }
+    }
+}
+
+impl Default for TimeZone {
+    fn default() -> TimeZone {
+        TimeZone::Utc
+    }
+}
+
+impl FromStr for TimeZone {
+    type Err = Error;
+
+    /// Parse `utc`, `local`, or an offset such as `+05:30`, `-0600`, or `+01`.
+    fn from_str(s: &str) -> Result<TimeZone> {
+        let invalid = || ErrorKind::InvalidExportOption(format!("unknown time zone {:?}", s));
+        match s {
+            "utc" | "UTC" | "Z" => return Ok(TimeZone::Utc),
+            "local" => return Ok(TimeZone::Local),
+            _ => {}
+        }
+        let sign = match s.chars().next() {
+            Some('+') => 1,
+            Some('-') => -1,
+            _ => return Err(invalid().into()),
+        };
+        let digits: String = s[1..].chars().filter(|c| *c != ':').collect();
+        if !(digits.len() == 2 || digits.len() == 4) || !digits.chars().all(|c| c.is_digit(10)) {
+            return Err(invalid().into());
+        }
+        let hours: i32 = digits[..2].parse::<i32>().context(invalid())?;
+        let minutes: i32 = if digits.len() == 4 {
+            digits[2..].parse::<i32>().context(invalid())?
+        } else {
+            0
+        };
+        if hours > 23 || minutes > 59 {
+            return Err(invalid().into());
+        }
+        Ok(TimeZone::Fixed(FixedOffset::east(
+            sign * (hours * 3600 + minutes * 60),
+        )))
+    }
+}
+
+/// Settings for an export.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExportOptions {
+    pub format: Format,
+    pub columns: Vec<Column>,
+    pub time_zone: TimeZone,
+    /// Number of decimals of the values. By default, values are written in their shortest
+    /// decimal form.
+    pub precision: Option<usize>,
+}
+
+impl Default for ExportOptions {
+    fn default() -> ExportOptions {
+        ExportOptions {
+            format: Format::default(),
+            columns: Column::defaults("EC,TDS,S,SG").unwrap(),
+            time_zone: TimeZone::default(),
+            precision: None,
+        }
+    }
+}
+
+/// The readings taken by a sensor at the same time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Row {
+    pub timestamp: DateTime<Utc>,
+    pub sensor_id: SensorId,
+    pub kind: &'static str,
+    pub values: Vec<(SensorType, f32)>,
+}
+
+impl Row {
+    /// Group `measurements` into rows, in the order they were taken. Colours are skipped.
+    pub fn group(measurements: &[Measurement]) -> Vec<Row> {
+        let mut rows: Vec<Row> = Vec::new();
+        for m in measurements
+            .iter()
+            .filter(|m| m.sensor_type != SensorType::Rgb)
+        {
+            let kind = m.sensor_type.sensor_kind();
+            let index = rows.iter().position(|r| {
+                r.timestamp == m.timestamp && r.sensor_id == m.sensor_id && r.kind == kind
+            });
+            match index {
+                Some(index) => rows[index].values.push((m.sensor_type, m.value)),
+                None => rows.push(Row {
+                    timestamp: m.timestamp,
+                    sensor_id: m.sensor_id,
+                    kind,
+                    values: vec![(m.sensor_type, m.value)],
+                }),
+            }
+        }
+        rows
+    }
+
+    /// The value of `sensor_type` in this row, if any.
+    pub fn value(&self, sensor_type: SensorType) -> Option<f32> {
+        self.values
+            .iter()
+            .find(|&&(t, _)| t == sensor_type)
+            .map(|&(_, v)| v)
+    }
+}
+
+// Quote a CSV field, if needed.
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Writes measurements, as rows, to `W`.
+pub struct Exporter<W: Write> {
+    writer: W,
+    options: ExportOptions,
+    header_written: bool,
+}
+
+impl<W: Write> Exporter<W> {
+    /// Create a new exporter.
+    pub fn new(writer: W, options: ExportOptions) -> Exporter<W> {
+        Exporter {
+            writer,
+            options,
+            header_written: false,
+        }
+    }
+
+    /// The writer of this exporter.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn format_value(&self, value: f32) -> String {
+        match self.options.precision {
+            Some(precision) => format!("{:.*}", precision, value),
+            None => format!("{}", value),
+        }
+    }
+
+    fn format_row(&self, row: &Row) -> String {
+        let cells = self.options.columns.iter().map(|column| match *column {
+            Column::Timestamp => Some(self.options.time_zone.format(&row.timestamp)),
+            Column::Sensor => Some(format!("{}", row.sensor_id)),
+            Column::Type => Some(row.kind.to_string()),
+            // NaN, and infinities, have no JSON form, and are left out.
+            Column::Field(sensor_type) => row
+                .value(sensor_type)
+                .filter(|v| v.is_finite())
+                .map(|v| self.format_value(v)),
+        });
+        match self.options.format {
+            Format::Csv => cells
+                .map(|cell| csv_field(&cell.unwrap_or_default()))
+                .collect::<Vec<String>>()
+                .join(","),
+            Format::JsonLines => {
+                let members: Vec<String> = self
+                    .options
+                    .columns
+                    .iter()
+                    .zip(cells)
+                    .map(|(column, cell)| {
+                        let value = match (*column, cell) {
+                            (_, None) => "null".to_string(),
+                            // Values are written as they were formatted, to keep their
+                            // precision.
+                            (Column::Field(_), Some(cell)) => cell,
+                            (_, Some(cell)) => serde_json::to_string(&cell).unwrap(),
+                        };
+                        format!(
+                            "{}:{}",
+                            serde_json::to_string(&column.to_string()).unwrap(),
+                            value
+                        )
+                    })
+                    .collect();
+                format!("{{{}}}", members.join(","))
+            }
+        }
+    }
+
+    /// Write the rows for `measurements`, and a CSV header before the first one. Rows
+    /// without a value in any of the columns are skipped. Returns the number of rows
+    /// written.
+    pub fn write(&mut self, measurements: &[Measurement]) -> Result<usize> {
+        let has_field = |row: &Row| {
+            self.options.columns.iter().any(|column| match *column {
+                Column::Field(sensor_type) => row.value(sensor_type).is_some(),
+                _ => false,
+            })
+        };
+        let lines: Vec<String> = Row::group(measurements)
+            .iter()
+            .filter(|row| has_field(row))
+            .map(|row| self.format_row(row))
+            .collect();
+        if !self.header_written && self.options.format == Format::Csv {
+            let header: Vec<String> = self.options.columns.iter().map(|c| c.to_string()).collect();
+            let _write =
+                writeln!(self.writer, "{}", header.join(",")).context(ErrorKind::ExportWrite)?;
+        }
+        self.header_written = true;
+        for line in &lines {
+            let _write = writeln!(self.writer, "{}", line).context(ErrorKind::ExportWrite)?;
+        }
+        let _flush = self.writer.flush().context(ErrorKind::ExportWrite)?;
+        Ok(lines.len())
+    }
+
+    /// Write the measurements received by `subscriber`, for as long as the program runs.
+    /// Malformed publications are logged, and skipped.
+    pub fn follow(&mut self, subscriber: &mut Subscriber) -> Result<()> {
+        self.follow_until(subscriber, &AtomicBool::new(false))
+    }
+
+    /// Write the measurements received by `subscriber`, like `follow`, until `stop` is set.
+    pub fn follow_until(&mut self, subscriber: &mut Subscriber, stop: &AtomicBool) -> Result<()> {
+        let check = Duration::from_millis(STOP_CHECK_INTERVAL);
+        while !stop.load(Ordering::SeqCst) {
+            if !subscriber.poll(check)? {
+                continue;
+            }
+            match subscriber.recv_measurements() {
+                Ok(measurements) => {
+                    let _rows = self.write(&measurements)?;
+                }
+                Err(e) => warn!("skipping publication: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Export the measurements taken in `[from, to)`, stored in the log in `dir`, whose topics
+/// start with one of `topics`, or every measurement when there are none. Returns the number
+/// of rows written.
+pub fn export_range<P, W>(
+    dir: P,
+    from: &DateTime<Utc>,
+    to: &DateTime<Utc>,
+    topics: &[&str],
+    exporter: &mut Exporter<W>,
+) -> Result<usize>
+where
+    P: AsRef<Path>,
+    W: Write,
+{
+    let measurements: Vec<Measurement> = LogReader::new(dir)
+        .read_range(from, to)?
+        .into_iter()
+        .filter(|m| topics.is_empty() || topics.iter().any(|t| m.topic().starts_with(t)))
+        .collect();
+    exporter.write(&measurements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone as ChronoTimeZone};
+    use std::env;
+    use std::fs;
+    use store::{LogPolicy, MeasurementLog};
+
+    fn measurements() -> Vec<Measurement> {
+        let at = Utc.ymd(2017, 11, 18).and_hms(23, 40, 5);
+        let id = "0123456789abcdef".parse().unwrap();
+        vec![
+            Measurement::new(at, SensorType::Conductivity, id, 1.5),
+            Measurement::new(at, SensorType::TotalDissolvedSolids, id, 320.0),
+            Measurement::new(at, SensorType::Ph, "01".parse().unwrap(), 7.012),
+        ]
+    }
+
+    fn export(options: ExportOptions) -> String {
+        let mut exporter = Exporter::new(Vec::new(), options);
+        let _rows = exporter.write(&measurements()).unwrap();
+        String::from_utf8(exporter.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn parse_export_options() {
+        assert_eq!(Format::JsonLines, "jsonl".parse().unwrap());
+        assert!("xml".parse::<Format>().is_err());
+
+        assert_eq!(
+            vec![
+                Column::Timestamp,
+                Column::Field(SensorType::Salinity),
+                Column::Field(SensorType::Temperature),
+            ],
+            Column::parse_list("timestamp, S,temperature").unwrap()
+        );
+        assert!(Column::parse_list("timestamp,value").is_err());
+        assert_eq!(
+            vec![
+                "timestamp",
+                "sensor",
+                "type",
+                "EC",
+                "SG",
+                "pH",
+                "temperature"
+            ],
+            Column::defaults("EC,SG")
+                .unwrap()
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<String>>()
+        );
+        assert!(Column::defaults("EC,PH").is_err());
+
+        let at = Utc.ymd(2017, 11, 18).and_hms(23, 40, 5);
+        let tz: TimeZone = "-06:00".parse().unwrap();
+        assert_eq!("2017-11-18T17:40:05-06:00", tz.format(&at));
+        let tz: TimeZone = "+0530".parse().unwrap();
+        assert_eq!("2017-11-19T05:10:05+05:30", tz.format(&at));
+        assert_eq!(
+            "2017-11-18T23:40:05.250Z",
+            TimeZone::Utc.format(&(at + Duration::milliseconds(250)))
+        );
+        assert!("+24:00".parse::<TimeZone>().is_err());
+        assert!("cest".parse::<TimeZone>().is_err());
+    }
+
+    #[test]
+    fn export_csv_rows() {
+        assert_eq!(
+            "timestamp,sensor,type,EC,TDS,S,SG,pH,temperature\n\
+             2017-11-18T23:40:05Z,0123456789abcdef,conductivity,1.5,320,,,,\n\
+             2017-11-18T23:40:05Z,01,ph,,,,,7.012,\n",
+            export(ExportOptions::default())
+        );
+
+        // Rows without a value in the selected columns are skipped.
+        let options = ExportOptions {
+            columns: Column::parse_list("sensor,EC,TDS").unwrap(),
+            precision: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            "sensor,EC,TDS\n0123456789abcdef,1.50,320.00\n",
+            export(options)
+        );
+    }
+
+    #[test]
+    fn export_json_lines() {
+        let options = ExportOptions {
+            format: Format::JsonLines,
+            columns: Column::parse_list("timestamp,type,EC,S,pH").unwrap(),
+            time_zone: "+01:00".parse().unwrap(),
+            precision: Some(1),
+        };
+        assert_eq!(
+            "{\"timestamp\":\"2017-11-19T00:40:05+01:00\",\"type\":\"conductivity\",\"EC\":1.5,\"S\":null,\"pH\":null}\n\
+             {\"timestamp\":\"2017-11-19T00:40:05+01:00\",\"type\":\"ph\",\"EC\":null,\"S\":null,\"pH\":7.0}\n",
+            export(options)
+        );
+    }
+
+    #[test]
+    fn export_stored_measurements() {
+        let dir = env::temp_dir().join(format!("benita-export-{}", ::std::process::id()));
+        let _remove = fs::remove_dir_all(&dir);
+        let mut log = MeasurementLog::open(&dir, LogPolicy::default()).unwrap();
+        for m in measurements() {
+            log.append(&m).unwrap();
+        }
+        log.sync().unwrap();
+
+        let at = Utc.ymd(2017, 11, 18).and_hms(23, 40, 5);
+        let options = ExportOptions {
+            columns: Column::parse_list("sensor,pH").unwrap(),
+            ..Default::default()
+        };
+        let mut exporter = Exporter::new(Vec::new(), options);
+        let to = at + Duration::seconds(1);
+        assert_eq!(
+            1,
+            export_range(&dir, &at, &to, &["ph-"], &mut exporter).unwrap()
+        );
+        assert_eq!(
+            0,
+            export_range(&dir, &to, &(to + Duration::days(1)), &[], &mut exporter).unwrap()
+        );
+        assert_eq!(
+            "sensor,pH\n01,7.012\n",
+            String::from_utf8(exporter.into_inner()).unwrap()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,764 @@
+//! Export of measurements in the InfluxDB line protocol.
+//!
+//! Measurements taken by a sensor at the same time are written as a single point, with the
+//! sensor id, type, site, and firmware version as tags, and a field for each output, e.g.
+//!
+//! ```text
+//! benita,firmware=2.10,sensor=0123456789abcdef,site=tank-a,type=conductivity EC=1.5,TDS=320 1511048405000000000
+//! ```
+//!
+//! Fields are named `EC`, `TDS`, `S`, `SG`, `pH`, and `temperature`, and timestamps are in
+//! nanoseconds. Lines are written by an `InfluxSink`, in batches, to a rotating file, a UDP
+//! socket, or an HTTP write endpoint, with retries.
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use config::{InfluxConfig, InfluxDestination};
+use errors::*;
+use measurement::{Measurement, SensorType};
+use network::subscriber::{Subscriber, STOP_CHECK_INTERVAL};
+
+use chrono::{DateTime, Utc};
+
+/// Name of the InfluxDB measurement, unless configured otherwise.
+pub const DEFAULT_MEASUREMENT: &str = "benita";
+/// Number of lines written at once, unless configured otherwise.
+pub const DEFAULT_BATCH_SIZE: usize = 100;
+/// Longest time lines wait to be written, in seconds, unless configured otherwise.
+pub const DEFAULT_FLUSH_INTERVAL_SECS: u64 = 10;
+/// Number of times a failed write is retried, unless configured otherwise.
+pub const DEFAULT_RETRIES: u32 = 3;
+/// Most lines kept while the destination is unavailable. Older lines are dropped.
+pub const MAX_PENDING_LINES: usize = 10_000;
+/// Largest UDP datagram sent, in bytes, to avoid fragmentation.
+pub const UDP_PAYLOAD_SIZE: usize = 1400;
+/// Time allowed for an HTTP write, in seconds.
+pub const HTTP_TIMEOUT_SECS: u64 = 5;
+
+// Escape the characters that the line protocol gives a meaning to.
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn escape_key(s: &str) -> String {
+    escape(s, &[',', '=', ' '])
+}
+
+/// A point in the line protocol.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Point {
+    pub measurement: String,
+    pub tags: BTreeMap<String, String>,
+    pub fields: BTreeMap<String, f64>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Point {
+    /// Create a new point, without tags, or fields.
+    pub fn new(measurement: &str, timestamp: DateTime<Utc>) -> Point {
+        Point {
+            measurement: measurement.to_string(),
+            tags: BTreeMap::new(),
+            fields: BTreeMap::new(),
+            timestamp,
+        }
+    }
+}
+
+impl fmt::Display for Point {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", escape(&self.measurement, &[',', ' ']))?;
+        for (key, value) in &self.tags {
+            write!(f, ",{}={}", escape_key(key), escape_key(value))?;
+        }
+        let mut separator = ' ';
+        for (key, value) in &self.fields {
+            write!(f, "{}{}={}", separator, escape_key(key), value)?;
+            separator = ',';
+        }
+        write!(f, " {}", self.timestamp.timestamp_nanos())
+    }
+}
+
+/// The field for values of a sensor type, e.g. `TDS`.
+pub fn field_name(sensor_type: SensorType) -> Option<&'static str> {
+    match sensor_type {
+        SensorType::Conductivity => Some("EC"),
+        SensorType::TotalDissolvedSolids => Some("TDS"),
+        SensorType::Salinity => Some("S"),
+        SensorType::SpecificGravity => Some("SG"),
+        SensorType::Ph => Some("pH"),
+        SensorType::Temperature => Some("temperature"),
+        SensorType::Rgb => None,
+    }
+}
+
+/// Turns measurements into points, with the tags of their sensor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineEncoder {
+    measurement: String,
+    site: Option<String>,
+    firmware: BTreeMap<String, String>,
+}
+
+impl LineEncoder {
+    /// Create a new encoder, for points of `measurement`.
+    pub fn new(measurement: &str) -> LineEncoder {
+        LineEncoder {
+            measurement: measurement.to_string(),
+            site: None,
+            firmware: BTreeMap::new(),
+        }
+    }
+
+    /// Tag every point with `site`.
+    pub fn set_site(&mut self, site: &str) {
+        self.site = Some(site.to_string());
+    }
+
+    /// Tag the points of the sensor `sensor_id` with its firmware version.
+    pub fn set_firmware(&mut self, sensor_id: &str, firmware: &str) {
+        let _firmware = self
+            .firmware
+            .insert(sensor_id.to_string(), firmware.to_string());
+    }
+
+    /// The points for `measurements`. Measurements of the same sensor, at the same time,
+    /// share a point. Colours are skipped.
+    pub fn points(&self, measurements: &[Measurement]) -> Vec<Point> {
+        let mut points: Vec<Point> = Vec::new();
+        for m in measurements {
+            let field = match field_name(m.sensor_type) {
+                Some(field) => field,
+                None => continue,
+            };
+            let sensor = format!("{}", m.sensor_id);
+            let kind = m.sensor_type.sensor_kind();
+            let value = m.value_f64();
+            let index = points.iter().position(|p| {
+                p.timestamp == m.timestamp && p.tags["sensor"] == sensor && p.tags["type"] == kind
+            });
+            let index = match index {
+                Some(index) => index,
+                None => {
+                    let mut point = Point::new(&self.measurement, m.timestamp);
+                    let _tag = point.tags.insert("type".to_string(), kind.to_string());
+                    if let Some(ref site) = self.site {
+                        let _tag = point.tags.insert("site".to_string(), site.clone());
+                    }
+                    if let Some(firmware) = self.firmware.get(&sensor) {
+                        let _tag = point.tags.insert("firmware".to_string(), firmware.clone());
+                    }
+                    let _tag = point.tags.insert("sensor".to_string(), sensor);
+                    points.push(point);
+                    points.len() - 1
+                }
+            };
+            let _field = points[index].fields.insert(field.to_string(), value);
+        }
+        points
+    }
+}
+
+/// Somewhere to write lines of the line protocol.
+pub trait LineDestination {
+    /// Write a batch of lines, without their line endings.
+    fn write_lines(&mut self, lines: &[String]) -> Result<()>;
+}
+
+/// Appends lines to a file, which is rotated to `<path>.1`, `<path>.2`, and so on, when it
+/// grows past its size limit.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileDestination {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl FileDestination {
+    /// Create a new destination, keeping up to `max_files` rotated files.
+    pub fn new<P: AsRef<Path>>(path: P, max_bytes: u64, max_files: usize) -> FileDestination {
+        FileDestination {
+            path: path.as_ref().to_path_buf(),
+            max_bytes,
+            max_files,
+        }
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&self) -> Result<()> {
+        if self.max_files == 0 {
+            let _remove = fs::remove_file(&self.path).context(ErrorKind::LogStorage)?;
+            return Ok(());
+        }
+        let oldest = self.rotated(self.max_files);
+        if oldest.exists() {
+            let _remove = fs::remove_file(&oldest).context(ErrorKind::LogStorage)?;
+        }
+        for n in (1..self.max_files).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                let _rename =
+                    fs::rename(&from, self.rotated(n + 1)).context(ErrorKind::LogStorage)?;
+            }
+        }
+        let _rename = fs::rename(&self.path, self.rotated(1)).context(ErrorKind::LogStorage)?;
+        Ok(())
+    }
+}
+
+impl LineDestination for FileDestination {
+    fn write_lines(&mut self, lines: &[String]) -> Result<()> {
+        let mut text = lines.join("\n");
+        text.push('\n');
+        let size = fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        if size > 0 && size + text.len() as u64 > self.max_bytes {
+            let _rotate = self.rotate()?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context(ErrorKind::LogStorage)?;
+        let _write = file
+            .write_all(text.as_bytes())
+            .context(ErrorKind::LogStorage)?;
+        Ok(())
+    }
+}
+
+/// Sends lines to a UDP listener, packing as many as fit in each datagram.
+///
+/// When a datagram can not be sent, the batch resumes from it the next time it is written,
+/// so that the lines already sent are not sent twice.
+pub struct UdpDestination {
+    socket: UdpSocket,
+    addr: SocketAddr,
+    // The first line of a batch that was not sent whole, and the number of its lines that were.
+    resume: Option<(String, usize)>,
+}
+
+impl UdpDestination {
+    /// Create a new destination, sending to `addr`, e.g. `localhost:8089`.
+    pub fn new(addr: &str) -> Result<UdpDestination> {
+        let addr = addr
+            .to_socket_addrs()
+            .context(ErrorKind::SocketConnect)?
+            .next()
+            .ok_or(ErrorKind::SocketConnect)?;
+        let local = if addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local).context(ErrorKind::SocketBind)?;
+        Ok(UdpDestination {
+            socket,
+            addr,
+            resume: None,
+        })
+    }
+
+    // Send a datagram with the lines of `batch` after the first `sent`, or remember where to
+    // resume the batch from.
+    fn send(&mut self, payload: &str, batch: &[String], sent: usize) -> Result<()> {
+        match self.socket.send_to(payload.as_bytes(), &self.addr) {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                if sent > 0 {
+                    self.resume = Some((batch[0].clone(), sent));
+                }
+                Err(e).context(ErrorKind::SocketSend).map_err(Error::from)
+            }
+        }
+    }
+}
+
+impl LineDestination for UdpDestination {
+    fn write_lines(&mut self, lines: &[String]) -> Result<()> {
+        let mut sent = match self.resume.take() {
+            Some((ref first, sent)) if lines.first() == Some(first) && sent <= lines.len() => sent,
+            _ => 0,
+        };
+        let mut payload = String::new();
+        let mut packed = 0;
+        for line in &lines[sent..] {
+            if !payload.is_empty() && payload.len() + line.len() + 1 > UDP_PAYLOAD_SIZE {
+                let _send = self.send(&payload, lines, sent)?;
+                sent += packed;
+                packed = 0;
+                payload.clear();
+            }
+            payload.push_str(line);
+            payload.push('\n');
+            packed += 1;
+        }
+        if !payload.is_empty() {
+            let _send = self.send(&payload, lines, sent)?;
+        }
+        Ok(())
+    }
+}
+
+/// Posts lines to an HTTP write endpoint, e.g. `http://localhost:8086/write?db=benita`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpDestination {
+    host: String,
+    path: String,
+}
+
+impl HttpDestination {
+    /// Create a new destination for `url`. Only plain `http` is supported.
+    pub fn new(url: &str) -> Result<HttpDestination> {
+        if !url.starts_with("http://") {
+            return Err(ErrorKind::ConfigParse.into());
+        }
+        let rest = &url["http://".len()..];
+        let (host, path) = match rest.find('/') {
+            Some(start) => (&rest[..start], &rest[start..]),
+            None => (rest, "/write"),
+        };
+        if host.is_empty() {
+            return Err(ErrorKind::ConfigParse.into());
+        }
+        let host = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+        Ok(HttpDestination {
+            host,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl LineDestination for HttpDestination {
+    fn write_lines(&mut self, lines: &[String]) -> Result<()> {
+        let body = lines.join("\n");
+        let stream = TcpStream::connect(&self.host).context(ErrorKind::SocketConnect)?;
+        let timeout = Duration::from_secs(HTTP_TIMEOUT_SECS);
+        let _timeout = stream
+            .set_read_timeout(Some(timeout))
+            .context(ErrorKind::SocketReceive)?;
+        let _timeout = stream
+            .set_write_timeout(Some(timeout))
+            .context(ErrorKind::SocketSend)?;
+        let _write = write!(
+            &stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            body.len(),
+            body
+        ).context(ErrorKind::SocketSend)?;
+        let mut status = String::new();
+        let _read = BufReader::new(&stream)
+            .read_line(&mut status)
+            .context(ErrorKind::SocketReceive)?;
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(ErrorKind::InfluxWrite(status.trim().to_string()).into()),
+        }
+    }
+}
+
+/// Writes measurements to a `LineDestination`, in batches, retrying failed writes.
+///
+/// Lines are written once a batch is full, or the flush interval has passed. Lines that
+/// could not be written are kept, up to `MAX_PENDING_LINES`, and written again once the
+/// flush interval has passed, not on every push.
+pub struct InfluxSink {
+    encoder: LineEncoder,
+    destination: Box<LineDestination>,
+    pending: VecDeque<String>,
+    batch_size: usize,
+    flush_interval: Duration,
+    retries: u32,
+    last_flush: Instant,
+    // Whether the last flush failed.
+    failing: bool,
+}
+
+impl InfluxSink {
+    /// Create a new sink, with the default batch size, flush interval, and retries.
+    pub fn new<D>(encoder: LineEncoder, destination: D) -> InfluxSink
+    where
+        D: LineDestination + 'static,
+    {
+        InfluxSink {
+            encoder,
+            destination: Box::new(destination),
+            pending: VecDeque::new(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            flush_interval: Duration::from_secs(DEFAULT_FLUSH_INTERVAL_SECS),
+            retries: DEFAULT_RETRIES,
+            last_flush: Instant::now(),
+            failing: false,
+        }
+    }
+
+    /// Create a new sink from `InfluxConfig`.
+    pub fn from_config(config: &InfluxConfig) -> Result<InfluxSink> {
+        let mut encoder = LineEncoder::new(config.measurement.unwrap_or(DEFAULT_MEASUREMENT));
+        if let Some(site) = config.site {
+            encoder.set_site(site);
+        }
+        for (sensor_id, firmware) in &config.firmware {
+            encoder.set_firmware(sensor_id, firmware);
+        }
+        let mut sink = match config.destination {
+            InfluxDestination::File {
+                ref path,
+                max_bytes,
+                max_files,
+            } => InfluxSink::new(
+                encoder,
+                FileDestination::new(
+                    path,
+                    max_bytes.unwrap_or(16 * 1024 * 1024),
+                    max_files.unwrap_or(5),
+                ),
+            ),
+            InfluxDestination::Udp { ref addr } => {
+                InfluxSink::new(encoder, UdpDestination::new(addr)?)
+            }
+            InfluxDestination::Http { ref url } => {
+                InfluxSink::new(encoder, HttpDestination::new(url)?)
+            }
+        };
+        if let Some(batch_size) = config.batch_size {
+            sink.set_batch_size(batch_size);
+        }
+        if let Some(secs) = config.flush_interval {
+            sink.set_flush_interval(Duration::from_secs(secs));
+        }
+        if let Some(retries) = config.retries {
+            sink.set_retries(retries);
+        }
+        Ok(sink)
+    }
+
+    /// Set the number of lines written at once.
+    pub fn set_batch_size(&mut self, batch_size: usize) {
+        self.batch_size = ::std::cmp::max(batch_size, 1);
+    }
+
+    /// Set the longest time lines wait to be written.
+    pub fn set_flush_interval(&mut self, interval: Duration) {
+        self.flush_interval = interval;
+    }
+
+    /// Set the number of times a failed write is retried, right away. The lines of a write
+    /// that still fails wait for the next flush interval.
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+
+    /// The number of lines waiting to be written.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Queue the lines for `measurements`, and write them if a flush is due. Returns the
+    /// number of lines written.
+    ///
+    /// After a failed flush, full batches wait for the flush interval too.
+    pub fn push(&mut self, measurements: &[Measurement]) -> Result<usize> {
+        for point in self.encoder.points(measurements) {
+            self.pending.push_back(format!("{}", point));
+        }
+        while self.pending.len() > MAX_PENDING_LINES {
+            let _dropped = self.pending.pop_front();
+            warn!("dropping an unwritten line, too many are pending");
+        }
+        let full = !self.failing && self.pending.len() >= self.batch_size;
+        if full || self.last_flush.elapsed() >= self.flush_interval {
+            return self.flush();
+        }
+        Ok(0)
+    }
+
+    /// Write every pending line, in batches. Returns the number of lines written.
+    pub fn flush(&mut self) -> Result<usize> {
+        self.last_flush = Instant::now();
+        let mut written = 0;
+        while !self.pending.is_empty() {
+            let count = ::std::cmp::min(self.batch_size, self.pending.len());
+            let batch: Vec<String> = self.pending.iter().take(count).cloned().collect();
+            if let Err(e) = self.write_with_retries(&batch) {
+                self.failing = true;
+                return Err(e);
+            }
+            self.pending.drain(..count);
+            written += count;
+        }
+        self.failing = false;
+        Ok(written)
+    }
+
+    fn write_with_retries(&mut self, batch: &[String]) -> Result<()> {
+        let mut attempt = 0;
+        loop {
+            match self.destination.write_lines(batch) {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    if attempt >= self.retries {
+                        return Err(e);
+                    }
+                    warn!("could not write to InfluxDB, retrying: {}", e);
+                }
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Write the measurements received by `subscriber`, for as long as the program runs.
+    /// Failed writes are logged, and their lines kept for the next flush.
+    pub fn run(&mut self, subscriber: Subscriber) -> Result<()> {
+        self.run_until(subscriber, &AtomicBool::new(false))
+    }
+
+    /// Write the measurements received by `subscriber`, like `run`, until `stop` is set. The
+    /// pending lines are written once more before returning.
+    pub fn run_until(&mut self, mut subscriber: Subscriber, stop: &AtomicBool) -> Result<()> {
+        let check = ::std::cmp::min(
+            self.flush_interval,
+            Duration::from_millis(STOP_CHECK_INTERVAL),
+        );
+        while !stop.load(Ordering::SeqCst) {
+            let result = if subscriber.poll(check)? {
+                match subscriber.recv_measurements() {
+                    Ok(measurements) => self.push(&measurements),
+                    Err(e) => {
+                        warn!("skipping publication: {}", e);
+                        continue;
+                    }
+                }
+            } else if self.last_flush.elapsed() >= self.flush_interval {
+                self.flush()
+            } else {
+                Ok(0)
+            };
+            if let Err(e) = result {
+                error!("could not write to InfluxDB: {}", e);
+            }
+        }
+        self.flush().map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::env;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    fn measurements() -> Vec<Measurement> {
+        let at = Utc.ymd(2017, 11, 18).and_hms(23, 40, 5);
+        let id = "0123456789abcdef".parse().unwrap();
+        vec![
+            Measurement::new(at, SensorType::Conductivity, id, 1.5),
+            Measurement::new(at, SensorType::TotalDissolvedSolids, id, 320.0),
+            Measurement::new(at, SensorType::Ph, "01".parse().unwrap(), 7.012),
+        ]
+    }
+
+    // Keeps the batches it is asked to write, failing the first `failures` of them.
+    struct FakeDestination {
+        batches: Arc<Mutex<Vec<Vec<String>>>>,
+        failures: u32,
+    }
+
+    impl LineDestination for FakeDestination {
+        fn write_lines(&mut self, lines: &[String]) -> Result<()> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err(ErrorKind::SocketSend.into());
+            }
+            self.batches.lock().unwrap().push(lines.to_vec());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn encode_points_in_the_line_protocol() {
+        let mut encoder = LineEncoder::new("benita");
+        encoder.set_site("tank a");
+        encoder.set_firmware("0123456789abcdef", "2.10");
+        let points = encoder.points(&measurements());
+        assert_eq!(2, points.len());
+        assert_eq!(
+            "benita,firmware=2.10,sensor=0123456789abcdef,site=tank\\ a,type=conductivity \
+             EC=1.5,TDS=320 1511048405000000000",
+            format!("{}", points[0])
+        );
+        assert_eq!(
+            "benita,sensor=01,site=tank\\ a,type=ph pH=7.012 1511048405000000000",
+            format!("{}", points[1])
+        );
+
+        let mut point = Point::new("my measurement,1", points[0].timestamp);
+        let _tag = point.tags.insert("a=b".to_string(), "c,d".to_string());
+        let _field = point.fields.insert("v".to_string(), -0.5);
+        assert_eq!(
+            "my\\ measurement\\,1,a\\=b=c\\,d v=-0.5 1511048405000000000",
+            format!("{}", point)
+        );
+    }
+
+    #[test]
+    fn write_in_batches_with_retries() {
+        let batches = Arc::new(Mutex::new(Vec::new()));
+        let destination = FakeDestination {
+            batches: batches.clone(),
+            failures: 1,
+        };
+        let mut sink = InfluxSink::new(LineEncoder::new("benita"), destination);
+        sink.set_batch_size(3);
+        sink.set_flush_interval(Duration::from_secs(3600));
+
+        // Two points wait for a full batch.
+        assert_eq!(0, sink.push(&measurements()).unwrap());
+        assert_eq!(2, sink.pending());
+        // The first write fails, and is retried.
+        assert_eq!(4, sink.push(&measurements()).unwrap());
+        assert_eq!(0, sink.pending());
+        let sizes: Vec<usize> = batches.lock().unwrap().iter().map(|b| b.len()).collect();
+        assert_eq!(vec![3, 1], sizes);
+
+        // Lines that could not be written are kept, and full batches wait for the flush
+        // interval.
+        let destination = FakeDestination {
+            batches: batches.clone(),
+            failures: 2,
+        };
+        let mut sink = InfluxSink::new(LineEncoder::new("benita"), destination);
+        sink.set_batch_size(3);
+        sink.set_flush_interval(Duration::from_secs(3600));
+        sink.set_retries(1);
+        assert_eq!(0, sink.push(&measurements()).unwrap());
+        assert!(sink.flush().is_err());
+        assert_eq!(2, sink.pending());
+        assert_eq!(0, sink.push(&measurements()).unwrap());
+        assert_eq!(4, sink.pending());
+        sink.set_flush_interval(Duration::from_secs(0));
+        assert_eq!(6, sink.push(&measurements()).unwrap());
+        assert_eq!(0, sink.pending());
+    }
+
+    #[test]
+    fn write_to_rotating_files() {
+        let dir = env::temp_dir().join(format!("benita-influx-{}", ::std::process::id()));
+        let _remove = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("points.lp");
+        let mut destination = FileDestination::new(&path, 100, 2);
+        let line = "x".repeat(60);
+        for _ in 0..4 {
+            destination.write_lines(&[line.clone()]).unwrap();
+        }
+        assert_eq!(61, fs::metadata(&path).unwrap().len());
+        assert!(destination.rotated(1).exists());
+        assert!(destination.rotated(2).exists());
+        assert!(!destination.rotated(3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn write_to_a_udp_listener() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut destination = UdpDestination::new(&addr.to_string()).unwrap();
+        let lines = vec!["a v=1 1".to_string(), "b v=2 2".to_string()];
+        destination.write_lines(&lines).unwrap();
+        let mut buf = [0; 1500];
+        let len = listener.recv(&mut buf).unwrap();
+        assert_eq!(b"a v=1 1\nb v=2 2\n", &buf[..len]);
+
+        // Long batches are split at line boundaries.
+        let lines = vec!["x".repeat(1000), "y".repeat(1000)];
+        destination.write_lines(&lines).unwrap();
+        assert_eq!(1001, listener.recv(&mut buf).unwrap());
+        assert_eq!(1001, listener.recv(&mut buf).unwrap());
+
+        // A batch resumes from the datagram that could not be sent, e.g. one too large.
+        let mut lines = vec!["x".repeat(1000), "y".repeat(70_000), "z".repeat(1000)];
+        assert!(destination.write_lines(&lines).is_err());
+        assert_eq!(1001, listener.recv(&mut buf).unwrap());
+        lines[1] = "y".repeat(1000);
+        destination.write_lines(&lines).unwrap();
+        assert_eq!(1001, listener.recv(&mut buf).unwrap());
+        assert_eq!(b'y', buf[0]);
+        assert_eq!(1001, listener.recv(&mut buf).unwrap());
+        assert_eq!(b'z', buf[0]);
+    }
+
+    #[test]
+    fn write_to_an_http_endpoint() {
+        assert!(HttpDestination::new("https://localhost/write").is_err());
+        assert_eq!(
+            HttpDestination {
+                host: "localhost:80".to_string(),
+                path: "/write".to_string(),
+            },
+            HttpDestination::new("http://localhost").unwrap()
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // An endpoint that fails the first write, and accepts the second one.
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in &["500 Internal Server Error", "204 No Content"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with("v=1 1") {
+                    let len = stream.read(&mut buf).unwrap();
+                    request.push_str(&String::from_utf8_lossy(&buf[..len]));
+                }
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+
+        let url = format!("http://{}/write?db=benita", addr);
+        let mut destination = HttpDestination::new(&url).unwrap();
+        let lines = vec!["a v=1 1".to_string()];
+        match destination.write_lines(&lines) {
+            Err(e) => assert_eq!(
+                ErrorKind::InfluxWrite("HTTP/1.1 500 Internal Server Error".to_string()),
+                e.kind()
+            ),
+            Ok(_) => panic!("the write should fail"),
+        }
+        destination.write_lines(&lines).unwrap();
+        let requests = server.join().unwrap();
+        assert!(requests[1].starts_with("POST /write?db=benita HTTP/1.1\r\n"));
+        assert!(requests[1].contains("Content-Length: 7\r\n"));
+    }
+}
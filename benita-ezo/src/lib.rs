@@ -1,4 +1,6 @@
 //! Submodule that provides functionality for EZO water sensor chips.
+extern crate base64;
+extern crate chrono;
 extern crate ezo_common;
 extern crate ezo_ec;
 extern crate ezo_ph;
@@ -6,11 +8,17 @@ extern crate ezo_rtd;
 #[macro_use]
 extern crate failure;
 extern crate i2cdev;
+extern crate libc;
 #[macro_use]
 extern crate log;
 extern crate neuras;
+extern crate rand;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+extern crate sha1;
 extern crate toml;
 extern crate zmq;
 
@@ -19,9 +27,20 @@ pub mod devices;
 #[macro_use]
 pub mod network;
 
+pub mod aggregate;
+pub mod alarm;
 pub mod config;
 pub mod errors;
+pub mod export;
+pub mod influx;
+pub mod measurement;
+pub mod metrics;
+pub mod node;
+pub mod schedule;
+pub mod state;
+pub mod store;
 pub mod utilities;
+pub mod validate;
 
 pub mod common_ezo;
 
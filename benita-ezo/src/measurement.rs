@@ -0,0 +1,596 @@
+//! Measurement records shared by publishers, loggers, and collectors.
+//!
+//! A `Measurement` is a single value taken from a sensor at a given time. It has a compact,
+//! fixed-size, binary encoding of 28 bytes, for logging:
+//!
+//! | bytes   | field       | encoding                                   |
+//! |---------|-------------|--------------------------------------------|
+//! | 0..4    | timestamp   | `u32`, seconds since the UNIX epoch        |
+//! | 4..8    | sensor type | `u32`, see `SensorType::code`              |
+//! | 8..24   | sensor id   | 16-byte UUID                               |
+//! | 24..28  | value       | `f32`, in the canonical unit for the type  |
+//!
+//! All integers are big-endian (network byte order).
+//!
+//! Measurements also have a text form, compatible with the lines sent by the
+//! `SensorPublisher`:
+//!
+//! ```text
+//! <type>-<id> <timestamp> <value> <unit>
+//! ```
+//!
+//! e.g. `temperature-0123456789abcdef 2017-11-18T23:40:05Z 19.521 celsius`.
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use errors::*;
+use network::subscriber::Publication;
+
+use chrono::{DateTime, TimeZone, Utc};
+
+/// Size, in bytes, of an encoded `Measurement`.
+pub const RECORD_SIZE: usize = 28;
+
+/// The type of quantity measured by a sensor.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum SensorType {
+    /// Temperature, in degrees Celsius.
+    Temperature,
+    /// pH.
+    Ph,
+    /// Electrical conductivity, in μS/cm.
+    Conductivity,
+    /// Colour, packed as `0x00RRGGBB` in the bits of the value.
+    Rgb,
+    /// Total dissolved solids, in ppm.
+    TotalDissolvedSolids,
+    /// Salinity, in PSU (ppt).
+    Salinity,
+    /// Specific gravity.
+    SpecificGravity,
+}
+
+impl SensorType {
+    /// Every known sensor type.
+    pub fn all() -> &'static [SensorType] {
+        static ALL: [SensorType; 7] = [
+            SensorType::Temperature,
+            SensorType::Ph,
+            SensorType::Conductivity,
+            SensorType::Rgb,
+            SensorType::TotalDissolvedSolids,
+            SensorType::Salinity,
+            SensorType::SpecificGravity,
+        ];
+        &ALL
+    }
+
+    /// The numeric code used in the binary encoding.
+    pub fn code(&self) -> u32 {
+        match *self {
+            SensorType::Temperature => 1,
+            SensorType::Ph => 2,
+            SensorType::Conductivity => 3,
+            SensorType::Rgb => 4,
+            SensorType::TotalDissolvedSolids => 5,
+            SensorType::Salinity => 6,
+            SensorType::SpecificGravity => 7,
+        }
+    }
+
+    /// The sensor type for a numeric code.
+    pub fn from_code(code: u32) -> Result<SensorType> {
+        match SensorType::all().iter().find(|t| t.code() == code) {
+            Some(t) => Ok(*t),
+            None => Err(ErrorKind::InvalidRecord.into()),
+        }
+    }
+
+    /// The name used in text forms, e.g. `temperature`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SensorType::Temperature => "temperature",
+            SensorType::Ph => "ph",
+            SensorType::Conductivity => "conductivity",
+            SensorType::Rgb => "rgb",
+            SensorType::TotalDissolvedSolids => "tds",
+            SensorType::Salinity => "salinity",
+            SensorType::SpecificGravity => "sg",
+        }
+    }
+
+    /// The canonical unit for values of this type.
+    pub fn unit(&self) -> &'static str {
+        match *self {
+            SensorType::Temperature => "celsius",
+            SensorType::Ph => "pH",
+            SensorType::Conductivity => "uS/cm",
+            SensorType::Rgb => "rgb",
+            SensorType::TotalDissolvedSolids => "ppm",
+            SensorType::Salinity => "ppt",
+            SensorType::SpecificGravity => "sg",
+        }
+    }
+
+    /// The kind of sensor that measures this type, e.g. `conductivity` for `tds`.
+    pub fn sensor_kind(&self) -> &'static str {
+        match *self {
+            SensorType::Conductivity
+            | SensorType::TotalDissolvedSolids
+            | SensorType::Salinity
+            | SensorType::SpecificGravity => "conductivity",
+            _ => self.name(),
+        }
+    }
+
+    /// The sensor type for a conductivity output parameter, as in `EC,TDS,S,SG`.
+    pub fn from_output_param(param: &str) -> Result<SensorType> {
+        match param.trim() {
+            "EC" => Ok(SensorType::Conductivity),
+            "TDS" => Ok(SensorType::TotalDissolvedSolids),
+            "S" => Ok(SensorType::Salinity),
+            "SG" => Ok(SensorType::SpecificGravity),
+            _ => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
+}
+
+impl fmt::Display for SensorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for SensorType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SensorType> {
+        match s {
+            "ec" => return Ok(SensorType::Conductivity),
+            "pH" => return Ok(SensorType::Ph),
+            _ => {}
+        }
+        match SensorType::all().iter().find(|t| t.name() == s) {
+            Some(t) => Ok(*t),
+            None => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
+}
+
+/// A 16-byte sensor identifier (UUID).
+///
+/// Its text form is made of an even number of hexadecimal digits, up to 32, hyphens are
+/// ignored. Shorter identifiers, such as `0123456789abcdef`, are padded with zeros on the
+/// left, and are written back without their leading zero bytes.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SensorId(pub [u8; 16]);
+
+impl fmt::Display for SensorId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let first = self.0.iter().position(|b| *b != 0).unwrap_or(15);
+        for b in &self.0[first..] {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SensorId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SensorId> {
+        let digits: Vec<u8> = s.bytes().filter(|b| *b != b'-').collect();
+        // An odd number of digits would be written back with one more, e.g. `abc` as `0abc`
+        if digits.is_empty() || digits.len() > 32 || digits.len() % 2 != 0 {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        let mut nibbles = Vec::with_capacity(32);
+        for _ in digits.len()..32 {
+            nibbles.push(0);
+        }
+        for d in digits {
+            let nibble = match (d as char).to_digit(16) {
+                Some(n) => n as u8,
+                None => return Err(ErrorKind::ResponseParse.into()),
+            };
+            nibbles.push(nibble);
+        }
+        let mut id = [0u8; 16];
+        for (i, pair) in nibbles.chunks(2).enumerate() {
+            id[i] = (pair[0] << 4) | pair[1];
+        }
+        Ok(SensorId(id))
+    }
+}
+
+fn write_u32(buf: &mut [u8], n: u32) {
+    buf[0] = (n >> 24) as u8;
+    buf[1] = (n >> 16) as u8;
+    buf[2] = (n >> 8) as u8;
+    buf[3] = n as u8;
+}
+
+fn read_u32(buf: &[u8]) -> u32 {
+    (u32::from(buf[0]) << 24)
+        | (u32::from(buf[1]) << 16)
+        | (u32::from(buf[2]) << 8)
+        | u32::from(buf[3])
+}
+
+/// Parse a measurement topic, `<type>-<id>`.
+pub fn parse_topic(topic: &str) -> Result<(SensorType, SensorId)> {
+    match topic.find('-') {
+        Some(idx) => Ok((topic[..idx].parse()?, topic[idx + 1..].parse()?)),
+        None => Err(ErrorKind::ResponseParse.into()),
+    }
+}
+
+/// A single value taken from a sensor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Measurement {
+    /// When the value was taken. The binary encoding keeps whole seconds.
+    pub timestamp: DateTime<Utc>,
+    /// The type of quantity measured.
+    pub sensor_type: SensorType,
+    /// The sensor that took the value.
+    pub sensor_id: SensorId,
+    /// The value, in the canonical unit for the sensor type.
+    pub value: f32,
+}
+
+impl Measurement {
+    /// Create a new measurement.
+    pub fn new(
+        timestamp: DateTime<Utc>,
+        sensor_type: SensorType,
+        sensor_id: SensorId,
+        value: f32,
+    ) -> Measurement {
+        Measurement {
+            timestamp,
+            sensor_type,
+            sensor_id,
+            value,
+        }
+    }
+
+    /// Create a new colour measurement.
+    pub fn rgb(timestamp: DateTime<Utc>, sensor_id: SensorId, rgb: (u8, u8, u8)) -> Measurement {
+        let bits = (u32::from(rgb.0) << 16) | (u32::from(rgb.1) << 8) | u32::from(rgb.2);
+        Measurement::new(timestamp, SensorType::Rgb, sensor_id, f32::from_bits(bits))
+    }
+
+    /// The colour of an `Rgb` measurement.
+    pub fn to_rgb(&self) -> Option<(u8, u8, u8)> {
+        match self.sensor_type {
+            SensorType::Rgb => {
+                let bits = self.value.to_bits();
+                Some(((bits >> 16) as u8, (bits >> 8) as u8, bits as u8))
+            }
+            _ => None,
+        }
+    }
+
+    /// The topic for the measurement, `<type>-<id>`.
+    pub fn topic(&self) -> String {
+        format!("{}-{}", self.sensor_type, self.sensor_id)
+    }
+
+    /// The value, as the `f64` with its shortest decimal form, e.g. 7.012, where widening
+    /// the `f32` would give 7.01200008392334.
+    pub fn value_f64(&self) -> f64 {
+        format!("{}", self.value)
+            .parse::<f64>()
+            .unwrap_or_else(|_| f64::from(self.value))
+    }
+
+    /// Encode the measurement in its binary form.
+    ///
+    /// Timestamps that do not fit in 32 bits (before 1970, or after 2106) yield an error.
+    pub fn to_bytes(&self) -> Result<[u8; RECORD_SIZE]> {
+        let secs = self.timestamp.timestamp();
+        if secs < 0 || secs > i64::from(u32::max_value()) {
+            return Err(ErrorKind::InvalidRecord.into());
+        }
+        let mut buf = [0u8; RECORD_SIZE];
+        write_u32(&mut buf[0..4], secs as u32);
+        write_u32(&mut buf[4..8], self.sensor_type.code());
+        buf[8..24].copy_from_slice(&self.sensor_id.0);
+        write_u32(&mut buf[24..28], self.value.to_bits());
+        Ok(buf)
+    }
+
+    /// Decode a measurement from its binary form.
+    pub fn from_bytes(buf: &[u8]) -> Result<Measurement> {
+        if buf.len() != RECORD_SIZE {
+            return Err(ErrorKind::InvalidRecord.into());
+        }
+        let timestamp = Utc.timestamp(i64::from(read_u32(&buf[0..4])), 0);
+        let sensor_type = SensorType::from_code(read_u32(&buf[4..8]))?;
+        let mut id = [0u8; 16];
+        id.copy_from_slice(&buf[8..24]);
+        let value = f32::from_bits(read_u32(&buf[24..28]));
+        Ok(Measurement::new(
+            timestamp,
+            sensor_type,
+            SensorId(id),
+            value,
+        ))
+    }
+
+    /// Write the binary form of the measurement.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let buf = self.to_bytes()?;
+        let _write = writer.write_all(&buf).context(ErrorKind::RunTime(
+            "could not write measurement".to_string(),
+        ))?;
+        Ok(())
+    }
+
+    /// Read a measurement in binary form. Returns `None` at the end of the input.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Option<Measurement>> {
+        let mut buf = [0u8; RECORD_SIZE];
+        let mut filled = 0;
+        while filled < RECORD_SIZE {
+            let n = reader
+                .read(&mut buf[filled..])
+                .context(ErrorKind::RunTime("could not read measurement".to_string()))?;
+            if n == 0 {
+                break;
+            }
+            filled += n;
+        }
+        match filled {
+            0 => Ok(None),
+            RECORD_SIZE => Ok(Some(Measurement::from_bytes(&buf)?)),
+            // Truncated record.
+            _ => Err(ErrorKind::InvalidRecord.into()),
+        }
+    }
+
+    /// Parse the text form of a measurement.
+    pub fn parse(s: &str) -> Result<Measurement> {
+        let publication = Publication::parse(s)?;
+        let mut measurements = Measurement::from_publication(&publication)?;
+        match measurements.len() {
+            1 => Ok(measurements.remove(0)),
+            _ => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
+
+    /// Convert a publication into measurements.
+    ///
+    /// The publication topic must be `<type>-<id>`. Conductivity readings yield one
+    /// measurement for each output parameter in the unit, e.g. `EC,TDS`, and temperatures are
+    /// converted to degrees Celsius.
+    pub fn from_publication(publication: &Publication) -> Result<Vec<Measurement>> {
+        let (sensor_type, sensor_id) = parse_topic(&publication.topic)?;
+        let timestamp = publication.timestamp;
+        let unit = publication.unit.as_str();
+
+        let measurement =
+            |t: SensorType, v: f64| Measurement::new(timestamp, t, sensor_id, v as f32);
+
+        match sensor_type {
+            SensorType::Temperature => {
+                let value = publication.value().ok_or(ErrorKind::ResponseParse)?;
+                let celsius = match unit.to_lowercase().as_str() {
+                    "celsius" | "c" => value,
+                    "fahrenheit" | "f" => (value - 32.0) * 5.0 / 9.0,
+                    "kelvin" | "k" => value - 273.15,
+                    _ => return Err(ErrorKind::ResponseParse.into()),
+                };
+                Ok(vec![measurement(SensorType::Temperature, celsius)])
+            }
+            SensorType::Conductivity
+            | SensorType::TotalDissolvedSolids
+            | SensorType::Salinity
+            | SensorType::SpecificGravity
+                if unit.contains(',') || unit.to_uppercase() == unit =>
+            {
+                let params: Vec<&str> = unit.split(',').collect();
+                if params.len() != publication.values.len() {
+                    return Err(ErrorKind::ResponseParse.into());
+                }
+                params
+                    .iter()
+                    .zip(publication.values.iter())
+                    .map(|(p, v)| Ok(measurement(SensorType::from_output_param(p)?, *v)))
+                    .collect()
+            }
+            _ => {
+                if publication.values.len() != 1 || unit != sensor_type.unit() {
+                    return Err(ErrorKind::ResponseParse.into());
+                }
+                Ok(vec![measurement(sensor_type, publication.values[0])])
+            }
+        }
+    }
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {:?} {} {}",
+            self.topic(),
+            self.timestamp,
+            self.value,
+            self.sensor_type.unit()
+        )
+    }
+}
+
+impl FromStr for Measurement {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Measurement> {
+        Measurement::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sensor_id() -> SensorId {
+        "0123456789abcdef0123456789abcdef".parse().unwrap()
+    }
+
+    fn measurements() -> Vec<Measurement> {
+        let timestamp = Utc.ymd(2017, 11, 18).and_hms(23, 40, 5);
+        vec![
+            Measurement::new(timestamp, SensorType::Temperature, sensor_id(), 19.521),
+            Measurement::new(timestamp, SensorType::Ph, sensor_id(), 7.012),
+            Measurement::new(timestamp, SensorType::Conductivity, sensor_id(), 1413.0),
+            Measurement::new(
+                timestamp,
+                SensorType::TotalDissolvedSolids,
+                sensor_id(),
+                706.5,
+            ),
+            Measurement::new(timestamp, SensorType::Salinity, sensor_id(), 0.7),
+            Measurement::new(timestamp, SensorType::SpecificGravity, sensor_id(), 1.0),
+            Measurement::rgb(timestamp, sensor_id(), (255, 128, 0)),
+        ]
+    }
+
+    #[test]
+    fn encode_and_decode_binary_measurements() {
+        for m in measurements() {
+            let buf = m.to_bytes().unwrap();
+            assert_eq!(RECORD_SIZE, buf.len());
+            assert_eq!(m, Measurement::from_bytes(&buf).unwrap());
+        }
+    }
+
+    #[test]
+    fn widen_values_in_their_decimal_form() {
+        let m = Measurement::new(Utc.timestamp(0, 0), SensorType::Ph, sensor_id(), 7.012);
+        assert_eq!(7.012, m.value_f64());
+        assert!(f64::from(m.value) != 7.012);
+    }
+
+    #[test]
+    fn encode_binary_measurement_layout() {
+        let m = Measurement::new(
+            Utc.timestamp(0x5a10_c4e5, 0),
+            SensorType::Ph,
+            SensorId([0xaa; 16]),
+            1.0,
+        );
+        let buf = m.to_bytes().unwrap();
+        assert_eq!([0x5a, 0x10, 0xc4, 0xe5], buf[0..4]);
+        assert_eq!([0, 0, 0, 2], buf[4..8]);
+        assert_eq!([0xaa; 16], buf[8..24]);
+        assert_eq!([0x3f, 0x80, 0, 0], buf[24..28]);
+    }
+
+    #[test]
+    fn encode_binary_measurement_out_of_range_yields_err() {
+        let m = Measurement::new(
+            Utc.ymd(1969, 12, 31).and_hms(23, 59, 59),
+            SensorType::Ph,
+            sensor_id(),
+            7.0,
+        );
+        assert!(m.to_bytes().is_err());
+    }
+
+    #[test]
+    fn decode_invalid_binary_measurement_yields_err() {
+        let mut buf = measurements()[0].to_bytes().unwrap();
+        assert!(Measurement::from_bytes(&buf[..27]).is_err());
+        buf[7] = 99;
+        assert!(Measurement::from_bytes(&buf).is_err());
+    }
+
+    #[test]
+    fn write_and_read_binary_measurements() {
+        let mut log = Vec::new();
+        for m in measurements() {
+            m.write_to(&mut log).unwrap();
+        }
+        assert_eq!(7 * RECORD_SIZE, log.len());
+
+        let mut reader = &log[..];
+        let mut read = Vec::new();
+        while let Some(m) = Measurement::read_from(&mut reader).unwrap() {
+            read.push(m);
+        }
+        assert_eq!(measurements(), read);
+
+        let mut truncated = &log[..RECORD_SIZE + 3];
+        assert!(Measurement::read_from(&mut truncated).unwrap().is_some());
+        assert!(Measurement::read_from(&mut truncated).is_err());
+    }
+
+    #[test]
+    fn format_and_parse_text_measurements() {
+        for m in measurements() {
+            let text = m.to_string();
+            assert_eq!(m, text.parse::<Measurement>().unwrap());
+        }
+        let m = measurements()[0];
+        assert_eq!(
+            "temperature-0123456789abcdef0123456789abcdef 2017-11-18T23:40:05Z 19.521 celsius",
+            m.to_string()
+        );
+    }
+
+    #[test]
+    fn parse_current_publisher_lines() {
+        let m: Measurement = "temperature-0123456789abcdef 2017-11-18T23:40:05.5Z 66.2 fahrenheit"
+            .parse()
+            .unwrap();
+        assert_eq!(SensorType::Temperature, m.sensor_type);
+        assert_eq!("0123456789abcdef", m.sensor_id.to_string());
+        assert!((m.value - 19.0).abs() < 0.001);
+
+        let publication =
+            Publication::parse("conductivity-01 2017-11-18T23:40:05Z 1413,706,0.7,1.0 EC,TDS,S,SG")
+                .unwrap();
+        let ms = Measurement::from_publication(&publication).unwrap();
+        let types: Vec<SensorType> = ms.iter().map(|m| m.sensor_type).collect();
+        assert_eq!(
+            vec![
+                SensorType::Conductivity,
+                SensorType::TotalDissolvedSolids,
+                SensorType::Salinity,
+                SensorType::SpecificGravity,
+            ],
+            types
+        );
+        assert_eq!(706.0, ms[1].value);
+    }
+
+    #[test]
+    fn parse_invalid_text_measurements_yields_err() {
+        let invalid = [
+            "temperature 2017-11-18T23:40:05Z 19.5 celsius",
+            "humidity-01 2017-11-18T23:40:05Z 19.5 percent",
+            "temperature-xyz 2017-11-18T23:40:05Z 19.5 celsius",
+            "temperature-01 2017-11-18T23:40:05Z 19.5 rankine",
+            "conductivity-01 2017-11-18T23:40:05Z 1413,706 EC",
+            "ph-01 2017-11-18T23:40:05Z 7.0 celsius",
+        ];
+        for s in invalid.iter() {
+            assert!(s.parse::<Measurement>().is_err());
+        }
+    }
+
+    #[test]
+    fn parse_and_format_sensor_ids() {
+        let id: SensorId = "01234567-89ab-cdef-0123-456789abcdef".parse().unwrap();
+        assert_eq!("0123456789abcdef0123456789abcdef", id.to_string());
+        let id: SensorId = "0abc".parse().unwrap();
+        assert_eq!("0abc", id.to_string());
+        assert_eq!("00", SensorId::default().to_string());
+        assert!("".parse::<SensorId>().is_err());
+        assert!("abc".parse::<SensorId>().is_err());
+        assert!("0123456789abcdef0123456789abcdef0"
+            .parse::<SensorId>()
+            .is_err());
+    }
+}
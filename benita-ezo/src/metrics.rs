@@ -0,0 +1,390 @@
+//! Metrics of sensors, and their commands, in the Prometheus text format.
+//!
+//! `Metrics` keeps the latest value of every sensor quantity, the counts, failures and
+//! latencies of the commands sent to every sensor, and the health reported by their
+//! `DeviceStatus`. They are rendered as:
+//!
+//! ```text
+//! benita_sensor_value{sensor="ph-01",quantity="ph",unit="pH"} 7.012
+//! benita_sensor_reading_age_seconds{sensor="ph-01",quantity="ph"} 4.5
+//! benita_commands_total{sensor="ph1",method="GET",command="reading"} 12
+//! benita_command_failures_total{sensor="ph1",method="GET",command="reading",kind="SocketReceive"} 1
+//! benita_device_supply_voltage_volts{sensor="ph1"} 4.505
+//! benita_device_restart_reason{sensor="ph1",reason="powered-off"} 1
+//! benita_command_duration_seconds_bucket{sensor="ph1",le="0.1"} 11
+//! ```
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use errors::*;
+use measurement::{Measurement, SensorType};
+use network::subscriber::Subscriber;
+
+use chrono::{DateTime, Utc};
+
+/// Upper bounds, in seconds, of the command latency histogram buckets.
+pub const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+// The latest value of a sensor quantity.
+#[derive(Clone, Debug, PartialEq)]
+struct Latest {
+    unit: String,
+    value: f64,
+    timestamp: DateTime<Utc>,
+}
+
+// The health of a device, from its `DeviceStatus`.
+#[derive(Clone, Debug, PartialEq)]
+struct DeviceHealth {
+    restart_reason: String,
+    voltage: f64,
+}
+
+// A latency histogram. Bucket counts are not cumulative.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Histogram {
+    buckets: [u64; 11],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+/// Metrics of sensors, and their commands.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    values: BTreeMap<(String, String), Latest>,
+    commands: BTreeMap<(String, String, String), u64>,
+    failures: BTreeMap<(String, String, String, String), u64>,
+    devices: BTreeMap<String, DeviceHealth>,
+    latencies: BTreeMap<String, Histogram>,
+}
+
+// The name of an `ErrorKind`, without its fields.
+fn kind_name(kind: &ErrorKind) -> String {
+    let name = format!("{:?}", kind);
+    match name.find('(') {
+        Some(end) => name[..end].to_string(),
+        None => name,
+    }
+}
+
+// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_nanos()) / 1e9
+}
+
+impl Metrics {
+    /// Create new, empty, metrics.
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Record the latest value of a sensor quantity, e.g. `ph`, or `tds`.
+    pub fn observe_value(
+        &mut self,
+        sensor: &str,
+        quantity: &str,
+        unit: &str,
+        value: f64,
+        timestamp: DateTime<Utc>,
+    ) {
+        let key = (sensor.to_string(), quantity.to_string());
+        if let Some(latest) = self.values.get(&key) {
+            if latest.timestamp > timestamp {
+                return;
+            }
+        }
+        let latest = Latest {
+            unit: unit.to_string(),
+            value,
+            timestamp,
+        };
+        let _insert = self.values.insert(key, latest);
+    }
+
+    /// Record a measurement, under its topic.
+    pub fn observe_measurement(&mut self, m: &Measurement) {
+        self.observe_value(
+            &m.topic(),
+            m.sensor_type.name(),
+            m.sensor_type.unit(),
+            f64::from(m.value),
+            m.timestamp,
+        );
+    }
+
+    /// Record a command sent to a sensor, with its error, if it failed, and how long it took.
+    pub fn observe_command(
+        &mut self,
+        sensor: &str,
+        method: &str,
+        command: &str,
+        error: Option<&ErrorKind>,
+        latency: Duration,
+    ) {
+        let key = (sensor.to_string(), method.to_string(), command.to_string());
+        if let Some(kind) = error {
+            let failure = (key.0.clone(), key.1.clone(), key.2.clone(), kind_name(kind));
+            *self.failures.entry(failure).or_insert(0) += 1;
+        }
+        *self.commands.entry(key).or_insert(0) += 1;
+        self.latencies
+            .entry(sensor.to_string())
+            .or_insert_with(Histogram::default)
+            .observe(seconds(latency));
+    }
+
+    /// Record the `DeviceStatus` of a sensor.
+    pub fn observe_device_status(&mut self, sensor: &str, restart_reason: &str, voltage: f64) {
+        let health = DeviceHealth {
+            restart_reason: restart_reason.to_string(),
+            voltage,
+        };
+        let _insert = self.devices.insert(sensor.to_string(), health);
+    }
+
+    /// Render the metrics in the Prometheus text format. Reading ages are relative to `now`.
+    pub fn render(&self, now: &DateTime<Utc>) -> String {
+        let mut out = String::new();
+        // Writing to a `String` does not fail.
+        let _render = self.render_to(&mut out, now);
+        out
+    }
+
+    fn render_to(&self, out: &mut String, now: &DateTime<Utc>) -> ::std::fmt::Result {
+        writeln!(
+            out,
+            "# HELP benita_sensor_value Latest value of a sensor quantity."
+        )?;
+        writeln!(out, "# TYPE benita_sensor_value gauge")?;
+        for (&(ref sensor, ref quantity), latest) in &self.values {
+            writeln!(
+                out,
+                "benita_sensor_value{{sensor=\"{}\",quantity=\"{}\",unit=\"{}\"}} {}",
+                escape(sensor),
+                escape(quantity),
+                escape(&latest.unit),
+                latest.value
+            )?;
+        }
+        writeln!(
+            out,
+            "# HELP benita_sensor_reading_age_seconds Time since the latest value was taken."
+        )?;
+        writeln!(out, "# TYPE benita_sensor_reading_age_seconds gauge")?;
+        for (&(ref sensor, ref quantity), latest) in &self.values {
+            let age = (*now - latest.timestamp).num_milliseconds() as f64 / 1000.0;
+            writeln!(
+                out,
+                "benita_sensor_reading_age_seconds{{sensor=\"{}\",quantity=\"{}\"}} {}",
+                escape(sensor),
+                escape(quantity),
+                age
+            )?;
+        }
+        writeln!(
+            out,
+            "# HELP benita_commands_total Commands sent to a sensor."
+        )?;
+        writeln!(out, "# TYPE benita_commands_total counter")?;
+        for (&(ref sensor, ref method, ref command), count) in &self.commands {
+            writeln!(
+                out,
+                "benita_commands_total{{sensor=\"{}\",method=\"{}\",command=\"{}\"}} {}",
+                escape(sensor),
+                escape(method),
+                escape(command),
+                count
+            )?;
+        }
+        writeln!(
+            out,
+            "# HELP benita_command_failures_total Failed commands, by error kind."
+        )?;
+        writeln!(out, "# TYPE benita_command_failures_total counter")?;
+        for (&(ref sensor, ref method, ref command, ref kind), count) in &self.failures {
+            writeln!(
+                out,
+                "benita_command_failures_total{{sensor=\"{}\",method=\"{}\",command=\"{}\",kind=\"{}\"}} {}",
+                escape(sensor),
+                escape(method),
+                escape(command),
+                escape(kind),
+                count
+            )?;
+        }
+        writeln!(
+            out,
+            "# HELP benita_device_supply_voltage_volts Supply voltage reported by the device."
+        )?;
+        writeln!(out, "# TYPE benita_device_supply_voltage_volts gauge")?;
+        for (sensor, health) in &self.devices {
+            writeln!(
+                out,
+                "benita_device_supply_voltage_volts{{sensor=\"{}\"}} {}",
+                escape(sensor),
+                health.voltage
+            )?;
+        }
+        writeln!(
+            out,
+            "# HELP benita_device_restart_reason Reason of the last restart of the device."
+        )?;
+        writeln!(out, "# TYPE benita_device_restart_reason gauge")?;
+        for (sensor, health) in &self.devices {
+            writeln!(
+                out,
+                "benita_device_restart_reason{{sensor=\"{}\",reason=\"{}\"}} 1",
+                escape(sensor),
+                escape(&health.restart_reason)
+            )?;
+        }
+        writeln!(
+            out,
+            "# HELP benita_command_duration_seconds Time taken by commands sent to a sensor."
+        )?;
+        writeln!(out, "# TYPE benita_command_duration_seconds histogram")?;
+        for (sensor, histogram) in &self.latencies {
+            let sensor = escape(sensor);
+            let mut cumulative = 0;
+            for (le, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "benita_command_duration_seconds_bucket{{sensor=\"{}\",le=\"{}\"}} {}",
+                    sensor, le, cumulative
+                )?;
+            }
+            writeln!(
+                out,
+                "benita_command_duration_seconds_bucket{{sensor=\"{}\",le=\"+Inf\"}} {}",
+                sensor, histogram.count
+            )?;
+            writeln!(
+                out,
+                "benita_command_duration_seconds_sum{{sensor=\"{}\"}} {}",
+                sensor, histogram.sum
+            )?;
+            writeln!(
+                out,
+                "benita_command_duration_seconds_count{{sensor=\"{}\"}} {}",
+                sensor, histogram.count
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Record the measurements published on `url`, in a new thread.
+///
+/// Only measurement topics are subscribed to, i.e. not statistics, or alarms.
+pub fn record_publications(metrics: Arc<Mutex<Metrics>>, url: &str) -> Result<JoinHandle<()>> {
+    let topics: Vec<&str> = SensorType::all().iter().map(|t| t.name()).collect();
+    let mut subscriber = Subscriber::connect(url, &topics)?;
+    subscriber.set_reconnect_timeout(Some(Duration::from_secs(60)));
+    let handle = thread::spawn(move || loop {
+        match subscriber.recv_measurements() {
+            Ok(measurements) => {
+                let mut metrics = match metrics.lock() {
+                    Ok(metrics) => metrics,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+                for m in &measurements {
+                    metrics.observe_measurement(m);
+                }
+            }
+            Err(e) => warn!("skipping publication: {}", e),
+        }
+    });
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.ymd(2017, 11, 18).and_hms(0, 0, 0) + ::chrono::Duration::seconds(secs)
+    }
+
+    #[test]
+    fn render_latest_values_and_their_age() {
+        let mut metrics = Metrics::new();
+        let id = "01".parse().unwrap();
+        metrics.observe_measurement(&Measurement::new(at(0), SensorType::Ph, id, 7.5));
+        metrics.observe_measurement(&Measurement::new(at(10), SensorType::Ph, id, 7.25));
+        // Older values are ignored.
+        metrics.observe_measurement(&Measurement::new(at(5), SensorType::Ph, id, 9.0));
+        metrics.observe_value("ec1", "tds", "ppm", 320.0, at(12));
+
+        let text = metrics.render(&at(14));
+        assert!(text
+            .contains("benita_sensor_value{sensor=\"ph-01\",quantity=\"ph\",unit=\"pH\"} 7.25\n"));
+        assert!(text
+            .contains("benita_sensor_value{sensor=\"ec1\",quantity=\"tds\",unit=\"ppm\"} 320\n"));
+        assert!(text
+            .contains("benita_sensor_reading_age_seconds{sensor=\"ph-01\",quantity=\"ph\"} 4\n"));
+        assert!(text.contains("# TYPE benita_sensor_value gauge\n"));
+    }
+
+    #[test]
+    fn render_command_counts_failures_and_latencies() {
+        let mut metrics = Metrics::new();
+        let ms = Duration::from_millis;
+        metrics.observe_command("ph1", "GET", "reading", None, ms(3));
+        metrics.observe_command("ph1", "GET", "reading", None, ms(40));
+        metrics.observe_command(
+            "ph1",
+            "GET",
+            "reading",
+            Some(&ErrorKind::RunTime("timeout".to_string())),
+            ms(20_000),
+        );
+        metrics.observe_device_status("ph1", "powered-off", 4.505);
+
+        let text = metrics.render(&at(0));
+        let expected = [
+            "benita_commands_total{sensor=\"ph1\",method=\"GET\",command=\"reading\"} 3",
+            "benita_command_failures_total{sensor=\"ph1\",method=\"GET\",command=\"reading\",kind=\"RunTime\"} 1",
+            "benita_device_supply_voltage_volts{sensor=\"ph1\"} 4.505",
+            "benita_device_restart_reason{sensor=\"ph1\",reason=\"powered-off\"} 1",
+            "benita_command_duration_seconds_bucket{sensor=\"ph1\",le=\"0.005\"} 1",
+            "benita_command_duration_seconds_bucket{sensor=\"ph1\",le=\"0.025\"} 1",
+            "benita_command_duration_seconds_bucket{sensor=\"ph1\",le=\"0.05\"} 2",
+            "benita_command_duration_seconds_bucket{sensor=\"ph1\",le=\"10\"} 2",
+            "benita_command_duration_seconds_bucket{sensor=\"ph1\",le=\"+Inf\"} 3",
+            "benita_command_duration_seconds_sum{sensor=\"ph1\"} 20.043",
+            "benita_command_duration_seconds_count{sensor=\"ph1\"} 3",
+        ];
+        for line in &expected {
+            assert!(text.contains(&format!("{}\n", line)), "missing: {}", line);
+        }
+    }
+
+    #[test]
+    fn escape_label_values() {
+        assert_eq!(r#"a\"b\\c\n"#, escape("a\"b\\c\n"));
+    }
+}
@@ -0,0 +1,135 @@
+//! Remote queries for the alarms raised on a node.
+//!
+//! Nodes that evaluate alarm rules answer `alarms` requests with their active alarms:
+//!
+//! ```text
+//! alarms <count>
+//! <rule> <severity> <sensor> <since> <value>
+//! ...
+//! ```
+use std::fmt;
+
+use alarm::{ActiveAlarm, AlarmEngine};
+use errors::*;
+use network::{Endpoint, SocketReply, SocketRequest};
+
+use zmq::Socket;
+
+/// Request for the active alarms of a node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlarmsRequest;
+
+impl_SocketRequest_for! {
+    AlarmsRequest: ActiveAlarms,
+    req_str: {
+        match req_str {
+            "alarms" => Ok(AlarmsRequest),
+            _ => Err(ErrorKind::RequestParse.into()),
+        }
+    },
+    _req_out: {
+        "alarms".to_string()
+    }
+}
+
+/// The active alarms of a node, in reply to an `AlarmsRequest`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ActiveAlarms(pub Vec<ActiveAlarm>);
+
+impl ActiveAlarms {
+    fn parse(rep_str: &str) -> Result<ActiveAlarms> {
+        let mut lines = rep_str.lines();
+        let header: Vec<&str> = match lines.next() {
+            Some(header) => header.split_whitespace().collect(),
+            None => return Err(ErrorKind::ResponseParse.into()),
+        };
+        if header.len() != 2 || header[0] != "alarms" {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        let count = header[1]
+            .parse::<usize>()
+            .context(ErrorKind::ResponseParse)?;
+        let alarms = lines
+            .map(ActiveAlarm::parse)
+            .collect::<Result<Vec<ActiveAlarm>>>()?;
+        if alarms.len() != count {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        Ok(ActiveAlarms(alarms))
+    }
+}
+
+impl fmt::Display for ActiveAlarms {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "alarms {}", self.0.len())?;
+        for alarm in &self.0 {
+            write!(f, "\n{}", alarm)?;
+        }
+        Ok(())
+    }
+}
+
+impl_SocketReply_for!(ActiveAlarms);
+
+/// Reply to `req_str`, if it is an `alarms` request.
+pub fn respond(engine: &AlarmEngine, req_str: &str) -> Option<String> {
+    match <AlarmsRequest as SocketRequest>::from_str(req_str) {
+        Ok(_) => Some(format!("{}", ActiveAlarms(engine.active()))),
+        Err(_) => None,
+    }
+}
+
+// Creates a client for alarm requests to any node that evaluates alarm rules.
+network_socket!(
+    AlarmsRequester,
+    "Socket that requests the active alarms of a node."
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alarm::{AlarmRule, Severity};
+    use chrono::{TimeZone, Utc};
+    use measurement::{Measurement, SensorType};
+
+    fn engine_with_alarm() -> AlarmEngine {
+        let rule = AlarmRule {
+            name: "ec-high".to_string(),
+            sensor: "conductivity-01".to_string(),
+            high: Some(1500.0),
+            ..AlarmRule::default()
+        };
+        let mut engine = AlarmEngine::new(&[rule]).unwrap();
+        let t = Utc.ymd(2017, 11, 18).and_hms(0, 0, 0);
+        let m = Measurement::new(t, SensorType::Conductivity, "01".parse().unwrap(), 1800.0);
+        assert_eq!(1, engine.push(&m).len());
+        engine
+    }
+
+    #[test]
+    fn parse_alarms_request_from_valid_str() {
+        let request = <AlarmsRequest as SocketRequest>::from_str("alarms").unwrap();
+        assert_eq!("alarms", SocketRequest::to_string(&request));
+        assert!(<AlarmsRequest as SocketRequest>::from_str("alarms 1").is_err());
+        assert!(<AlarmsRequest as SocketRequest>::from_str("reading").is_err());
+    }
+
+    #[test]
+    fn respond_with_the_active_alarms() {
+        let engine = engine_with_alarm();
+        assert_eq!(None, respond(&engine, "history"));
+        let reply = respond(&engine, "alarms").unwrap();
+        assert_eq!(
+            "alarms 1\nec-high warning conductivity-01 2017-11-18T00:00:00Z 1800",
+            reply
+        );
+        let alarms = <ActiveAlarms as SocketReply>::from_str(&reply).unwrap();
+        assert_eq!(1, alarms.0.len());
+        assert_eq!(Severity::Warning, alarms.0[0].severity);
+        assert_eq!(
+            ActiveAlarms(vec![]),
+            <ActiveAlarms as SocketReply>::from_str("alarms 0").unwrap()
+        );
+        assert!(<ActiveAlarms as SocketReply>::from_str("alarms 2\n").is_err());
+    }
+}
@@ -0,0 +1,850 @@
+//! Service broker for networked sensors, in the spirit of the Majordomo pattern.
+//!
+//! Sensor nodes run a `BrokerWorker` that registers the service names they offer
+//! (e.g. `tank3/ph`), and clients send `SocketRequest` strings to the `Broker`, addressed
+//! by service name, through a `BrokerClient`. Clients only need to know the broker URL.
+//!
+//! Every message is a multi-part ZMQ message:
+//!
+//! * client to broker: `[MDPC01, service, request]`
+//! * broker to client: `[MDPC01, service, status, reply]`
+//! * worker to broker: `["", MDPW01, READY, service...]`, `["", MDPW01, REPLY, client, service, reply]`,
+//!   `["", MDPW01, HEARTBEAT]`, or `["", MDPW01, DISCONNECT]`
+//! * broker to worker: `["", MDPW01, REQUEST, client, service, request]`, `["", MDPW01, HEARTBEAT]`,
+//!   or `["", MDPW01, DISCONNECT]`
+//!
+//! The `status` frame sent to clients is one of `ok`, `unknown-service`, or `timeout`.
+//! Clients are answered with the envelope of their request, e.g. with the request id of a
+//! correlated `REQ` socket, so that they can drop late replies.
+//! Requests time out when they wait in queue for too long, or when the worker they were
+//! sent to is not heard from before it expires. They are not sent to another worker, since
+//! the first one may already have acted on them.
+use std::collections::{HashMap, VecDeque};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use errors::*;
+use network::Endpoint;
+use utilities::poll_readable;
+
+use zmq::{self, Context, Socket};
+
+/// Header for client messages.
+pub const CLIENT_HEADER: &'static [u8] = b"MDPC01";
+/// Header for worker messages.
+pub const WORKER_HEADER: &'static [u8] = b"MDPW01";
+
+const READY: &'static [u8] = b"\x01";
+const REQUEST: &'static [u8] = b"\x02";
+const REPLY: &'static [u8] = b"\x03";
+const HEARTBEAT: &'static [u8] = b"\x04";
+const DISCONNECT: &'static [u8] = b"\x05";
+
+const STATUS_OK: &'static [u8] = b"ok";
+const STATUS_UNKNOWN: &'static [u8] = b"unknown-service";
+const STATUS_TIMEOUT: &'static [u8] = b"timeout";
+
+/// Default interval between heartbeats, in milliseconds.
+pub const HEARTBEAT_INTERVAL: u64 = 2_500;
+/// Default number of missed heartbeats before a peer is considered dead.
+pub const HEARTBEAT_LIVENESS: u32 = 3;
+/// Default time a request waits in queue for a worker, in milliseconds.
+pub const REQUEST_TIMEOUT: u64 = 30_000;
+/// Default time a client waits for a reply, in milliseconds. It is longer than the time the
+/// broker takes to answer with a `timeout` status.
+pub const CLIENT_TIMEOUT: u64 = 45_000;
+
+fn frame_str(frame: &[u8]) -> Result<String> {
+    match ::std::str::from_utf8(frame) {
+        Ok(s) => Ok(s.to_string()),
+        Err(_) => Err(ErrorKind::BrokerProtocol.into()),
+    }
+}
+
+// The frames that address a client, before the empty delimiter: its identity, and any
+// request id.
+type Envelope = Vec<Vec<u8>>;
+
+// A client request waiting for an available worker.
+struct QueuedRequest {
+    client: Envelope,
+    body: Vec<u8>,
+    expiry: Instant,
+}
+
+// A worker known to the broker.
+struct Worker {
+    services: Vec<String>,
+    expiry: Instant,
+    // The client, and service, of the request the worker is busy with.
+    busy: Option<(Envelope, String)>,
+}
+
+/// Broker that routes requests from clients to the workers offering the requested service.
+///
+/// Requests for a service that has been registered, but which currently has no available
+/// workers (e.g. while a sensor node restarts), are queued until a worker becomes available,
+/// or until the request times out. Requests for services that have never been registered
+/// are immediately answered with an `unknown-service` status.
+pub struct Broker {
+    socket: Socket,
+    services: HashMap<String, VecDeque<QueuedRequest>>,
+    workers: HashMap<Vec<u8>, Worker>,
+    waiting: Vec<Vec<u8>>,
+    heartbeat: Duration,
+    liveness: u32,
+    request_timeout: Duration,
+    heartbeat_at: Instant,
+}
+
+impl Broker {
+    /// Create a new broker from a `ROUTER` socket.
+    pub fn new(socket: Socket) -> Result<Broker> {
+        let heartbeat = Duration::from_millis(HEARTBEAT_INTERVAL);
+        Ok(Broker {
+            socket,
+            services: HashMap::new(),
+            workers: HashMap::new(),
+            waiting: Vec::new(),
+            heartbeat,
+            liveness: HEARTBEAT_LIVENESS,
+            request_timeout: Duration::from_millis(REQUEST_TIMEOUT),
+            heartbeat_at: Instant::now() + heartbeat,
+        })
+    }
+
+    /// Create a new broker with a `ROUTER` socket bound to the given URL.
+    pub fn bind(url: &str) -> Result<Broker> {
+        let context = Context::new();
+        let socket = context
+            .socket(zmq::ROUTER)
+            .context(ErrorKind::SocketCreate)?;
+        let _bind = socket.bind(url).context(ErrorKind::SocketBind)?;
+        Broker::new(socket)
+    }
+
+    /// Set the interval between heartbeats sent to workers.
+    pub fn set_heartbeat_interval(&mut self, interval: Duration) {
+        self.heartbeat = interval;
+        self.heartbeat_at = Instant::now() + interval;
+    }
+
+    /// Set the number of missed heartbeats after which a worker is considered dead.
+    pub fn set_heartbeat_liveness(&mut self, liveness: u32) {
+        self.liveness = liveness;
+    }
+
+    /// Set the time that a request waits in queue for a worker.
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.request_timeout = timeout;
+    }
+
+    /// Names of the services that have been registered by workers.
+    pub fn services(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.services.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// Run the broker. This will run for as long as the program runs.
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            let _step = self.poll_once()?;
+        }
+    }
+
+    /// Process incoming messages until the next heartbeat is due, then do housekeeping.
+    pub fn poll_once(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let timeout = if self.heartbeat_at > now {
+            self.heartbeat_at - now
+        } else {
+            Duration::from_secs(0)
+        };
+        if poll_readable(&self.socket, timeout)? {
+            let frames = self
+                .socket
+                .recv_multipart(0)
+                .context(ErrorKind::SocketReceive)?;
+            if let Err(e) = self.route(frames) {
+                warn!("dropping invalid broker message: {}", e);
+            }
+        }
+        self.purge_workers()?;
+        self.expire_requests()?;
+        if Instant::now() >= self.heartbeat_at {
+            self.send_heartbeats()?;
+            self.heartbeat_at = Instant::now() + self.heartbeat;
+        }
+        self.dispatch()
+    }
+
+    // Route a message to its handler, according to its header.
+    fn route(&mut self, mut frames: Vec<Vec<u8>>) -> Result<()> {
+        let delimiter = match frames.iter().position(|frame| frame.is_empty()) {
+            Some(at) if at > 0 && frames.len() > at + 1 => at,
+            _ => return Err(ErrorKind::BrokerProtocol.into()),
+        };
+        let mut rest = frames.split_off(delimiter + 2);
+        let header = frames.pop().unwrap();
+        let _delimiter = frames.pop();
+        let envelope = frames;
+        match header.as_slice() {
+            CLIENT_HEADER => {
+                if rest.len() != 2 {
+                    return Err(ErrorKind::BrokerProtocol.into());
+                }
+                let body = rest.pop().unwrap();
+                let service = frame_str(&rest.pop().unwrap())?;
+                self.client_message(envelope, service, body)
+            }
+            WORKER_HEADER => {
+                if rest.is_empty() || envelope.len() != 1 {
+                    return Err(ErrorKind::BrokerProtocol.into());
+                }
+                let command = rest.remove(0);
+                let worker = envelope.into_iter().next().unwrap();
+                self.worker_message(worker, &command, rest)
+            }
+            _ => Err(ErrorKind::BrokerProtocol.into()),
+        }
+    }
+
+    fn client_message(&mut self, client: Envelope, service: String, body: Vec<u8>) -> Result<()> {
+        debug!("broker request for service {:?}", &service);
+        let expiry = Instant::now() + self.request_timeout;
+        if let Some(queue) = self.services.get_mut(&service) {
+            queue.push_back(QueuedRequest {
+                client,
+                body,
+                expiry,
+            });
+            return Ok(());
+        }
+        warn!("request for unknown service {:?}", &service);
+        self.reply_to_client(client, &service, STATUS_UNKNOWN, Vec::new())
+    }
+
+    fn worker_message(
+        &mut self,
+        worker: Vec<u8>,
+        command: &[u8],
+        mut rest: Vec<Vec<u8>>,
+    ) -> Result<()> {
+        let known = self.workers.contains_key(&worker);
+        match command {
+            READY if known => {
+                // A worker may only register once. Start over.
+                self.delete_worker(&worker, true)
+            }
+            READY => {
+                let mut services = Vec::new();
+                for frame in rest {
+                    services.push(frame_str(&frame)?);
+                }
+                if services.is_empty() {
+                    return Err(ErrorKind::BrokerProtocol.into());
+                }
+                for service in &services {
+                    info!("worker registered service {:?}", service);
+                    self.services
+                        .entry(service.clone())
+                        .or_insert_with(VecDeque::new);
+                }
+                let expiry = self.worker_expiry();
+                let _old = self.workers.insert(
+                    worker.clone(),
+                    Worker {
+                        services,
+                        expiry,
+                        busy: None,
+                    },
+                );
+                self.waiting.push(worker);
+                Ok(())
+            }
+            REPLY if known => {
+                if rest.len() != 3 {
+                    return Err(ErrorKind::BrokerProtocol.into());
+                }
+                let body = rest.pop().unwrap();
+                let service = frame_str(&rest.pop().unwrap())?;
+                let client = rest.pop().unwrap();
+                // The reply goes back with the envelope of the request the worker is busy
+                // with, unless that request has timed out.
+                let busy = self.workers.get_mut(&worker).and_then(|w| w.busy.take());
+                match busy {
+                    Some((envelope, _)) if envelope[0] == client => {
+                        let _reply = self.reply_to_client(envelope, &service, STATUS_OK, body)?;
+                    }
+                    _ => warn!("dropping reply for service {:?} to a gone request", &service),
+                }
+                self.worker_waiting(worker);
+                Ok(())
+            }
+            HEARTBEAT if known => {
+                let expiry = self.worker_expiry();
+                if let Some(w) = self.workers.get_mut(&worker) {
+                    w.expiry = expiry;
+                }
+                Ok(())
+            }
+            DISCONNECT => self.delete_worker(&worker, false),
+            REPLY | HEARTBEAT => self.send_to_worker(&worker, DISCONNECT, Vec::new()),
+            _ => Err(ErrorKind::BrokerProtocol.into()),
+        }
+    }
+
+    fn worker_expiry(&self) -> Instant {
+        Instant::now() + self.heartbeat * self.liveness
+    }
+
+    fn worker_waiting(&mut self, worker: Vec<u8>) {
+        let expiry = self.worker_expiry();
+        if let Some(w) = self.workers.get_mut(&worker) {
+            w.expiry = expiry;
+            w.busy = None;
+        }
+        self.waiting.push(worker);
+    }
+
+    fn delete_worker(&mut self, worker: &[u8], disconnect: bool) -> Result<()> {
+        if disconnect {
+            let _send = self.send_to_worker(worker, DISCONNECT, Vec::new())?;
+        }
+        self.waiting.retain(|w| w.as_slice() != worker);
+        self.workers.remove(worker);
+        Ok(())
+    }
+
+    // Remove workers that have not been heard from, answering the requests of busy ones with
+    // a timeout. Their services remain registered, so that requests are queued until they
+    // come back.
+    fn purge_workers(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let expired: Vec<Vec<u8>> = self
+            .workers
+            .iter()
+            .filter(|&(_, w)| w.expiry < now)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for worker in expired {
+            warn!("deleting expired worker");
+            self.waiting.retain(|w| *w != worker);
+            let busy = self.workers.remove(&worker).and_then(|w| w.busy);
+            if let Some((client, service)) = busy {
+                warn!("request for service {:?} timed out in its worker", &service);
+                let _reply = self.reply_to_client(client, &service, STATUS_TIMEOUT, Vec::new())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Reply with a timeout to clients whose requests have waited for too long.
+    fn expire_requests(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        for (service, queue) in self.services.iter_mut() {
+            while queue.front().map_or(false, |r| r.expiry < now) {
+                let request = queue.pop_front().unwrap();
+                expired.push((service.clone(), request.client));
+            }
+        }
+        for (service, client) in expired {
+            warn!("request for service {:?} timed out", &service);
+            let _reply = self.reply_to_client(client, &service, STATUS_TIMEOUT, Vec::new())?;
+        }
+        Ok(())
+    }
+
+    fn send_heartbeats(&self) -> Result<()> {
+        for worker in &self.waiting {
+            let _send = self.send_to_worker(worker, HEARTBEAT, Vec::new())?;
+        }
+        Ok(())
+    }
+
+    // Send queued requests to waiting workers that offer their services.
+    fn dispatch(&mut self) -> Result<()> {
+        let mut names: Vec<String> = self.services.keys().cloned().collect();
+        names.sort();
+        for service in names {
+            loop {
+                if self.services[&service].is_empty() {
+                    break;
+                }
+                let position = {
+                    let workers = &self.workers;
+                    self.waiting.iter().position(|w| {
+                        workers
+                            .get(w)
+                            .map_or(false, |w| w.services.contains(&service))
+                    })
+                };
+                let worker = match position {
+                    Some(idx) => self.waiting.remove(idx),
+                    None => break,
+                };
+                let request = self
+                    .services
+                    .get_mut(&service)
+                    .and_then(|q| q.pop_front())
+                    .unwrap();
+                let body = vec![
+                    request.client[0].clone(),
+                    service.as_bytes().to_vec(),
+                    request.body,
+                ];
+                // The worker has until it expires to reply.
+                let expiry = self.worker_expiry();
+                if let Some(w) = self.workers.get_mut(&worker) {
+                    w.expiry = expiry;
+                    w.busy = Some((request.client, service.clone()));
+                }
+                let _send = self.send_to_worker(&worker, REQUEST, body)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_to_worker(&self, worker: &[u8], command: &[u8], body: Vec<Vec<u8>>) -> Result<()> {
+        let mut frames = vec![
+            worker.to_vec(),
+            Vec::new(),
+            WORKER_HEADER.to_vec(),
+            command.to_vec(),
+        ];
+        frames.extend(body);
+        let _send = self
+            .socket
+            .send_multipart(frames, 0)
+            .context(ErrorKind::SocketSend)?;
+        Ok(())
+    }
+
+    fn reply_to_client(
+        &self,
+        client: Envelope,
+        service: &str,
+        status: &[u8],
+        body: Vec<u8>,
+    ) -> Result<()> {
+        let mut frames = client;
+        frames.extend(vec![
+            Vec::new(),
+            CLIENT_HEADER.to_vec(),
+            service.as_bytes().to_vec(),
+            status.to_vec(),
+            body,
+        ]);
+        let _send = self
+            .socket
+            .send_multipart(frames, 0)
+            .context(ErrorKind::SocketSend)?;
+        Ok(())
+    }
+}
+
+/// A request received by a worker, from a client of the broker.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkerRequest {
+    client: Vec<u8>,
+    /// The requested service name.
+    pub service: String,
+    /// The request string, e.g. a `SocketRequest`.
+    pub body: String,
+}
+
+/// Worker that offers services to clients through a `Broker`.
+///
+/// Heartbeats are exchanged with the broker, and the worker reconnects on its own when
+/// the broker is not heard from.
+pub struct BrokerWorker {
+    context: Context,
+    socket: Socket,
+    broker_url: String,
+    services: Vec<String>,
+    heartbeat: Duration,
+    max_liveness: u32,
+    liveness: u32,
+    reconnect: Duration,
+    heartbeat_at: Instant,
+}
+
+impl BrokerWorker {
+    /// Create a new worker connected to the broker at `broker_url`, which offers the given
+    /// services.
+    pub fn new(broker_url: &str, services: &[&str]) -> Result<BrokerWorker> {
+        BrokerWorker::with_context(Context::new(), broker_url, services)
+    }
+
+    /// Create a new worker using an existing ZMQ `Context`.
+    pub fn with_context(
+        context: Context,
+        broker_url: &str,
+        services: &[&str],
+    ) -> Result<BrokerWorker> {
+        let socket = context
+            .socket(zmq::DEALER)
+            .context(ErrorKind::SocketCreate)?;
+        let heartbeat = Duration::from_millis(HEARTBEAT_INTERVAL);
+        let mut worker = BrokerWorker {
+            context,
+            socket,
+            broker_url: broker_url.to_string(),
+            services: services.iter().map(|s| s.to_string()).collect(),
+            heartbeat,
+            max_liveness: HEARTBEAT_LIVENESS,
+            liveness: HEARTBEAT_LIVENESS,
+            reconnect: heartbeat,
+            heartbeat_at: Instant::now() + heartbeat,
+        };
+        let _connect = worker.connect_to_broker()?;
+        Ok(worker)
+    }
+
+    /// Set the interval between heartbeats sent to the broker.
+    pub fn set_heartbeat_interval(&mut self, interval: Duration) {
+        self.heartbeat = interval;
+        self.heartbeat_at = Instant::now() + interval;
+    }
+
+    /// Set the delay before reconnecting to an unresponsive broker.
+    pub fn set_reconnect_delay(&mut self, delay: Duration) {
+        self.reconnect = delay;
+    }
+
+    // Connect, or reconnect, to the broker, and register our services.
+    fn connect_to_broker(&mut self) -> Result<()> {
+        let socket = self
+            .context
+            .socket(zmq::DEALER)
+            .context(ErrorKind::SocketCreate)?;
+        let _linger = socket.set_linger(0).context(ErrorKind::SocketCreate)?;
+        let _connect = socket
+            .connect(&self.broker_url)
+            .context(ErrorKind::SocketConnect)?;
+        self.socket = socket;
+        info!("worker connected to broker at {}", &self.broker_url);
+        let services = self
+            .services
+            .iter()
+            .map(|s| s.as_bytes().to_vec())
+            .collect();
+        let _ready = self.send_to_broker(READY, services)?;
+        self.liveness = self.max_liveness;
+        self.heartbeat_at = Instant::now() + self.heartbeat;
+        Ok(())
+    }
+
+    fn send_to_broker(&self, command: &[u8], body: Vec<Vec<u8>>) -> Result<()> {
+        let mut frames = vec![Vec::new(), WORKER_HEADER.to_vec(), command.to_vec()];
+        frames.extend(body);
+        let _send = self
+            .socket
+            .send_multipart(frames, 0)
+            .context(ErrorKind::SocketSend)?;
+        Ok(())
+    }
+
+    /// Wait for the next request from the broker.
+    pub fn recv(&mut self) -> Result<WorkerRequest> {
+        loop {
+            if poll_readable(&self.socket, self.heartbeat)? {
+                let mut frames = self
+                    .socket
+                    .recv_multipart(0)
+                    .context(ErrorKind::SocketReceive)?;
+                self.liveness = self.max_liveness;
+                if frames.len() < 3 || !frames[0].is_empty() || frames[1] != WORKER_HEADER {
+                    warn!("dropping invalid message from broker");
+                    continue;
+                }
+                let rest = frames.split_off(3);
+                match frames[2].as_slice() {
+                    REQUEST if rest.len() == 3 => {
+                        return Ok(WorkerRequest {
+                            client: rest[0].clone(),
+                            service: frame_str(&rest[1])?,
+                            body: frame_str(&rest[2])?,
+                        });
+                    }
+                    HEARTBEAT => {}
+                    DISCONNECT => {
+                        let _reconnect = self.connect_to_broker()?;
+                    }
+                    _ => warn!("dropping invalid message from broker"),
+                }
+            } else {
+                self.liveness -= 1;
+                if self.liveness == 0 {
+                    warn!("broker is not responding, reconnecting");
+                    thread::sleep(self.reconnect);
+                    let _reconnect = self.connect_to_broker()?;
+                }
+            }
+            if Instant::now() >= self.heartbeat_at {
+                let _heartbeat = self.send_to_broker(HEARTBEAT, Vec::new())?;
+                self.heartbeat_at = Instant::now() + self.heartbeat;
+            }
+        }
+    }
+
+    /// Send the reply to a request received with `recv`.
+    pub fn reply(&self, request: &WorkerRequest, reply: &str) -> Result<()> {
+        let body = vec![
+            request.client.clone(),
+            request.service.as_bytes().to_vec(),
+            reply.as_bytes().to_vec(),
+        ];
+        self.send_to_broker(REPLY, body)
+    }
+
+    /// Serve requests forever, using `handler` to build the reply for each request.
+    ///
+    /// The handler receives the service name, and the request string.
+    pub fn run<F>(&mut self, mut handler: F) -> Result<()>
+    where
+        F: FnMut(&str, &str) -> String,
+    {
+        loop {
+            let request = self.recv()?;
+            let reply = handler(&request.service, &request.body);
+            let _reply = self.reply(&request, &reply)?;
+        }
+    }
+}
+
+impl Drop for BrokerWorker {
+    fn drop(&mut self) {
+        let _disconnect = self.send_to_broker(DISCONNECT, Vec::new());
+    }
+}
+
+/// Client that sends requests to a named service through a `Broker`.
+///
+/// It implements `Endpoint`, so any `SocketRequest` may be sent with it. Requests that get
+/// no reply within the timeout yield `ErrorKind::ServiceTimeout`, and the next request may
+/// be sent right away.
+pub struct BrokerClient {
+    socket: Socket,
+    service: String,
+    timeout: Duration,
+}
+
+impl BrokerClient {
+    /// Create a new client from a `REQ` socket, for the given service.
+    pub fn new(socket: Socket, service: &str) -> Result<BrokerClient> {
+        // Late replies are dropped, so that the socket may send again after a timeout.
+        let _relaxed = socket
+            .set_req_relaxed(true)
+            .context(ErrorKind::SocketCreate)?;
+        let _correlate = socket
+            .set_req_correlate(true)
+            .context(ErrorKind::SocketCreate)?;
+        Ok(BrokerClient {
+            socket,
+            service: service.to_string(),
+            timeout: Duration::from_millis(CLIENT_TIMEOUT),
+        })
+    }
+
+    /// Set the time to wait for every reply.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// The service that requests are sent to.
+    pub fn service(&self) -> &str {
+        &self.service
+    }
+
+    /// Change the service that requests are sent to.
+    pub fn set_service(&mut self, service: &str) {
+        self.service = service.to_string();
+    }
+
+    /// Send a request string to the service, and return the reply string.
+    pub fn request(&self, request: &str) -> Result<String> {
+        let _send = self.send(request.as_bytes())?;
+        if !self.poll(self.timeout)? {
+            return Err(ErrorKind::ServiceTimeout.into());
+        }
+        self.recv()
+    }
+}
+
+impl Endpoint for BrokerClient {
+    /// Binds the socket to the given URL.
+    fn bind(&self, url: &str) -> Result<()> {
+        let _bind = self.socket.bind(url).context(ErrorKind::SocketBind)?;
+        Ok(())
+    }
+
+    /// Connects the socket to the given URL.
+    fn connect(&self, url: &str) -> Result<()> {
+        let _connect = self.socket.connect(url).context(ErrorKind::SocketConnect)?;
+        Ok(())
+    }
+
+    /// Sends a message to the service, through the broker.
+    fn send(&self, msg: &[u8]) -> Result<()> {
+        let frames = vec![
+            CLIENT_HEADER.to_vec(),
+            self.service.as_bytes().to_vec(),
+            msg.to_vec(),
+        ];
+        let _send = self
+            .socket
+            .send_multipart(frames, 0)
+            .context(ErrorKind::SocketSend)?;
+        Ok(())
+    }
+
+    /// Receives the reply from the service, through the broker.
+    fn recv(&self) -> Result<String> {
+        let frames = self
+            .socket
+            .recv_multipart(0)
+            .context(ErrorKind::SocketReceive)?;
+        parse_client_reply(frames)
+    }
+
+    /// Waits for a reply from the service.
+    fn poll(&self, timeout: Duration) -> Result<bool> {
+        poll_readable(&self.socket, timeout)
+    }
+}
+
+// Parse the reply frames received by a client.
+fn parse_client_reply(mut frames: Vec<Vec<u8>>) -> Result<String> {
+    if frames.len() != 4 || frames[0] != CLIENT_HEADER {
+        return Err(ErrorKind::BrokerProtocol.into());
+    }
+    let body = frames.pop().unwrap();
+    let status = frames.pop().unwrap();
+    match status.as_slice() {
+        STATUS_OK => frame_str(&body),
+        STATUS_UNKNOWN => Err(ErrorKind::UnknownService.into()),
+        STATUS_TIMEOUT => Err(ErrorKind::ServiceTimeout.into()),
+        _ => Err(ErrorKind::BrokerProtocol.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client_reply(status: &[u8], body: &[u8]) -> Vec<Vec<u8>> {
+        vec![
+            CLIENT_HEADER.to_vec(),
+            b"tank3/ph".to_vec(),
+            status.to_vec(),
+            body.to_vec(),
+        ]
+    }
+
+    #[test]
+    fn parse_valid_client_reply() {
+        let reply = parse_client_reply(client_reply(b"ok", b"7.020")).unwrap();
+        assert_eq!("7.020", reply);
+    }
+
+    #[test]
+    fn parse_client_reply_with_error_status_yields_err() {
+        let reply = parse_client_reply(client_reply(b"unknown-service", b""));
+        assert_eq!(ErrorKind::UnknownService, reply.unwrap_err().kind());
+        let reply = parse_client_reply(client_reply(b"timeout", b""));
+        assert_eq!(ErrorKind::ServiceTimeout, reply.unwrap_err().kind());
+        let reply = parse_client_reply(client_reply(b"maybe", b""));
+        assert_eq!(ErrorKind::BrokerProtocol, reply.unwrap_err().kind());
+    }
+
+    #[test]
+    fn parse_invalid_client_reply_yields_err() {
+        let reply = parse_client_reply(vec![b"MDPC01".to_vec(), b"ok".to_vec()]);
+        assert!(reply.is_err());
+        let mut frames = client_reply(b"ok", b"7.020");
+        frames[0] = WORKER_HEADER.to_vec();
+        assert!(parse_client_reply(frames).is_err());
+    }
+
+    #[test]
+    fn broker_routes_requests_to_workers_by_service() {
+        let context = Context::new();
+        let router = context.socket(zmq::ROUTER).unwrap();
+        router.bind("tcp://127.0.0.1:0").unwrap();
+        let url = router.get_last_endpoint().unwrap().unwrap();
+        let mut broker = Broker::new(router).unwrap();
+        let _broker = thread::spawn(move || broker.run());
+
+        let worker_url = url.clone();
+        let _worker = thread::spawn(move || {
+            let mut worker = BrokerWorker::new(&worker_url, &["tank3/ph", "tank3/ec"]).unwrap();
+            worker.run(|service, request| format!("{} {}", service, request))
+        });
+
+        let socket = context.socket(zmq::REQ).unwrap();
+        socket.connect(&url).unwrap();
+        let mut client = BrokerClient::new(socket, "tank3/ph").unwrap();
+
+        // Give the worker some time to register its services.
+        thread::sleep(Duration::from_millis(200));
+        let reply = client.request("read").unwrap();
+        assert_eq!("tank3/ph read", reply);
+
+        client.set_service("tank3/ec");
+        assert_eq!(
+            "tank3/ec output-status",
+            client.request("output-status").unwrap()
+        );
+
+        client.set_service("tank9/rtd");
+        let reply = client.request("read");
+        assert_eq!(ErrorKind::UnknownService, reply.unwrap_err().kind());
+    }
+
+    #[test]
+    fn time_out_the_requests_of_workers_that_expire() {
+        let context = Context::new();
+        let router = context.socket(zmq::ROUTER).unwrap();
+        router.bind("tcp://127.0.0.1:0").unwrap();
+        let url = router.get_last_endpoint().unwrap().unwrap();
+        let mut broker = Broker::new(router).unwrap();
+        broker.set_heartbeat_interval(Duration::from_millis(50));
+        broker.set_heartbeat_liveness(2);
+        let _broker = thread::spawn(move || broker.run());
+
+        // A worker that takes a request, and is never heard from again.
+        let worker = context.socket(zmq::DEALER).unwrap();
+        worker.connect(&url).unwrap();
+        let ready = vec![
+            Vec::new(),
+            WORKER_HEADER.to_vec(),
+            READY.to_vec(),
+            b"tank3/ph".to_vec(),
+        ];
+        worker.send_multipart(ready, 0).unwrap();
+        thread::sleep(Duration::from_millis(50));
+
+        let socket = context.socket(zmq::REQ).unwrap();
+        socket.connect(&url).unwrap();
+        let mut client = BrokerClient::new(socket, "tank3/ph").unwrap();
+        let reply = client.request("read");
+        assert_eq!(ErrorKind::ServiceTimeout, reply.unwrap_err().kind());
+        let mut requests = 0;
+        while let Ok(frames) = worker.recv_multipart(zmq::DONTWAIT) {
+            if frames[2] == REQUEST {
+                requests += 1;
+            }
+        }
+        assert_eq!(1, requests);
+
+        // The client gives up on its own, too, and may send again.
+        client.set_timeout(Duration::from_millis(50));
+        let reply = client.request("read");
+        assert_eq!(ErrorKind::ServiceTimeout, reply.unwrap_err().kind());
+        let reply = client.request("read");
+        assert_eq!(ErrorKind::ServiceTimeout, reply.unwrap_err().kind());
+    }
+}
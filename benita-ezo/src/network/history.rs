@@ -0,0 +1,487 @@
+//! Remote queries for the measurements stored on a node.
+//!
+//! Nodes that keep a measurement log answer `history` requests:
+//!
+//! ```text
+//! history <sensor> <from> <to> [step] [after <cursor>]
+//! ```
+//!
+//! where `<sensor>` is a measurement topic, e.g. `ph-0123456789abcdef`, `<from>` and `<to>`
+//! are RFC 3339 timestamps, and `<step>` is an optional number of seconds. When a step is
+//! given, only the first measurement in every step is returned.
+//!
+//! Measurements are returned in pages, in the order they were stored, so that a long time
+//! range does not hold the socket for too long:
+//!
+//! ```text
+//! history <count> <next>
+//! <measurement>
+//! ...
+//! ```
+//!
+//! where `<next>` is the `<cursor>` of the request for the following page, or `end`. The
+//! cursor is a position in the measurement log, so that every page is read from where the
+//! previous one stopped.
+use std::fmt;
+
+use errors::*;
+use measurement::{parse_topic, Measurement, SensorId, SensorType};
+use network::{Endpoint, ReplyStatus, SocketReply, SocketRequest};
+use store::{LogCursor, LogReader};
+
+use chrono::{DateTime, Utc};
+use zmq::Socket;
+
+/// Default maximum number of measurements in a reply.
+pub const DEFAULT_PAGE_SIZE: usize = 500;
+
+/// Request for the measurements taken by a sensor in `[from, to)`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryRequest {
+    pub sensor_type: SensorType,
+    pub sensor_id: SensorId,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Return at most one measurement every `step` seconds.
+    pub step: Option<u64>,
+    /// Continue after a previous page, from its `next` cursor.
+    pub after: Option<LogCursor>,
+}
+
+impl HistoryRequest {
+    /// Create a new request for the sensor with the given topic, e.g. `ph-0123`.
+    pub fn new(sensor: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<HistoryRequest> {
+        let (sensor_type, sensor_id) = parse_topic(sensor).context(ErrorKind::RequestParse)?;
+        Ok(HistoryRequest {
+            sensor_type,
+            sensor_id,
+            from,
+            to,
+            step: None,
+            after: None,
+        })
+    }
+
+    fn parse(req_str: &str) -> Result<HistoryRequest> {
+        let mut args = req_str.split_whitespace();
+        if args.next() != Some("history") {
+            return Err(ErrorKind::RequestParse.into());
+        }
+        let (sensor, from, to) = match (args.next(), args.next(), args.next()) {
+            (Some(sensor), Some(from), Some(to)) => (sensor, from, to),
+            _ => return Err(ErrorKind::RequestParse.into()),
+        };
+        let from = from
+            .parse::<DateTime<Utc>>()
+            .context(ErrorKind::RequestParse)?;
+        let to = to
+            .parse::<DateTime<Utc>>()
+            .context(ErrorKind::RequestParse)?;
+        let mut request = HistoryRequest::new(sensor, from, to)?;
+        let mut arg = args.next();
+        match arg {
+            Some(step) if step != "after" => {
+                match step.parse::<u64>().context(ErrorKind::NumberParse)? {
+                    0 => return Err(ErrorKind::RequestParse.into()),
+                    step => request.step = Some(step),
+                }
+                arg = args.next();
+            }
+            _ => {}
+        }
+        if arg.is_some() {
+            let cursor = match (arg, args.next()) {
+                (Some("after"), Some(cursor)) => cursor,
+                _ => return Err(ErrorKind::RequestParse.into()),
+            };
+            request.after = Some(
+                cursor
+                    .parse::<LogCursor>()
+                    .context(ErrorKind::RequestParse)?,
+            );
+        }
+        if args.next().is_some() {
+            return Err(ErrorKind::RequestParse.into());
+        }
+        Ok(request)
+    }
+}
+
+impl_SocketRequest_for! {
+    HistoryRequest: HistoryPage,
+    req_str: {
+        HistoryRequest::parse(req_str)
+    },
+    req_out: {
+        let mut out = format!(
+            "history {}-{} {:?} {:?}",
+            req_out.sensor_type, req_out.sensor_id, req_out.from, req_out.to
+        );
+        if let Some(step) = req_out.step {
+            out.push_str(&format!(" {}", step));
+        }
+        if let Some(after) = req_out.after {
+            out.push_str(&format!(" after {}", after));
+        }
+        out
+    }
+}
+
+/// A page of measurements, in reply to a `HistoryRequest`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryPage {
+    pub measurements: Vec<Measurement>,
+    /// Where the next page starts, if there are more measurements.
+    pub next: Option<LogCursor>,
+}
+
+impl HistoryPage {
+    fn parse(rep_str: &str) -> Result<HistoryPage> {
+        let mut lines = rep_str.lines();
+        let header: Vec<&str> = match lines.next() {
+            Some(header) => header.split_whitespace().collect(),
+            None => return Err(ErrorKind::ResponseParse.into()),
+        };
+        if header.len() != 3 || header[0] != "history" {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        let count = header[1]
+            .parse::<usize>()
+            .context(ErrorKind::ResponseParse)?;
+        let next = match header[2] {
+            "end" => None,
+            next => Some(
+                next.parse::<LogCursor>()
+                    .context(ErrorKind::ResponseParse)?,
+            ),
+        };
+        let measurements = lines
+            .map(Measurement::parse)
+            .collect::<Result<Vec<Measurement>>>()?;
+        if measurements.len() != count {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        Ok(HistoryPage { measurements, next })
+    }
+}
+
+impl fmt::Display for HistoryPage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "history {} ", self.measurements.len())?;
+        match self.next {
+            Some(ref next) => write!(f, "{}", next)?,
+            None => write!(f, "end")?,
+        }
+        for m in &self.measurements {
+            write!(f, "\n{}", m)?;
+        }
+        Ok(())
+    }
+}
+
+impl_SocketReply_for!(HistoryPage);
+
+/// Answers `history` requests from a measurement log.
+pub struct HistoryService {
+    reader: LogReader,
+    page_size: usize,
+}
+
+impl HistoryService {
+    /// Create a new service for the log read by `reader`.
+    pub fn new(reader: LogReader) -> HistoryService {
+        HistoryService {
+            reader,
+            page_size: DEFAULT_PAGE_SIZE,
+        }
+    }
+
+    /// Set the maximum number of measurements in a reply.
+    pub fn set_page_size(&mut self, page_size: usize) {
+        self.page_size = page_size;
+    }
+
+    /// Query the log, returning the page of results that starts at `request.after`, or
+    /// the first page.
+    ///
+    /// The log is read from the cursor on, and only until the page is full. The first page
+    /// starts at the first segment that can hold measurements taken in the requested range.
+    pub fn query(&self, request: &HistoryRequest) -> Result<HistoryPage> {
+        let start = match request.after {
+            Some(after) => after,
+            None => self.reader.seek(&request.from, &request.to)?,
+        };
+        let mut measurements = Vec::new();
+        let mut last_step = None;
+        for record in self.reader.scan(start)? {
+            let (cursor, m) = record?;
+            if m.sensor_type != request.sensor_type
+                || m.sensor_id != request.sensor_id
+                || m.timestamp < request.from
+                || m.timestamp >= request.to
+            {
+                continue;
+            }
+            if let Some(step) = request.step {
+                // Steps are aligned to the epoch, so that they do not move from page to page.
+                let this_step = Some(m.timestamp.timestamp() / step as i64);
+                if this_step == last_step {
+                    continue;
+                }
+                last_step = this_step;
+            }
+            if measurements.len() == self.page_size {
+                return Ok(HistoryPage {
+                    measurements,
+                    next: Some(cursor),
+                });
+            }
+            measurements.push(m);
+        }
+        Ok(HistoryPage {
+            measurements,
+            next: None,
+        })
+    }
+
+    /// Reply to `req_str`, if it is a `history` request. Failed queries reply with `err`.
+    pub fn respond(&self, req_str: &str) -> Option<String> {
+        let request = match <HistoryRequest as SocketRequest>::from_str(req_str) {
+            Ok(request) => request,
+            Err(_) => return None,
+        };
+        match self.query(&request) {
+            Ok(page) => Some(format!("{}", page)),
+            Err(e) => {
+                error!("history query failed: {}", e);
+                Some(format!("{:?}", ReplyStatus::Err))
+            }
+        }
+    }
+}
+
+// Creates a client for history requests to any responder with a measurement log.
+network_socket!(
+    HistoryRequester,
+    "Socket that requests the measurements stored on a node."
+);
+
+/// Send a `HistoryRequest`, and collect the measurements in every page of the reply.
+pub fn fetch_history<T: Endpoint>(
+    endpoint: &T,
+    request: &HistoryRequest,
+) -> Result<Vec<Measurement>> {
+    let mut request = request.clone();
+    let mut measurements = Vec::new();
+    loop {
+        let page = request.send(endpoint)?;
+        measurements.extend(page.measurements);
+        match page.next {
+            Some(next) if request.after.map_or(true, |after| next > after) => {
+                request.after = Some(next)
+            }
+            Some(_) => return Err(ErrorKind::ResponseParse.into()),
+            None => return Ok(measurements),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::fs;
+    use std::path::PathBuf;
+    use store::{LogPolicy, MeasurementLog};
+    use zmq::{self, Context};
+
+    fn log_dir(name: &str) -> PathBuf {
+        let dir = ::std::env::temp_dir().join(format!(
+            "benita-history-{}-{}",
+            name,
+            ::std::process::id()
+        ));
+        let _remove = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.timestamp(1_500_000_000 + secs, 0)
+    }
+
+    // Write 10 pH, and 10 temperature, measurements. Two pH measurements every 10 seconds.
+    fn write_log(dir: &PathBuf) {
+        let mut log = MeasurementLog::open(dir, LogPolicy::default()).unwrap();
+        let id: SensorId = "01".parse().unwrap();
+        for i in 0..10 {
+            let t = at(10 * (i / 2));
+            log.append(&Measurement::new(t, SensorType::Ph, id, i as f32))
+                .unwrap();
+            log.append(&Measurement::new(t, SensorType::Temperature, id, 20.0))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn parse_history_request_from_valid_str() {
+        let req_str = "history ph-01 2017-07-14T02:40:00Z 2017-07-14T03:40:00Z";
+        let request = <HistoryRequest as SocketRequest>::from_str(req_str).unwrap();
+        assert_eq!(SensorType::Ph, request.sensor_type);
+        assert_eq!(None, request.step);
+        assert_eq!(req_str, SocketRequest::to_string(&request));
+
+        let req_str = "history conductivity-01 2017-07-14T02:40:00Z 2017-07-14T03:40:00Z 60";
+        let request = <HistoryRequest as SocketRequest>::from_str(req_str).unwrap();
+        assert_eq!(SensorType::Conductivity, request.sensor_type);
+        assert_eq!(Some(60), request.step);
+        assert_eq!(req_str, SocketRequest::to_string(&request));
+
+        let req_str = "history ph-01 2017-07-14T02:40:00Z 2017-07-14T03:40:00Z after 2:56";
+        let request = <HistoryRequest as SocketRequest>::from_str(req_str).unwrap();
+        assert_eq!(None, request.step);
+        assert_eq!(
+            Some(LogCursor {
+                segment: 2,
+                offset: 56,
+            }),
+            request.after
+        );
+        assert_eq!(req_str, SocketRequest::to_string(&request));
+
+        let req_str = "history ph-01 2017-07-14T02:40:00Z 2017-07-14T03:40:00Z 60 after 0:0";
+        let request = <HistoryRequest as SocketRequest>::from_str(req_str).unwrap();
+        assert_eq!(Some(60), request.step);
+        assert_eq!(Some(LogCursor::default()), request.after);
+        assert_eq!(req_str, SocketRequest::to_string(&request));
+    }
+
+    #[test]
+    fn parse_history_request_from_invalid_str_yields_err() {
+        let invalid = [
+            "history",
+            "history ph-01 2017-07-14T02:40:00Z",
+            "history ph 2017-07-14T02:40:00Z 2017-07-14T03:40:00Z",
+            "history ph-01 yesterday 2017-07-14T03:40:00Z",
+            "history ph-01 2017-07-14T02:40:00Z 2017-07-14T03:40:00Z 0",
+            "history ph-01 2017-07-14T02:40:00Z 2017-07-14T03:40:00Z 60 extra",
+            "history ph-01 2017-07-14T02:40:00Z 2017-07-14T03:40:00Z after",
+            "history ph-01 2017-07-14T02:40:00Z 2017-07-14T03:40:00Z after 2",
+            "history ph-01 2017-07-14T02:40:00Z 2017-07-14T03:40:00Z after 0:0 60",
+            "reading",
+        ];
+        for req_str in invalid.iter() {
+            assert!(<HistoryRequest as SocketRequest>::from_str(req_str).is_err());
+        }
+    }
+
+    #[test]
+    fn parse_history_page_from_valid_str() {
+        let rep_str = "history 2 0:112\n\
+                       ph-01 2017-07-14T02:40:00Z 7 pH\n\
+                       ph-01 2017-07-14T02:40:30Z 7.1 pH";
+        let page = <HistoryPage as SocketReply>::from_str(rep_str).unwrap();
+        assert_eq!(2, page.measurements.len());
+        assert_eq!(
+            Some(LogCursor {
+                segment: 0,
+                offset: 112,
+            }),
+            page.next
+        );
+        assert_eq!(rep_str, SocketReply::to_string(&page));
+
+        let page = <HistoryPage as SocketReply>::from_str("history 0 end").unwrap();
+        assert_eq!(
+            HistoryPage {
+                measurements: vec![],
+                next: None,
+            },
+            page
+        );
+    }
+
+    #[test]
+    fn parse_history_page_from_invalid_str_yields_err() {
+        let invalid = [
+            "",
+            "err",
+            "history 1 end",
+            "history 1 never\nph-01 2017-07-14T02:40:00Z 7 pH",
+            "history 1 2017-07-14T02:41:00Z\nph-01 2017-07-14T02:40:00Z 7 pH",
+        ];
+        for rep_str in invalid.iter() {
+            assert!(<HistoryPage as SocketReply>::from_str(rep_str).is_err());
+        }
+    }
+
+    #[test]
+    fn query_history_in_pages() {
+        let dir = log_dir("pages");
+        write_log(&dir);
+        let mut service = HistoryService::new(LogReader::new(&dir));
+        service.set_page_size(3);
+
+        let mut request = HistoryRequest::new("ph-01", at(0), at(100)).unwrap();
+        let page = service.query(&request).unwrap();
+        let values: Vec<f32> = page.measurements.iter().map(|m| m.value).collect();
+        assert_eq!(vec![0.0, 1.0, 2.0], values);
+        assert!(page.next.is_some());
+
+        // The next page continues from the cursor.
+        request.after = page.next;
+        let page = service.query(&request).unwrap();
+        let values: Vec<f32> = page.measurements.iter().map(|m| m.value).collect();
+        assert_eq!(vec![3.0, 4.0, 5.0], values);
+
+        request.from = at(30);
+        request.after = None;
+        let page = service.query(&request).unwrap();
+        let values: Vec<f32> = page.measurements.iter().map(|m| m.value).collect();
+        assert_eq!(vec![6.0, 7.0, 8.0], values);
+        assert!(page.next.is_some());
+
+        request.from = at(0);
+        request.after = None;
+        request.step = Some(20);
+        service.set_page_size(DEFAULT_PAGE_SIZE);
+        let page = service.query(&request).unwrap();
+        let values: Vec<f32> = page.measurements.iter().map(|m| m.value).collect();
+        assert_eq!(vec![0.0, 4.0, 8.0], values);
+        assert_eq!(None, page.next);
+        let _remove = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn fetch_every_page_of_history() {
+        let dir = log_dir("fetch");
+        write_log(&dir);
+        let mut service = HistoryService::new(LogReader::new(&dir));
+        service.set_page_size(2);
+
+        let context = Context::new();
+        let responder = context.socket(zmq::REP).unwrap();
+        responder.bind("tcp://127.0.0.1:0").unwrap();
+        let url = responder.get_last_endpoint().unwrap().unwrap();
+        let server = ::std::thread::spawn(move || {
+            // One request for every page.
+            for _ in 0..5 {
+                let req_str = responder.recv_string(0).unwrap().unwrap();
+                let reply = service.respond(&req_str).unwrap();
+                responder.send(reply.as_bytes(), 0).unwrap();
+            }
+        });
+
+        let socket = context.socket(zmq::REQ).unwrap();
+        socket.connect(&url).unwrap();
+        let client = HistoryRequester::new(socket).unwrap();
+        let request = HistoryRequest::new("ph-01", at(0), at(100)).unwrap();
+        let measurements = fetch_history(&client, &request).unwrap();
+        let values: Vec<f32> = measurements.iter().map(|m| m.value).collect();
+        assert_eq!(
+            vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0],
+            values
+        );
+        server.join().unwrap();
+        let _remove = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,1346 @@
+//! HTTP/JSON gateway for sensor APIs.
+//!
+//! An `HttpGateway` exposes the `EzoChipAPI`, and the type-specific API, of every sensor
+//! added to it as JSON resources, for clients that do not speak ZMQ:
+//!
+//! ```text
+//! GET    /sensors                       the names, and kinds, of the sensors
+//! GET    /sensors/ph1/reading           {"pH": 7.012}
+//! GET    /sensors/ec1/calibration       {"calibration": "two-point"}
+//! POST   /sensors/rtd1/scale            {"value": "fahrenheit"}
+//! DELETE /sensors/ph1/calibration       clears the calibration
+//! ```
+//!
+//! Sensors are added through `ConductivityResource`, `PhResource` and `TemperatureResource`,
+//! which wrap anything that implements the sensor API, i.e. a requester connected to a
+//! sensor server, or a local sensor. A `RemoteSensor` connects such a requester to a sensor
+//! server, and gives up on replies that take longer than `REPLY_TIMEOUT_MS`, with a
+//! `504 Gateway Timeout`, so that a server that is down does not hang the gateway.
+//!
+//! Commands that change the sensor reply with `{"status": "ok"}`. Errors reply with
+//! `{"error": <message>, "kind": <ErrorKind>}`, and the HTTP status of their `ErrorKind`.
+//!
+//! Conductivity, and pH, servers only change the calibration, e.g. on `POST calibration`,
+//! `DELETE calibration`, `POST import`, or `POST factory-reset`, for the holder of their
+//! session lock, see `network::session`. Clients send the token of their session in the
+//! `X-Benita-Session` header, or the `session` query parameter. Requests refused for want of
+//! the lock reply with `428 Precondition Required`, and those refused because someone else
+//! holds it with `409 Conflict`.
+//!
+//! `GET /metrics` replies with the gateway's `Metrics`, in the Prometheus text format. The
+//! readings requested through the gateway are kept as the latest values of their sensor.
+//! Scrapes do not wait for the sensors: a `StatusRefresher` requests the status of every
+//! sensor in the background, every `STATUS_INTERVAL_SECS`, and keeps it in the metrics.
+//!
+//! `GET /stream` upgrades to a WebSocket stream of the gateway's publications, see
+//! `network::websocket`.
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common_ezo::requests::{CalibrationClear, Factory, Import};
+use common_ezo::EzoChipAPI;
+use conductivity::network::requests as conductivity_requests;
+use conductivity::network::ConductivityRequester;
+use conductivity::ConductivityAPI;
+use config::{GatewayConfig, SensorKind};
+use errors::*;
+use measurement::SensorType;
+use metrics::{record_publications, Metrics};
+use network::session::SessionRequester;
+use network::websocket::{serve_stream, stream_publications, StreamHub};
+use network::{ReplyStatus, SocketRequest};
+use ph::network::requests as ph_requests;
+use ph::network::PhRequester;
+use ph::PhAPI;
+use temperature::network::TemperatureRequester;
+use temperature::TemperatureAPI;
+use utilities::create_timed_requester;
+
+use chrono::Utc;
+use serde_json::{self, Map, Value};
+use zmq::Context;
+
+/// Time allowed to read a request, or write a response, in seconds.
+pub const IO_TIMEOUT_SECS: u64 = 5;
+
+/// Largest request body accepted by the gateway, in bytes.
+pub const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Longest request line, or header, accepted by the gateway, in bytes.
+pub const MAX_LINE_SIZE: usize = 8 * 1024;
+
+/// Most headers accepted in a request.
+pub const MAX_HEADERS: usize = 100;
+
+/// Time allowed for a sensor server to reply, in milliseconds.
+pub const REPLY_TIMEOUT_MS: u64 = 5_000;
+
+/// Time between the requests for the status of every sensor, in seconds.
+pub const STATUS_INTERVAL_SECS: u64 = 30;
+
+/// Header with the token of the session lock a request is sent in.
+pub const SESSION_HEADER: &str = "x-benita-session";
+
+/// The HTTP status code for an `ErrorKind`.
+pub fn status_code(kind: &ErrorKind) -> u16 {
+    match *kind {
+        ErrorKind::AddressParse
+        | ErrorKind::CommandParse
+        | ErrorKind::NumberParse
+        | ErrorKind::RequestParse => 400,
+        ErrorKind::UnknownResource(_) => 404,
+        ErrorKind::SessionLocked(_) => 409,
+        ErrorKind::SessionRequired => 428,
+        ErrorKind::ServiceTimeout => 504,
+        ErrorKind::CommandRequest
+        | ErrorKind::CommandReply
+        | ErrorKind::CommandResponse
+        | ErrorKind::ResponseParse
+        | ErrorKind::SensorTrouble
+        | ErrorKind::SocketConnect
+        | ErrorKind::SocketSend
+        | ErrorKind::SocketReceive
+        | ErrorKind::UnknownService => 502,
+        _ => 500,
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        409 => "Conflict",
+        428 => "Precondition Required",
+        502 => "Bad Gateway",
+        504 => "Gateway Timeout",
+        _ => "Internal Server Error",
+    }
+}
+
+/// An HTTP request, as read by the gateway.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpRequest {
+    pub method: String,
+    /// The path, without the query string.
+    pub path: String,
+    /// The query string, without the `?`.
+    pub query: String,
+    /// The headers, with lowercase names.
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpRequest {
+    /// Create a new request.
+    pub fn new(method: &str, path: &str, body: &str) -> HttpRequest {
+        HttpRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: String::new(),
+            headers: Vec::new(),
+            body: body.to_string(),
+        }
+    }
+
+    /// Read a request, with its `Content-Length` body.
+    ///
+    /// Requests with lines longer than `MAX_LINE_SIZE`, more than `MAX_HEADERS` headers, or
+    /// a body larger than `MAX_BODY_SIZE`, are refused with `ErrorKind::RequestParse`.
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<HttpRequest> {
+        let mut line = String::new();
+        let _read = read_line(reader, &mut line)?;
+        let (method, target) = {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/") => {
+                    (method.to_string(), target.to_string())
+                }
+                _ => return Err(ErrorKind::RequestParse.into()),
+            }
+        };
+        let mut length = 0;
+        let mut headers = Vec::new();
+        loop {
+            line.clear();
+            let read = read_line(reader, &mut line)?;
+            if read == 0 {
+                return Err(ErrorKind::RequestParse.into());
+            }
+            let header = line.trim();
+            if header.is_empty() {
+                break;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(ErrorKind::RequestParse.into());
+            }
+            let mut pair = header.splitn(2, ':');
+            if let (Some(name), Some(value)) = (pair.next(), pair.next()) {
+                let name = name.trim().to_lowercase();
+                if name == "content-length" {
+                    length = value
+                        .trim()
+                        .parse::<usize>()
+                        .context(ErrorKind::RequestParse)?;
+                }
+                headers.push((name, value.trim().to_string()));
+            }
+        }
+        if length > MAX_BODY_SIZE {
+            return Err(ErrorKind::RequestParse.into());
+        }
+        let mut body = vec![0; length];
+        let _read = reader
+            .read_exact(&mut body)
+            .context(ErrorKind::SocketReceive)?;
+        let body = String::from_utf8(body).context(ErrorKind::RequestParse)?;
+        let (path, query) = match target.find('?') {
+            Some(end) => (target[..end].to_string(), target[end + 1..].to_string()),
+            None => (target, String::new()),
+        };
+        Ok(HttpRequest {
+            method,
+            path,
+            query,
+            headers,
+            body,
+        })
+    }
+
+    /// The value of the first header named `name`, in any case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, ref value)| value.as_str())
+    }
+
+    /// The values of the query parameter `name`, e.g. `ph`, and `alarm`, for `topic` in
+    /// `topic=ph&topic=alarm`.
+    pub fn query_values(&self, name: &str) -> Vec<&str> {
+        self.query
+            .split('&')
+            .filter_map(|pair| {
+                let mut pair = pair.splitn(2, '=');
+                match (pair.next(), pair.next()) {
+                    (Some(n), Some(value)) if n == name => Some(value),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// The body, as JSON. An empty body is `null`.
+    pub fn json(&self) -> Result<Value> {
+        if self.body.trim().is_empty() {
+            return Ok(Value::Null);
+        }
+        Ok(serde_json::from_str(&self.body).context(ErrorKind::RequestParse)?)
+    }
+}
+
+// Read a line of at most `MAX_LINE_SIZE` bytes.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> Result<usize> {
+    let read = reader
+        .by_ref()
+        .take(MAX_LINE_SIZE as u64 + 1)
+        .read_line(line)
+        .context(ErrorKind::SocketReceive)?;
+    if read > MAX_LINE_SIZE {
+        return Err(ErrorKind::RequestParse.into());
+    }
+    Ok(read)
+}
+
+/// An HTTP response, as written by the gateway.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl HttpResponse {
+    /// A JSON response.
+    pub fn json(status: u16, value: &Value) -> HttpResponse {
+        HttpResponse {
+            status,
+            content_type: "application/json",
+            body: value.to_string(),
+        }
+    }
+
+    /// A JSON response for an error, with the status of its `ErrorKind`.
+    pub fn error(e: &Error) -> HttpResponse {
+        let kind = e.kind();
+        let value = json!({
+            "error": format!("{}", e),
+            "kind": format!("{:?}", kind),
+        });
+        HttpResponse::json(status_code(&kind), &value)
+    }
+
+    /// Write the response, and close the connection.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let _write = write!(
+            writer,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            reason(self.status),
+            self.content_type,
+            self.body.len(),
+            self.body
+        ).context(ErrorKind::SocketSend)?;
+        let _flush = writer.flush().context(ErrorKind::SocketSend)?;
+        Ok(())
+    }
+}
+
+/// A sensor whose API is exposed by the gateway.
+pub trait SensorResource {
+    /// The kind of sensor.
+    fn kind(&self) -> SensorKind;
+    /// Handle a request for one of the sensor's resources, e.g. `GET reading`.
+    fn handle(&self, method: &str, resource: &str, body: &Value) -> Result<Value>;
+    /// Handle a request in the session of the holder of `token`, the sensor's session lock.
+    /// Sensors without a session lock handle it as any other request.
+    fn handle_as(&self, _token: &str, method: &str, resource: &str, body: &Value) -> Result<Value> {
+        self.handle(method, resource, body)
+    }
+}
+
+// The value of a reply, as a JSON number when it is one.
+fn json_value<T: ::std::fmt::Display>(reply: &T) -> Value {
+    let s = format!("{}", reply);
+    match s.parse::<f64>() {
+        Ok(n) => json!(n),
+        Err(_) => Value::String(s),
+    }
+}
+
+// An object with the comma-separated values of a reply, under the given names.
+fn json_fields<T: ::std::fmt::Display>(names: &[&str], reply: &T) -> Value {
+    let s = format!("{}", reply);
+    let mut map = Map::new();
+    for (name, value) in names.iter().zip(s.split(',')) {
+        let _insert = map.insert(name.to_string(), json_value(&value));
+    }
+    Value::Object(map)
+}
+
+fn json_status(reply: &ReplyStatus) -> Value {
+    json!({ "status": format!("{:?}", reply) })
+}
+
+// The `value` of a request body.
+fn body_value(body: &Value) -> Result<&Value> {
+    match body.get("value") {
+        Some(value) => Ok(value),
+        None => Err(ErrorKind::RequestParse.into()),
+    }
+}
+
+fn body_f64(body: &Value) -> Result<f64> {
+    match body_value(body)?.as_f64() {
+        Some(value) => Ok(value),
+        None => Err(ErrorKind::NumberParse.into()),
+    }
+}
+
+fn body_str(body: &Value) -> Result<&str> {
+    match body_value(body)?.as_str() {
+        Some(value) => Ok(value),
+        None => Err(ErrorKind::RequestParse.into()),
+    }
+}
+
+fn body_bool(body: &Value) -> Result<bool> {
+    match *body_value(body)? {
+        Value::Bool(value) => Ok(value),
+        Value::String(ref s) if s == "on" => Ok(true),
+        Value::String(ref s) if s == "off" => Ok(false),
+        _ => Err(ErrorKind::RequestParse.into()),
+    }
+}
+
+// The request that changes the calibration of a sensor, and needs its session lock, for
+// `method` on `resource`. Returns `None` for the other resources.
+fn calibration_request(
+    kind: SensorKind,
+    method: &str,
+    resource: &str,
+    body: &Value,
+) -> Result<Option<String>> {
+    let point = body.get("point").and_then(|p| p.as_str());
+    let request = match (kind, method, resource, point) {
+        (SensorKind::Temperature, _, _, _) => return Ok(None),
+        (_, "DELETE", "calibration", _) => SocketRequest::to_string(&CalibrationClear),
+        (_, "POST", "factory-reset", _) => SocketRequest::to_string(&Factory),
+        (_, "POST", "import", _) => SocketRequest::to_string(&Import(body_str(body)?.to_string())),
+        (SensorKind::Conductivity, "POST", "calibration", Some("dry")) => {
+            SocketRequest::to_string(&conductivity_requests::CalibrationDry)
+        }
+        (SensorKind::Conductivity, "POST", "calibration", Some("single")) => {
+            SocketRequest::to_string(&conductivity_requests::CalibrationOnePoint(body_f64(body)?))
+        }
+        (SensorKind::Conductivity, "POST", "calibration", Some("low")) => {
+            SocketRequest::to_string(&conductivity_requests::CalibrationLow(body_f64(body)?))
+        }
+        (SensorKind::Conductivity, "POST", "calibration", Some("high")) => {
+            SocketRequest::to_string(&conductivity_requests::CalibrationHigh(body_f64(body)?))
+        }
+        (SensorKind::Ph, "POST", "calibration", Some("low")) => {
+            SocketRequest::to_string(&ph_requests::CalibrationLow(body_f64(body)?))
+        }
+        (SensorKind::Ph, "POST", "calibration", Some("mid")) => {
+            SocketRequest::to_string(&ph_requests::CalibrationMid(body_f64(body)?))
+        }
+        (SensorKind::Ph, "POST", "calibration", Some("high")) => {
+            SocketRequest::to_string(&ph_requests::CalibrationHigh(body_f64(body)?))
+        }
+        (_, "POST", "calibration", _) => return Err(ErrorKind::RequestParse.into()),
+        _ => return Ok(None),
+    };
+    Ok(Some(request))
+}
+
+fn unknown(method: &str, resource: &str) -> Error {
+    ErrorKind::UnknownResource(format!("{} {}", method, resource)).into()
+}
+
+// Resources common to every EZO chip.
+fn chip_resource<T>(sensor: &T, method: &str, resource: &str, body: &Value) -> Result<Value>
+where
+    T: EzoChipAPI<SensorError = Error, SensorReply = ReplyStatus>,
+{
+    match (method, resource) {
+        ("GET", "info") => Ok(json_fields(
+            &["device", "firmware"],
+            &sensor.get_device_info()?,
+        )),
+        ("GET", "status") => Ok(json_fields(
+            &["restart_reason", "voltage"],
+            &sensor.get_device_status()?,
+        )),
+        ("DELETE", "calibration") => Ok(json_status(&sensor.set_calibration_clear()?)),
+        ("GET", "export") => Ok(json!({ "export": format!("{}", sensor.get_export_line()?) })),
+        ("GET", "export-info") => Ok(json_fields(&["lines", "bytes"], &sensor.get_export_info()?)),
+        ("POST", "import") => Ok(json_status(&sensor.set_import_line(body_str(body)?)?)),
+        ("POST", "address") => match body_value(body)?.as_u64() {
+            Some(address) if address <= u64::from(::std::u16::MAX) => {
+                Ok(json_status(&sensor.set_device_address(address as u16)?))
+            }
+            _ => Err(ErrorKind::AddressParse.into()),
+        },
+        ("POST", "factory-reset") => Ok(json_status(&sensor.set_factory_reset()?)),
+        ("POST", "find") => Ok(json_status(&sensor.set_find_mode()?)),
+        ("POST", "sleep") => Ok(json_status(&sensor.set_sleep()?)),
+        ("GET", "led") => Ok(json!({ "led": format!("{}", sensor.get_led_status()?) })),
+        ("POST", "led") => {
+            if body_bool(body)? {
+                Ok(json_status(&sensor.set_led_on()?))
+            } else {
+                Ok(json_status(&sensor.set_led_off()?))
+            }
+        }
+        ("GET", "protocol-lock") => Ok(json!({
+            "protocol_lock": format!("{}", sensor.get_protocol_lock_status()?)
+        })),
+        ("POST", "protocol-lock") => {
+            if body_bool(body)? {
+                Ok(json_status(&sensor.set_protocol_lock_on()?))
+            } else {
+                Ok(json_status(&sensor.set_protocol_lock_off()?))
+            }
+        }
+        _ => Err(unknown(method, resource)),
+    }
+}
+
+/// Exposes anything that implements the `ConductivityAPI`.
+///
+/// Readings are split by the current output parameters, e.g. `{"EC": 1.0, "TDS": 0.5}`.
+pub struct ConductivityResource<T>(pub T);
+
+impl<T> SensorResource for ConductivityResource<T>
+where
+    T: ConductivityAPI<Error = Error, DefaultReply = ReplyStatus>
+        + EzoChipAPI<SensorError = Error, SensorReply = ReplyStatus>,
+{
+    fn kind(&self) -> SensorKind {
+        SensorKind::Conductivity
+    }
+
+    fn handle(&self, method: &str, resource: &str, body: &Value) -> Result<Value> {
+        let sensor = &self.0;
+        match (method, resource) {
+            ("GET", "reading") => {
+                let params = format!("{}", sensor.get_output_params()?);
+                let names: Vec<&str> = params.split(',').collect();
+                Ok(json_fields(&names, &sensor.get_reading()?))
+            }
+            ("GET", "calibration") => Ok(json!({
+                "calibration": format!("{}", sensor.get_calibration_status()?)
+            })),
+            ("POST", "calibration") => {
+                let point = body.get("point").and_then(|p| p.as_str());
+                let reply = match point {
+                    Some("dry") => sensor.set_calibration_dry()?,
+                    Some("single") => sensor.set_calibration_single(body_f64(body)?)?,
+                    Some("low") => sensor.set_calibration_low(body_f64(body)?)?,
+                    Some("high") => sensor.set_calibration_high(body_f64(body)?)?,
+                    _ => return Err(ErrorKind::RequestParse.into()),
+                };
+                Ok(json_status(&reply))
+            }
+            ("GET", "compensation") => Ok(json!({
+                "compensation": json_value(&sensor.get_compensation()?)
+            })),
+            ("POST", "compensation") => Ok(json_status(&sensor.set_compensation(body_f64(body)?)?)),
+            ("GET", "output") => {
+                let params = format!("{}", sensor.get_output_params()?);
+                let names: Vec<&str> = params.split(',').filter(|p| !p.is_empty()).collect();
+                Ok(json!({ "output": names }))
+            }
+            ("POST", "output") => {
+                let param = body.get("param").and_then(|p| p.as_str());
+                let reply = match (param, body_bool(body)?) {
+                    (Some("EC"), true) => sensor.set_output_conductivity_on()?,
+                    (Some("EC"), false) => sensor.set_output_conductivity_off()?,
+                    (Some("TDS"), true) => sensor.set_output_tds_on()?,
+                    (Some("TDS"), false) => sensor.set_output_tds_off()?,
+                    (Some("S"), true) => sensor.set_output_salinity_on()?,
+                    (Some("S"), false) => sensor.set_output_salinity_off()?,
+                    (Some("SG"), true) => sensor.set_output_specific_gravity_on()?,
+                    (Some("SG"), false) => sensor.set_output_specific_gravity_off()?,
+                    _ => return Err(ErrorKind::RequestParse.into()),
+                };
+                Ok(json_status(&reply))
+            }
+            ("GET", "probe-type") => Ok(json!({
+                "probe_type": json_value(&sensor.get_probe_type_status()?)
+            })),
+            ("POST", "probe-type") => {
+                // Probe types are K 0.1, K 1.0 and K 10, in tenths.
+                let reply = match (body_f64(body)? * 10.0).round() as i64 {
+                    1 => sensor.set_probe_type_point_one()?,
+                    10 => sensor.set_probe_type_one()?,
+                    100 => sensor.set_probe_type_ten()?,
+                    _ => return Err(ErrorKind::RequestParse.into()),
+                };
+                Ok(json_status(&reply))
+            }
+            _ => chip_resource(sensor, method, resource, body),
+        }
+    }
+}
+
+/// Exposes anything that implements the `PhAPI`.
+pub struct PhResource<T>(pub T);
+
+impl<T> SensorResource for PhResource<T>
+where
+    T: PhAPI<Error = Error, DefaultReply = ReplyStatus>
+        + EzoChipAPI<SensorError = Error, SensorReply = ReplyStatus>,
+{
+    fn kind(&self) -> SensorKind {
+        SensorKind::Ph
+    }
+
+    fn handle(&self, method: &str, resource: &str, body: &Value) -> Result<Value> {
+        let sensor = &self.0;
+        match (method, resource) {
+            ("GET", "reading") => Ok(json!({ "pH": json_value(&sensor.get_reading()?) })),
+            ("GET", "calibration") => Ok(json!({
+                "calibration": format!("{}", sensor.get_calibration_status()?)
+            })),
+            ("POST", "calibration") => {
+                let point = body.get("point").and_then(|p| p.as_str());
+                let reply = match point {
+                    Some("low") => sensor.set_calibration_low(body_f64(body)?)?,
+                    Some("mid") => sensor.set_calibration_mid(body_f64(body)?)?,
+                    Some("high") => sensor.set_calibration_high(body_f64(body)?)?,
+                    _ => return Err(ErrorKind::RequestParse.into()),
+                };
+                Ok(json_status(&reply))
+            }
+            ("GET", "compensation") => Ok(json!({
+                "compensation": json_value(&sensor.get_compensation()?)
+            })),
+            ("POST", "compensation") => Ok(json_status(&sensor.set_compensation(body_f64(body)?)?)),
+            ("GET", "slope") => Ok(json_fields(&["acid", "base"], &sensor.get_slope()?)),
+            _ => chip_resource(sensor, method, resource, body),
+        }
+    }
+}
+
+/// Exposes anything that implements the `TemperatureAPI`.
+pub struct TemperatureResource<T>(pub T);
+
+impl<T> SensorResource for TemperatureResource<T>
+where
+    T: TemperatureAPI<Error = Error, DefaultReply = ReplyStatus>
+        + EzoChipAPI<SensorError = Error, SensorReply = ReplyStatus>,
+{
+    fn kind(&self) -> SensorKind {
+        SensorKind::Temperature
+    }
+
+    fn handle(&self, method: &str, resource: &str, body: &Value) -> Result<Value> {
+        let sensor = &self.0;
+        match (method, resource) {
+            ("GET", "reading") => {
+                let scale = sensor.get_scale()?;
+                Ok(json!({
+                    "temperature": json_value(&sensor.get_reading()?),
+                    "scale": format!("{}", scale),
+                }))
+            }
+            ("GET", "calibration") => Ok(json!({
+                "calibration": format!("{}", sensor.get_calibration_status()?)
+            })),
+            ("POST", "calibration") => Ok(json_status(
+                &sensor.set_calibration_temperature(body_f64(body)?)?,
+            )),
+            ("GET", "scale") => Ok(json!({ "scale": format!("{}", sensor.get_scale()?) })),
+            ("POST", "scale") => {
+                let reply = match body_str(body)? {
+                    "celsius" => sensor.set_scale_to_celsius()?,
+                    "fahrenheit" => sensor.set_scale_to_fahrenheit()?,
+                    "kelvin" => sensor.set_scale_to_kelvin()?,
+                    _ => return Err(ErrorKind::RequestParse.into()),
+                };
+                Ok(json_status(&reply))
+            }
+            ("GET", "data-logger") => Ok(json!({
+                "interval": json_value(&sensor.get_data_logger_status()?)
+            })),
+            ("POST", "data-logger") => match body_value(body)?.as_u64() {
+                Some(0) => Ok(json_status(&sensor.set_data_logger_off()?)),
+                Some(n) if n <= u64::from(::std::u32::MAX) => {
+                    Ok(json_status(&sensor.set_data_logger_interval(n as u32)?))
+                }
+                _ => Err(ErrorKind::NumberParse.into()),
+            },
+            ("GET", "memory") => Ok(json!({
+                "memory": json_value(&sensor.get_memory_recall_last()?)
+            })),
+            ("DELETE", "memory") => Ok(json_status(&sensor.set_memory_clear()?)),
+            _ => chip_resource(sensor, method, resource, body),
+        }
+    }
+}
+
+/// A sensor server, reached through a requester that waits up to a timeout for every reply.
+///
+/// Replies that time out yield `ErrorKind::ServiceTimeout`. The `REQ` socket can not send
+/// again until it gets a reply, so the requester is replaced after every timeout.
+///
+/// Requests that change the calibration, in the session of a token, are sent as
+/// `as <token> <request>` by a `SessionRequester` of their own.
+pub struct RemoteSensor {
+    context: Context,
+    kind: SensorKind,
+    url: String,
+    timeout: Duration,
+    resource: RefCell<Box<SensorResource>>,
+}
+
+impl RemoteSensor {
+    /// Connect to the server, at `url`, of a sensor of the given kind.
+    pub fn connect(
+        context: &Context,
+        kind: SensorKind,
+        url: &str,
+        timeout: Duration,
+    ) -> Result<RemoteSensor> {
+        let resource = requester_resource(context, kind, url, timeout)?;
+        Ok(RemoteSensor {
+            context: context.clone(),
+            kind,
+            url: url.to_string(),
+            timeout,
+            resource: RefCell::new(resource),
+        })
+    }
+}
+
+impl SensorResource for RemoteSensor {
+    fn kind(&self) -> SensorKind {
+        self.kind
+    }
+
+    fn handle(&self, method: &str, resource: &str, body: &Value) -> Result<Value> {
+        let result = self.resource.borrow().handle(method, resource, body);
+        if let Err(ref e) = result {
+            if e.kind() == ErrorKind::ServiceTimeout {
+                match requester_resource(&self.context, self.kind, &self.url, self.timeout) {
+                    Ok(requester) => *self.resource.borrow_mut() = requester,
+                    Err(e) => warn!("could not reconnect to {}: {}", self.url, e),
+                }
+            }
+        }
+        result
+    }
+
+    fn handle_as(&self, token: &str, method: &str, resource: &str, body: &Value) -> Result<Value> {
+        let request = match calibration_request(self.kind, method, resource, body)? {
+            Some(request) => request,
+            None => return self.handle(method, resource, body),
+        };
+        let socket = create_timed_requester(&self.context, &self.url, self.timeout)?;
+        let requester = SessionRequester::new(socket)?;
+        Ok(json_status(&requester.send_as(token, &request)?))
+    }
+}
+
+// A resource for a new timed requester, connected to `url`.
+fn requester_resource(
+    context: &Context,
+    kind: SensorKind,
+    url: &str,
+    timeout: Duration,
+) -> Result<Box<SensorResource>> {
+    let socket = create_timed_requester(context, url, timeout)?;
+    let resource: Box<SensorResource> = match kind {
+        SensorKind::Conductivity => {
+            Box::new(ConductivityResource(ConductivityRequester::new(socket)?))
+        }
+        SensorKind::Ph => Box::new(PhResource(PhRequester::new(socket)?)),
+        SensorKind::Temperature => {
+            Box::new(TemperatureResource(TemperatureRequester::new(socket)?))
+        }
+    };
+    Ok(resource)
+}
+
+/// HTTP server that exposes the API of its sensors as JSON resources.
+pub struct HttpGateway {
+    listener: TcpListener,
+    sensors: Vec<(String, Box<SensorResource>)>,
+    metrics: Arc<Mutex<Metrics>>,
+    hub: Arc<Mutex<StreamHub>>,
+}
+
+impl HttpGateway {
+    /// Create a new gateway listening on the given address, e.g. `127.0.0.1:8080`.
+    pub fn bind(addr: &str) -> Result<HttpGateway> {
+        let listener = TcpListener::bind(addr).context(ErrorKind::SocketBind)?;
+        Ok(HttpGateway {
+            listener,
+            sensors: Vec::new(),
+            metrics: Arc::new(Mutex::new(Metrics::new())),
+            hub: Arc::new(Mutex::new(StreamHub::new())),
+        })
+    }
+
+    /// Create a new gateway, with a `RemoteSensor` for each of its sensors, from
+    /// `GatewayConfig`.
+    ///
+    /// When a `sub_url` is configured, its publications are recorded as the latest values of
+    /// their sensors, and streamed to WebSocket clients. The status of the sensors is
+    /// refreshed by a `StatusRefresher`, in a new thread, with requesters of its own.
+    pub fn from_config(config: &GatewayConfig) -> Result<HttpGateway> {
+        let mut gateway = HttpGateway::bind(config.http_addr)?;
+        if let Some(sub_url) = config.sub_url {
+            let _thread = record_publications(gateway.metrics(), sub_url)?;
+            let _thread = stream_publications(gateway.stream_hub(), sub_url)?;
+        }
+        let context = Context::new();
+        let timeout = Duration::from_millis(REPLY_TIMEOUT_MS);
+        for sensor in &config.sensors {
+            let remote = RemoteSensor::connect(&context, sensor.kind, sensor.url, timeout)?;
+            gateway.add_sensor(sensor.name, remote);
+        }
+        let sensors: Vec<(String, SensorKind, String)> = config
+            .sensors
+            .iter()
+            .map(|s| (s.name.to_string(), s.kind, s.url.to_string()))
+            .collect();
+        let metrics = gateway.metrics();
+        let _thread = thread::spawn(move || {
+            let mut refresher = StatusRefresher::new(metrics);
+            for (name, kind, url) in sensors {
+                match RemoteSensor::connect(&context, kind, &url, timeout) {
+                    Ok(remote) => refresher.add_sensor(&name, remote),
+                    Err(e) => warn!("could not connect to {}: {}", url, e),
+                }
+            }
+            refresher.run(Duration::from_secs(STATUS_INTERVAL_SECS));
+        });
+        Ok(gateway)
+    }
+
+    /// The address the gateway is listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr().context(ErrorKind::SocketBind)?)
+    }
+
+    /// Expose a sensor under `/sensors/<name>`.
+    pub fn add_sensor<S>(&mut self, name: &str, sensor: S)
+    where
+        S: SensorResource + 'static,
+    {
+        self.sensors.push((name.to_string(), Box::new(sensor)));
+    }
+
+    /// Run the gateway. This will run for as long as the program runs.
+    pub fn run(&self) -> Result<()> {
+        loop {
+            if let Err(e) = self.serve_one() {
+                warn!("could not serve HTTP request: {}", e);
+            }
+        }
+    }
+
+    /// Accept one connection, and answer its request.
+    pub fn serve_one(&self) -> Result<()> {
+        let (stream, peer) = self.listener.accept().context(ErrorKind::SocketReceive)?;
+        let timeout = Duration::from_secs(IO_TIMEOUT_SECS);
+        let _timeout = stream
+            .set_read_timeout(Some(timeout))
+            .context(ErrorKind::SocketReceive)?;
+        let _timeout = stream
+            .set_write_timeout(Some(timeout))
+            .context(ErrorKind::SocketSend)?;
+        let request = HttpRequest::read_from(&mut BufReader::new(&stream));
+        let response = match request {
+            Ok(request) => {
+                debug!("{} {} from {}", &request.method, &request.path, peer);
+                if request.path == "/stream" && request.method == "GET" {
+                    return self.stream(stream, &request);
+                }
+                self.respond(&request)
+            }
+            Err(e) => HttpResponse::error(&e),
+        };
+        let mut stream: &TcpStream = &stream;
+        response.write_to(&mut stream)
+    }
+
+    // Upgrade a request to a WebSocket stream, served by its own thread.
+    fn stream(&self, stream: TcpStream, request: &HttpRequest) -> Result<()> {
+        let upgrade = request
+            .header("upgrade")
+            .map(|u| u.eq_ignore_ascii_case("websocket"))
+            .unwrap_or(false);
+        let key = match request.header("sec-websocket-key") {
+            Some(key) if upgrade => key.to_string(),
+            _ => {
+                let e: Error = ErrorKind::RequestParse.into();
+                let mut stream: &TcpStream = &stream;
+                return HttpResponse::error(&e).write_to(&mut stream);
+            }
+        };
+        let prefixes: Vec<String> = request
+            .query_values("topic")
+            .iter()
+            .map(|t| t.to_string())
+            .collect();
+        let hub = self.stream_hub();
+        let _thread = thread::spawn(move || {
+            if let Err(e) = serve_stream(stream, &key, &prefixes, &hub) {
+                warn!("could not stream to WebSocket client: {}", e);
+            }
+        });
+        Ok(())
+    }
+
+    /// The hub of the WebSocket streams, shared with anything that publishes to them.
+    pub fn stream_hub(&self) -> Arc<Mutex<StreamHub>> {
+        self.hub.clone()
+    }
+
+    /// The metrics of the gateway, shared with anything that records more of them.
+    pub fn metrics(&self) -> Arc<Mutex<Metrics>> {
+        self.metrics.clone()
+    }
+
+    /// Answer a request.
+    pub fn respond(&self, request: &HttpRequest) -> HttpResponse {
+        if request.path == "/metrics" && request.method == "GET" {
+            return self.scrape();
+        }
+        match self.route(request) {
+            Ok(value) => HttpResponse::json(200, &value),
+            Err(e) => {
+                if status_code(&e.kind()) >= 500 {
+                    error!("{} {} failed: {}", &request.method, &request.path, e);
+                }
+                HttpResponse::error(&e)
+            }
+        }
+    }
+
+    fn route(&self, request: &HttpRequest) -> Result<Value> {
+        let method = request.method.as_str();
+        let segments: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+        if segments.first() != Some(&"sensors") {
+            return Err(unknown(method, &request.path));
+        }
+        match segments.len() {
+            1 if method == "GET" => {
+                let sensors: Vec<Value> = self
+                    .sensors
+                    .iter()
+                    .map(|&(ref name, ref sensor)| {
+                        json!({ "name": name, "kind": kind_name(sensor.kind()) })
+                    })
+                    .collect();
+                Ok(json!({ "sensors": sensors }))
+            }
+            3 => {
+                let body = request.json()?;
+                let token = request
+                    .header(SESSION_HEADER)
+                    .or_else(|| request.query_values("session").first().cloned());
+                match self.sensors.iter().find(|&&(ref n, _)| n == segments[1]) {
+                    Some(&(ref name, ref sensor)) => {
+                        self.command(name, sensor.as_ref(), token, method, segments[2], &body)
+                    }
+                    None => Err(unknown(method, &request.path)),
+                }
+            }
+            _ => Err(unknown(method, &request.path)),
+        }
+    }
+
+    // Send a command to a sensor, in the session of `token`, if any, and record it in the
+    // metrics.
+    fn command(
+        &self,
+        name: &str,
+        sensor: &SensorResource,
+        token: Option<&str>,
+        method: &str,
+        resource: &str,
+        body: &Value,
+    ) -> Result<Value> {
+        let started = Instant::now();
+        let result = match token {
+            Some(token) => sensor.handle_as(token, method, resource, body),
+            None => sensor.handle(method, resource, body),
+        };
+        record_command(&self.metrics, name, method, resource, &result, started.elapsed());
+        result
+    }
+
+    // Render the metrics, as they were last recorded.
+    fn scrape(&self) -> HttpResponse {
+        HttpResponse {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body: lock_metrics(&self.metrics).render(&Utc::now()),
+        }
+    }
+}
+
+/// Requests the status of its sensors, and keeps it in the metrics of a gateway, so that
+/// scrapes of `/metrics` do not wait for the sensors.
+///
+/// Sensors are not shared with the gateway, e.g. a `RemoteSensor` of the refresher has a
+/// requester of its own.
+pub struct StatusRefresher {
+    sensors: Vec<(String, Box<SensorResource>)>,
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+impl StatusRefresher {
+    /// Create a new refresher, that keeps the status of its sensors in `metrics`.
+    pub fn new(metrics: Arc<Mutex<Metrics>>) -> StatusRefresher {
+        StatusRefresher {
+            sensors: Vec::new(),
+            metrics,
+        }
+    }
+
+    /// Refresh the status of a sensor, under the name it has in the gateway.
+    pub fn add_sensor<S>(&mut self, name: &str, sensor: S)
+    where
+        S: SensorResource + 'static,
+    {
+        self.sensors.push((name.to_string(), Box::new(sensor)));
+    }
+
+    /// Request the status of every sensor, once.
+    pub fn refresh(&self) {
+        for &(ref name, ref sensor) in &self.sensors {
+            let started = Instant::now();
+            let result = sensor.handle("GET", "status", &Value::Null);
+            record_command(&self.metrics, name, "GET", "status", &result, started.elapsed());
+            if let Err(e) = result {
+                warn!("could not get the status of {}: {}", name, e);
+            }
+        }
+    }
+
+    /// Request the status of every sensor, every `interval`. This will run for as long as
+    /// the program runs.
+    pub fn run(&self, interval: Duration) {
+        loop {
+            self.refresh();
+            thread::sleep(interval);
+        }
+    }
+}
+
+fn lock_metrics(metrics: &Mutex<Metrics>) -> MutexGuard<Metrics> {
+    match metrics.lock() {
+        Ok(metrics) => metrics,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+// Record a command sent to a sensor, and the values of its reply, in the metrics.
+fn record_command(
+    metrics: &Mutex<Metrics>,
+    name: &str,
+    method: &str,
+    resource: &str,
+    result: &Result<Value>,
+    elapsed: Duration,
+) {
+    let kind = result.as_ref().err().map(|e| e.kind());
+    // Unknown resources are not commands.
+    if let Some(ErrorKind::UnknownResource(_)) = kind {
+        return;
+    }
+    let mut metrics = lock_metrics(metrics);
+    metrics.observe_command(name, method, resource, kind.as_ref(), elapsed);
+    if let Ok(ref value) = *result {
+        match (method, resource) {
+            ("GET", "reading") => observe_reading(&mut metrics, name, value),
+            ("GET", "status") => {
+                let reason = value.get("restart_reason").and_then(|r| r.as_str());
+                let voltage = value.get("voltage").and_then(|v| v.as_f64());
+                if let (Some(reason), Some(voltage)) = (reason, voltage) {
+                    metrics.observe_device_status(name, reason, voltage);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Keep the values of a reading as the latest values of the sensor.
+fn observe_reading(metrics: &mut Metrics, sensor: &str, reading: &Value) {
+    let fields = match reading.as_object() {
+        Some(fields) => fields,
+        None => return,
+    };
+    let now = Utc::now();
+    for (field, value) in fields {
+        let value = match value.as_f64() {
+            Some(value) => value,
+            None => continue,
+        };
+        let (sensor_type, unit) = match field.as_str() {
+            "pH" => (SensorType::Ph, SensorType::Ph.unit()),
+            "temperature" => {
+                let scale = reading.get("scale").and_then(|s| s.as_str());
+                (SensorType::Temperature, scale.unwrap_or("celsius"))
+            }
+            param => match SensorType::from_output_param(param) {
+                Ok(sensor_type) => (sensor_type, sensor_type.unit()),
+                Err(_) => continue,
+            },
+        };
+        metrics.observe_value(sensor, sensor_type.name(), unit, value, now);
+    }
+}
+
+fn kind_name(kind: SensorKind) -> &'static str {
+    match kind {
+        SensorKind::Conductivity => "conductivity",
+        SensorKind::Ph => "ph",
+        SensorKind::Temperature => "temperature",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use network::websocket;
+    use std::io::Cursor;
+
+    // A sensor with a single `reading` resource.
+    struct FakeSensor;
+
+    impl SensorResource for FakeSensor {
+        fn kind(&self) -> SensorKind {
+            SensorKind::Ph
+        }
+
+        fn handle(&self, method: &str, resource: &str, body: &Value) -> Result<Value> {
+            match (method, resource) {
+                ("GET", "reading") => Ok(json!({ "pH": 7.012 })),
+                ("POST", "compensation") => {
+                    let _value = body_f64(body)?;
+                    Ok(json_status(&ReplyStatus::Ok))
+                }
+                ("GET", "status") => Err(ErrorKind::SensorTrouble.into()),
+                _ => Err(unknown(method, resource)),
+            }
+        }
+    }
+
+    fn gateway() -> HttpGateway {
+        let mut gateway = HttpGateway::bind("127.0.0.1:0").unwrap();
+        gateway.add_sensor("ph1", FakeSensor);
+        gateway
+    }
+
+    #[test]
+    fn read_http_requests() {
+        let raw = "POST /sensors/rtd1/scale?verbose=1 HTTP/1.1\r\nHost: localhost\r\n\
+                   content-length: 19\r\n\r\n{\"value\": \"kelvin\"}";
+        let request = HttpRequest::read_from(&mut Cursor::new(raw)).unwrap();
+        assert_eq!("POST", request.method);
+        assert_eq!("/sensors/rtd1/scale", request.path);
+        assert_eq!(vec!["1"], request.query_values("verbose"));
+        assert_eq!(Some("localhost"), request.header("Host"));
+        assert_eq!(json!({ "value": "kelvin" }), request.json().unwrap());
+
+        let request = HttpRequest::read_from(&mut Cursor::new("GET / HTTP/1.0\r\n\r\n")).unwrap();
+        assert_eq!(Value::Null, request.json().unwrap());
+
+        for raw in &[
+            "GET /\r\n\r\n",
+            "GET / HTTP/1.1\r\n",
+            "GET / HTTP/1.1\r\nContent-Length: x\r\n\r\n",
+        ] {
+            assert!(HttpRequest::read_from(&mut Cursor::new(*raw)).is_err());
+        }
+
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_SIZE));
+        assert!(HttpRequest::read_from(&mut Cursor::new(long)).is_err());
+        let many = format!("GET / HTTP/1.1\r\n{}\r\n", "X-A: b\r\n".repeat(MAX_HEADERS + 1));
+        assert!(HttpRequest::read_from(&mut Cursor::new(many)).is_err());
+    }
+
+    #[test]
+    fn map_error_kinds_to_status_codes() {
+        assert_eq!(400, status_code(&ErrorKind::RequestParse));
+        assert_eq!(400, status_code(&ErrorKind::NumberParse));
+        assert_eq!(
+            404,
+            status_code(&ErrorKind::UnknownResource("x".to_string()))
+        );
+        assert_eq!(502, status_code(&ErrorKind::SensorTrouble));
+        assert_eq!(502, status_code(&ErrorKind::SocketReceive));
+        assert_eq!(
+            409,
+            status_code(&ErrorKind::SessionLocked("ana".to_string()))
+        );
+        assert_eq!(428, status_code(&ErrorKind::SessionRequired));
+        assert_eq!(504, status_code(&ErrorKind::ServiceTimeout));
+        assert_eq!(500, status_code(&ErrorKind::LogStorage));
+    }
+
+    #[test]
+    fn route_requests_to_sensor_resources() {
+        let gateway = gateway();
+        let response = gateway.respond(&HttpRequest::new("GET", "/sensors/ph1/reading", ""));
+        assert_eq!(200, response.status);
+        assert_eq!(r#"{"pH":7.012}"#, response.body);
+
+        let response = gateway.respond(&HttpRequest::new("GET", "/sensors", ""));
+        assert_eq!(r#"{"sensors":[{"kind":"ph","name":"ph1"}]}"#, response.body);
+
+        let post = |body: &str| {
+            gateway
+                .respond(&HttpRequest::new("POST", "/sensors/ph1/compensation", body))
+                .status
+        };
+        assert_eq!(200, post(r#"{"value": 25.0}"#));
+        assert_eq!(400, post(r#"{"value": "warm"}"#));
+        assert_eq!(400, post("{"));
+
+        let status =
+            |method: &str, path: &str| gateway.respond(&HttpRequest::new(method, path, "")).status;
+        assert_eq!(404, status("GET", "/sensors/ec1/reading"));
+        assert_eq!(404, status("DELETE", "/sensors/ph1/reading"));
+        assert_eq!(404, status("GET", "/"));
+        assert_eq!(502, status("GET", "/sensors/ph1/status"));
+    }
+
+    #[test]
+    fn scrape_metrics_of_sensor_commands() {
+        let gateway = gateway();
+        let _reading = gateway.respond(&HttpRequest::new("GET", "/sensors/ph1/reading", ""));
+        let _unknown = gateway.respond(&HttpRequest::new("GET", "/sensors/ph1/slope", ""));
+        let mut refresher = StatusRefresher::new(gateway.metrics());
+        refresher.add_sensor("ph1", FakeSensor);
+        refresher.refresh();
+
+        let response = gateway.respond(&HttpRequest::new("GET", "/metrics", ""));
+        assert_eq!(200, response.status);
+        assert_eq!("text/plain; version=0.0.4", response.content_type);
+        let expected = [
+            "benita_sensor_value{sensor=\"ph1\",quantity=\"ph\",unit=\"pH\"} 7.012",
+            "benita_commands_total{sensor=\"ph1\",method=\"GET\",command=\"reading\"} 1",
+            "benita_commands_total{sensor=\"ph1\",method=\"GET\",command=\"status\"} 1",
+            "benita_command_failures_total{sensor=\"ph1\",method=\"GET\",command=\"status\",kind=\"SensorTrouble\"} 1",
+            "benita_command_duration_seconds_count{sensor=\"ph1\"} 2",
+        ];
+        for line in &expected {
+            assert!(response.body.contains(line), "missing: {}", line);
+        }
+        assert!(!response.body.contains("slope"));
+    }
+
+    #[test]
+    fn serve_requests_on_the_loopback_interface() {
+        let gateway = gateway();
+        let addr = gateway.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(b"GET /sensors/ph1/reading HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        gateway.serve_one().unwrap();
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: application/json\r\n"));
+        assert!(response.ends_with("\r\n\r\n{\"pH\":7.012}"));
+    }
+
+    #[test]
+    fn time_out_requests_to_unreachable_sensors() {
+        let context = Context::new();
+        let mut gateway = HttpGateway::bind("127.0.0.1:0").unwrap();
+        // Nothing is bound to the endpoint, so requests are never answered.
+        let timeout = Duration::from_millis(50);
+        let sensor =
+            RemoteSensor::connect(&context, SensorKind::Ph, "inproc://unbound", timeout).unwrap();
+        gateway.add_sensor("ph1", sensor);
+
+        let status = |path: &str| gateway.respond(&HttpRequest::new("GET", path, "")).status;
+        assert_eq!(504, status("/sensors/ph1/reading"));
+        // The requester was replaced, so it can send again.
+        assert_eq!(504, status("/sensors/ph1/reading"));
+        // Scrapes do not request anything from the sensors.
+        let response = gateway.respond(&HttpRequest::new("GET", "/metrics", ""));
+        assert_eq!(200, response.status);
+        assert!(response.body.contains("command=\"reading\",kind=\"ServiceTimeout\"} 2"));
+        assert!(!response.body.contains("command=\"status\""));
+    }
+
+    #[test]
+    fn send_calibration_requests_in_the_session_of_their_token() {
+        use network::session::CalibrationLock;
+
+        let context = Context::new();
+        let responder = context.socket(::zmq::REP).unwrap();
+        responder.bind("inproc://http-calibration").unwrap();
+        // Answers every request like a pH server, with a session lock, does.
+        let server = thread::spawn(move || {
+            let lock = CalibrationLock::new();
+            let mut answered = Vec::new();
+            for _ in 0..5 {
+                let req_str = responder.recv_string(0).unwrap().unwrap();
+                let guarded = |r: &str| r.starts_with("calibration-");
+                let reply = lock
+                    .respond(&req_str, guarded, |r| {
+                        answered.push(r.to_string());
+                        Ok("ok".to_string())
+                    })
+                    .unwrap();
+                responder.send(reply.as_bytes(), 0).unwrap();
+            }
+            answered
+        });
+        let timeout = Duration::from_millis(REPLY_TIMEOUT_MS);
+        let url = "inproc://http-calibration";
+        let mut gateway = HttpGateway::bind("127.0.0.1:0").unwrap();
+        let sensor = RemoteSensor::connect(&context, SensorKind::Ph, url, timeout).unwrap();
+        gateway.add_sensor("ph1", sensor);
+        let clear = HttpRequest::new("DELETE", "/sensors/ph1/calibration", "");
+
+        let response = gateway.respond(&clear);
+        assert_eq!(428, response.status);
+        assert!(response.body.contains("SessionRequired"));
+
+        let session = create_timed_requester(&context, url, timeout).unwrap();
+        let token = SessionRequester::new(session)
+            .unwrap()
+            .lock("ana", None)
+            .unwrap()
+            .token;
+
+        let mut as_ana = clear.clone();
+        as_ana.headers.push((SESSION_HEADER.to_string(), "ana".to_string()));
+        assert_eq!(409, gateway.respond(&as_ana).status);
+
+        let mut as_ana = HttpRequest::new(
+            "POST",
+            "/sensors/ph1/calibration",
+            r#"{"point": "mid", "value": 7.0}"#,
+        );
+        as_ana.query = format!("session={}", token);
+        let response = gateway.respond(&as_ana);
+        assert_eq!(200, response.status);
+        assert_eq!(r#"{"status":"Ok"}"#, response.body);
+        assert_eq!(409, gateway.respond(&clear).status);
+
+        assert_eq!(vec!["calibration-mid 7.000"], server.join().unwrap());
+    }
+
+    #[test]
+    fn stream_publications_over_websockets() {
+        let gateway = gateway();
+        let addr = gateway.local_addr().unwrap();
+        let measurement = json!({"type": "measurement", "value": 7.012});
+        gateway
+            .stream_hub()
+            .lock()
+            .unwrap()
+            .publish("ph-01", &measurement);
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(
+                    b"GET /stream?topic=ph HTTP/1.1\r\nHost: localhost\r\n\
+                      Upgrade: websocket\r\nConnection: Upgrade\r\n\
+                      Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+                )
+                .unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") {
+                reader.read_line(&mut head).unwrap();
+            }
+            let (_, latest) = websocket::read_frame(&mut reader).unwrap();
+            (head, String::from_utf8(latest).unwrap())
+        });
+        gateway.serve_one().unwrap();
+        let (head, latest) = client.join().unwrap();
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+        assert_eq!(r#"{"type":"measurement","value":7.012}"#, latest);
+
+        // Requests that do not upgrade are rejected.
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .write_all(b"GET /stream HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        gateway.serve_one().unwrap();
+        assert!(client
+            .join()
+            .unwrap()
+            .starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+}
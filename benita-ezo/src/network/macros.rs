@@ -37,6 +37,29 @@ macro_rules! network_sensor_socket {
             }
         }
 
+        endpoint_trait_impl!($name);
+    };
+    // Sensor socket with a session lock that guards the calibration of the sensor.
+    ($name:ident, $sensor:ident, $doc:tt, session) => {
+        #[ doc = $doc ]
+        pub struct $name {
+            socket: Socket,
+            pub sensor: $sensor,
+            /// The session lock of the calibration of the sensor.
+            pub session: CalibrationLock,
+        }
+
+        impl $name {
+            /// Create a new network socket.
+            pub fn new(socket: Socket, sensor: $sensor) -> Result<$name> {
+                Ok($name {
+                    socket,
+                    sensor,
+                    session: CalibrationLock::new(),
+                })
+            }
+        }
+
         endpoint_trait_impl!($name);
     };
 }
@@ -66,10 +89,12 @@ macro_rules! endpoint_trait_impl {
 
             /// Receives a message from the network socket.
             fn recv(&self) -> Result<String> {
-                let received_result = self
-                    .socket
-                    .recv_string(0)
-                    .context(ErrorKind::SocketReceive)?;
+                let received_result = match self.socket.recv_string(0) {
+                    Ok(received) => received,
+                    // The receive timeout of a timed requester ran out.
+                    Err(::zmq::Error::EAGAIN) => return Err(ErrorKind::ServiceTimeout.into()),
+                    Err(e) => Err(e).context(ErrorKind::SocketReceive)?,
+                };
                 // We match against the resulting `Resulṭ<String, Vec<u8>>`
                 let response = match received_result {
                     Ok(r) => r,
@@ -77,6 +102,11 @@ macro_rules! endpoint_trait_impl {
                 };
                 Ok(response)
             }
+
+            /// Waits for a message to arrive on the network socket.
+            fn poll(&self, timeout: ::std::time::Duration) -> Result<bool> {
+                ::utilities::poll_readable(&self.socket, timeout)
+            }
         }
     };
 }
@@ -253,6 +283,79 @@ macro_rules! sensor_socket_commands {
     };
 }
 
+// Evaluate a request string with the first request type that parses it, and return the text
+// form of its reply. The requests common to every EZO chip are evaluated on `$endpoint`,
+// along with the given ones, listed as `Request => call`, or `Request(field) => call`.
+macro_rules! respond_to_requests {
+    ( @dispatch $req_str:expr, { $( $request:ident $( ( $field:ident ) )* => $call:expr ),* $(,)* } ) => {{
+        $(
+            if let Ok(request) = <$request as SocketRequest>::from_str($req_str) {
+                let $request $( ( $field ) )* = request;
+                let reply = $call?;
+                return Ok(<_ as SocketReply>::to_string(&reply));
+            }
+        )*
+        Err(ErrorKind::RequestParse.into())
+    }};
+    ( $endpoint:expr, $req_str:expr, { $( $request:ident $( ( $field:ident ) )* => $call:expr ),* $(,)* } ) => {
+        respond_to_requests!(@dispatch $req_str, {
+            CalibrationClear => $endpoint.set_calibration_clear(),
+            DeviceAddress(address) => $endpoint.set_device_address(address),
+            DeviceInformation => $endpoint.get_device_info(),
+            Export => $endpoint.get_export_line(),
+            ExportInfo => $endpoint.get_export_info(),
+            Factory => $endpoint.set_factory_reset(),
+            Find => $endpoint.set_find_mode(),
+            Import(line) => $endpoint.set_import_line(&line),
+            LedOff => $endpoint.set_led_off(),
+            LedOn => $endpoint.set_led_on(),
+            LedState => $endpoint.get_led_status(),
+            ProtocolLockDisable => $endpoint.set_protocol_lock_off(),
+            ProtocolLockEnable => $endpoint.set_protocol_lock_on(),
+            ProtocolLockState => $endpoint.get_protocol_lock_status(),
+            Sleep => $endpoint.set_sleep(),
+            Status => $endpoint.get_device_status(),
+            $( $request $( ( $field ) )* => $call ),*
+        })
+    };
+}
+
+// Send a request string as the first request type that parses it, and return the debug form
+// of its typed reply. The requests common to every EZO chip are tried along with the given
+// ones.
+macro_rules! request_typed_reply {
+    ( @dispatch $endpoint:expr, $req_str:expr, [ $( $request:ident ),* $(,)* ] ) => {{
+        $(
+            if let Ok(request) = <$request as SocketRequest>::from_str($req_str) {
+                let reply = request.send($endpoint)?;
+                return Ok(format!("{:?}", reply));
+            }
+        )*
+        Err(ErrorKind::RequestParse.into())
+    }};
+    ( $endpoint:expr, $req_str:expr, [ $( $request:ident ),* $(,)* ] ) => {
+        request_typed_reply!(@dispatch $endpoint, $req_str, [
+            CalibrationClear,
+            DeviceAddress,
+            DeviceInformation,
+            Export,
+            ExportInfo,
+            Factory,
+            Find,
+            Import,
+            LedOff,
+            LedOn,
+            LedState,
+            ProtocolLockDisable,
+            ProtocolLockEnable,
+            ProtocolLockState,
+            Sleep,
+            Status,
+            $( $request ),*
+        ])
+    };
+}
+
 // Implements SocketRequest for commands
 #[macro_export]
 macro_rules! impl_SocketRequest_for {
@@ -307,6 +410,9 @@ macro_rules! impl_SocketReply_for {
             fn recv<T: Endpoint>(endpoint: &T) -> Result<$name> {
                 let rep_string = endpoint.recv()?;
                 debug!("received socket reply string: {:?}", &rep_string);
+                if let Some(refused) = ::network::session::refusal(&rep_string) {
+                    return Err(refused);
+                }
                 let response = <$name as SocketReply>::from_str(&rep_string)?;
                 debug!("parsed socket reply: {:?}", &response);
                 Ok(response)
@@ -5,6 +5,17 @@ mod traits;
 // Common network items
 mod common;
 
+pub mod alarm;
+pub mod broker;
+pub mod history;
+pub mod http;
+pub mod mqtt;
+pub mod publisher;
+pub mod session;
+pub mod state;
+pub mod subscriber;
+pub mod websocket;
+
 pub use conductivity::network as conductivity;
 pub use ph::network as ph;
 pub use temperature::network as temperature;
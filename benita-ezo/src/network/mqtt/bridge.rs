@@ -0,0 +1,504 @@
+//! Bridge between the sensor network, and an MQTT broker.
+use std::time::Duration;
+
+use super::discovery::{
+    discovery_messages, ConductivityDiscovery, Discoverable, PhDiscovery, TemperatureDiscovery,
+};
+use super::{MqttClient, MqttMessage, MqttTransport, QoS};
+use conductivity::network::ConductivityRequester;
+use config::{MqttConfig, SensorKind};
+use errors::*;
+use measurement::{Measurement, SensorType};
+use network::subscriber::Subscriber;
+use network::Endpoint;
+use ph::network::PhRequester;
+use temperature::network::TemperatureRequester;
+
+use chrono::SecondsFormat;
+use zmq::{self, Context, Socket};
+
+/// Prefix of every topic, unless configured otherwise.
+pub const DEFAULT_PREFIX: &str = "benita";
+/// Keep alive interval, in seconds, unless configured otherwise.
+pub const DEFAULT_KEEP_ALIVE: u16 = 60;
+/// Time allowed for a command server to reply, in milliseconds.
+pub const REPLY_TIMEOUT_MS: i64 = 5_000;
+
+// Command server of a sensor, reachable through the bridge.
+struct BridgedSensor {
+    name: String,
+    url: String,
+    socket: Socket,
+}
+
+// Sensor announced to Home Assistant.
+struct DiscoveredSensor {
+    name: String,
+    topic: String,
+    sensor: Box<Discoverable>,
+}
+
+/// Republishes measurements to MQTT, and forwards MQTT commands to sensor command servers.
+pub struct MqttBridge<T: MqttTransport> {
+    transport: T,
+    prefix: String,
+    qos: QoS,
+    retain: bool,
+    context: Context,
+    subscriber: Option<Subscriber>,
+    sensors: Vec<BridgedSensor>,
+    discovery_prefix: Option<String>,
+    discovered: Vec<DiscoveredSensor>,
+}
+
+impl<T: MqttTransport> MqttBridge<T> {
+    /// Create a new bridge, with topics under `prefix`.
+    pub fn new(transport: T, prefix: &str) -> MqttBridge<T> {
+        MqttBridge {
+            transport,
+            prefix: prefix.trim_right_matches('/').to_string(),
+            qos: QoS::default(),
+            retain: false,
+            context: Context::new(),
+            subscriber: None,
+            sensors: Vec::new(),
+            discovery_prefix: None,
+            discovered: Vec::new(),
+        }
+    }
+
+    /// Set the QoS of the publications, and command subscriptions.
+    pub fn set_qos(&mut self, qos: QoS) {
+        self.qos = qos;
+    }
+
+    /// Set whether measurements are retained by the broker.
+    pub fn set_retain(&mut self, retain: bool) {
+        self.retain = retain;
+    }
+
+    /// Republish the measurements received by `subscriber`.
+    pub fn set_subscriber(&mut self, subscriber: Subscriber) {
+        self.subscriber = Some(subscriber);
+    }
+
+    /// The connection to the broker.
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Accept requests for the command server at `url` on `<prefix>/<name>/command`.
+    pub fn add_sensor(&mut self, name: &str, url: &str) -> Result<()> {
+        let socket = self.requester(url)?;
+        let filter = self.command_topic(name);
+        let _subscribe = self.transport.subscribe(&filter, self.qos)?;
+        self.sensors.push(BridgedSensor {
+            name: name.to_string(),
+            url: url.to_string(),
+            socket,
+        });
+        Ok(())
+    }
+
+    /// Announce the sensors added with `add_discoverable` to Home Assistant, under
+    /// `prefix`. They are announced again whenever Home Assistant comes online.
+    pub fn set_discovery_prefix(&mut self, prefix: &str) -> Result<()> {
+        let prefix = prefix.trim_right_matches('/').to_string();
+        let _subscribe = self
+            .transport
+            .subscribe(&format!("{}/status", prefix), self.qos)?;
+        self.discovery_prefix = Some(prefix);
+        Ok(())
+    }
+
+    /// Announce the sensor `name`, which publishes measurements on `topic`, i.e.
+    /// `<type>-<id>`, to Home Assistant.
+    pub fn add_discoverable<D>(&mut self, name: &str, topic: &str, sensor: D)
+    where
+        D: Discoverable + 'static,
+    {
+        self.discovered.push(DiscoveredSensor {
+            name: name.to_string(),
+            topic: topic.to_string(),
+            sensor: Box::new(sensor),
+        });
+    }
+
+    /// Publish the Home Assistant config messages of every discoverable sensor. Sensors that
+    /// fail to reply are skipped. Returns the number of messages published.
+    pub fn publish_discovery(&mut self) -> Result<usize> {
+        let discovery_prefix = match self.discovery_prefix {
+            Some(ref prefix) => prefix.clone(),
+            None => return Ok(0),
+        };
+        let mut messages = Vec::new();
+        for discovered in &self.discovered {
+            match discovery_messages(
+                &*discovered.sensor,
+                &discovered.name,
+                &discovered.topic,
+                &self.prefix,
+                &discovery_prefix,
+                self.qos,
+            ) {
+                Ok(sensor_messages) => messages.extend(sensor_messages),
+                Err(e) => warn!("could not announce {}: {}", discovered.name, e),
+            }
+        }
+        for message in &messages {
+            let _publish = self.transport.publish(message)?;
+        }
+        Ok(messages.len())
+    }
+
+    fn requester(&self, url: &str) -> Result<Socket> {
+        let socket = self
+            .context
+            .socket(zmq::REQ)
+            .context(ErrorKind::SocketCreate)?;
+        let _linger = socket.set_linger(0).context(ErrorKind::SocketCreate)?;
+        let _connect = socket.connect(url).context(ErrorKind::SocketConnect)?;
+        Ok(socket)
+    }
+
+    /// The topic for the measurements of a sensor, `<prefix>/<type>-<id>`.
+    pub fn measurement_topic(&self, measurement: &Measurement) -> String {
+        format!("{}/{}", self.prefix, measurement.topic())
+    }
+
+    /// The topic for the requests to a sensor, `<prefix>/<name>/command`.
+    pub fn command_topic(&self, name: &str) -> String {
+        format!("{}/{}/command", self.prefix, name)
+    }
+
+    /// The topic for the replies of a sensor, `<prefix>/<name>/response`.
+    pub fn response_topic(&self, name: &str) -> String {
+        format!("{}/{}/response", self.prefix, name)
+    }
+
+    /// Publish a measurement, as JSON.
+    pub fn publish_measurement(&mut self, measurement: &Measurement) -> Result<()> {
+        let payload = json!({
+            "timestamp": measurement.timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            "value": measurement.value_f64(),
+            "unit": measurement.sensor_type.unit(),
+        });
+        let topic = self.measurement_topic(measurement);
+        let message = MqttMessage::new(
+            &topic,
+            payload.to_string().as_bytes(),
+            self.qos,
+            self.retain,
+        );
+        self.transport.publish(&message)
+    }
+
+    /// Handle a message received from the broker. Requests on a command topic are sent to
+    /// the sensor's command server, and its reply, or `err`, is published on the response
+    /// topic. Home Assistant coming online triggers the discovery messages. Returns `false`
+    /// for messages on other topics.
+    pub fn handle_message(&mut self, message: &MqttMessage) -> Result<bool> {
+        let online = match self.discovery_prefix {
+            Some(ref prefix) => message.topic == format!("{}/status", prefix),
+            None => false,
+        };
+        if online {
+            if message.payload == b"online" {
+                let _count = self.publish_discovery()?;
+            }
+            return Ok(true);
+        }
+        let index = match self
+            .sensors
+            .iter()
+            .position(|s| message.topic == self.command_topic(&s.name))
+        {
+            Some(index) => index,
+            None => return Ok(false),
+        };
+        let reply = match String::from_utf8(message.payload.clone()) {
+            Ok(request) => match self.request(index, request.trim()) {
+                Ok(reply) => reply,
+                Err(e) => {
+                    warn!(
+                        "request {:?} to {} failed: {}",
+                        request, self.sensors[index].name, e
+                    );
+                    "err".to_string()
+                }
+            },
+            Err(_) => "err".to_string(),
+        };
+        let topic = self.response_topic(&self.sensors[index].name);
+        let response = MqttMessage::new(&topic, reply.as_bytes(), self.qos, false);
+        let _publish = self.transport.publish(&response)?;
+        Ok(true)
+    }
+
+    // Send a request string to a sensor's command server, and return its reply string.
+    fn request(&mut self, index: usize, request: &str) -> Result<String> {
+        let _send = self.sensors[index]
+            .socket
+            .send(request.as_bytes(), 0)
+            .context(ErrorKind::SocketSend)?;
+        let readable = {
+            let mut items = [self.sensors[index].socket.as_poll_item(zmq::POLLIN)];
+            let _poll =
+                zmq::poll(&mut items, REPLY_TIMEOUT_MS).context(ErrorKind::SocketReceive)?;
+            items[0].is_readable()
+        };
+        if !readable {
+            // The `REQ` socket can not send again until it gets a reply, so start over.
+            let socket = self.requester(&self.sensors[index].url)?;
+            self.sensors[index].socket = socket;
+            return Err(ErrorKind::ServiceTimeout.into());
+        }
+        match self.sensors[index]
+            .socket
+            .recv_string(0)
+            .context(ErrorKind::SocketReceive)?
+        {
+            Ok(reply) => Ok(reply),
+            Err(_) => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
+
+    /// Republish the pending measurements, and handle the commands received within
+    /// `timeout`. Returns the number of messages bridged.
+    pub fn step(&mut self, timeout: Duration) -> Result<usize> {
+        let mut count = 0;
+        let mut measurements = Vec::new();
+        if let Some(ref mut subscriber) = self.subscriber {
+            while subscriber.poll(Duration::from_millis(0))? {
+                match subscriber.recv_measurements() {
+                    Ok(received) => measurements.extend(received),
+                    Err(e) => warn!("skipping publication: {}", e),
+                }
+            }
+        }
+        // Publications that fail, e.g. while the client reconnects to the broker, are
+        // dropped, so that the bridge outlasts the broker going down.
+        for measurement in &measurements {
+            match self.publish_measurement(measurement) {
+                Ok(()) => count += 1,
+                Err(e) => warn!("dropping measurement {}: {}", measurement, e),
+            }
+        }
+        if let Some(message) = self.transport.recv(timeout)? {
+            match self.handle_message(&message) {
+                Ok(true) => count += 1,
+                Ok(false) => {}
+                Err(e) => warn!("dropping message on {}: {}", message.topic, e),
+            }
+        }
+        Ok(count)
+    }
+
+    /// Run the bridge, forever.
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            let _count = self.step(Duration::from_millis(100))?;
+        }
+    }
+}
+
+impl MqttBridge<MqttClient> {
+    /// Connect to the broker, the publisher, and the command servers in `config`.
+    pub fn from_config(config: &MqttConfig) -> Result<MqttBridge<MqttClient>> {
+        let client = MqttClient::connect(
+            config.broker_addr,
+            config.client_id,
+            config.keep_alive.unwrap_or(DEFAULT_KEEP_ALIVE),
+            config.username,
+            config.password,
+        )?;
+        let mut bridge = MqttBridge::new(client, config.topic_prefix.unwrap_or(DEFAULT_PREFIX));
+        bridge.set_qos(QoS::from_u8(config.qos)?);
+        bridge.set_retain(config.retain);
+        if let Some(sub_url) = config.sub_url {
+            let topics: Vec<&str> = SensorType::all().iter().map(|t| t.name()).collect();
+            bridge.set_subscriber(Subscriber::connect(sub_url, &topics)?);
+        }
+        for sensor in &config.sensors {
+            let _add = bridge.add_sensor(sensor.name, sensor.url)?;
+        }
+        if let Some(discovery_prefix) = config.discovery_prefix {
+            let _prefix = bridge.set_discovery_prefix(discovery_prefix)?;
+            for sensor in &config.sensors {
+                let topic = match sensor.topic {
+                    Some(topic) => topic,
+                    None => {
+                        warn!("sensor {} has no topic to announce", sensor.name);
+                        continue;
+                    }
+                };
+                let socket = bridge
+                    .context
+                    .socket(zmq::REQ)
+                    .context(ErrorKind::SocketCreate)?;
+                match sensor.kind {
+                    SensorKind::Conductivity => {
+                        let requester = ConductivityRequester::new(socket)?;
+                        let _connect = requester.connect(sensor.url)?;
+                        bridge.add_discoverable(
+                            sensor.name,
+                            topic,
+                            ConductivityDiscovery(requester),
+                        );
+                    }
+                    SensorKind::Ph => {
+                        let requester = PhRequester::new(socket)?;
+                        let _connect = requester.connect(sensor.url)?;
+                        bridge.add_discoverable(sensor.name, topic, PhDiscovery(requester));
+                    }
+                    SensorKind::Temperature => {
+                        let requester = TemperatureRequester::new(socket)?;
+                        let _connect = requester.connect(sensor.url)?;
+                        bridge.add_discoverable(
+                            sensor.name,
+                            topic,
+                            TemperatureDiscovery(requester),
+                        );
+                    }
+                }
+            }
+            let _count = bridge.publish_discovery()?;
+        }
+        Ok(bridge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use network::mqtt::topic_matches;
+    use std::collections::VecDeque;
+    use std::thread;
+
+    use chrono::{TimeZone, Utc};
+    use measurement::SensorId;
+    use network::mqtt::discovery::{Device, Entity};
+
+    /// In-process stand-in for a broker, which keeps every publication, and delivers the
+    /// queued messages that match a subscription.
+    #[derive(Default)]
+    pub struct MemoryBroker {
+        pub published: Vec<MqttMessage>,
+        pub subscriptions: Vec<String>,
+        pub incoming: VecDeque<MqttMessage>,
+    }
+
+    impl MqttTransport for MemoryBroker {
+        fn publish(&mut self, message: &MqttMessage) -> Result<()> {
+            self.published.push(message.clone());
+            Ok(())
+        }
+
+        fn subscribe(&mut self, filter: &str, _qos: QoS) -> Result<()> {
+            self.subscriptions.push(filter.to_string());
+            Ok(())
+        }
+
+        fn recv(&mut self, _timeout: Duration) -> Result<Option<MqttMessage>> {
+            while let Some(message) = self.incoming.pop_front() {
+                if self
+                    .subscriptions
+                    .iter()
+                    .any(|f| topic_matches(f, &message.topic))
+                {
+                    return Ok(Some(message));
+                }
+            }
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn publish_measurements_as_json() {
+        let mut bridge = MqttBridge::new(MemoryBroker::default(), "site/");
+        bridge.set_qos(QoS::AtLeastOnce);
+        bridge.set_retain(true);
+        let measurement = Measurement::new(
+            Utc.ymd(2017, 11, 18).and_hms(23, 40, 5),
+            SensorType::Ph,
+            "0123456789abcdef".parse::<SensorId>().unwrap(),
+            7.012,
+        );
+        bridge.publish_measurement(&measurement).unwrap();
+
+        let published = &bridge.transport().published[0];
+        assert_eq!("site/ph-0123456789abcdef", published.topic);
+        assert_eq!(QoS::AtLeastOnce, published.qos);
+        assert!(published.retain);
+        assert_eq!(
+            r#"{"timestamp":"2017-11-18T23:40:05Z","unit":"pH","value":7.012}"#,
+            String::from_utf8(published.payload.clone()).unwrap()
+        );
+    }
+
+    struct FakeSensor;
+
+    impl Discoverable for FakeSensor {
+        fn device(&self) -> Result<Device> {
+            Device::parse("RTD,2.01")
+        }
+
+        fn entities(&self) -> Result<Vec<Entity>> {
+            Ok(vec![Entity::temperature("kelvin")?])
+        }
+    }
+
+    #[test]
+    fn announce_sensors_when_home_assistant_comes_online() {
+        let mut bridge = MqttBridge::new(MemoryBroker::default(), "benita");
+        bridge.add_discoverable("rtd1", "temperature-0123456789abcdef", FakeSensor);
+        assert_eq!(0, bridge.publish_discovery().unwrap());
+
+        bridge.set_discovery_prefix("homeassistant").unwrap();
+        assert_eq!(1, bridge.publish_discovery().unwrap());
+        let online = MqttMessage::new("homeassistant/status", b"online", QoS::AtMostOnce, false);
+        bridge.transport.incoming.push_back(online);
+        assert_eq!(1, bridge.step(Duration::from_millis(10)).unwrap());
+
+        let published = &bridge.transport().published;
+        assert_eq!(2, published.len());
+        assert_eq!(
+            "homeassistant/sensor/benita-0123456789abcdef/temperature/config",
+            published[1].topic
+        );
+        assert!(published[1].retain);
+    }
+
+    #[test]
+    fn forward_commands_to_command_servers() {
+        let context = Context::new();
+        let responder = context.socket(zmq::REP).unwrap();
+        responder.bind("tcp://127.0.0.1:0").unwrap();
+        let url = responder.get_last_endpoint().unwrap().unwrap();
+        let server = thread::spawn(move || {
+            let request = responder.recv_string(0).unwrap().unwrap();
+            assert_eq!("read", request);
+            responder.send(b"7.012", 0).unwrap();
+        });
+
+        let mut bridge = MqttBridge::new(MemoryBroker::default(), "benita");
+        bridge.add_sensor("ph1", &url).unwrap();
+        assert_eq!(
+            vec!["benita/ph1/command".to_string()],
+            bridge.transport().subscriptions
+        );
+
+        let command = MqttMessage::new("benita/ph1/command", b"read", QoS::AtMostOnce, false);
+        bridge.transport.incoming.push_back(command);
+        let other = MqttMessage::new("benita/ph2/command", b"read", QoS::AtMostOnce, false);
+        bridge.transport.incoming.push_back(other);
+        assert_eq!(1, bridge.step(Duration::from_millis(10)).unwrap());
+        assert_eq!(0, bridge.step(Duration::from_millis(10)).unwrap());
+        server.join().unwrap();
+
+        let published = &bridge.transport().published;
+        assert_eq!(1, published.len());
+        assert_eq!("benita/ph1/response", published[0].topic);
+        assert_eq!(b"7.012".to_vec(), published[0].payload);
+    }
+}
@@ -0,0 +1,724 @@
+//! Minimal MQTT 3.1.1 client.
+//!
+//! Supports what the bridge needs: publishing, and subscribing, with QoS 0 and 1, and
+//! keeping the connection alive. QoS 1 publications wait for their `PUBACK`.
+//!
+//! When the connection to the broker drops, the client reconnects, waiting longer after
+//! every failed attempt, and subscribes to its topics again. QoS 1 publications that were
+//! sent, but not acknowledged, before the connection dropped are sent again, as duplicates.
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{MqttMessage, MqttTransport, QoS};
+use errors::*;
+
+/// Time allowed to read the rest of a packet, or a reply, in seconds.
+pub const IO_TIMEOUT_SECS: u64 = 5;
+
+/// Delay before reconnecting to the broker, in milliseconds. It doubles after every failed
+/// attempt, up to `RECONNECT_MAX_SECS`.
+pub const RECONNECT_MIN_MS: u64 = 500;
+
+/// Longest delay between attempts to reconnect to the broker, in seconds.
+pub const RECONNECT_MAX_SECS: u64 = 60;
+
+/// `SUBACK` return code of a refused subscription.
+const SUBSCRIPTION_FAILURE: u8 = 0x80;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// Fixed header flag of a `PUBLISH` packet sent again.
+const DUP: u8 = 0x08;
+
+fn encode_length(mut len: usize, out: &mut Vec<u8>) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+fn encode_u16(n: u16, out: &mut Vec<u8>) {
+    out.push((n >> 8) as u8);
+    out.push(n as u8);
+}
+
+fn encode_str(s: &str, out: &mut Vec<u8>) {
+    encode_u16(s.len() as u16, out);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut out = vec![header];
+    encode_length(body.len(), &mut out);
+    out.extend_from_slice(body);
+    out
+}
+
+fn decode_u16(body: &[u8], at: usize) -> Result<u16> {
+    match (body.get(at), body.get(at + 1)) {
+        (Some(hi), Some(lo)) => Ok(u16::from(*hi) << 8 | u16::from(*lo)),
+        _ => Err(ErrorKind::MqttProtocol.into()),
+    }
+}
+
+/// A `CONNECT` packet, for a clean session.
+pub fn connect_packet(
+    client_id: &str,
+    keep_alive: u16,
+    username: Option<&str>,
+    password: Option<&str>,
+) -> Vec<u8> {
+    let mut body = Vec::new();
+    encode_str("MQTT", &mut body);
+    body.push(4);
+    let mut flags = 0x02;
+    if username.is_some() {
+        flags |= 0x80;
+    }
+    if password.is_some() {
+        flags |= 0x40;
+    }
+    body.push(flags);
+    encode_u16(keep_alive, &mut body);
+    encode_str(client_id, &mut body);
+    for field in username.iter().chain(password.iter()) {
+        encode_str(field, &mut body);
+    }
+    packet(CONNECT << 4, &body)
+}
+
+/// A `PUBLISH` packet. The packet id is only sent for QoS 1.
+pub fn publish_packet(message: &MqttMessage, packet_id: u16) -> Vec<u8> {
+    let mut header = PUBLISH << 4 | (message.qos as u8) << 1;
+    if message.retain {
+        header |= 0x01;
+    }
+    let mut body = Vec::new();
+    encode_str(&message.topic, &mut body);
+    if message.qos == QoS::AtLeastOnce {
+        encode_u16(packet_id, &mut body);
+    }
+    body.extend_from_slice(&message.payload);
+    packet(header, &body)
+}
+
+/// A `SUBSCRIBE` packet, for a single topic filter.
+pub fn subscribe_packet(packet_id: u16, filter: &str, qos: QoS) -> Vec<u8> {
+    let mut body = Vec::new();
+    encode_u16(packet_id, &mut body);
+    encode_str(filter, &mut body);
+    body.push(qos as u8);
+    packet(SUBSCRIBE << 4 | 0x02, &body)
+}
+
+/// Parse the fixed header flags, and body, of a `PUBLISH` packet. Returns the message, and
+/// its packet id for QoS 1.
+pub fn parse_publish(header: u8, body: &[u8]) -> Result<(MqttMessage, Option<u16>)> {
+    let qos = QoS::from_u8((header >> 1) & 0x03)?;
+    let topic_len = decode_u16(body, 0)? as usize;
+    let mut at = 2 + topic_len;
+    if body.len() < at {
+        return Err(ErrorKind::MqttProtocol.into());
+    }
+    let topic = String::from_utf8(body[2..at].to_vec()).context(ErrorKind::MqttProtocol)?;
+    let packet_id = match qos {
+        QoS::AtMostOnce => None,
+        QoS::AtLeastOnce => {
+            let id = decode_u16(body, at)?;
+            at += 2;
+            Some(id)
+        }
+    };
+    let message = MqttMessage {
+        topic,
+        payload: body[at..].to_vec(),
+        qos,
+        retain: header & 0x01 == 0x01,
+    };
+    Ok((message, packet_id))
+}
+
+/// Read a packet, returning its fixed header byte, and its body.
+pub fn read_packet<R: Read>(reader: &mut R) -> Result<(u8, Vec<u8>)> {
+    let mut byte = [0; 1];
+    let _read = reader
+        .read_exact(&mut byte)
+        .context(ErrorKind::SocketReceive)?;
+    let header = byte[0];
+    let body = read_body(reader)?;
+    Ok((header, body))
+}
+
+fn read_body<R: Read>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut len = 0;
+    let mut multiplier = 1;
+    let mut byte = [0; 1];
+    for i in 0..4 {
+        let _read = reader
+            .read_exact(&mut byte)
+            .context(ErrorKind::SocketReceive)?;
+        len += (byte[0] & 0x7f) as usize * multiplier;
+        multiplier *= 128;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        if i == 3 {
+            return Err(ErrorKind::MqttProtocol.into());
+        }
+    }
+    let mut body = vec![0; len];
+    let _read = reader
+        .read_exact(&mut body)
+        .context(ErrorKind::SocketReceive)?;
+    Ok(body)
+}
+
+// What it takes to connect to the broker again.
+struct ConnectOptions {
+    addr: String,
+    client_id: String,
+    keep_alive: u16,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl ConnectOptions {
+    // Open a connection, and wait for the broker to accept it.
+    fn open(&self) -> Result<TcpStream> {
+        let mut stream = TcpStream::connect(&self.addr[..]).context(ErrorKind::SocketConnect)?;
+        let connect = connect_packet(
+            &self.client_id,
+            self.keep_alive,
+            self.username.as_ref().map(|s| &s[..]),
+            self.password.as_ref().map(|s| &s[..]),
+        );
+        let _write = stream.write_all(&connect).context(ErrorKind::SocketSend)?;
+        let _timeout = stream
+            .set_read_timeout(Some(Duration::from_secs(IO_TIMEOUT_SECS)))
+            .context(ErrorKind::SocketReceive)?;
+        let (header, body) = read_packet(&mut stream)?;
+        if header >> 4 != CONNACK || body.len() != 2 {
+            return Err(ErrorKind::MqttProtocol.into());
+        }
+        if body[1] != 0 {
+            return Err(ErrorKind::RunTime(format!("MQTT connection refused: {}", body[1])).into());
+        }
+        Ok(stream)
+    }
+}
+
+// Read the next packet, if one starts before `timeout`.
+fn poll_stream(stream: &mut TcpStream, timeout: Duration) -> Result<Option<(u8, Vec<u8>)>> {
+    // A zero timeout would block.
+    let timeout = cmp::max(timeout, Duration::from_millis(1));
+    let _timeout = stream
+        .set_read_timeout(Some(timeout))
+        .context(ErrorKind::SocketReceive)?;
+    let mut byte = [0; 1];
+    match stream.read(&mut byte) {
+        Ok(0) => return Err(ErrorKind::SocketReceive.into()),
+        Ok(_) => {}
+        Err(ref e)
+            if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+        {
+            return Ok(None)
+        }
+        Err(e) => {
+            return Err(e)
+                .context(ErrorKind::SocketReceive)
+                .map_err(Error::from)
+        }
+    }
+    let _timeout = stream
+        .set_read_timeout(Some(Duration::from_secs(IO_TIMEOUT_SECS)))
+        .context(ErrorKind::SocketReceive)?;
+    let body = read_body(stream)?;
+    Ok(Some((byte[0], body)))
+}
+
+/// Client connected to an MQTT broker.
+pub struct MqttClient {
+    options: ConnectOptions,
+    // `None` while the connection is down.
+    stream: Option<TcpStream>,
+    keep_alive: Duration,
+    last_sent: Instant,
+    next_packet_id: u16,
+    // Publications received while waiting for an acknowledgement.
+    pending: VecDeque<MqttMessage>,
+    // Subscriptions to restore after reconnecting.
+    subscriptions: Vec<(String, QoS)>,
+    // QoS 1 publications in flight when the connection dropped, to send again after
+    // reconnecting.
+    unacked: VecDeque<(u16, MqttMessage)>,
+    backoff: Duration,
+    reconnect_at: Instant,
+}
+
+impl MqttClient {
+    /// Connect to the broker at `addr`, e.g. `localhost:1883`, with a clean session.
+    pub fn connect(
+        addr: &str,
+        client_id: &str,
+        keep_alive: u16,
+        username: Option<&str>,
+        password: Option<&str>,
+    ) -> Result<MqttClient> {
+        let options = ConnectOptions {
+            addr: addr.to_string(),
+            client_id: client_id.to_string(),
+            keep_alive,
+            username: username.map(|s| s.to_string()),
+            password: password.map(|s| s.to_string()),
+        };
+        let stream = options.open()?;
+        Ok(MqttClient {
+            options,
+            stream: Some(stream),
+            keep_alive: Duration::from_secs(u64::from(keep_alive)),
+            last_sent: Instant::now(),
+            next_packet_id: 1,
+            pending: VecDeque::new(),
+            subscriptions: Vec::new(),
+            unacked: VecDeque::new(),
+            backoff: Duration::from_millis(RECONNECT_MIN_MS),
+            reconnect_at: Instant::now(),
+        })
+    }
+
+    /// Is the client connected to the broker? It is not while it waits to reconnect.
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Disconnect from the broker.
+    pub fn disconnect(mut self) -> Result<()> {
+        if self.stream.is_none() {
+            return Ok(());
+        }
+        self.send(&packet(DISCONNECT << 4, &[]))
+    }
+
+    // Drop the connection after it failed with `e`, and schedule the next attempt to
+    // reconnect.
+    fn lose_connection(&mut self, e: Error) -> Error {
+        if self.stream.take().is_some() {
+            warn!(
+                "lost the connection to the MQTT broker at {}: {}",
+                self.options.addr, e
+            );
+            self.reconnect_at = Instant::now() + self.backoff;
+        }
+        e
+    }
+
+    // Reconnect, if the connection is down and the next attempt is due, subscribe to the
+    // topics again, and send the unacknowledged publications again. A failed attempt drops
+    // the connection, and doubles the delay before the next one.
+    fn reconnect(&mut self) -> Result<()> {
+        if self.stream.is_some() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        if now < self.reconnect_at {
+            return Err(ErrorKind::SocketConnect.into());
+        }
+        let restored = self.options.open().and_then(|stream| {
+            self.stream = Some(stream);
+            self.last_sent = Instant::now();
+            self.restore_session()
+        });
+        match restored {
+            Ok(()) => {
+                info!("reconnected to the MQTT broker at {}", self.options.addr);
+                self.backoff = Duration::from_millis(RECONNECT_MIN_MS);
+                Ok(())
+            }
+            Err(e) => {
+                let e = self.lose_connection(e);
+                self.reconnect_at = now + self.backoff;
+                self.backoff = cmp::min(self.backoff * 2, Duration::from_secs(RECONNECT_MAX_SECS));
+                Err(e)
+            }
+        }
+    }
+
+    // Subscribe to the topics again, and send the unacknowledged publications again, on a
+    // new connection.
+    fn restore_session(&mut self) -> Result<()> {
+        for (filter, qos) in self.subscriptions.clone() {
+            let _subscribe = self.send_subscribe(&filter, qos)?;
+        }
+        while let Some((packet_id, message)) = self.unacked.front().cloned() {
+            let mut bytes = publish_packet(&message, packet_id);
+            bytes[0] |= DUP;
+            let _send = self.send(&bytes)?;
+            let _ack = self.wait_for(PUBACK, packet_id)?;
+            let _acked = self.unacked.pop_front();
+        }
+        Ok(())
+    }
+
+    // Sleep until the next attempt to reconnect, or until `deadline` if it comes first,
+    // and make the attempt once it is due.
+    fn wait_to_reconnect(&mut self, deadline: Instant) {
+        let now = Instant::now();
+        if self.reconnect_at > now {
+            thread::sleep(cmp::min(self.reconnect_at, deadline) - now);
+        }
+        if Instant::now() >= self.reconnect_at {
+            if let Err(e) = self.reconnect() {
+                warn!(
+                    "could not reconnect to the MQTT broker at {}: {}",
+                    self.options.addr, e
+                );
+            }
+        }
+    }
+
+    fn send(&mut self, bytes: &[u8]) -> Result<()> {
+        let _reconnect = self.reconnect()?;
+        let written = match self.stream {
+            Some(ref mut stream) => stream.write_all(bytes),
+            None => return Err(ErrorKind::SocketSend.into()),
+        };
+        match written.context(ErrorKind::SocketSend) {
+            Ok(()) => {
+                self.last_sent = Instant::now();
+                Ok(())
+            }
+            Err(e) => Err(self.lose_connection(e.into())),
+        }
+    }
+
+    fn packet_id(&mut self) -> u16 {
+        let id = self.next_packet_id;
+        self.next_packet_id = self.next_packet_id.checked_add(1).unwrap_or(1);
+        id
+    }
+
+    // Read the next packet, if one starts before `timeout`.
+    fn poll_packet(&mut self, timeout: Duration) -> Result<Option<(u8, Vec<u8>)>> {
+        let polled = match self.stream {
+            Some(ref mut stream) => poll_stream(stream, timeout),
+            None => return Err(ErrorKind::SocketReceive.into()),
+        };
+        polled.map_err(|e| self.lose_connection(e))
+    }
+
+    // Handle a packet from the broker, returning it if it is a publication.
+    fn handle(&mut self, header: u8, body: &[u8]) -> Result<Option<MqttMessage>> {
+        match header >> 4 {
+            PUBLISH => {
+                let (message, packet_id) = parse_publish(header, body)?;
+                if let Some(id) = packet_id {
+                    let mut ack = Vec::new();
+                    encode_u16(id, &mut ack);
+                    let _send = self.send(&packet(PUBACK << 4, &ack))?;
+                }
+                Ok(Some(message))
+            }
+            PUBACK | SUBACK | PINGRESP => Ok(None),
+            _ => Err(ErrorKind::MqttProtocol.into()),
+        }
+    }
+
+    // Wait for the acknowledgement of a packet, keeping any publications received meanwhile.
+    // Returns the body of the acknowledgement.
+    fn wait_for(&mut self, kind: u8, packet_id: u16) -> Result<Vec<u8>> {
+        let deadline = Instant::now() + Duration::from_secs(IO_TIMEOUT_SECS);
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(ErrorKind::ServiceTimeout.into());
+            }
+            let (header, body) = match self.poll_packet(deadline - now)? {
+                Some(packet) => packet,
+                None => continue,
+            };
+            if header >> 4 == kind && decode_u16(&body, 0)? == packet_id {
+                return Ok(body);
+            }
+            if let Some(message) = self.handle(header, &body)? {
+                self.pending.push_back(message);
+            }
+        }
+    }
+
+    // Subscribe on the current connection. Fails if the broker refuses the subscription.
+    fn send_subscribe(&mut self, filter: &str, qos: QoS) -> Result<()> {
+        let packet_id = self.packet_id();
+        let _send = self.send(&subscribe_packet(packet_id, filter, qos))?;
+        let body = self.wait_for(SUBACK, packet_id)?;
+        match body.get(2) {
+            Some(&SUBSCRIPTION_FAILURE) => {
+                Err(ErrorKind::RunTime(format!("MQTT subscription refused: {}", filter)).into())
+            }
+            Some(&granted) if QoS::from_u8(granted).is_ok() => Ok(()),
+            _ => Err(ErrorKind::MqttProtocol.into()),
+        }
+    }
+
+    fn keep_alive(&mut self) -> Result<()> {
+        let secs = self.keep_alive.as_secs();
+        if secs > 0 && self.last_sent.elapsed() >= Duration::from_secs(secs / 2 + secs % 2) {
+            let _ping = self.send(&packet(PINGREQ << 4, &[]))?;
+        }
+        Ok(())
+    }
+}
+
+impl MqttTransport for MqttClient {
+    fn publish(&mut self, message: &MqttMessage) -> Result<()> {
+        let packet_id = match message.qos {
+            QoS::AtMostOnce => 0,
+            QoS::AtLeastOnce => self.packet_id(),
+        };
+        let _send = self.send(&publish_packet(message, packet_id))?;
+        if message.qos == QoS::AtLeastOnce {
+            if let Err(e) = self.wait_for(PUBACK, packet_id) {
+                if self.stream.is_none() {
+                    self.unacked.push_back((packet_id, message.clone()));
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn subscribe(&mut self, filter: &str, qos: QoS) -> Result<()> {
+        let _subscribe = self.send_subscribe(filter, qos)?;
+        self.subscriptions.push((filter.to_string(), qos));
+        Ok(())
+    }
+
+    // While the connection is down, this waits to reconnect instead of failing.
+    fn recv(&mut self, timeout: Duration) -> Result<Option<MqttMessage>> {
+        if let Some(message) = self.pending.pop_front() {
+            return Ok(Some(message));
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            if self.stream.is_none() {
+                self.wait_to_reconnect(deadline);
+                continue;
+            }
+            let received = self
+                .keep_alive()
+                .and_then(|_| self.poll_packet(deadline - now));
+            let handled = match received {
+                Ok(Some((header, body))) => self.handle(header, &body),
+                Ok(None) => Ok(None),
+                Err(e) => Err(e),
+            };
+            match handled {
+                Ok(Some(message)) => return Ok(Some(message)),
+                Ok(None) => {}
+                Err(e) => {
+                    let _lost = self.lose_connection(e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn encode_packets() {
+        assert_eq!(
+            vec![
+                0x10, 16, 0, 4, b'M', b'Q', b'T', b'T', 4, 0x02, 0, 60, 0, 4, b'n', b'o', b'd',
+                b'e',
+            ],
+            connect_packet("node", 60, None, None)
+        );
+        let message = MqttMessage::new("a/b", b"7.0", QoS::AtLeastOnce, true);
+        assert_eq!(
+            vec![0x33, 10, 0, 3, b'a', b'/', b'b', 0, 9, b'7', b'.', b'0'],
+            publish_packet(&message, 9)
+        );
+        assert_eq!(
+            vec![0x82, 8, 0, 1, 0, 3, b'a', b'/', b'+', 1],
+            subscribe_packet(1, "a/+", QoS::AtLeastOnce)
+        );
+
+        let mut long = Vec::new();
+        encode_length(321, &mut long);
+        assert_eq!(vec![0xc1, 0x02], long);
+    }
+
+    #[test]
+    fn decode_packets() {
+        let message = MqttMessage::new("a/b", b"7.0", QoS::AtLeastOnce, true);
+        let bytes = publish_packet(&message, 9);
+        let (header, body) = read_packet(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!((message, Some(9)), parse_publish(header, &body).unwrap());
+
+        // Truncated packets, and topics, yield errors.
+        assert!(read_packet(&mut Cursor::new(vec![0x30, 5, 0, 3])).is_err());
+        assert!(parse_publish(0x30, &[0, 9, b'a']).is_err());
+        assert!(parse_publish(0x34, &[0, 1, b'a']).is_err());
+    }
+
+    #[test]
+    fn publish_and_receive_with_a_broker() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // A broker that accepts one client, acknowledges its packets, and echoes the
+        // first publication back to it.
+        let broker = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let (header, _) = read_packet(&mut stream).unwrap();
+            assert_eq!(CONNECT, header >> 4);
+            stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+            let (header, body) = read_packet(&mut stream).unwrap();
+            assert_eq!(SUBSCRIBE, header >> 4);
+            stream.write_all(&[0x90, 3, body[0], body[1], 1]).unwrap();
+            let (header, body) = read_packet(&mut stream).unwrap();
+            let (message, packet_id) = parse_publish(header, &body).unwrap();
+            let id = packet_id.unwrap();
+            stream
+                .write_all(&[0x40, 2, (id >> 8) as u8, id as u8])
+                .unwrap();
+            stream.write_all(&publish_packet(&message, 7)).unwrap();
+            let (header, body) = read_packet(&mut stream).unwrap();
+            assert_eq!((PUBACK, vec![0, 7]), (header >> 4, body));
+            let (header, _) = read_packet(&mut stream).unwrap();
+            assert_eq!(DISCONNECT, header >> 4);
+        });
+
+        let mut client = MqttClient::connect(&addr.to_string(), "node", 0, None, None).unwrap();
+        client.subscribe("benita/#", QoS::AtLeastOnce).unwrap();
+        let message = MqttMessage::new("benita/ph-01", b"7.0", QoS::AtLeastOnce, false);
+        client.publish(&message).unwrap();
+        let received = client.recv(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!("benita/ph-01", received.topic);
+        assert_eq!(b"7.0".to_vec(), received.payload);
+        assert_eq!(None, client.recv(Duration::from_millis(10)).unwrap());
+        client.disconnect().unwrap();
+        broker.join().unwrap();
+    }
+
+    // Accept a client, and acknowledge its `CONNECT`.
+    fn accept(listener: &TcpListener) -> TcpStream {
+        let (mut stream, _) = listener.accept().unwrap();
+        let (header, _) = read_packet(&mut stream).unwrap();
+        assert_eq!(CONNECT, header >> 4);
+        stream.write_all(&[0x20, 2, 0, 0]).unwrap();
+        stream
+    }
+
+    #[test]
+    fn refused_subscriptions_yield_err() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let broker = thread::spawn(move || {
+            let mut stream = accept(&listener);
+            let (_, body) = read_packet(&mut stream).unwrap();
+            stream
+                .write_all(&[0x90, 3, body[0], body[1], SUBSCRIPTION_FAILURE])
+                .unwrap();
+            let _disconnect = read_packet(&mut stream);
+        });
+
+        let mut client = MqttClient::connect(&addr.to_string(), "node", 0, None, None).unwrap();
+        assert!(client.subscribe("$SYS/#", QoS::AtMostOnce).is_err());
+        client.disconnect().unwrap();
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn reconnect_and_subscribe_again_when_the_broker_drops() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let broker = thread::spawn(move || {
+            // Drop the first connection right after the subscription.
+            {
+                let mut stream = accept(&listener);
+                let (_, body) = read_packet(&mut stream).unwrap();
+                stream.write_all(&[0x90, 3, body[0], body[1], 0]).unwrap();
+            }
+            let mut stream = accept(&listener);
+            let (header, body) = read_packet(&mut stream).unwrap();
+            assert_eq!(SUBSCRIBE, header >> 4);
+            assert_eq!(b"benita/#".to_vec(), body[4..12].to_vec());
+            stream.write_all(&[0x90, 3, body[0], body[1], 0]).unwrap();
+            let message = MqttMessage::new("benita/ph-01", b"7.0", QoS::AtMostOnce, false);
+            stream.write_all(&publish_packet(&message, 0)).unwrap();
+            let _disconnect = read_packet(&mut stream);
+        });
+
+        let mut client = MqttClient::connect(&addr.to_string(), "node", 0, None, None).unwrap();
+        client.subscribe("benita/#", QoS::AtMostOnce).unwrap();
+        let received = client.recv(Duration::from_secs(5)).unwrap().unwrap();
+        assert!(client.is_connected());
+        assert_eq!("benita/ph-01", received.topic);
+        client.disconnect().unwrap();
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn send_unacknowledged_publications_again_after_reconnecting() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let broker = thread::spawn(move || {
+            // Drop the first connection before acknowledging the publication.
+            let first_id = {
+                let mut stream = accept(&listener);
+                let (header, body) = read_packet(&mut stream).unwrap();
+                assert_eq!(0, header & DUP);
+                parse_publish(header, &body).unwrap().1.unwrap()
+            };
+            let mut stream = accept(&listener);
+            let (header, body) = read_packet(&mut stream).unwrap();
+            assert_eq!(DUP, header & DUP);
+            let (message, packet_id) = parse_publish(header, &body).unwrap();
+            assert_eq!(Some(first_id), packet_id);
+            assert_eq!("benita/ph-01", message.topic);
+            stream
+                .write_all(&[0x40, 2, (first_id >> 8) as u8, first_id as u8])
+                .unwrap();
+            let message = MqttMessage::new("benita/done", b"", QoS::AtMostOnce, false);
+            stream.write_all(&publish_packet(&message, 0)).unwrap();
+            let _disconnect = read_packet(&mut stream);
+        });
+
+        let mut client = MqttClient::connect(&addr.to_string(), "node", 0, None, None).unwrap();
+        let message = MqttMessage::new("benita/ph-01", b"7.0", QoS::AtLeastOnce, false);
+        assert!(client.publish(&message).is_err());
+        assert!(!client.is_connected());
+        let received = client.recv(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!("benita/done", received.topic);
+        client.disconnect().unwrap();
+        broker.join().unwrap();
+    }
+}
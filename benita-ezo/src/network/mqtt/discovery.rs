@@ -0,0 +1,323 @@
+//! Home Assistant MQTT discovery.
+//!
+//! Every quantity measured by a sensor is announced as a Home Assistant `sensor` entity, by
+//! a retained config message on
+//! `<discovery prefix>/sensor/benita-<id>/<type>/config`, whose state topic is the one the
+//! bridge republishes its measurements on. Conductivity sensors announce one entity for
+//! each of their current output parameters, and temperature sensors announce their current
+//! scale, converting the published degrees Celsius in the value template.
+//!
+//! Each sensor is announced as a device, with the model, and firmware version, from its
+//! `DeviceInfo`.
+use common_ezo::EzoChipAPI;
+use conductivity::ConductivityAPI;
+use errors::*;
+use measurement::{parse_topic, SensorId, SensorType};
+use network::ReplyStatus;
+use ph::PhAPI;
+use temperature::TemperatureAPI;
+
+use super::{MqttMessage, QoS};
+
+/// Discovery prefix used by Home Assistant, unless configured otherwise.
+pub const DISCOVERY_PREFIX: &str = "homeassistant";
+
+/// The model, and firmware version, of a sensor device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Device {
+    pub model: String,
+    pub firmware: String,
+}
+
+impl Device {
+    /// Parse a device from the text form of a `DeviceInfo`, e.g. `EC,2.10`.
+    pub fn parse(info: &str) -> Result<Device> {
+        let fields: Vec<&str> = info.split(',').map(|f| f.trim()).collect();
+        if fields.len() != 2 || fields.iter().any(|f| f.is_empty()) {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        Ok(Device {
+            model: fields[0].to_string(),
+            firmware: fields[1].to_string(),
+        })
+    }
+}
+
+/// A quantity measured by a sensor, announced as a Home Assistant entity.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entity {
+    pub quantity: SensorType,
+    /// The unit shown by Home Assistant, if any.
+    pub unit: Option<&'static str>,
+    /// The template that extracts the value from the published JSON.
+    pub value_template: String,
+}
+
+impl Entity {
+    /// An entity for values in the canonical unit of `quantity`.
+    pub fn new(quantity: SensorType) -> Entity {
+        let unit = match quantity {
+            SensorType::Temperature => Some("°C"),
+            SensorType::Ph => Some("pH"),
+            SensorType::Conductivity => Some("µS/cm"),
+            SensorType::TotalDissolvedSolids => Some("ppm"),
+            SensorType::Salinity => Some("ppt"),
+            SensorType::SpecificGravity | SensorType::Rgb => None,
+        };
+        Entity {
+            quantity,
+            unit,
+            value_template: "{{ value_json.value }}".to_string(),
+        }
+    }
+
+    /// An entity for temperatures on `scale`, as in the text form of a `TemperatureScale`.
+    pub fn temperature(scale: &str) -> Result<Entity> {
+        let (unit, template) = match scale.trim() {
+            "celsius" => ("°C", "{{ value_json.value }}"),
+            "fahrenheit" => ("°F", "{{ (value_json.value * 9 / 5 + 32) | round(3) }}"),
+            "kelvin" => ("K", "{{ (value_json.value + 273.15) | round(3) }}"),
+            _ => return Err(ErrorKind::ResponseParse.into()),
+        };
+        Ok(Entity {
+            quantity: SensorType::Temperature,
+            unit: Some(unit),
+            value_template: template.to_string(),
+        })
+    }
+
+    /// The entities for the output parameters of a conductivity sensor, as in the text form
+    /// of an `OutputStringStatus`, e.g. `EC,TDS`.
+    pub fn conductivity(params: &str) -> Result<Vec<Entity>> {
+        params
+            .split(',')
+            .filter(|p| !p.trim().is_empty())
+            .map(|p| Ok(Entity::new(SensorType::from_output_param(p)?)))
+            .collect()
+    }
+
+    fn label(&self) -> &'static str {
+        match self.quantity {
+            SensorType::Temperature => "temperature",
+            SensorType::Ph => "pH",
+            SensorType::Conductivity => "EC",
+            SensorType::TotalDissolvedSolids => "TDS",
+            SensorType::Salinity => "salinity",
+            SensorType::SpecificGravity => "specific gravity",
+            SensorType::Rgb => "colour",
+        }
+    }
+
+    /// The retained config message for this entity of the sensor `name`, which publishes on
+    /// `<state_prefix>/<type>-<id>`.
+    pub fn config_message(
+        &self,
+        name: &str,
+        sensor_id: &SensorId,
+        device: &Device,
+        state_prefix: &str,
+        discovery_prefix: &str,
+        qos: QoS,
+    ) -> MqttMessage {
+        let object = format!("{}-{}", self.quantity, sensor_id);
+        let mut payload = json!({
+            "name": format!("{} {}", name, self.label()),
+            "unique_id": format!("benita-{}", object),
+            "state_topic": format!("{}/{}", state_prefix, object),
+            "value_template": self.value_template,
+            "state_class": "measurement",
+            "device": {
+                "identifiers": [format!("benita-{}", sensor_id)],
+                "name": name,
+                "manufacturer": "Atlas Scientific",
+                "model": device.model,
+                "sw_version": device.firmware,
+            },
+        });
+        if let Some(unit) = self.unit {
+            payload["unit_of_measurement"] = json!(unit);
+        }
+        if self.quantity == SensorType::Temperature {
+            payload["device_class"] = json!("temperature");
+        }
+        let topic = format!(
+            "{}/sensor/benita-{}/{}/config",
+            discovery_prefix, sensor_id, self.quantity
+        );
+        MqttMessage::new(&topic, payload.to_string().as_bytes(), qos, true)
+    }
+}
+
+/// A sensor that can be announced to Home Assistant.
+pub trait Discoverable {
+    /// The model, and firmware version, of the sensor.
+    fn device(&self) -> Result<Device>;
+    /// The quantities currently measured by the sensor.
+    fn entities(&self) -> Result<Vec<Entity>>;
+}
+
+/// The config messages for every entity of the sensor `name`, which publishes measurements
+/// on `topic`, i.e. `<type>-<id>`.
+pub fn discovery_messages<D: Discoverable + ?Sized>(
+    sensor: &D,
+    name: &str,
+    topic: &str,
+    state_prefix: &str,
+    discovery_prefix: &str,
+    qos: QoS,
+) -> Result<Vec<MqttMessage>> {
+    let (_, sensor_id) = parse_topic(topic)?;
+    let device = sensor.device()?;
+    Ok(sensor
+        .entities()?
+        .iter()
+        .map(|e| {
+            e.config_message(
+                name,
+                &sensor_id,
+                &device,
+                state_prefix,
+                discovery_prefix,
+                qos,
+            )
+        })
+        .collect())
+}
+
+fn device<T>(sensor: &T) -> Result<Device>
+where
+    T: EzoChipAPI<SensorError = Error, SensorReply = ReplyStatus>,
+{
+    Device::parse(&format!("{}", sensor.get_device_info()?))
+}
+
+/// Announces anything that implements the `ConductivityAPI`, with one entity for each
+/// output parameter.
+pub struct ConductivityDiscovery<T>(pub T);
+
+impl<T> Discoverable for ConductivityDiscovery<T>
+where
+    T: ConductivityAPI<Error = Error, DefaultReply = ReplyStatus>
+        + EzoChipAPI<SensorError = Error, SensorReply = ReplyStatus>,
+{
+    fn device(&self) -> Result<Device> {
+        device(&self.0)
+    }
+
+    fn entities(&self) -> Result<Vec<Entity>> {
+        Entity::conductivity(&format!("{}", self.0.get_output_params()?))
+    }
+}
+
+/// Announces anything that implements the `PhAPI`.
+pub struct PhDiscovery<T>(pub T);
+
+impl<T> Discoverable for PhDiscovery<T>
+where
+    T: PhAPI<Error = Error, DefaultReply = ReplyStatus>
+        + EzoChipAPI<SensorError = Error, SensorReply = ReplyStatus>,
+{
+    fn device(&self) -> Result<Device> {
+        device(&self.0)
+    }
+
+    fn entities(&self) -> Result<Vec<Entity>> {
+        Ok(vec![Entity::new(SensorType::Ph)])
+    }
+}
+
+/// Announces anything that implements the `TemperatureAPI`, on its current scale.
+pub struct TemperatureDiscovery<T>(pub T);
+
+impl<T> Discoverable for TemperatureDiscovery<T>
+where
+    T: TemperatureAPI<Error = Error, DefaultReply = ReplyStatus>
+        + EzoChipAPI<SensorError = Error, SensorReply = ReplyStatus>,
+{
+    fn device(&self) -> Result<Device> {
+        device(&self.0)
+    }
+
+    fn entities(&self) -> Result<Vec<Entity>> {
+        Ok(vec![Entity::temperature(&format!(
+            "{}",
+            self.0.get_scale()?
+        ))?])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{self, Value};
+
+    // A conductivity sensor with EC, and TDS, output parameters.
+    struct FakeSensor;
+
+    impl Discoverable for FakeSensor {
+        fn device(&self) -> Result<Device> {
+            Device::parse("EC,2.10")
+        }
+
+        fn entities(&self) -> Result<Vec<Entity>> {
+            Entity::conductivity("EC,TDS")
+        }
+    }
+
+    #[test]
+    fn entities_of_sensor_quantities() {
+        let entities = Entity::conductivity("EC,TDS,S,SG").unwrap();
+        let units: Vec<Option<&str>> = entities.iter().map(|e| e.unit).collect();
+        assert_eq!(vec![Some("µS/cm"), Some("ppm"), Some("ppt"), None], units);
+        assert!(Entity::conductivity("").unwrap().is_empty());
+        assert!(Entity::conductivity("EC,PH").is_err());
+
+        let entity = Entity::temperature("fahrenheit").unwrap();
+        assert_eq!(Some("°F"), entity.unit);
+        assert_eq!(
+            "{{ (value_json.value * 9 / 5 + 32) | round(3) }}",
+            entity.value_template
+        );
+        assert!(Entity::temperature("rankine").is_err());
+
+        assert!(Device::parse("EC").is_err());
+        assert!(Device::parse("EC,").is_err());
+    }
+
+    #[test]
+    fn config_messages_of_discoverable_sensors() {
+        let messages = discovery_messages(
+            &FakeSensor,
+            "ec1",
+            "conductivity-0123456789abcdef",
+            "benita",
+            DISCOVERY_PREFIX,
+            QoS::AtLeastOnce,
+        )
+        .unwrap();
+        assert_eq!(2, messages.len());
+        assert_eq!(
+            "homeassistant/sensor/benita-0123456789abcdef/tds/config",
+            messages[1].topic
+        );
+        assert!(messages[1].retain);
+        assert_eq!(QoS::AtLeastOnce, messages[1].qos);
+
+        let config: Value = serde_json::from_slice(&messages[1].payload).unwrap();
+        assert_eq!("ec1 TDS", config["name"]);
+        assert_eq!("benita/tds-0123456789abcdef", config["state_topic"]);
+        assert_eq!("benita-tds-0123456789abcdef", config["unique_id"]);
+        assert_eq!("ppm", config["unit_of_measurement"]);
+        assert_eq!("EC", config["device"]["model"]);
+        assert_eq!("2.10", config["device"]["sw_version"]);
+        assert_eq!(
+            "benita-0123456789abcdef",
+            config["device"]["identifiers"][0]
+        );
+
+        // Topics need a sensor id.
+        assert!(
+            discovery_messages(&FakeSensor, "ec1", "ec1", "benita", "ha", QoS::AtMostOnce).is_err()
+        );
+    }
+}
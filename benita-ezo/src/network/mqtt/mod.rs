@@ -0,0 +1,121 @@
+//! MQTT bridge for site gateways.
+//!
+//! Measurements received from a `SensorPublisher` are republished on
+//! `<prefix>/<type>-<id>`, as JSON:
+//!
+//! ```text
+//! {"timestamp":"2017-11-18T23:40:05Z","unit":"pH","value":7.012}
+//! ```
+//!
+//! Requests for a sensor's command server are accepted on `<prefix>/<name>/command`, and its
+//! replies are published on `<prefix>/<name>/response`, both as the plain strings used by
+//! `SocketRequest` and `SocketReply`.
+//!
+//! Sensors can also be announced to Home Assistant, see `discovery`.
+use std::time::Duration;
+
+use errors::*;
+
+pub mod bridge;
+pub mod client;
+pub mod discovery;
+
+pub use self::bridge::MqttBridge;
+pub use self::client::MqttClient;
+
+/// Quality of service of MQTT publications, and subscriptions. Exactly once delivery (QoS 2)
+/// is not supported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QoS {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+}
+
+impl QoS {
+    /// The QoS for the given level.
+    pub fn from_u8(level: u8) -> Result<QoS> {
+        match level {
+            0 => Ok(QoS::AtMostOnce),
+            1 => Ok(QoS::AtLeastOnce),
+            _ => Err(ErrorKind::MqttProtocol.into()),
+        }
+    }
+}
+
+impl Default for QoS {
+    fn default() -> QoS {
+        QoS::AtMostOnce
+    }
+}
+
+/// A message published on an MQTT topic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MqttMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: QoS,
+    pub retain: bool,
+}
+
+impl MqttMessage {
+    /// Create a new message.
+    pub fn new(topic: &str, payload: &[u8], qos: QoS, retain: bool) -> MqttMessage {
+        MqttMessage {
+            topic: topic.to_string(),
+            payload: payload.to_vec(),
+            qos,
+            retain,
+        }
+    }
+}
+
+/// Connection to an MQTT broker, or an in-process stand-in for one.
+pub trait MqttTransport {
+    /// Publish a message.
+    fn publish(&mut self, message: &MqttMessage) -> Result<()>;
+    /// Subscribe to the topics matching `filter`.
+    fn subscribe(&mut self, filter: &str, qos: QoS) -> Result<()>;
+    /// Wait up to `timeout` for a message on a subscribed topic.
+    fn recv(&mut self, timeout: Duration) -> Result<Option<MqttMessage>>;
+}
+
+/// Returns `true` if `topic` matches the subscription `filter`, which may hold the `+` and
+/// `#` wildcards.
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for pattern in filter.split('/') {
+        if pattern == "#" {
+            return true;
+        }
+        match levels.next() {
+            Some(level) if pattern == "+" || pattern == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_topic_filters() {
+        assert!(topic_matches("benita/ph1/command", "benita/ph1/command"));
+        assert!(topic_matches("benita/+/command", "benita/ph1/command"));
+        assert!(topic_matches("benita/#", "benita/ph1/command"));
+        assert!(topic_matches("#", "benita"));
+        assert!(!topic_matches("benita/+", "benita/ph1/command"));
+        assert!(!topic_matches("benita/+/command", "benita/ph1/response"));
+        assert!(!topic_matches(
+            "benita/ph1/command/extra",
+            "benita/ph1/command"
+        ));
+    }
+
+    #[test]
+    fn qos_levels() {
+        assert_eq!(QoS::AtLeastOnce, QoS::from_u8(1).unwrap());
+        assert!(QoS::from_u8(2).is_err());
+    }
+}
@@ -0,0 +1,949 @@
+//! Periodic publication of sensor readings over a `PUB` socket.
+//!
+//! A `SensorPublisher` samples any number of sensors, each on its own schedule, and publishes
+//! every reading as a timestamped message on the sensor's topic:
+//!
+//! ```text
+//! <topic> <timestamp> <value> <unit>
+//! ```
+//!
+//! e.g. `temperature-0123456789abcdef 2017-11-18T23:40:05.123456789Z 19.521 celsius`.
+//!
+//! When a `MeasurementLog` is set, every sample is also stored locally, whether there are
+//! subscribers or not. When an `Aggregator` is set, the statistics of every window are
+//! published on their own `stats/` topics. When an `AlarmEngine` is set, the alarms raised,
+//! and cleared, by every sample are published on the `alarm` topic. Only topics of the form
+//! `<type>-<id>` can be stored, aggregated, or watched for alarms.
+//!
+//! A publisher with a responder socket answers `alarms` requests with its active alarms, and
+//! `history` requests from its measurement log, while it waits for the next sample. When
+//! allowed with `set_reload_requests`, a `reload` request is answered with `ok`, and left for
+//! the owner of the publisher to act on, with `take_reload_request`.
+//!
+//! Every sensor has an operating state, queried, and changed, with `state` requests. Readings
+//! are only stored, aggregated, and watched for alarms while their sensor is `sampling`.
+use std::cell::Cell;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use aggregate::Aggregator;
+use alarm::AlarmEngine;
+use common_ezo::EzoChipAPI;
+use conductivity::{ConductivityAPI, ConductivitySensor};
+use config::{PublisherConfig, SensorConfig, SensorKind};
+use errors::*;
+use measurement::Measurement;
+use network::alarm;
+use network::history::HistoryService;
+use network::state::{SensorStates, StateRequest};
+use network::subscriber::Publication;
+use network::{ReplyStatus, SocketRequest};
+use ph::{PhAPI, PhSensor};
+use schedule::{reading_latency, Job, Schedule, Sleep};
+use state::{SensorState, StateChange, FAULT_THRESHOLD};
+use store::{LogReader, MeasurementLog};
+use temperature::{TemperatureAPI, TemperatureSensor};
+use utilities::poll_readable;
+
+use chrono::{DateTime, Utc};
+use zmq::{self, Context, Socket};
+
+/// A reading taken from a sensor, ready to be published.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    /// The reading, as returned by the sensor.
+    pub value: String,
+    /// The unit, or scale, of the reading.
+    pub unit: String,
+}
+
+/// A sensor that can be sampled by the publisher.
+pub trait SampleSource {
+    /// Take a reading from the sensor.
+    fn sample(&self) -> Result<Sample>;
+    /// Put the sensor to sleep (low-power mode) until the next sample.
+    fn sleep(&self) -> Result<()>;
+    /// The time it takes to take a reading.
+    fn latency(&self) -> Duration {
+        Duration::from_millis(0)
+    }
+}
+
+/// Samples anything that implements the `ConductivityAPI`.
+///
+/// The unit is given by the current output parameters, e.g. `EC,TDS`.
+pub struct ConductivitySource<T>(pub T);
+
+impl<T> SampleSource for ConductivitySource<T>
+where
+    T: ConductivityAPI<Error = Error> + EzoChipAPI<SensorError = Error>,
+{
+    fn sample(&self) -> Result<Sample> {
+        let params = self.0.get_output_params()?;
+        let reading = self.0.get_reading()?;
+        Ok(Sample {
+            value: format!("{}", reading),
+            unit: format!("{}", params),
+        })
+    }
+
+    fn sleep(&self) -> Result<()> {
+        let _sleep = self.0.set_sleep()?;
+        Ok(())
+    }
+
+    fn latency(&self) -> Duration {
+        reading_latency(SensorKind::Conductivity)
+    }
+}
+
+/// Samples anything that implements the `PhAPI`.
+pub struct PhSource<T>(pub T);
+
+impl<T> SampleSource for PhSource<T>
+where
+    T: PhAPI<Error = Error> + EzoChipAPI<SensorError = Error>,
+{
+    fn sample(&self) -> Result<Sample> {
+        let reading = self.0.get_reading()?;
+        Ok(Sample {
+            value: format!("{}", reading),
+            unit: "pH".to_string(),
+        })
+    }
+
+    fn sleep(&self) -> Result<()> {
+        let _sleep = self.0.set_sleep()?;
+        Ok(())
+    }
+
+    fn latency(&self) -> Duration {
+        reading_latency(SensorKind::Ph)
+    }
+}
+
+/// Samples anything that implements the `TemperatureAPI`.
+///
+/// The unit is given by the current temperature scale, e.g. `celsius`.
+pub struct TemperatureSource<T>(pub T);
+
+impl<T> SampleSource for TemperatureSource<T>
+where
+    T: TemperatureAPI<Error = Error> + EzoChipAPI<SensorError = Error>,
+{
+    fn sample(&self) -> Result<Sample> {
+        let scale = self.0.get_scale()?;
+        let reading = self.0.get_reading()?;
+        Ok(Sample {
+            value: format!("{}", reading),
+            unit: format!("{}", scale),
+        })
+    }
+
+    fn sleep(&self) -> Result<()> {
+        let _sleep = self.0.set_sleep()?;
+        Ok(())
+    }
+
+    fn latency(&self) -> Duration {
+        reading_latency(SensorKind::Temperature)
+    }
+}
+
+/// Format a publication message.
+pub fn publication(topic: &str, timestamp: &DateTime<Utc>, sample: &Sample) -> String {
+    format!("{} {:?} {} {}", topic, timestamp, sample.value, sample.unit)
+}
+
+// Store, aggregate, and watch for alarms, the measurements in a publication message. Returns
+// the statistics of the windows that are closed by them, and the alarm events they cause.
+fn record(
+    log: &mut Option<MeasurementLog>,
+    aggregator: &mut Option<Aggregator>,
+    alarms: &mut Option<AlarmEngine>,
+    topic: &str,
+    msg: &str,
+) -> Vec<String> {
+    let mut messages = Vec::new();
+    if log.is_none() && aggregator.is_none() && alarms.is_none() {
+        return messages;
+    }
+    let parsed = Publication::parse(msg).and_then(|p| Measurement::from_publication(&p));
+    let measurements = match parsed {
+        Ok(measurements) => measurements,
+        Err(e) => {
+            warn!("could not convert {} to measurements: {}", topic, e);
+            return messages;
+        }
+    };
+    for m in &measurements {
+        if let Some(ref mut log) = *log {
+            if let Err(e) = log.append(m) {
+                warn!("could not store {}: {}", topic, e);
+            }
+        }
+        if let Some(ref mut aggregator) = *aggregator {
+            messages.extend(aggregator.push(m).iter().map(|s| s.to_string()));
+        }
+        if let Some(ref mut alarms) = *alarms {
+            for event in alarms.push(m) {
+                info!("alarm: {}", &event);
+                messages.push(event.to_string());
+            }
+        }
+    }
+    messages
+}
+
+// A sensor that is sampled, and published, on its schedule.
+struct PublishedSensor {
+    topic: String,
+    job: Job,
+    source: Box<SampleSource>,
+    state: SensorState,
+    // Failed readings in a row.
+    failures: u32,
+}
+
+// Change the state of a sensor, if the change is allowed, and publish it. Sensors that go to
+// sleep are put to sleep. Returns the number of messages sent.
+fn change_state(
+    socket: &Socket,
+    sensor: &mut PublishedSensor,
+    state: SensorState,
+) -> Result<usize> {
+    let from = sensor.state;
+    sensor.state = from.change_to(state)?;
+    if from == state {
+        return Ok(0);
+    }
+    sensor.failures = 0;
+    info!("{} changed from {} to {}", &sensor.topic, from, state);
+    if state == SensorState::Sleeping {
+        if let Err(e) = sensor.source.sleep() {
+            warn!("could not put {} to sleep: {}", &sensor.topic, e);
+        }
+    }
+    let change = StateChange {
+        sensor: sensor.topic.clone(),
+        timestamp: Utc::now(),
+        from,
+        to: state,
+    };
+    let msg = change.to_string();
+    debug!("publishing: {}", &msg);
+    let _send = socket
+        .send(msg.as_bytes(), 0)
+        .context(ErrorKind::SocketSend)?;
+    Ok(1)
+}
+
+// The instant of a wall-clock time, or now, if it has passed.
+fn instant_at(time: &DateTime<Utc>) -> Instant {
+    let now = Instant::now();
+    match (*time - Utc::now()).to_std() {
+        Ok(wait) => now + wait,
+        Err(_) => now,
+    }
+}
+
+/// Publisher that samples sensors on their configured schedules.
+pub struct SensorPublisher {
+    socket: Socket,
+    sensors: Vec<PublishedSensor>,
+    log: Option<MeasurementLog>,
+    history: Option<HistoryService>,
+    aggregator: Option<Aggregator>,
+    alarms: Option<AlarmEngine>,
+    responder: Option<Socket>,
+    reload_requests: bool,
+    reload_requested: Cell<bool>,
+}
+
+impl SensorPublisher {
+    /// Create a new publisher from a `PUB` socket.
+    pub fn new(socket: Socket) -> Result<SensorPublisher> {
+        Ok(SensorPublisher {
+            socket,
+            sensors: Vec::new(),
+            log: None,
+            history: None,
+            aggregator: None,
+            alarms: None,
+            responder: None,
+            reload_requests: false,
+            reload_requested: Cell::new(false),
+        })
+    }
+
+    /// Create a new publisher with a `PUB` socket bound to the given URL.
+    pub fn bind(url: &str) -> Result<SensorPublisher> {
+        let context = Context::new();
+        let socket = context.socket(zmq::PUB).context(ErrorKind::SocketCreate)?;
+        let _bind = socket.bind(url).context(ErrorKind::SocketBind)?;
+        SensorPublisher::new(socket)
+    }
+
+    /// Create a new publisher, with its I2C sensors, from `PublisherConfig`.
+    pub fn from_config(config: &PublisherConfig) -> Result<SensorPublisher> {
+        let mut publisher = SensorPublisher::bind(config.pub_url)?;
+        if let Some(ref storage) = config.storage {
+            let log = MeasurementLog::open(&storage.path, storage.policy())?;
+            publisher.set_log(log);
+        }
+        if !config.aggregate.is_empty() {
+            publisher.set_aggregator(Aggregator::new(&config.aggregate));
+        }
+        if !config.alarms.is_empty() {
+            publisher.set_alarms(AlarmEngine::new(&config.alarms)?);
+        }
+        if let Some(rep_url) = config.rep_url {
+            let context = Context::new();
+            let socket = context.socket(zmq::REP).context(ErrorKind::SocketCreate)?;
+            let _bind = socket.bind(rep_url).context(ErrorKind::SocketBind)?;
+            publisher.set_responder(socket);
+        }
+        for sensor in &config.sensors {
+            let path = match sensor.path.to_str() {
+                Some(path) => path,
+                _ => return Err(ErrorKind::InvalidDevice)?,
+            };
+            let sensor_config = SensorConfig::new(path, sensor.address);
+            let interval = Duration::from_millis(sensor.interval);
+            match sensor.kind {
+                SensorKind::Conductivity => {
+                    let device = ConductivitySensor::from_config(sensor_config)?;
+                    publisher.add_sensor(
+                        sensor.topic,
+                        ConductivitySource(device),
+                        interval,
+                        sensor.sleep,
+                    );
+                }
+                SensorKind::Ph => {
+                    let device = PhSensor::from_config(sensor_config)?;
+                    publisher.add_sensor(sensor.topic, PhSource(device), interval, sensor.sleep);
+                }
+                SensorKind::Temperature => {
+                    let device = TemperatureSensor::from_config(sensor_config)?;
+                    publisher.add_sensor(
+                        sensor.topic,
+                        TemperatureSource(device),
+                        interval,
+                        sensor.sleep,
+                    );
+                }
+            }
+        }
+        Ok(publisher)
+    }
+
+    /// Add a sensor to be sampled every `interval`, and published on `topic`. When `sleep` is
+    /// `true`, the sensor is put to sleep after every sample.
+    pub fn add_sensor<S>(&mut self, topic: &str, source: S, interval: Duration, sleep: bool)
+    where
+        S: SampleSource + 'static,
+    {
+        let sleep = if sleep { Sleep::Always } else { Sleep::Never };
+        self.add_scheduled_sensor(topic, source, Schedule::Every(interval), sleep);
+    }
+
+    /// Add a sensor to be sampled on `schedule`, and published on `topic`. Readings start
+    /// ahead of their scheduled time by the latency of the sensor.
+    pub fn add_scheduled_sensor<S>(
+        &mut self,
+        topic: &str,
+        source: S,
+        schedule: Schedule,
+        sleep: Sleep,
+    ) where
+        S: SampleSource + 'static,
+    {
+        let job = Job::new(schedule, source.latency(), sleep, &Utc::now());
+        self.sensors.push(PublishedSensor {
+            topic: topic.to_string(),
+            job,
+            source: Box::new(source),
+            state: SensorState::Sampling,
+            failures: 0,
+        });
+    }
+
+    /// Stop sampling the sensor published on `topic`. Returns `false` if there is none.
+    pub fn remove_sensor(&mut self, topic: &str) -> bool {
+        let count = self.sensors.len();
+        self.sensors.retain(|s| s.topic != topic);
+        self.sensors.len() < count
+    }
+
+    /// Publish the sensor published on `topic` on `new_topic` instead, keeping its schedule.
+    /// Returns `false` if there is none.
+    pub fn rename_sensor(&mut self, topic: &str, new_topic: &str) -> bool {
+        match self.sensors.iter_mut().find(|s| s.topic == topic) {
+            Some(sensor) => {
+                sensor.topic = new_topic.to_string();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Sample the sensor published on `topic` on `schedule` instead, as with
+    /// `Job::reschedule`. Returns `false` if there is none.
+    pub fn reschedule_sensor(&mut self, topic: &str, schedule: Schedule, sleep: Sleep) -> bool {
+        match self.sensors.iter_mut().find(|s| s.topic == topic) {
+            Some(sensor) => {
+                sensor.job.reschedule(schedule, sleep, &Utc::now());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The topics of the sensors sampled.
+    pub fn topics(&self) -> Vec<&str> {
+        self.sensors.iter().map(|s| s.topic.as_str()).collect()
+    }
+
+    /// The state of the sensor published on `topic`, if there is one.
+    pub fn state(&self, topic: &str) -> Option<SensorState> {
+        self.sensors
+            .iter()
+            .find(|s| s.topic == topic)
+            .map(|s| s.state)
+    }
+
+    /// The states of every sensor, by topic.
+    pub fn states(&self) -> Vec<(String, SensorState)> {
+        self.sensors
+            .iter()
+            .map(|s| (s.topic.clone(), s.state))
+            .collect()
+    }
+
+    /// Change the state of the sensor published on `topic`, if the change is allowed. The
+    /// change is published on the `state` topic.
+    pub fn set_state(&mut self, topic: &str, state: SensorState) -> Result<()> {
+        let sensor = match self.sensors.iter_mut().find(|s| s.topic == topic) {
+            Some(sensor) => sensor,
+            None => return Err(ErrorKind::UnknownResource(topic.to_string()).into()),
+        };
+        let _published = change_state(&self.socket, sensor, state)?;
+        Ok(())
+    }
+
+    /// When the next sensor is due to be sampled, if there are any.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.sensors
+            .iter()
+            .filter_map(|s| s.job.start_at())
+            .min()
+            .map(|start| instant_at(&start))
+    }
+
+    /// Publish on the given `PUB` socket, instead of the current one.
+    pub fn set_socket(&mut self, socket: Socket) {
+        self.socket = socket;
+    }
+
+    /// Store every sample in the given measurement log.
+    pub fn set_log(&mut self, log: MeasurementLog) {
+        self.history = Some(HistoryService::new(LogReader::new(log.dir())));
+        self.log = Some(log);
+    }
+
+    /// Stop storing samples.
+    pub fn remove_log(&mut self) {
+        self.log = None;
+        self.history = None;
+    }
+
+    /// The measurement log, if one is set.
+    pub fn log(&self) -> Option<&MeasurementLog> {
+        self.log.as_ref()
+    }
+
+    /// Publish the statistics computed by the given aggregator.
+    pub fn set_aggregator(&mut self, aggregator: Aggregator) {
+        self.aggregator = Some(aggregator);
+    }
+
+    /// Stop publishing statistics.
+    pub fn remove_aggregator(&mut self) {
+        self.aggregator = None;
+    }
+
+    /// Publish the alarms raised, and cleared, by the given engine.
+    pub fn set_alarms(&mut self, alarms: AlarmEngine) {
+        self.alarms = Some(alarms);
+    }
+
+    /// Stop watching samples for alarms.
+    pub fn remove_alarms(&mut self) {
+        self.alarms = None;
+    }
+
+    /// The alarm engine, if one is set.
+    pub fn alarms(&self) -> Option<&AlarmEngine> {
+        self.alarms.as_ref()
+    }
+
+    /// Answer `alarms`, and `history`, requests on the given `REP` socket.
+    pub fn set_responder(&mut self, socket: Socket) {
+        self.responder = Some(socket);
+    }
+
+    /// Stop answering requests. The responder socket is closed.
+    pub fn remove_responder(&mut self) {
+        self.responder = None;
+    }
+
+    /// Answer `reload` requests, which are refused unless allowed.
+    pub fn set_reload_requests(&mut self, allowed: bool) {
+        self.reload_requests = allowed;
+    }
+
+    /// Whether a `reload` request was answered since the last call.
+    pub fn take_reload_request(&self) -> bool {
+        self.reload_requested.replace(false)
+    }
+
+    /// Run the publisher. This will run for as long as the program runs, or until there are
+    /// no sensors left to publish.
+    pub fn run(&mut self) -> Result<()> {
+        loop {
+            let next_at = match self.next_due() {
+                Some(next_at) => next_at,
+                None => return Err(ErrorKind::RunTime("no sensors to publish".to_string()).into()),
+            };
+            let now = Instant::now();
+            if next_at > now {
+                let _served = self.wait(next_at - now)?;
+            }
+            let _published = self.publish_due()?;
+        }
+    }
+
+    /// Wait for `timeout`, answering the requests that arrive on the responder socket, if
+    /// there is one. Returns the number of requests answered.
+    pub fn wait(&mut self, timeout: Duration) -> Result<usize> {
+        if self.responder.is_some() {
+            return self.serve(timeout);
+        }
+        thread::sleep(timeout);
+        Ok(0)
+    }
+
+    /// Answer the requests that arrive on the responder socket within `timeout`. Returns the
+    /// number of requests answered.
+    pub fn serve(&mut self, timeout: Duration) -> Result<usize> {
+        let deadline = Instant::now() + timeout;
+        let mut served = 0;
+        loop {
+            let readable = match self.responder {
+                Some(ref socket) => {
+                    let now = Instant::now();
+                    let wait = if deadline > now {
+                        deadline - now
+                    } else {
+                        Duration::from_secs(0)
+                    };
+                    poll_readable(socket, wait)?
+                }
+                None => false,
+            };
+            if !readable {
+                return Ok(served);
+            }
+            let _serve = self.serve_one()?;
+            served += 1;
+        }
+    }
+
+    // Receive one request on the responder socket, and reply to it.
+    fn serve_one(&mut self) -> Result<()> {
+        let received = match self.responder {
+            Some(ref socket) => socket.recv_string(0).context(ErrorKind::SocketReceive)?,
+            None => return Ok(()),
+        };
+        let reply = match received {
+            Ok(req_str) => self.respond(&req_str),
+            Err(_) => format!("{:?}", ReplyStatus::Err),
+        };
+        if let Some(ref socket) = self.responder {
+            let _send = socket
+                .send(reply.as_bytes(), 0)
+                .context(ErrorKind::SocketSend)?;
+        }
+        Ok(())
+    }
+
+    // Reply to `alarms`, `history`, `reload`, or `state`, requests. Anything else is an error.
+    fn respond(&mut self, req_str: &str) -> String {
+        if req_str.trim() == "reload" && self.reload_requests {
+            self.reload_requested.set(true);
+            return format!("{:?}", ReplyStatus::Ok);
+        }
+        if let Ok(request) = <StateRequest as SocketRequest>::from_str(req_str) {
+            return match self.answer_state(&request) {
+                Ok(states) => states.to_string(),
+                Err(e) => {
+                    warn!("could not answer {:?}: {}", req_str, e);
+                    format!("{:?}", ReplyStatus::Err)
+                }
+            };
+        }
+        let mut reply = None;
+        if let Some(ref alarms) = self.alarms {
+            reply = alarm::respond(alarms, req_str);
+        }
+        if reply.is_none() {
+            if let Some(ref history) = self.history {
+                reply = history.respond(req_str);
+            }
+        }
+        reply.unwrap_or_else(|| format!("{:?}", ReplyStatus::Err))
+    }
+
+    // Change the state of a sensor, if requested, and list the states asked for.
+    fn answer_state(&mut self, request: &StateRequest) -> Result<SensorStates> {
+        let sensor = match request.sensor {
+            Some(ref sensor) => sensor,
+            None => return Ok(SensorStates(self.states())),
+        };
+        if let Some(state) = request.state {
+            self.set_state(sensor, state)?;
+        }
+        match self.state(sensor) {
+            Some(state) => Ok(SensorStates(vec![(sensor.to_string(), state)])),
+            None => Err(ErrorKind::UnknownResource(sensor.to_string()).into()),
+        }
+    }
+
+    /// Sample, and publish, every sensor that is due. Returns the number of messages sent.
+    ///
+    /// Sensor errors are logged, and do not stop the publisher.
+    pub fn publish_due(&mut self) -> Result<usize> {
+        let mut published = 0;
+        for sensor in self.sensors.iter_mut() {
+            let now = Utc::now();
+            if !sensor.job.is_due(&now) {
+                continue;
+            }
+            sensor.job.advance(&now);
+            if !sensor.state.samples() {
+                continue;
+            }
+            let sample = match sensor.source.sample() {
+                Ok(sample) => sample,
+                Err(e) => {
+                    error!("could not sample {}: {}", &sensor.topic, e);
+                    sensor.failures += 1;
+                    if sensor.failures >= FAULT_THRESHOLD && sensor.state == SensorState::Sampling {
+                        published += change_state(&self.socket, sensor, SensorState::Fault)?;
+                    }
+                    continue;
+                }
+            };
+            sensor.failures = 0;
+            if sensor.state == SensorState::Fault {
+                published += change_state(&self.socket, sensor, SensorState::Sampling)?;
+            }
+            // Chips being calibrated, or serviced, are kept awake.
+            if sensor.state == SensorState::Sampling && sensor.job.sleeps_after(&now) {
+                if let Err(e) = sensor.source.sleep() {
+                    warn!("could not put {} to sleep: {}", &sensor.topic, e);
+                }
+            }
+            let topic = sensor.state.topic(&sensor.topic);
+            let msg = publication(&topic, &Utc::now(), &sample);
+            let mut messages = if sensor.state.records() {
+                record(
+                    &mut self.log,
+                    &mut self.aggregator,
+                    &mut self.alarms,
+                    &sensor.topic,
+                    &msg,
+                )
+            } else {
+                Vec::new()
+            };
+            messages.insert(0, msg);
+            for msg in messages {
+                debug!("publishing: {}", &msg);
+                let _send = self
+                    .socket
+                    .send(msg.as_bytes(), 0)
+                    .context(ErrorKind::SocketSend)?;
+                published += 1;
+            }
+        }
+        // Windows that ended without newer samples.
+        if let Some(ref mut aggregator) = self.aggregator {
+            for stats in aggregator.flush(&Utc::now()) {
+                let msg = stats.to_string();
+                debug!("publishing: {}", &msg);
+                let _send = self
+                    .socket
+                    .send(msg.as_bytes(), 0)
+                    .context(ErrorKind::SocketSend)?;
+                published += 1;
+            }
+        }
+        Ok(published)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    struct FakeSource {
+        samples: Rc<Cell<u32>>,
+        sleeps: Rc<Cell<u32>>,
+    }
+
+    impl SampleSource for FakeSource {
+        fn sample(&self) -> Result<Sample> {
+            self.samples.set(self.samples.get() + 1);
+            Ok(Sample {
+                value: "19.521".to_string(),
+                unit: "celsius".to_string(),
+            })
+        }
+
+        fn sleep(&self) -> Result<()> {
+            self.sleeps.set(self.sleeps.get() + 1);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn format_publication_message() {
+        let timestamp = Utc.ymd(2017, 11, 18).and_hms_milli(23, 40, 5, 123);
+        let sample = Sample {
+            value: "19.521".to_string(),
+            unit: "celsius".to_string(),
+        };
+        assert_eq!(
+            "temperature-0123 2017-11-18T23:40:05.123Z 19.521 celsius",
+            publication("temperature-0123", &timestamp, &sample)
+        );
+    }
+
+    #[test]
+    fn publish_only_the_sensors_that_are_due() {
+        let context = Context::new();
+        let socket = context.socket(zmq::PUB).unwrap();
+        socket.bind("inproc://publisher-test").unwrap();
+        let mut publisher = SensorPublisher::new(socket).unwrap();
+
+        let (samples, sleeps) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let fast = FakeSource {
+            samples: samples.clone(),
+            sleeps: sleeps.clone(),
+        };
+        let slow = FakeSource {
+            samples: samples.clone(),
+            sleeps: sleeps.clone(),
+        };
+        publisher.add_sensor("fast", fast, Duration::from_millis(0), false);
+        publisher.add_sensor("slow", slow, Duration::from_secs(3600), true);
+
+        assert_eq!(2, publisher.publish_due().unwrap());
+        assert_eq!(1, sleeps.get());
+        assert_eq!(1, publisher.publish_due().unwrap());
+        assert_eq!(3, samples.get());
+        assert_eq!(1, sleeps.get());
+    }
+
+    #[test]
+    fn sample_aligned_sensors_on_the_hour() {
+        let context = Context::new();
+        let socket = context.socket(zmq::PUB).unwrap();
+        socket.bind("inproc://publisher-schedule-test").unwrap();
+        let mut publisher = SensorPublisher::new(socket).unwrap();
+
+        let (samples, sleeps) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let source = FakeSource {
+            samples: samples.clone(),
+            sleeps: sleeps.clone(),
+        };
+        let hourly = Schedule::Aligned(Duration::from_secs(3600));
+        publisher.add_scheduled_sensor("ph-01", source, hourly, Sleep::Auto);
+        assert_eq!(0, publisher.publish_due().unwrap());
+        assert_eq!(0, samples.get());
+        let next_due = publisher.next_due().unwrap();
+        assert!(next_due <= Instant::now() + Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn update_sensors_in_place() {
+        let context = Context::new();
+        let socket = context.socket(zmq::PUB).unwrap();
+        socket.bind("inproc://publisher-update-test").unwrap();
+        let mut publisher = SensorPublisher::new(socket).unwrap();
+
+        let (samples, sleeps) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let source = FakeSource {
+            samples: samples.clone(),
+            sleeps: sleeps.clone(),
+        };
+        publisher.add_sensor("ph-01", source, Duration::from_secs(3600), false);
+        assert_eq!(1, publisher.publish_due().unwrap());
+        assert_eq!(0, publisher.publish_due().unwrap());
+
+        // A shorter interval takes effect right away.
+        let schedule = Schedule::Every(Duration::from_millis(0));
+        assert!(publisher.reschedule_sensor("ph-01", schedule, Sleep::Always));
+        assert!(publisher.rename_sensor("ph-01", "ph-02"));
+        assert_eq!(vec!["ph-02"], publisher.topics());
+        assert_eq!(1, publisher.publish_due().unwrap());
+        assert_eq!(1, sleeps.get());
+
+        assert!(!publisher.remove_sensor("ph-01"));
+        assert!(publisher.remove_sensor("ph-02"));
+        assert_eq!(None, publisher.next_due());
+        assert_eq!(0, publisher.publish_due().unwrap());
+        assert_eq!(2, samples.get());
+
+        assert_eq!("err", publisher.respond("reload"));
+        assert!(!publisher.take_reload_request());
+        publisher.set_reload_requests(true);
+        assert_eq!("ok", publisher.respond("reload"));
+        assert!(publisher.take_reload_request());
+        assert!(!publisher.take_reload_request());
+    }
+
+    #[test]
+    fn store_samples_without_subscribers() {
+        use store::{LogPolicy, MeasurementLog};
+
+        let dir = ::std::env::temp_dir().join(format!("benita-publisher-{}", ::std::process::id()));
+        let _remove = ::std::fs::remove_dir_all(&dir);
+
+        let context = Context::new();
+        let socket = context.socket(zmq::PUB).unwrap();
+        socket.bind("inproc://publisher-log-test").unwrap();
+        let mut publisher = SensorPublisher::new(socket).unwrap();
+        publisher.set_log(MeasurementLog::open(&dir, LogPolicy::default()).unwrap());
+
+        let (samples, sleeps) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let source = FakeSource { samples, sleeps };
+        publisher.add_sensor("temperature-01", source, Duration::from_millis(0), false);
+
+        assert_eq!(1, publisher.publish_due().unwrap());
+        assert_eq!(1, publisher.publish_due().unwrap());
+        let stored = publisher.log().unwrap().read_all().unwrap();
+        assert_eq!(2, stored.len());
+        assert_eq!(19.521, stored[0].value);
+        assert_eq!("temperature-01", stored[1].topic());
+        let _remove = ::std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stop_running_without_sensors() {
+        let context = Context::new();
+        let socket = context.socket(zmq::PUB).unwrap();
+        socket.bind("inproc://publisher-empty-test").unwrap();
+        let mut publisher = SensorPublisher::new(socket).unwrap();
+        let (samples, sleeps) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let source = FakeSource { samples, sleeps };
+        publisher.add_sensor("ph-01", source, Duration::from_secs(60), false);
+        assert!(publisher.remove_sensor("ph-01"));
+        assert!(publisher.run().is_err());
+    }
+
+    #[test]
+    fn sensor_states_over_the_responder() {
+        use store::{LogPolicy, MeasurementLog};
+
+        let dir = ::std::env::temp_dir().join(format!("benita-states-{}", ::std::process::id()));
+        let _remove = ::std::fs::remove_dir_all(&dir);
+
+        let context = Context::new();
+        let socket = context.socket(zmq::PUB).unwrap();
+        socket.bind("inproc://publisher-state-test").unwrap();
+        let mut publisher = SensorPublisher::new(socket).unwrap();
+        publisher.set_log(MeasurementLog::open(&dir, LogPolicy::default()).unwrap());
+
+        let (samples, sleeps) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let source = FakeSource {
+            samples: samples.clone(),
+            sleeps: sleeps.clone(),
+        };
+        publisher.add_sensor("ph-01", source, Duration::from_millis(0), false);
+
+        assert_eq!("states 1\nph-01 sampling", publisher.respond("state"));
+        assert_eq!(
+            "states 1\nph-01 calibrating",
+            publisher.respond("state ph-01 calibration")
+        );
+        assert_eq!(1, publisher.publish_due().unwrap());
+        assert_eq!(1, samples.get());
+        assert!(publisher.log().unwrap().read_all().unwrap().is_empty());
+
+        assert_eq!("err", publisher.respond("state ph-01 sleeping"));
+        assert_eq!("err", publisher.respond("state ph-02 sampling"));
+        assert_eq!(Some(SensorState::Calibrating), publisher.state("ph-01"));
+
+        publisher.set_state("ph-01", SensorState::Sampling).unwrap();
+        assert_eq!(1, publisher.publish_due().unwrap());
+        assert_eq!(1, publisher.log().unwrap().read_all().unwrap().len());
+
+        publisher.set_state("ph-01", SensorState::Sleeping).unwrap();
+        assert_eq!(1, sleeps.get());
+        assert_eq!(0, publisher.publish_due().unwrap());
+        assert_eq!(2, samples.get());
+        let _remove = ::std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn publish_and_answer_alarms() {
+        use alarm::AlarmRule;
+        use network::alarm::{ActiveAlarms, AlarmsRequester};
+        use network::{Endpoint, SocketReply};
+
+        let context = Context::new();
+        let socket = context.socket(zmq::PUB).unwrap();
+        socket.bind("inproc://publisher-alarm-test").unwrap();
+        let mut publisher = SensorPublisher::new(socket).unwrap();
+        let rule = AlarmRule {
+            name: "too-warm".to_string(),
+            sensor: "temperature-01".to_string(),
+            high: Some(18.0),
+            ..AlarmRule::default()
+        };
+        publisher.set_alarms(AlarmEngine::new(&[rule]).unwrap());
+        let responder = context.socket(zmq::REP).unwrap();
+        responder.bind("inproc://publisher-alarm-rep").unwrap();
+        publisher.set_responder(responder);
+
+        let (samples, sleeps) = (Rc::new(Cell::new(0)), Rc::new(Cell::new(0)));
+        let source = FakeSource { samples, sleeps };
+        publisher.add_sensor("temperature-01", source, Duration::from_millis(0), false);
+
+        // The reading, and the raised alarm.
+        assert_eq!(2, publisher.publish_due().unwrap());
+        assert_eq!(1, publisher.publish_due().unwrap());
+        assert_eq!(1, publisher.alarms().unwrap().active().len());
+
+        let requester = AlarmsRequester::new(context.socket(zmq::REQ).unwrap()).unwrap();
+        requester.connect("inproc://publisher-alarm-rep").unwrap();
+        requester.send(b"alarms").unwrap();
+        assert_eq!(1, publisher.serve(Duration::from_millis(100)).unwrap());
+        let reply = requester.recv().unwrap();
+        let alarms = <ActiveAlarms as SocketReply>::from_str(&reply).unwrap();
+        assert_eq!("too-warm", alarms.0[0].rule);
+
+        // Without a measurement log, history requests are errors.
+        requester
+            .send(b"history temperature-01 2017-07-14T02:40:00Z 2017-07-14T03:40:00Z")
+            .unwrap();
+        assert_eq!(1, publisher.serve(Duration::from_millis(100)).unwrap());
+        assert_eq!("err", requester.recv().unwrap());
+    }
+}
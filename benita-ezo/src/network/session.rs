@@ -0,0 +1,658 @@
+//! Session locks that guard the calibration of networked sensors.
+//!
+//! Requests that change the calibration of a conductivity, or pH, responder, e.g.
+//! `calibration-low 4.000` or `calibration-clear`, are only answered in the session of the
+//! client that holds its lock. Clients acquire the lock under their name, and get a random
+//! token from the responder, which they use to keep the lock with heartbeats, send their
+//! requests, and release the lock when they are done:
+//!
+//! ```text
+//! session-lock <owner> [<timeout>]
+//! session-heartbeat <token>
+//! as <token> <request>
+//! session-unlock <token>
+//! session-status
+//! ```
+//!
+//! A lock expires `timeout` seconds, or `DEFAULT_SESSION_TIMEOUT`, after the last heartbeat,
+//! or request, of its owner. Timeouts longer than `MAX_SESSION_TIMEOUT` are refused. The
+//! lock is answered with `token <token> <seconds-left>`, and every other session request with
+//! the state of the lock, `unlocked` or `locked <owner> <seconds-left>`. The owner's name is
+//! only shown to other clients, the token is never.
+//!
+//! Refused requests are answered with the reason, e.g. `err locked by ana`, which requesters
+//! return as `ErrorKind::SessionLocked`, or `ErrorKind::SessionRequired`.
+use std::cell::RefCell;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use errors::*;
+use network::{Endpoint, ReplyStatus, SocketReply, SocketRequest};
+
+use rand::{self, Rng};
+use zmq::Socket;
+
+/// Seconds a session lock is kept without heartbeats, unless given.
+pub const DEFAULT_SESSION_TIMEOUT: u64 = 300;
+
+/// Most seconds a session lock may be kept without heartbeats.
+pub const MAX_SESSION_TIMEOUT: u64 = 86_400;
+
+// The prefix of the replies to refused requests.
+const LOCKED_BY: &str = "locked by ";
+
+/// Request to acquire the session lock of a responder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionLock {
+    /// The name of the client, shown to other clients.
+    pub owner: String,
+    /// Seconds to keep the lock without heartbeats, if not the default.
+    pub timeout: Option<u64>,
+}
+
+impl_SocketRequest_for! {
+    SessionLock: SessionToken,
+    req_str: {
+        let args: Vec<&str> = req_str.split_whitespace().collect();
+        if args.len() < 2 || args.len() > 3 || args[0] != "session-lock" {
+            return Err(ErrorKind::RequestParse.into());
+        }
+        let timeout = match args.get(2) {
+            Some(timeout) => Some(timeout.parse::<u64>().context(ErrorKind::NumberParse)?),
+            None => None,
+        };
+        if timeout.map_or(false, |t| t > MAX_SESSION_TIMEOUT) {
+            return Err(ErrorKind::RequestParse.into());
+        }
+        Ok(SessionLock {
+            owner: args[1].to_string(),
+            timeout,
+        })
+    },
+    req_out: {
+        match req_out.timeout {
+            Some(timeout) => format!("session-lock {} {}", req_out.owner, timeout),
+            None => format!("session-lock {}", req_out.owner),
+        }
+    }
+}
+
+/// Request to keep the session lock of a responder, with the token of its owner.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionHeartbeat(pub String);
+
+impl_SocketRequest_for! {
+    SessionHeartbeat: SessionStatus,
+    req_str: {
+        let args: Vec<&str> = req_str.split_whitespace().collect();
+        if args.len() != 2 || args[0] != "session-heartbeat" {
+            return Err(ErrorKind::RequestParse.into());
+        }
+        Ok(SessionHeartbeat(args[1].to_string()))
+    },
+    req_out: {
+        format!("session-heartbeat {}", req_out.0)
+    }
+}
+
+/// Request to release the session lock of a responder, with the token of its owner.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionUnlock(pub String);
+
+impl_SocketRequest_for! {
+    SessionUnlock: SessionStatus,
+    req_str: {
+        let args: Vec<&str> = req_str.split_whitespace().collect();
+        if args.len() != 2 || args[0] != "session-unlock" {
+            return Err(ErrorKind::RequestParse.into());
+        }
+        Ok(SessionUnlock(args[1].to_string()))
+    },
+    req_out: {
+        format!("session-unlock {}", req_out.0)
+    }
+}
+
+/// Request for the state of the session lock of a responder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionState;
+
+impl_SocketRequest_for! {
+    SessionState: SessionStatus,
+    req_str: {
+        match req_str {
+            "session-status" => Ok(SessionState),
+            _ => Err(ErrorKind::RequestParse.into()),
+        }
+    },
+    _req_out: {
+        "session-status".to_string()
+    }
+}
+
+/// A request sent in the session of the holder of `token`, e.g.
+/// `as 3f9a0c17d2e84b6a calibration-low 4.000`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OwnedRequest {
+    /// The token of the session, as given by the responder.
+    pub token: String,
+    /// The request, as understood by the responder.
+    pub request: String,
+}
+
+impl_SocketRequest_for! {
+    OwnedRequest: ReplyStatus,
+    req_str: {
+        let mut args = req_str.splitn(3, ' ');
+        match (args.next(), args.next(), args.next()) {
+            (Some("as"), Some(token), Some(request)) if !token.is_empty() && !request.is_empty() => {
+                Ok(OwnedRequest {
+                    token: token.to_string(),
+                    request: request.to_string(),
+                })
+            }
+            _ => Err(ErrorKind::RequestParse.into()),
+        }
+    },
+    req_out: {
+        format!("as {} {}", req_out.token, req_out.request)
+    }
+}
+
+/// The state of the session lock of a responder.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionStatus {
+    /// No client holds the lock.
+    Unlocked,
+    /// The lock is held by `owner` for `seconds` more, unless kept.
+    Locked { owner: String, seconds: u64 },
+}
+
+impl SessionStatus {
+    fn parse(rep_str: &str) -> Result<SessionStatus> {
+        let fields: Vec<&str> = rep_str.split_whitespace().collect();
+        if fields == ["unlocked"] {
+            return Ok(SessionStatus::Unlocked);
+        }
+        if fields.len() != 3 || fields[0] != "locked" {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        let seconds = fields[2].parse::<u64>().context(ErrorKind::ResponseParse)?;
+        Ok(SessionStatus::Locked {
+            owner: fields[1].to_string(),
+            seconds,
+        })
+    }
+}
+
+impl fmt::Display for SessionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SessionStatus::Unlocked => write!(f, "unlocked"),
+            SessionStatus::Locked { ref owner, seconds } => {
+                write!(f, "locked {} {}", owner, seconds)
+            }
+        }
+    }
+}
+
+impl_SocketReply_for!(SessionStatus);
+
+/// The session lock granted to a client, with the token of its session.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionToken {
+    /// The token that proves the ownership of the lock.
+    pub token: String,
+    /// Seconds the lock is held, unless kept.
+    pub seconds: u64,
+}
+
+impl SessionToken {
+    fn parse(rep_str: &str) -> Result<SessionToken> {
+        let fields: Vec<&str> = rep_str.split_whitespace().collect();
+        if fields.len() != 3 || fields[0] != "token" {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        let seconds = fields[2].parse::<u64>().context(ErrorKind::ResponseParse)?;
+        Ok(SessionToken {
+            token: fields[1].to_string(),
+            seconds,
+        })
+    }
+}
+
+impl fmt::Display for SessionToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "token {} {}", self.token, self.seconds)
+    }
+}
+
+impl_SocketReply_for!(SessionToken);
+
+/// The reply to a request refused by a session lock, e.g. `err locked by ana`.
+pub fn refusal_reply(error: &Error) -> String {
+    format!("{:?} {}", ReplyStatus::Err, error)
+}
+
+/// The error of a refused request, if `rep_str` is the reply to one.
+pub fn refusal(rep_str: &str) -> Option<Error> {
+    let err = format!("{:?} ", ReplyStatus::Err);
+    if !rep_str.starts_with(&err) {
+        return None;
+    }
+    let reason = rep_str[err.len()..].trim();
+    if reason.starts_with(LOCKED_BY) {
+        let owner = reason[LOCKED_BY.len()..].to_string();
+        Some(ErrorKind::SessionLocked(owner).into())
+    } else if reason == ErrorKind::SessionRequired.to_string() {
+        Some(ErrorKind::SessionRequired.into())
+    } else {
+        None
+    }
+}
+
+// The client that holds a lock, its token, and until when.
+#[derive(Clone, Debug)]
+struct Session {
+    owner: String,
+    token: String,
+    timeout: Duration,
+    expires: Instant,
+}
+
+/// The session lock of a responder. Only its owner may change the calibration of the
+/// sensor, until it releases the lock, or lets it expire.
+#[derive(Debug, Default)]
+pub struct CalibrationLock {
+    session: RefCell<Option<Session>>,
+}
+
+impl CalibrationLock {
+    /// Create a lock that nobody holds.
+    pub fn new() -> CalibrationLock {
+        CalibrationLock::default()
+    }
+
+    // Drop the session if it has expired, and return it otherwise.
+    fn holder(&self, now: Instant) -> Option<Session> {
+        let mut session = self.session.borrow_mut();
+        let expired = match *session {
+            Some(ref s) if s.expires <= now => {
+                info!("session of {} expired", &s.owner);
+                true
+            }
+            Some(_) => false,
+            None => return None,
+        };
+        if expired {
+            *session = None;
+        }
+        session.clone()
+    }
+
+    /// Acquire the lock for `owner`, for `timeout` after `now`, and return the token of the
+    /// new session. Fails with `ErrorKind::SessionLocked` if anyone holds it, and with
+    /// `ErrorKind::RequestParse` if the lock would expire past the end of time.
+    pub fn acquire(&self, owner: &str, timeout: Duration, now: Instant) -> Result<String> {
+        if let Some(holder) = self.holder(now) {
+            return Err(ErrorKind::SessionLocked(holder.owner).into());
+        }
+        let expires = match now.checked_add(timeout) {
+            Some(expires) => expires,
+            None => return Err(ErrorKind::RequestParse.into()),
+        };
+        let mut rng = rand::thread_rng();
+        let token = format!("{:016x}{:016x}", rng.gen::<u64>(), rng.gen::<u64>());
+        info!("session of {} locked for {:?}", owner, timeout);
+        *self.session.borrow_mut() = Some(Session {
+            owner: owner.to_string(),
+            token: token.clone(),
+            timeout,
+            expires,
+        });
+        Ok(token)
+    }
+
+    /// Keep the lock of the holder of `token` for its timeout after `now`. Fails if `token`
+    /// is not the token of the lock.
+    pub fn heartbeat(&self, token: &str, now: Instant) -> Result<()> {
+        match self.holder(now) {
+            Some(ref holder) if holder.token == token => {
+                if let Some(ref mut session) = *self.session.borrow_mut() {
+                    match now.checked_add(session.timeout) {
+                        Some(expires) => session.expires = expires,
+                        None => return Err(ErrorKind::RequestParse.into()),
+                    }
+                }
+                Ok(())
+            }
+            Some(holder) => Err(ErrorKind::SessionLocked(holder.owner).into()),
+            None => Err(ErrorKind::SessionRequired.into()),
+        }
+    }
+
+    /// Release the lock of the holder of `token`. Releasing a lock that nobody holds does
+    /// nothing. Fails with `ErrorKind::SessionLocked` if `token` is not the token of the
+    /// lock.
+    pub fn release(&self, token: &str, now: Instant) -> Result<()> {
+        match self.holder(now) {
+            Some(ref holder) if holder.token != token => {
+                Err(ErrorKind::SessionLocked(holder.owner.to_string()).into())
+            }
+            Some(holder) => {
+                info!("session of {} unlocked", holder.owner);
+                *self.session.borrow_mut() = None;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// The state of the lock at `now`.
+    pub fn status(&self, now: Instant) -> SessionStatus {
+        let _holder = self.holder(now);
+        match *self.session.borrow() {
+            Some(ref session) => SessionStatus::Locked {
+                owner: session.owner.clone(),
+                seconds: (session.expires - now).as_secs(),
+            },
+            None => SessionStatus::Unlocked,
+        }
+    }
+
+    // Answer a session request, if `req_str` is one.
+    fn answer(&self, req_str: &str, now: Instant) -> Option<Result<String>> {
+        if req_str.split_whitespace().next() == Some("session-lock") {
+            let granted = <SessionLock as SocketRequest>::from_str(req_str).and_then(|req| {
+                let timeout = req.timeout.unwrap_or(DEFAULT_SESSION_TIMEOUT);
+                let token = self.acquire(&req.owner, Duration::from_secs(timeout), now)?;
+                Ok(SessionToken { token, seconds: timeout })
+            });
+            return Some(granted.map(|token| SocketReply::to_string(&token)));
+        }
+        let answered = if let Ok(req) = <SessionHeartbeat as SocketRequest>::from_str(req_str) {
+            self.heartbeat(&req.0, now)
+        } else if let Ok(req) = <SessionUnlock as SocketRequest>::from_str(req_str) {
+            self.release(&req.0, now)
+        } else if <SessionState as SocketRequest>::from_str(req_str).is_ok() {
+            Ok(())
+        } else {
+            return None;
+        };
+        Some(answered.map(|_| SocketReply::to_string(&self.status(now))))
+    }
+
+    /// Answer session requests, and pass every other request to `respond`. Requests that
+    /// satisfy `guarded` are only passed on when sent by the holder of the lock, as an
+    /// `OwnedRequest`, and keep the lock. Refused requests are answered with the reason.
+    pub fn respond<G, F>(&self, req_str: &str, guarded: G, respond: F) -> Result<String>
+    where
+        G: Fn(&str) -> bool,
+        F: FnOnce(&str) -> Result<String>,
+    {
+        let now = Instant::now();
+        let refused = match self.answer(req_str, now) {
+            Some(Ok(reply)) => return Ok(reply),
+            Some(Err(e)) => e,
+            None => {
+                let (token, request) = match <OwnedRequest as SocketRequest>::from_str(req_str) {
+                    Ok(owned) => (Some(owned.token), owned.request),
+                    Err(_) => (None, req_str.to_string()),
+                };
+                if !guarded(&request) {
+                    return respond(&request);
+                }
+                let kept = match token {
+                    Some(ref token) => self.heartbeat(token, now),
+                    None => match self.holder(now) {
+                        Some(holder) => Err(ErrorKind::SessionLocked(holder.owner).into()),
+                        None => Err(ErrorKind::SessionRequired.into()),
+                    },
+                };
+                match kept {
+                    Ok(_) => return respond(&request),
+                    Err(e) => e,
+                }
+            }
+        };
+        warn!("refused {:?}: {}", req_str, refused);
+        Ok(refusal_reply(&refused))
+    }
+}
+
+// Creates a client for the session requests to any calibration responder.
+network_socket!(
+    SessionRequester,
+    "Socket that acquires, keeps, and releases, the session lock of a sensor responder."
+);
+
+impl SessionRequester {
+    /// Acquire the session lock for `owner`, with the default timeout unless given, and
+    /// return the token of the session.
+    pub fn lock(&self, owner: &str, timeout: Option<u64>) -> Result<SessionToken> {
+        SessionLock {
+            owner: owner.to_string(),
+            timeout,
+        }
+        .send(self)
+    }
+
+    /// Keep the session lock of the holder of `token`.
+    pub fn heartbeat(&self, token: &str) -> Result<SessionStatus> {
+        SessionHeartbeat(token.to_string()).send(self)
+    }
+
+    /// Release the session lock of the holder of `token`.
+    pub fn unlock(&self, token: &str) -> Result<SessionStatus> {
+        SessionUnlock(token.to_string()).send(self)
+    }
+
+    /// The state of the session lock.
+    pub fn status(&self) -> Result<SessionStatus> {
+        SessionState.send(self)
+    }
+
+    /// Send a request, e.g. `calibration-mid 7.000`, in the session of the holder of
+    /// `token`.
+    pub fn send_as(&self, token: &str, request: &str) -> Result<ReplyStatus> {
+        OwnedRequest {
+            token: token.to_string(),
+            request: request.to_string(),
+        }
+        .send(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calibration(req_str: &str) -> bool {
+        req_str.starts_with("calibration-")
+    }
+
+    #[test]
+    fn parse_session_requests_from_valid_str() {
+        let request = <SessionLock as SocketRequest>::from_str("session-lock ana 60").unwrap();
+        assert_eq!("ana", request.owner);
+        assert_eq!(Some(60), request.timeout);
+        assert_eq!("session-lock ana 60", SocketRequest::to_string(&request));
+        let request = <SessionLock as SocketRequest>::from_str("session-lock ana").unwrap();
+        assert_eq!(None, request.timeout);
+
+        let request = <OwnedRequest as SocketRequest>::from_str("as ana calibration-low 4.000");
+        assert_eq!("calibration-low 4.000", request.unwrap().request);
+
+        for invalid in &[
+            "session-lock",
+            "session-lock ana soon",
+            "session-heartbeat",
+            "session-unlock ana bob",
+            "as ana",
+        ] {
+            assert!(<SessionLock as SocketRequest>::from_str(invalid).is_err());
+            assert!(<SessionHeartbeat as SocketRequest>::from_str(invalid).is_err());
+            assert!(<SessionUnlock as SocketRequest>::from_str(invalid).is_err());
+            assert!(<OwnedRequest as SocketRequest>::from_str(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn parse_session_replies_and_refusals() {
+        let status = SessionStatus::Locked {
+            owner: "ana".to_string(),
+            seconds: 42,
+        };
+        let reply = <SessionStatus as SocketReply>::to_string(&status);
+        assert_eq!("locked ana 42", reply);
+        assert_eq!(status, SessionStatus::parse(&reply).unwrap());
+        assert_eq!(
+            SessionStatus::Unlocked,
+            SessionStatus::parse("unlocked").unwrap()
+        );
+        assert!(SessionStatus::parse("locked ana").is_err());
+
+        let token = SessionToken {
+            token: "0123456789abcdef".to_string(),
+            seconds: 300,
+        };
+        let reply = <SessionToken as SocketReply>::to_string(&token);
+        assert_eq!("token 0123456789abcdef 300", reply);
+        assert_eq!(token, SessionToken::parse(&reply).unwrap());
+        assert!(SessionToken::parse("locked ana 300").is_err());
+
+        let locked: Error = ErrorKind::SessionLocked("ana".to_string()).into();
+        assert_eq!("err locked by ana", refusal_reply(&locked));
+        assert_eq!(
+            ErrorKind::SessionLocked("ana".to_string()),
+            refusal("err locked by ana").unwrap().kind()
+        );
+        let required: Error = ErrorKind::SessionRequired.into();
+        assert_eq!(
+            ErrorKind::SessionRequired,
+            refusal(&refusal_reply(&required)).unwrap().kind()
+        );
+        assert!(refusal("err").is_none());
+        assert!(refusal("ok").is_none());
+    }
+
+    #[test]
+    fn locks_expire_without_heartbeats() {
+        let lock = CalibrationLock::new();
+        let start = Instant::now();
+        let minute = Duration::from_secs(60);
+        let token = lock.acquire("ana", minute, start).unwrap();
+        assert_eq!(32, token.len());
+        assert_eq!(
+            ErrorKind::SessionLocked("ana".to_string()),
+            lock.acquire("bob", minute, start).unwrap_err().kind()
+        );
+        assert!(lock.acquire("ana", minute, start).is_err());
+        assert!(lock.release("ana", start).is_err());
+        assert!(lock.heartbeat("ana", start).is_err());
+
+        let later = start + Duration::from_secs(45);
+        lock.heartbeat(&token, later).unwrap();
+        assert_eq!(
+            SessionStatus::Locked {
+                owner: "ana".to_string(),
+                seconds: 60,
+            },
+            lock.status(later)
+        );
+        assert!(lock.acquire("bob", minute, start + minute).is_err());
+
+        let expired = later + minute;
+        assert_eq!(SessionStatus::Unlocked, lock.status(expired));
+        assert_eq!(
+            ErrorKind::SessionRequired,
+            lock.heartbeat(&token, expired).unwrap_err().kind()
+        );
+        let other = lock.acquire("bob", minute, expired).unwrap();
+        assert_ne!(token, other);
+        assert!(lock.release(&token, expired).is_err());
+        lock.release(&other, expired).unwrap();
+        assert_eq!(SessionStatus::Unlocked, lock.status(expired));
+    }
+
+    #[test]
+    fn guard_calibration_requests_with_the_lock() {
+        let lock = CalibrationLock::new();
+        let echo = |req_str: &str| Ok(req_str.to_string());
+
+        assert_eq!("read", lock.respond("read", calibration, echo).unwrap());
+        assert_eq!(
+            "err calibration needs a session lock",
+            lock.respond("calibration-clear", calibration, echo)
+                .unwrap()
+        );
+        let reply = lock.respond("session-lock ana", calibration, echo).unwrap();
+        let granted = SessionToken::parse(&reply).unwrap();
+        assert_eq!(300, granted.seconds);
+        let token = granted.token;
+
+        let owned = format!("as {} calibration-low 4.000", token);
+        assert_eq!(
+            "calibration-low 4.000",
+            lock.respond(&owned, calibration, echo).unwrap()
+        );
+        for refused in &[
+            "as ana calibration-clear",
+            "as bob calibration-clear",
+            "calibration-clear",
+            "session-lock bob 60",
+            "session-lock ana",
+            "session-heartbeat ana",
+            "session-unlock ana",
+        ] {
+            assert_eq!(
+                "err locked by ana",
+                lock.respond(refused, calibration, echo).unwrap()
+            );
+        }
+        let status = lock.respond("session-status", calibration, echo).unwrap();
+        assert!(status.starts_with("locked ana "));
+        assert!(!status.contains(&token));
+        let unlock = format!("session-unlock {}", token);
+        assert_eq!(
+            "unlocked",
+            lock.respond(&unlock, calibration, echo).unwrap()
+        );
+        assert_eq!(
+            "unlocked",
+            lock.respond("session-status", calibration, echo).unwrap()
+        );
+    }
+
+    #[test]
+    fn refuse_timeouts_past_the_maximum() {
+        let huge = "session-lock x 18446744073709551615";
+        assert_eq!(
+            ErrorKind::RequestParse,
+            <SessionLock as SocketRequest>::from_str(huge)
+                .unwrap_err()
+                .kind()
+        );
+        let longest = format!("session-lock x {}", MAX_SESSION_TIMEOUT);
+        assert!(<SessionLock as SocketRequest>::from_str(&longest).is_ok());
+
+        let lock = CalibrationLock::new();
+        let now = Instant::now();
+        let forever = Duration::from_secs(::std::u64::MAX);
+        assert_eq!(
+            ErrorKind::RequestParse,
+            lock.acquire("x", forever, now).unwrap_err().kind()
+        );
+        assert_eq!(SessionStatus::Unlocked, lock.status(now));
+
+        let echo = |req_str: &str| Ok(req_str.to_string());
+        assert_eq!(
+            "err could not parse request",
+            lock.respond(huge, calibration, echo).unwrap()
+        );
+        assert_eq!(
+            "unlocked",
+            lock.respond("session-status", calibration, echo).unwrap()
+        );
+    }
+}
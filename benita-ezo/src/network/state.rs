@@ -0,0 +1,170 @@
+//! Remote queries, and changes, of the operating states of the sensors of a node.
+//!
+//! Publishers answer `state` requests with the states of all their sensors, or of one, and
+//! change the state of a sensor when one is given:
+//!
+//! ```text
+//! state [<sensor> [<state>]]
+//! ```
+//!
+//! Replies list the states after the request:
+//!
+//! ```text
+//! states <count>
+//! <sensor> <state>
+//! ...
+//! ```
+//!
+//! Sensors enter, and leave, `fault` on their own, so it cannot be requested.
+use std::fmt;
+
+use errors::*;
+use network::{Endpoint, SocketReply, SocketRequest};
+use state::SensorState;
+
+use zmq::Socket;
+
+/// Request for the states of the sensors of a node, or to change the state of one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StateRequest {
+    /// The topic of the sensor, or `None` for every sensor.
+    pub sensor: Option<String>,
+    /// The state to change the sensor to, if any.
+    pub state: Option<SensorState>,
+}
+
+impl StateRequest {
+    fn parse(req_str: &str) -> Result<StateRequest> {
+        let mut args = req_str.split_whitespace();
+        if args.next() != Some("state") {
+            return Err(ErrorKind::RequestParse.into());
+        }
+        let sensor = args.next().map(|s| s.to_string());
+        let state = match args.next() {
+            Some(state) => Some(state.parse::<SensorState>()?),
+            None => None,
+        };
+        if args.next().is_some() || state == Some(SensorState::Fault) {
+            return Err(ErrorKind::RequestParse.into());
+        }
+        Ok(StateRequest { sensor, state })
+    }
+}
+
+impl_SocketRequest_for! {
+    StateRequest: SensorStates,
+    req_str: {
+        StateRequest::parse(req_str)
+    },
+    req: {
+        let mut req_str = "state".to_string();
+        if let Some(ref sensor) = req.sensor {
+            req_str.push_str(&format!(" {}", sensor));
+            if let Some(state) = req.state {
+                req_str.push_str(&format!(" {}", state));
+            }
+        }
+        req_str
+    }
+}
+
+/// The states of the sensors of a node, in reply to a `StateRequest`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SensorStates(pub Vec<(String, SensorState)>);
+
+impl SensorStates {
+    fn parse(rep_str: &str) -> Result<SensorStates> {
+        let mut lines = rep_str.lines();
+        let header: Vec<&str> = match lines.next() {
+            Some(header) => header.split_whitespace().collect(),
+            None => return Err(ErrorKind::ResponseParse.into()),
+        };
+        if header.len() != 2 || header[0] != "states" {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        let count = header[1]
+            .parse::<usize>()
+            .context(ErrorKind::ResponseParse)?;
+        let mut states = Vec::new();
+        for line in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 2 {
+                return Err(ErrorKind::ResponseParse.into());
+            }
+            let state = fields[1]
+                .parse::<SensorState>()
+                .context(ErrorKind::ResponseParse)?;
+            states.push((fields[0].to_string(), state));
+        }
+        if states.len() != count {
+            return Err(ErrorKind::ResponseParse.into());
+        }
+        Ok(SensorStates(states))
+    }
+}
+
+impl fmt::Display for SensorStates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "states {}", self.0.len())?;
+        for &(ref sensor, state) in &self.0 {
+            write!(f, "\n{} {}", sensor, state)?;
+        }
+        Ok(())
+    }
+}
+
+impl_SocketReply_for!(SensorStates);
+
+// Creates a client for state requests to any node that publishes sensors.
+network_socket!(
+    StateRequester,
+    "Socket that requests, and changes, the states of the sensors of a node."
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_state_requests_from_valid_str() {
+        let request = <StateRequest as SocketRequest>::from_str("state").unwrap();
+        assert_eq!(StateRequest::default(), request);
+        assert_eq!("state", SocketRequest::to_string(&request));
+
+        let request = <StateRequest as SocketRequest>::from_str("state ph-01 cleaning").unwrap();
+        assert_eq!(Some("ph-01".to_string()), request.sensor);
+        assert_eq!(Some(SensorState::Maintenance), request.state);
+        assert_eq!(
+            "state ph-01 maintenance",
+            SocketRequest::to_string(&request)
+        );
+
+        for invalid in &[
+            "states",
+            "state ph-01 fault",
+            "state ph-01 resting",
+            "state a b c",
+        ] {
+            assert!(<StateRequest as SocketRequest>::from_str(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn parse_sensor_states_replies() {
+        let states = SensorStates(vec![
+            ("ph-01".to_string(), SensorState::Calibrating),
+            ("temperature-01".to_string(), SensorState::Sampling),
+        ]);
+        let reply = <SensorStates as SocketReply>::to_string(&states);
+        assert_eq!(
+            "states 2\nph-01 calibrating\ntemperature-01 sampling",
+            reply
+        );
+        assert_eq!(
+            states,
+            <SensorStates as SocketReply>::from_str(&reply).unwrap()
+        );
+        assert!(<SensorStates as SocketReply>::from_str("states 1").is_err());
+        assert!(<SensorStates as SocketReply>::from_str("err").is_err());
+    }
+}
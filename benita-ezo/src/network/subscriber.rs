@@ -0,0 +1,268 @@
+//! Subscription to sensor readings published over a `PUB` socket.
+//!
+//! Messages are expected in the format sent by the `SensorPublisher`:
+//!
+//! ```text
+//! <topic> <timestamp> <value>[,<value>...] <unit>
+//! ```
+use std::fmt;
+use std::time::Duration;
+
+use errors::*;
+use measurement::Measurement;
+use utilities::{atof, poll_readable};
+
+use chrono::{DateTime, Utc};
+use zmq::{self, Context, Socket};
+
+/// A sensor reading, as published by a `SensorPublisher`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Publication {
+    /// The topic, or channel, the reading was published on.
+    pub topic: String,
+    /// When the reading was taken.
+    pub timestamp: DateTime<Utc>,
+    /// The values in the reading. Conductivity readings may hold several values, one for
+    /// each output parameter.
+    pub values: Vec<f64>,
+    /// The unit, or scale, of the reading, e.g. `celsius`, `pH`, or `EC,TDS`.
+    pub unit: String,
+}
+
+impl Publication {
+    /// Parse a published message.
+    pub fn parse(msg: &str) -> Result<Publication> {
+        let mut split = msg.trim().splitn(4, ' ');
+        let topic = match split.next() {
+            Some(topic) if !topic.is_empty() => topic.to_string(),
+            _ => return Err(ErrorKind::ResponseParse.into()),
+        };
+        let timestamp = match split.next() {
+            Some(dt) => dt
+                .parse::<DateTime<Utc>>()
+                .context(ErrorKind::ResponseParse)?,
+            _ => return Err(ErrorKind::ResponseParse.into()),
+        };
+        let values = match split.next() {
+            Some(values) => values
+                .split(',')
+                .map(atof)
+                .collect::<Result<Vec<f64>>>()
+                .context(ErrorKind::ResponseParse)?,
+            _ => return Err(ErrorKind::ResponseParse.into()),
+        };
+        let unit = match split.next() {
+            Some(unit) if !unit.trim().is_empty() => unit.trim().to_string(),
+            _ => return Err(ErrorKind::ResponseParse.into()),
+        };
+        Ok(Publication {
+            topic,
+            timestamp,
+            values,
+            unit,
+        })
+    }
+
+    /// The first value in the reading, if it has any.
+    pub fn value(&self) -> Option<f64> {
+        self.values.first().cloned()
+    }
+}
+
+impl fmt::Display for Publication {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values: Vec<String> = self.values.iter().map(|v| format!("{}", v)).collect();
+        write!(
+            f,
+            "{} {:?} {} {}",
+            self.topic,
+            self.timestamp,
+            values.join(","),
+            self.unit
+        )
+    }
+}
+
+/// Longest time a loop over the publications of a subscriber waits before checking whether
+/// to stop, in milliseconds.
+pub const STOP_CHECK_INTERVAL: u64 = 500;
+
+/// Subscriber to sensor readings on one or more topic prefixes.
+///
+/// Malformed messages are reported as `ErrorKind::ResponseParse` errors, and the
+/// subscription continues. When a reconnect timeout is set, the subscriber reconnects to the
+/// publisher whenever nothing has been received for that long, which helps with publishers
+/// that restart behind proxies, or on new addresses.
+pub struct Subscriber {
+    context: Context,
+    socket: Socket,
+    url: String,
+    topics: Vec<String>,
+    reconnect_timeout: Option<Duration>,
+}
+
+impl Subscriber {
+    /// Create a new subscriber connected to the publisher at `url`, subscribed to the given
+    /// topic prefixes. An empty prefix subscribes to every topic.
+    pub fn connect(url: &str, topics: &[&str]) -> Result<Subscriber> {
+        let context = Context::new();
+        let socket = Subscriber::subscribe(&context, url, topics)?;
+        Ok(Subscriber {
+            context,
+            socket,
+            url: url.to_string(),
+            topics: topics.iter().map(|t| t.to_string()).collect(),
+            reconnect_timeout: None,
+        })
+    }
+
+    fn subscribe<T: AsRef<str>>(context: &Context, url: &str, topics: &[T]) -> Result<Socket> {
+        let socket = context.socket(zmq::SUB).context(ErrorKind::SocketCreate)?;
+        let _linger = socket.set_linger(0).context(ErrorKind::SocketCreate)?;
+        let _ivl = socket
+            .set_reconnect_ivl(100)
+            .context(ErrorKind::SocketCreate)?;
+        let _ivl_max = socket
+            .set_reconnect_ivl_max(5_000)
+            .context(ErrorKind::SocketCreate)?;
+        let _connect = socket.connect(url).context(ErrorKind::SocketConnect)?;
+        for topic in topics {
+            let _subscribe = socket
+                .set_subscribe(topic.as_ref().as_bytes())
+                .context(ErrorKind::SocketConnect)?;
+        }
+        Ok(socket)
+    }
+
+    /// Set the time without messages after which the subscriber reconnects.
+    pub fn set_reconnect_timeout(&mut self, timeout: Option<Duration>) {
+        self.reconnect_timeout = timeout;
+    }
+
+    /// Drop the current connection, and subscribe again.
+    pub fn reconnect(&mut self) -> Result<()> {
+        info!("reconnecting subscriber to {}", &self.url);
+        self.socket = Subscriber::subscribe(&self.context, &self.url, &self.topics)?;
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for a message. Returns `true` if one can be received without
+    /// blocking.
+    pub fn poll(&self, timeout: Duration) -> Result<bool> {
+        poll_readable(&self.socket, timeout)
+    }
+
+    /// Receive the next message, as a string.
+    pub fn recv_string(&mut self) -> Result<String> {
+        if let Some(timeout) = self.reconnect_timeout {
+            while !poll_readable(&self.socket, timeout)? {
+                let _reconnect = self.reconnect()?;
+            }
+        }
+        match self
+            .socket
+            .recv_string(0)
+            .context(ErrorKind::SocketReceive)?
+        {
+            Ok(msg) => Ok(msg),
+            Err(_) => Err(ErrorKind::ResponseParse.into()),
+        }
+    }
+
+    /// Receive, and parse, the next publication.
+    pub fn recv(&mut self) -> Result<Publication> {
+        let msg = self.recv_string()?;
+        debug!("received publication: {:?}", &msg);
+        Publication::parse(&msg)
+    }
+
+    /// Receive the next publication, as measurements.
+    pub fn recv_measurements(&mut self) -> Result<Vec<Measurement>> {
+        let publication = self.recv()?;
+        Measurement::from_publication(&publication)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn parse_publication_from_valid_str() {
+        let msg = "temperature-0123 2017-11-18T23:40:05.123Z 19.521 celsius";
+        let publication = Publication::parse(msg).unwrap();
+        assert_eq!(
+            publication,
+            Publication {
+                topic: "temperature-0123".to_string(),
+                timestamp: Utc.ymd(2017, 11, 18).and_hms_milli(23, 40, 5, 123),
+                values: vec![19.521],
+                unit: "celsius".to_string(),
+            }
+        );
+        assert_eq!(msg, publication.to_string());
+
+        let msg = "ec-0123 2017-11-18T23:40:05Z 1413,706 EC,TDS";
+        let publication = Publication::parse(msg).unwrap();
+        assert_eq!(vec![1413.0, 706.0], publication.values);
+        assert_eq!("EC,TDS", publication.unit);
+        assert_eq!(msg, publication.to_string());
+
+        let msg = "ph-0123 2017-11-18T17:40:05-06:00 7.012 pH";
+        let publication = Publication::parse(msg).unwrap();
+        assert_eq!(
+            Utc.ymd(2017, 11, 18).and_hms(23, 40, 5),
+            publication.timestamp
+        );
+        assert_eq!(Some(7.012), publication.value());
+    }
+
+    #[test]
+    fn parse_publication_from_invalid_str_yields_err() {
+        let invalid = [
+            "",
+            "temperature-0123",
+            "temperature-0123 2017-11-18T23:40:05Z",
+            "temperature-0123 2017-11-18T23:40:05Z 19.521",
+            "temperature-0123 2017-11-18T23:40:05Z 19.521 ",
+            "temperature-0123 yesterday 19.521 celsius",
+            "temperature-0123 2017-11-18T23:40:05Z hot celsius",
+            "ec-0123 2017-11-18T23:40:05Z 1413,,706 EC,TDS",
+        ];
+        for msg in invalid.iter() {
+            let publication = Publication::parse(msg);
+            assert_eq!(ErrorKind::ResponseParse, publication.unwrap_err().kind());
+        }
+    }
+
+    #[test]
+    fn subscriber_reports_malformed_messages_and_continues() {
+        let context = Context::new();
+        let publisher = context.socket(zmq::PUB).unwrap();
+        publisher.bind("tcp://127.0.0.1:0").unwrap();
+        let url = publisher.get_last_endpoint().unwrap().unwrap();
+
+        let mut subscriber = Subscriber::connect(&url, &["temp"]).unwrap();
+        // Allow the subscription to reach the publisher.
+        ::std::thread::sleep(Duration::from_millis(200));
+
+        publisher
+            .send("temperature-0123 garbage".as_bytes(), 0)
+            .unwrap();
+        publisher
+            .send("ph-0123 2017-11-18T23:40:05Z 7.0 pH".as_bytes(), 0)
+            .unwrap();
+        publisher
+            .send(
+                "temperature-0123 2017-11-18T23:40:05Z 19.5 celsius".as_bytes(),
+                0,
+            )
+            .unwrap();
+
+        assert!(subscriber.recv().is_err());
+        let publication = subscriber.recv().unwrap();
+        assert_eq!("temperature-0123", publication.topic);
+        assert_eq!(Some(19.5), publication.value());
+    }
+}
@@ -1,4 +1,6 @@
 //! Network traits
+use std::time::Duration;
+
 use errors::*;
 
 /// A response sent over a socket
@@ -14,6 +16,13 @@ where
     fn send(&self, msg: &[u8]) -> Result<()>;
     /// Receive a `String` from the endpoint.
     fn recv(&self) -> Result<String>;
+    /// Wait up to `timeout` for a message. Returns `true` if one is ready to be received.
+    ///
+    /// Endpoints that do not override it return `true` right away, and `recv` blocks until
+    /// a message arrives.
+    fn poll(&self, _timeout: Duration) -> Result<bool> {
+        Ok(true)
+    }
 }
 
 /// A request sent over a socket
@@ -0,0 +1,375 @@
+//! WebSocket stream of measurements, and alarm events, for browsers.
+//!
+//! The `HttpGateway` upgrades `GET /stream` requests to WebSocket connections, and sends
+//! every message received from its publisher as a JSON text frame:
+//!
+//! ```text
+//! {"type":"measurement","topic":"ph-0123456789abcdef","timestamp":"2017-11-18T23:40:05Z","value":7.012,"unit":"pH"}
+//! {"type":"alarm","event":"raise","rule":"ph-range","severity":"warning","sensor":"ph-0123456789abcdef","timestamp":"2017-11-18T23:40:05Z","value":8.6}
+//! ```
+//!
+//! Clients filter the stream by topic prefix, the same way `SUB` sockets do, with one or
+//! more `topic` parameters, e.g. `/stream?topic=ph&topic=alarm`. Alarm events are on the
+//! `alarm` topic. Without a `topic`, every message is sent. The latest measurement of every
+//! sensor that matches the filter is sent as soon as the client connects.
+//!
+//! Messages from clients are ignored, and the stream ends when the client disconnects.
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use alarm::{AlarmEvent, AlarmEventKind, ALARM_TOPIC};
+use errors::*;
+use measurement::{Measurement, SensorType};
+use network::subscriber::{Publication, Subscriber};
+
+use base64;
+use chrono::SecondsFormat;
+use serde_json::Value;
+use sha1::Sha1;
+
+/// Appended to the client's key to compute the `Sec-WebSocket-Accept` header.
+pub const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Time without messages after which the stream sends a ping, in seconds.
+pub const PING_INTERVAL_SECS: u64 = 30;
+
+/// The `Sec-WebSocket-Accept` header for a client's `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.trim().as_bytes());
+    sha1.update(WEBSOCKET_GUID.as_bytes());
+    base64::encode(&sha1.digest().bytes())
+}
+
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x80 | opcode];
+    let len = payload.len();
+    if len < 126 {
+        out.push(len as u8);
+    } else if len <= 0xffff {
+        out.push(126);
+        out.push((len >> 8) as u8);
+        out.push(len as u8);
+    } else {
+        out.push(127);
+        for i in (0..8).rev() {
+            out.push((len as u64 >> (8 * i)) as u8);
+        }
+    }
+    out.extend_from_slice(payload);
+    out
+}
+
+/// A text frame, as sent by the server, i.e. unmasked.
+pub fn text_frame(text: &str) -> Vec<u8> {
+    frame(0x1, text.as_bytes())
+}
+
+/// A ping frame, as sent by the server.
+pub fn ping_frame() -> Vec<u8> {
+    frame(0x9, &[])
+}
+
+/// The JSON messages for a message published by a `SensorPublisher`, with their topics.
+/// Readings yield one message for each of their measurements.
+pub fn stream_messages(msg: &str) -> Result<Vec<(String, Value)>> {
+    if msg.starts_with(ALARM_TOPIC) {
+        let event = AlarmEvent::parse(msg)?;
+        let kind = match event.kind {
+            AlarmEventKind::Raise => "raise",
+            AlarmEventKind::Clear => "clear",
+        };
+        let message = json!({
+            "type": "alarm",
+            "event": kind,
+            "rule": event.rule,
+            "severity": format!("{}", event.severity),
+            "sensor": event.sensor,
+            "timestamp": event.timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            "value": event.value,
+        });
+        return Ok(vec![(ALARM_TOPIC.to_string(), message)]);
+    }
+    let publication = Publication::parse(msg)?;
+    let measurements = Measurement::from_publication(&publication)?;
+    Ok(measurements
+        .iter()
+        .map(|m| {
+            let message = json!({
+                "type": "measurement",
+                "topic": m.topic(),
+                "timestamp": m.timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+                "value": m.value_f64(),
+                "unit": m.sensor_type.unit(),
+            });
+            (m.topic(), message)
+        })
+        .collect())
+}
+
+// A connected client, and the topic prefixes it is interested in.
+struct StreamClient {
+    prefixes: Vec<String>,
+    sender: Sender<String>,
+}
+
+impl StreamClient {
+    fn wants(&self, topic: &str) -> bool {
+        self.prefixes.is_empty() || self.prefixes.iter().any(|p| topic.starts_with(p.as_str()))
+    }
+}
+
+/// Fans out stream messages to the connected clients, keeping the latest measurement of
+/// every sensor.
+#[derive(Default)]
+pub struct StreamHub {
+    latest: BTreeMap<String, String>,
+    clients: Vec<StreamClient>,
+}
+
+impl StreamHub {
+    /// Create a new hub, without clients.
+    pub fn new() -> StreamHub {
+        StreamHub::default()
+    }
+
+    /// Send a message on `topic` to every interested client. Measurements are kept as the
+    /// latest message of their topic.
+    pub fn publish(&mut self, topic: &str, message: &Value) {
+        let text = message.to_string();
+        if message["type"] == "measurement" {
+            let _latest = self.latest.insert(topic.to_string(), text.clone());
+        }
+        // Clients that went away have dropped their receivers.
+        self.clients
+            .retain(|c| !c.wants(topic) || c.sender.send(text.clone()).is_ok());
+    }
+
+    /// Add a client, interested in the topics that start with any of `prefixes`. The
+    /// latest measurements of those topics are queued right away.
+    pub fn subscribe(&mut self, prefixes: &[String]) -> Receiver<String> {
+        let (sender, receiver) = channel();
+        let client = StreamClient {
+            prefixes: prefixes.to_vec(),
+            sender,
+        };
+        for (topic, text) in &self.latest {
+            if client.wants(topic) {
+                let _send = client.sender.send(text.clone());
+            }
+        }
+        self.clients.push(client);
+        receiver
+    }
+
+    /// The number of connected clients.
+    pub fn clients(&self) -> usize {
+        self.clients.len()
+    }
+}
+
+fn lock_hub(hub: &Arc<Mutex<StreamHub>>) -> MutexGuard<StreamHub> {
+    match hub.lock() {
+        Ok(hub) => hub,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Start a thread that subscribes to the readings, and alarm events, published at `url`, and
+/// sends them to the clients of `hub`.
+pub fn stream_publications(hub: Arc<Mutex<StreamHub>>, url: &str) -> Result<JoinHandle<()>> {
+    let mut topics: Vec<&str> = SensorType::all().iter().map(|t| t.name()).collect();
+    topics.push(ALARM_TOPIC);
+    let mut subscriber = Subscriber::connect(url, &topics)?;
+    subscriber.set_reconnect_timeout(Some(Duration::from_secs(60)));
+    let handle = thread::spawn(move || loop {
+        let messages = subscriber
+            .recv_string()
+            .and_then(|msg| stream_messages(&msg));
+        match messages {
+            Ok(messages) => {
+                let mut hub = lock_hub(&hub);
+                for &(ref topic, ref message) in &messages {
+                    hub.publish(topic, message);
+                }
+            }
+            Err(e) => warn!("skipping publication: {}", e),
+        }
+    });
+    Ok(handle)
+}
+
+/// Complete the WebSocket handshake for the client's `key`, and stream the messages of `hub`
+/// that match `prefixes`, until the client disconnects.
+pub fn serve_stream(
+    mut stream: TcpStream,
+    key: &str,
+    prefixes: &[String],
+    hub: &Arc<Mutex<StreamHub>>,
+) -> Result<()> {
+    let _write = write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    ).context(ErrorKind::SocketSend)?;
+    let receiver = lock_hub(hub).subscribe(prefixes);
+    let interval = Duration::from_secs(PING_INTERVAL_SECS);
+    loop {
+        let bytes = match receiver.recv_timeout(interval) {
+            Ok(text) => text_frame(&text),
+            Err(RecvTimeoutError::Timeout) => ping_frame(),
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        if stream.write_all(&bytes).is_err() {
+            // The client went away, its receiver is dropped along with this thread.
+            return Ok(());
+        }
+    }
+}
+
+/// Read a frame, as sent by a client, i.e. masked. Returns its opcode, and payload.
+pub fn read_frame<R: Read>(reader: &mut R) -> Result<(u8, Vec<u8>)> {
+    let mut header = [0; 2];
+    let _read = reader
+        .read_exact(&mut header)
+        .context(ErrorKind::SocketReceive)?;
+    let opcode = header[0] & 0x0f;
+    let masked = header[1] & 0x80 == 0x80;
+    let mut len = u64::from(header[1] & 0x7f);
+    if len >= 126 {
+        let size = if len == 126 { 2 } else { 8 };
+        let mut extended = [0; 8];
+        let _read = reader
+            .read_exact(&mut extended[..size])
+            .context(ErrorKind::SocketReceive)?;
+        len = extended[..size]
+            .iter()
+            .fold(0, |n, b| n << 8 | u64::from(*b));
+    }
+    let mut mask = [0; 4];
+    if masked {
+        let _read = reader
+            .read_exact(&mut mask)
+            .context(ErrorKind::SocketReceive)?;
+    }
+    let mut payload = vec![0; len as usize];
+    let _read = reader
+        .read_exact(&mut payload)
+        .context(ErrorKind::SocketReceive)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((opcode, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn accept_websocket_keys() {
+        // The example in RFC 6455.
+        assert_eq!(
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=",
+            accept_key("dGhlIHNhbXBsZSBub25jZQ==")
+        );
+    }
+
+    #[test]
+    fn encode_and_decode_frames() {
+        let frame = text_frame("hello");
+        assert_eq!(vec![0x81, 5, b'h', b'e', b'l', b'l', b'o'], frame);
+        assert_eq!(
+            (1, b"hello".to_vec()),
+            read_frame(&mut Cursor::new(frame)).unwrap()
+        );
+
+        let text = "x".repeat(300);
+        let frame = text_frame(&text);
+        assert_eq!(vec![0x81, 126, 1, 44], frame[..4].to_vec());
+        assert_eq!(
+            text.as_bytes(),
+            &read_frame(&mut Cursor::new(frame)).unwrap().1[..]
+        );
+
+        // Client frames are masked.
+        let masked = vec![0x81, 0x82, 1, 2, 3, 4, b'h' ^ 1, b'i' ^ 2];
+        assert_eq!(
+            (1, b"hi".to_vec()),
+            read_frame(&mut Cursor::new(masked)).unwrap()
+        );
+        assert_eq!(vec![0x89, 0], ping_frame());
+    }
+
+    #[test]
+    fn stream_messages_of_publications() {
+        let messages =
+            stream_messages("conductivity-0123456789abcdef 2017-11-18T23:40:05Z 1.5,320 EC,TDS")
+                .unwrap();
+        assert_eq!(2, messages.len());
+        assert_eq!("tds-0123456789abcdef", messages[1].0);
+        assert_eq!(
+            json!({
+                "type": "measurement",
+                "topic": "tds-0123456789abcdef",
+                "timestamp": "2017-11-18T23:40:05Z",
+                "value": 320.0,
+                "unit": "ppm",
+            }),
+            messages[1].1
+        );
+
+        let messages = stream_messages(
+            "alarm raise ph-range critical ph-0123456789abcdef 2017-11-18T23:40:05Z 8.6",
+        )
+        .unwrap();
+        assert_eq!("alarm", messages[0].0);
+        assert_eq!("raise", messages[0].1["event"]);
+        assert_eq!("critical", messages[0].1["severity"]);
+        assert_eq!(8.6, messages[0].1["value"]);
+
+        assert!(stream_messages("alarm raise").is_err());
+        assert!(stream_messages("ph-0123456789abcdef").is_err());
+    }
+
+    #[test]
+    fn send_latest_and_matching_messages_to_clients() {
+        let mut hub = StreamHub::new();
+        let ph = json!({"type": "measurement", "value": 7.0});
+        hub.publish("ph-01", &ph);
+        hub.publish("ph-01", &json!({"type": "measurement", "value": 7.5}));
+        hub.publish(
+            "temperature-01",
+            &json!({"type": "measurement", "value": 20.0}),
+        );
+        hub.publish("alarm", &json!({"type": "alarm"}));
+
+        // Only the latest measurements are queued on connection.
+        let alarms = hub.subscribe(&["alarm".to_string()]);
+        assert!(alarms.try_recv().is_err());
+        let everything = hub.subscribe(&[]);
+        let ph = hub.subscribe(&["ph".to_string()]);
+        assert_eq!(
+            r#"{"type":"measurement","value":7.5}"#,
+            ph.try_recv().unwrap()
+        );
+        assert!(ph.try_recv().is_err());
+        assert_eq!(2, everything.try_iter().count());
+
+        hub.publish("alarm", &json!({"type": "alarm"}));
+        assert_eq!(r#"{"type":"alarm"}"#, alarms.try_recv().unwrap());
+        assert!(ph.try_recv().is_err());
+        assert_eq!(1, everything.try_iter().count());
+
+        // Clients that went away are dropped.
+        drop(everything);
+        hub.publish("ph-01", &json!({"type": "measurement", "value": 7.0}));
+        assert_eq!(2, hub.clients());
+    }
+}
@@ -0,0 +1,1297 @@
+//! A whole sensor node, brought up from a `NodeConfig`.
+//!
+//! ```toml
+//! name = "tank-a"
+//!
+//! [[buses]]
+//! name = "i2c1"
+//! path = "/dev/i2c-1"
+//!
+//! [[sensors]]
+//! name = "ph1"
+//! kind = "ph"
+//! bus = "i2c1"
+//! address = 99
+//! topic = "ph-0123456789abcdef"
+//! rep_url = "tcp://*:5558"
+//! schedule = { interval = 10000 }
+//! options = { compensation = 25.0 }
+//!
+//! [publisher]
+//! url = "tcp://*:5557"
+//! rep_url = "tcp://*:5559"
+//! aggregate = ["1h"]
+//! reload = true
+//!
+//! [storage]
+//! path = "/var/lib/benita"
+//!
+//! [[alarms]]
+//! name = "ph-range"
+//! sensor = "ph-0123456789abcdef"
+//! low = 6.5
+//! high = 8.5
+//!
+//! [[exporters]]
+//! kind = "file"
+//! path = "/var/lib/benita/measurements.csv"
+//! ```
+//!
+//! Every sensor with a `rep_url` is owned by its responder, on a thread of its own, and the
+//! publisher samples it through a requester, giving up on samples not answered within
+//! `SAMPLE_TIMEOUT`. The publisher owns the other sensors, and samples them on the
+//! publisher's thread. Every exporter subscribes to the topics of the published sensors, on
+//! a thread of its own.
+//!
+//! A node brought up from a file reads it again when the process receives `SIGHUP`, or, with
+//! `reload = true`, when the publisher is sent a `reload` request, and applies the differences
+//! in place. Sensors are added, removed, and rescheduled without touching the others, and
+//! only the responders, and sockets, whose settings changed are brought up again. Sensors
+//! brought up again keep their operating state, unless it was `fault`. Exporters are brought
+//! up again when their settings, the publisher, or the published topics change.
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use aggregate::Aggregator;
+use alarm::AlarmEngine;
+use conductivity::{ConductivityAPI, ConductivitySensor};
+use config::{
+    ConfigLayers, ExporterConfig, InfluxConfig, NodeConfig, NodeSensorConfig, SensorConfig,
+    SensorKind, SensorOptions,
+};
+use errors::*;
+use export::{ExportOptions, Exporter};
+use influx::InfluxSink;
+use network::conductivity::{ConductivityRequester, ConductivityResponder};
+use network::ph::{PhRequester, PhResponder};
+use network::publisher::{ConductivitySource, PhSource, SensorPublisher, TemperatureSource};
+use network::subscriber::Subscriber;
+use network::temperature::{TemperatureRequester, TemperatureResponder};
+use network::{Endpoint, ReplyStatus};
+use ph::{PhAPI, PhSensor};
+use schedule::{Schedule, Sleep};
+use state::SensorState;
+use store::MeasurementLog;
+use temperature::{TemperatureAPI, TemperatureSensor};
+use utilities::{create_and_bind_responder, create_timed_requester};
+
+use libc;
+use zmq::{self, Context, Socket};
+
+/// Interval between the samples of a sensor without a schedule, in milliseconds.
+pub const DEFAULT_SAMPLE_INTERVAL: u64 = 10_000;
+
+/// Longest time the publisher waits for the responder of a sensor to reply, in milliseconds.
+pub const SAMPLE_TIMEOUT: u64 = 5_000;
+
+/// Longest time a running node waits before checking for a reload, in milliseconds.
+pub const RELOAD_CHECK_INTERVAL: u64 = 500;
+
+// Set when the process receives `SIGHUP`.
+static HANGUP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_hangup(_signal: libc::c_int) {
+    HANGUP.store(true, Ordering::SeqCst);
+}
+
+// Take note of `SIGHUP`, instead of terminating the process.
+fn catch_hangup() {
+    unsafe {
+        libc::signal(libc::SIGHUP, on_hangup as libc::sighandler_t);
+    }
+}
+
+// Whether the process received `SIGHUP` since the last call.
+fn take_hangup() -> bool {
+    HANGUP.swap(false, Ordering::SeqCst)
+}
+
+/// The URL to connect to a socket bound to `bind_url`, on the same host. Wildcard
+/// addresses, e.g. `tcp://*:5557`, are connected to on `localhost`.
+pub fn connect_url(bind_url: &str) -> String {
+    for wildcard in &["tcp://*:", "tcp://0.0.0.0:"] {
+        if bind_url.starts_with(wildcard) {
+            return format!("tcp://localhost:{}", &bind_url[wildcard.len()..]);
+        }
+    }
+    bind_url.to_string()
+}
+
+// Receive a request, and answer it.
+fn answer<T, F>(responder: &T, respond: &F) -> Result<()>
+where
+    T: Endpoint,
+    F: Fn(&str) -> Result<String>,
+{
+    let req_str = responder.recv()?;
+    info!("REQ: {}", &req_str);
+    let reply = match respond(&req_str) {
+        Ok(reply) => reply,
+        Err(e) => {
+            error!("could not answer {:?}: {}", &req_str, e);
+            format!("{:?}", ReplyStatus::Err)
+        }
+    };
+    info!("REP: {}", &reply);
+    let _send = responder.send(reply.as_bytes())?;
+    Ok(())
+}
+
+/// Answer the requests received by `responder`, for as long as the program runs. Requests
+/// that fail, or are not understood, are answered with `err`.
+pub fn serve_requests<T, F>(responder: &T, respond: F) -> Result<()>
+where
+    T: Endpoint,
+    F: Fn(&str) -> Result<String>,
+{
+    loop {
+        answer(responder, &respond)?;
+    }
+}
+
+/// Answer the requests received by `responder`, like `serve_requests`, until `stop` is set.
+/// A request that has arrived is always answered before stopping. Responders that keep the
+/// default `Endpoint::poll` only notice `stop` after answering a request.
+pub fn serve_requests_until<T, F>(responder: &T, respond: F, stop: &AtomicBool) -> Result<()>
+where
+    T: Endpoint,
+    F: Fn(&str) -> Result<String>,
+{
+    let timeout = Duration::from_millis(RELOAD_CHECK_INTERVAL);
+    while !stop.load(Ordering::SeqCst) {
+        if responder.poll(timeout)? {
+            answer(responder, &respond)?;
+        }
+    }
+    Ok(())
+}
+
+// Apply the options of a conductivity sensor.
+fn configure_conductivity<T>(sensor: &T, options: &SensorOptions) -> Result<()>
+where
+    T: ConductivityAPI<Error = Error, DefaultReply = ReplyStatus>,
+{
+    if let Some(temperature) = options.compensation {
+        let _set = sensor.set_compensation(temperature)?;
+    }
+    if let Some(ref output) = options.output {
+        let on = |param: &str| output.iter().any(|p| p == param);
+        let _ec = if on("EC") {
+            sensor.set_output_conductivity_on()?
+        } else {
+            sensor.set_output_conductivity_off()?
+        };
+        let _tds = if on("TDS") {
+            sensor.set_output_tds_on()?
+        } else {
+            sensor.set_output_tds_off()?
+        };
+        let _s = if on("S") {
+            sensor.set_output_salinity_on()?
+        } else {
+            sensor.set_output_salinity_off()?
+        };
+        let _sg = if on("SG") {
+            sensor.set_output_specific_gravity_on()?
+        } else {
+            sensor.set_output_specific_gravity_off()?
+        };
+    }
+    Ok(())
+}
+
+// Apply the options of a pH sensor.
+fn configure_ph<T>(sensor: &T, options: &SensorOptions) -> Result<()>
+where
+    T: PhAPI<Error = Error, DefaultReply = ReplyStatus>,
+{
+    if let Some(temperature) = options.compensation {
+        let _set = sensor.set_compensation(temperature)?;
+    }
+    Ok(())
+}
+
+// Apply the options of a temperature sensor.
+fn configure_temperature<T>(sensor: &T, options: &SensorOptions) -> Result<()>
+where
+    T: TemperatureAPI<Error = Error, DefaultReply = ReplyStatus>,
+{
+    let _set = match options.scale.as_ref().map(|s| s.as_str()) {
+        Some("celsius") => sensor.set_scale_to_celsius()?,
+        Some("fahrenheit") => sensor.set_scale_to_fahrenheit()?,
+        Some("kelvin") => sensor.set_scale_to_kelvin()?,
+        Some(_) => return Err(ErrorKind::ConfigParse.into()),
+        None => return Ok(()),
+    };
+    Ok(())
+}
+
+// Run `task` on a thread of its own, logging its error, if it stops with one.
+fn spawn<F>(name: String, task: F) -> JoinHandle<Result<()>>
+where
+    F: FnOnce() -> Result<()> + Send + 'static,
+{
+    thread::spawn(move || {
+        let result = task();
+        if let Err(ref e) = result {
+            error!("{} stopped: {}", name, e);
+        }
+        result
+    })
+}
+
+// The I2C settings of a sensor.
+fn sensor_config(config: &NodeConfig, sensor: &NodeSensorConfig) -> Result<SensorConfig> {
+    match config.bus(&sensor.bus) {
+        Some(bus) => Ok(SensorConfig {
+            path: bus.path.clone(),
+            address: sensor.address,
+        }),
+        None => Err(ErrorKind::InvalidDevice.into()),
+    }
+}
+
+// The schedule of a sensor, and whether it sleeps between samples.
+fn sample_schedule(sensor: &NodeSensorConfig) -> Result<(Schedule, Sleep)> {
+    let schedule = match sensor.schedule {
+        Some(ref schedule) => schedule.schedule()?,
+        None => Schedule::Every(Duration::from_millis(DEFAULT_SAMPLE_INTERVAL)),
+    };
+    Ok((schedule, Sleep::from(sensor.options.sleep)))
+}
+
+// A responder, or exporter, running on a thread of its own, until it is stopped.
+struct Task {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Result<()>>,
+}
+
+impl Task {
+    // Stop the task, and wait for the thread to finish. This releases the sensor, and
+    // sockets, of a responder, and the subscriber of an exporter.
+    fn stop(self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // The thread has logged its error, if it stopped with one.
+        let _joined = self.thread.join();
+    }
+}
+
+// Start the responder of a sensor, which owns the sensor. Returns once the sensor is set up,
+// or with the error that kept the responder from setting it up.
+fn spawn_responder(config: &NodeConfig, sensor: &NodeSensorConfig, url: &str) -> Result<Task> {
+    let sensor_config = sensor_config(config, sensor)?;
+    let kind = sensor.kind;
+    let options = sensor.options.clone();
+    let url = url.to_string();
+    let name = format!("responder of {}", &sensor.name);
+    info!("{} on {}", &name, &url);
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    let (ready, started) = mpsc::channel();
+    let thread = spawn(name, move || {
+        let socket = create_and_bind_responder(&url)?;
+        match kind {
+            SensorKind::Conductivity => {
+                let device = ConductivitySensor::from_config(sensor_config)?;
+                configure_conductivity(&device, &options)?;
+                let responder = ConductivityResponder::new(socket, device)?;
+                let _ready = ready.send(());
+                serve_requests_until(&responder, |req| responder.respond(req), &stopped)
+            }
+            SensorKind::Ph => {
+                let device = PhSensor::from_config(sensor_config)?;
+                configure_ph(&device, &options)?;
+                let responder = PhResponder::new(socket, device)?;
+                let _ready = ready.send(());
+                serve_requests_until(&responder, |req| responder.respond(req), &stopped)
+            }
+            SensorKind::Temperature => {
+                let device = TemperatureSensor::from_config(sensor_config)?;
+                configure_temperature(&device, &options)?;
+                let responder = TemperatureResponder::new(socket, device)?;
+                let _ready = ready.send(());
+                serve_requests_until(&responder, |req| responder.respond(req), &stopped)
+            }
+        }
+    });
+    // The thread drops its end of the channel without a word when the setup fails.
+    if started.recv().is_err() {
+        return match thread.join() {
+            Ok(Err(e)) => Err(e),
+            _ => Err(ErrorKind::InvalidDevice.into()),
+        };
+    }
+    Ok(Task { stop, thread })
+}
+
+// A requester to sample a sensor through its responder. A sample that times out is dropped,
+// and the requester may send the next request.
+fn sample_requester(url: &str) -> Result<Socket> {
+    let context = Context::new();
+    let timeout = Duration::from_millis(SAMPLE_TIMEOUT);
+    let socket = create_timed_requester(&context, &connect_url(url), timeout)?;
+    let _relaxed = socket
+        .set_req_relaxed(true)
+        .context(ErrorKind::SocketCreate)?;
+    let _correlate = socket
+        .set_req_correlate(true)
+        .context(ErrorKind::SocketCreate)?;
+    Ok(socket)
+}
+
+// Add a sensor to the publisher. Sensors with a responder are sampled through a
+// requester, and the others directly.
+fn add_sensor(
+    config: &NodeConfig,
+    publisher: &mut SensorPublisher,
+    sensor: &NodeSensorConfig,
+    topic: &str,
+) -> Result<()> {
+    let (schedule, sleep) = sample_schedule(sensor)?;
+    if let Some(ref url) = sensor.rep_url {
+        let socket = sample_requester(url)?;
+        match sensor.kind {
+            SensorKind::Conductivity => {
+                let requester = ConductivityRequester::new(socket)?;
+                publisher.add_scheduled_sensor(
+                    topic,
+                    ConductivitySource(requester),
+                    schedule,
+                    sleep,
+                );
+            }
+            SensorKind::Ph => {
+                let requester = PhRequester::new(socket)?;
+                publisher.add_scheduled_sensor(topic, PhSource(requester), schedule, sleep);
+            }
+            SensorKind::Temperature => {
+                let requester = TemperatureRequester::new(socket)?;
+                publisher.add_scheduled_sensor(
+                    topic,
+                    TemperatureSource(requester),
+                    schedule,
+                    sleep,
+                );
+            }
+        }
+        return Ok(());
+    }
+    let sensor_config = sensor_config(config, sensor)?;
+    match sensor.kind {
+        SensorKind::Conductivity => {
+            let device = ConductivitySensor::from_config(sensor_config)?;
+            configure_conductivity(&device, &sensor.options)?;
+            publisher.add_scheduled_sensor(topic, ConductivitySource(device), schedule, sleep);
+        }
+        SensorKind::Ph => {
+            let device = PhSensor::from_config(sensor_config)?;
+            configure_ph(&device, &sensor.options)?;
+            publisher.add_scheduled_sensor(topic, PhSource(device), schedule, sleep);
+        }
+        SensorKind::Temperature => {
+            let device = TemperatureSensor::from_config(sensor_config)?;
+            configure_temperature(&device, &sensor.options)?;
+            publisher.add_scheduled_sensor(topic, TemperatureSource(device), schedule, sleep);
+        }
+    }
+    Ok(())
+}
+
+// A `REP` socket bound to `url`, for the publisher.
+fn publisher_responder(url: &str) -> Result<Socket> {
+    let context = Context::new();
+    let socket = context.socket(zmq::REP).context(ErrorKind::SocketCreate)?;
+    let _bind = socket.bind(url).context(ErrorKind::SocketBind)?;
+    Ok(socket)
+}
+
+// The measurement log of the node, if it has storage.
+fn measurement_log(config: &NodeConfig) -> Result<Option<MeasurementLog>> {
+    match config.storage {
+        Some(ref storage) => Ok(Some(MeasurementLog::open(&storage.path, storage.policy())?)),
+        None => Ok(None),
+    }
+}
+
+// Create the publisher of the node, or `None` if it has none.
+fn build_publisher(config: &NodeConfig) -> Result<Option<SensorPublisher>> {
+    let publisher_config = match config.publisher {
+        Some(ref publisher_config) => publisher_config,
+        None => return Ok(None),
+    };
+    let mut publisher = SensorPublisher::bind(&publisher_config.url)?;
+    if let Some(log) = measurement_log(config)? {
+        publisher.set_log(log);
+    }
+    if !publisher_config.aggregate.is_empty() {
+        publisher.set_aggregator(Aggregator::new(&publisher_config.aggregate));
+    }
+    if !config.alarms.is_empty() {
+        publisher.set_alarms(AlarmEngine::new(&config.alarms)?);
+    }
+    if let Some(ref rep_url) = publisher_config.rep_url {
+        publisher.set_responder(publisher_responder(rep_url)?);
+    }
+    publisher.set_reload_requests(publisher_config.reload);
+    for sensor in &config.sensors {
+        if let Some(ref topic) = sensor.topic {
+            add_sensor(config, &mut publisher, sensor, topic)?;
+        }
+    }
+    Ok(Some(publisher))
+}
+
+// Start an exporter of the measurements published on `topics`.
+fn spawn_exporter(exporter: &ExporterConfig, sub_url: &str, topics: Vec<String>) -> Task {
+    let exporter = exporter.clone();
+    let sub_url = sub_url.to_string();
+    let name = match exporter {
+        ExporterConfig::Influx { .. } => "influx exporter".to_string(),
+        ExporterConfig::File { ref path, .. } => format!("exporter to {}", path.display()),
+    };
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    let thread = spawn(name, move || {
+        let topics: Vec<&str> = topics.iter().map(|t| t.as_str()).collect();
+        let mut subscriber = Subscriber::connect(&sub_url, &topics)?;
+        match exporter {
+            ExporterConfig::Influx {
+                ref measurement,
+                ref site,
+                ref firmware,
+                batch_size,
+                flush_interval,
+                retries,
+                ref destination,
+            } => {
+                let config = InfluxConfig {
+                    sub_url: &sub_url,
+                    measurement: measurement.as_ref().map(|m| m.as_str()),
+                    site: site.as_ref().map(|s| s.as_str()),
+                    firmware: firmware.clone(),
+                    batch_size,
+                    flush_interval,
+                    retries,
+                    destination: destination.clone(),
+                };
+                InfluxSink::from_config(&config)?.run_until(subscriber, &stopped)
+            }
+            ExporterConfig::File {
+                ref path,
+                ref format,
+                ref time_zone,
+                precision,
+            } => {
+                let mut options = ExportOptions::default();
+                if let Some(ref format) = *format {
+                    options.format = format.parse()?;
+                }
+                if let Some(ref time_zone) = *time_zone {
+                    options.time_zone = time_zone.parse()?;
+                }
+                options.precision = precision;
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .context(ErrorKind::ExportWrite)?;
+                Exporter::new(file, options).follow_until(&mut subscriber, &stopped)
+            }
+        }
+    });
+    Task { stop, thread }
+}
+
+/// The differences between two configurations of a node, by what they take to apply.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeChanges {
+    /// Sensors only in the new configuration.
+    pub added: Vec<String>,
+    /// Sensors only in the old configuration.
+    pub removed: Vec<String>,
+    /// Sensors to bring up again, because their kind, bus, address, responder URL, or chip
+    /// options changed.
+    pub restarted: Vec<String>,
+    /// Sensors with a new topic, schedule, or `sleep` option, and nothing else.
+    pub rescheduled: Vec<String>,
+    /// Whether the publisher was added, removed, or moved to another URL.
+    pub publisher: bool,
+    /// Whether the responder socket of the publisher, or its `reload` setting, changed.
+    pub publisher_responder: bool,
+    /// Whether the aggregated windows changed.
+    pub aggregate: bool,
+    /// Whether the alarm rules changed.
+    pub alarms: bool,
+    /// Whether the storage settings changed.
+    pub storage: bool,
+    /// Whether the exporters changed.
+    pub exporters: bool,
+}
+
+impl NodeChanges {
+    /// The changes that turn the `old` configuration into the `new` one.
+    pub fn between(old: &NodeConfig, new: &NodeConfig) -> NodeChanges {
+        let mut changes = NodeChanges::default();
+        for sensor in &old.sensors {
+            if new.sensor(&sensor.name).is_none() {
+                changes.removed.push(sensor.name.clone());
+            }
+        }
+        for sensor in &new.sensors {
+            let before = match old.sensor(&sensor.name) {
+                Some(before) => before,
+                None => {
+                    changes.added.push(sensor.name.clone());
+                    continue;
+                }
+            };
+            let bus_path = |config: &NodeConfig, s: &NodeSensorConfig| {
+                config.bus(&s.bus).map(|b| b.path.clone())
+            };
+            // `sleep` is applied by the publisher, the other options by the chip itself.
+            let chip_options = |s: &NodeSensorConfig| {
+                let mut options = s.options.clone();
+                options.sleep = None;
+                options
+            };
+            if before.kind != sensor.kind
+                || bus_path(old, before) != bus_path(new, sensor)
+                || before.address != sensor.address
+                || before.rep_url != sensor.rep_url
+                || chip_options(before) != chip_options(sensor)
+            {
+                changes.restarted.push(sensor.name.clone());
+            } else if before.topic != sensor.topic
+                || before.schedule != sensor.schedule
+                || before.options.sleep != sensor.options.sleep
+            {
+                changes.rescheduled.push(sensor.name.clone());
+            }
+        }
+        let url = |config: &NodeConfig| config.publisher.as_ref().map(|p| p.url.clone());
+        changes.publisher = url(old) != url(new);
+        if let (Some(before), Some(after)) = (old.publisher.as_ref(), new.publisher.as_ref()) {
+            changes.publisher_responder =
+                before.rep_url != after.rep_url || before.reload != after.reload;
+            changes.aggregate = before.aggregate != after.aggregate;
+        }
+        changes.alarms = old.alarms != new.alarms;
+        changes.storage = old.storage != new.storage;
+        changes.exporters = old.exporters != new.exporters;
+        changes
+    }
+
+    /// Whether there are no changes.
+    pub fn is_empty(&self) -> bool {
+        *self == NodeChanges::default()
+    }
+}
+
+/// Settings that override those of the configuration file of a node, e.g. from the
+/// environment, and command-line flags. They are applied again on every reload.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeOverrides {
+    /// Override with the environment variables named after this prefix, e.g. `BENITA`, as
+    /// with `ConfigLayers::merge_env`.
+    pub env_prefix: Option<String>,
+    /// Override single settings, e.g. `sensors.ph1.address`, after the variables, as with
+    /// `ConfigLayers::set`.
+    pub settings: Vec<(String, String)>,
+}
+
+impl NodeOverrides {
+    /// The settings of the file at `path`, with the overrides merged.
+    pub fn layers<P: AsRef<Path>>(&self, path: P) -> Result<ConfigLayers> {
+        let mut layers = ConfigLayers::default();
+        layers.merge_file(path)?;
+        if let Some(ref prefix) = self.env_prefix {
+            layers.merge_env(prefix)?;
+        }
+        for &(ref key, ref value) in &self.settings {
+            layers.set(key, value)?;
+        }
+        Ok(layers)
+    }
+
+    /// The configuration of the node, from the file at `path`, with the overrides merged.
+    /// With `strict`, the file is validated as by `NodeConfig::from_file_strict`, and then
+    /// the merged settings.
+    pub fn config<P: AsRef<Path>>(&self, path: P, strict: bool) -> Result<NodeConfig> {
+        let path = path.as_ref();
+        let layers = self.layers(path)?;
+        if strict {
+            let _file = NodeConfig::from_file_strict(path)?;
+            return NodeConfig::from_str_strict(&layers.to_toml()?);
+        }
+        NodeConfig::from_str(&layers.to_toml()?)
+    }
+}
+
+/// A sensor node, with its sensors, responders, publisher, and exporters.
+pub struct Node {
+    config: NodeConfig,
+    file: Option<PathBuf>,
+    strict: bool,
+    overrides: NodeOverrides,
+    responders: BTreeMap<String, Task>,
+    running: Option<SensorPublisher>,
+    exporters: Vec<Task>,
+}
+
+impl Node {
+    /// Create a new node from `NodeConfig`. Nothing is brought up until it runs.
+    pub fn new(config: NodeConfig) -> Node {
+        Node {
+            config,
+            file: None,
+            strict: false,
+            overrides: NodeOverrides::default(),
+            responders: BTreeMap::new(),
+            running: None,
+            exporters: Vec::new(),
+        }
+    }
+
+    /// Create a new node from the configuration file at `path`, which is read again on every
+    /// reload. With `strict`, the file is validated as by `NodeConfig::from_file_strict`.
+    pub fn from_file<P: AsRef<Path>>(path: P, strict: bool) -> Result<Node> {
+        Node::with_overrides(path, strict, NodeOverrides::default())
+    }
+
+    /// Create a new node from the configuration file at `path`, and the `overrides` of its
+    /// settings, which are merged again on every reload.
+    pub fn with_overrides<P: AsRef<Path>>(
+        path: P,
+        strict: bool,
+        overrides: NodeOverrides,
+    ) -> Result<Node> {
+        let config = overrides.config(path.as_ref(), strict)?;
+        let mut node = Node::new(config);
+        node.file = Some(path.as_ref().to_path_buf());
+        node.strict = strict;
+        node.overrides = overrides;
+        Ok(node)
+    }
+
+    /// The configuration of the node.
+    pub fn config(&self) -> &NodeConfig {
+        &self.config
+    }
+
+    /// The topics of the published sensors.
+    pub fn topics(&self) -> Vec<&str> {
+        self.config
+            .sensors
+            .iter()
+            .filter_map(|s| s.topic.as_ref().map(|t| t.as_str()))
+            .collect()
+    }
+
+    /// Create the publisher of the node, with its sensors, storage, aggregator, and alarms.
+    /// Returns `None` when the node has no publisher.
+    pub fn publisher(&self) -> Result<Option<SensorPublisher>> {
+        build_publisher(&self.config)
+    }
+
+    /// Read the configuration file of the node again, and apply the differences, as with
+    /// `apply`. The node keeps its configuration if the file cannot be read.
+    pub fn reload(&mut self) -> Result<NodeChanges> {
+        let config = match self.file {
+            Some(ref path) => self.overrides.config(path, self.strict)?,
+            None => {
+                let reason = "the node was not brought up from a file".to_string();
+                return Err(ErrorKind::ConfigRead(reason).into());
+            }
+        };
+        self.apply(config)
+    }
+
+    /// Apply the differences between `config` and the current configuration to the running
+    /// node. Sensors, responders, and settings that did not change are left as they are.
+    /// Changes that fail to apply are left out of the configuration of the node, so that the
+    /// next reload tries them again, and the first error is returned.
+    pub fn apply(&mut self, config: NodeConfig) -> Result<NodeChanges> {
+        let changes = NodeChanges::between(&self.config, &config);
+        let old = mem::replace(&mut self.config, config);
+        if changes.is_empty() {
+            info!("configuration of node {} unchanged", &self.config.name);
+            return Ok(changes);
+        }
+        info!(
+            "applying changes to node {}: {:?}",
+            &self.config.name, &changes
+        );
+        let stopped: Vec<&String> = changes.removed.iter().chain(&changes.restarted).collect();
+        // Sensors brought up again keep their state, e.g. a probe being calibrated.
+        let states = match self.running {
+            Some(ref publisher) => sensor_states(&old, publisher),
+            None => BTreeMap::new(),
+        };
+        let started: Vec<&String> = changes.added.iter().chain(&changes.restarted).collect();
+
+        // Release the sensors, and sockets, of stopped sensors, before bringing any up.
+        for name in &stopped {
+            if let Some(responder) = self.responders.remove(*name) {
+                responder.stop();
+            }
+        }
+        if changes.publisher {
+            self.running = None;
+        } else if let Some(ref mut publisher) = self.running {
+            for name in &stopped {
+                if let Some(topic) = old.sensor(name).and_then(|s| s.topic.as_ref()) {
+                    let _removed = publisher.remove_sensor(topic);
+                }
+            }
+        }
+
+        // The configuration as applied, with the old settings of what failed to apply.
+        let mut applied = self.config.clone();
+        let mut result = Ok(());
+        for name in &started {
+            let sensor = self.config.sensor(name).unwrap();
+            if let Some(ref url) = sensor.rep_url {
+                match spawn_responder(&self.config, sensor, url) {
+                    Ok(responder) => {
+                        let _previous = self.responders.insert(sensor.name.clone(), responder);
+                    }
+                    Err(e) => {
+                        keep_old_sensor(&old, &mut applied, name);
+                        result = result.and(Err(e));
+                    }
+                }
+            }
+        }
+        if changes.publisher {
+            match build_publisher(&self.config) {
+                Ok(publisher) => self.running = publisher,
+                Err(e) => {
+                    // Nothing is published, so the next reload builds the publisher again.
+                    applied.publisher = None;
+                    result = result.and(Err(e));
+                }
+            }
+        } else if let Some(ref mut publisher) = self.running {
+            let updated = update_publisher(&old, &self.config, &changes, publisher, &mut applied);
+            result = result.and(updated);
+        }
+        if let Some(ref mut publisher) = self.running {
+            restore_states(&self.config, publisher, &states);
+        }
+        self.config = applied;
+
+        let topics = |config: &NodeConfig| -> Vec<Option<String>> {
+            config.sensors.iter().map(|s| s.topic.clone()).collect()
+        };
+        let exporters_stale =
+            changes.publisher || changes.exporters || topics(&old) != topics(&self.config);
+        if exporters_stale && !(old.exporters.is_empty() && self.config.exporters.is_empty()) {
+            info!("bringing up the exporters of node {} again", &self.config.name);
+            self.stop_exporters();
+            self.start_exporters();
+        }
+        result.map(|_| changes)
+    }
+
+    // Start the exporters of the node, subscribed to the topics of its publisher.
+    fn start_exporters(&mut self) {
+        if let Some(ref config) = self.config.publisher {
+            let sub_url = connect_url(&config.url);
+            let topics: Vec<String> = self.topics().iter().map(|t| t.to_string()).collect();
+            for exporter in &self.config.exporters {
+                self.exporters.push(spawn_exporter(exporter, &sub_url, topics.clone()));
+            }
+        }
+    }
+
+    // Stop the exporters of the node, once they have written what they received.
+    fn stop_exporters(&mut self) {
+        for exporter in self.exporters.drain(..) {
+            exporter.stop();
+        }
+    }
+
+    /// Bring the node up, and run it for as long as the program runs. The configuration is
+    /// reloaded on `SIGHUP`, or on a `reload` request to the publisher, if it allows them.
+    pub fn run(&mut self) -> Result<()> {
+        info!("bringing up node {}", &self.config.name);
+        catch_hangup();
+        for sensor in &self.config.sensors {
+            if let Some(ref url) = sensor.rep_url {
+                let responder = spawn_responder(&self.config, sensor, url)?;
+                let _previous = self.responders.insert(sensor.name.clone(), responder);
+            }
+        }
+        self.running = build_publisher(&self.config)?;
+        self.start_exporters();
+        let check = Duration::from_millis(RELOAD_CHECK_INTERVAL);
+        loop {
+            let hangup = take_hangup();
+            let requested = self
+                .running
+                .as_ref()
+                .map_or(false, |p| p.take_reload_request());
+            if hangup || requested {
+                if let Err(e) = self.reload() {
+                    error!("could not reload node {}: {}", &self.config.name, e);
+                }
+            }
+            match self.running {
+                Some(ref mut publisher) => {
+                    let now = Instant::now();
+                    let wait = match publisher.next_due() {
+                        Some(next_at) if next_at > now => cmp::min(next_at - now, check),
+                        Some(_) => Duration::from_secs(0),
+                        None => check,
+                    };
+                    if wait > Duration::from_secs(0) {
+                        let _served = publisher.wait(wait)?;
+                    }
+                    let _published = publisher.publish_due()?;
+                }
+                None => thread::sleep(check),
+            }
+        }
+    }
+}
+
+// Put the settings of the sensor `name` in `old` back into `applied`, or leave the sensor
+// out, if it is new.
+fn keep_old_sensor(old: &NodeConfig, applied: &mut NodeConfig, name: &str) {
+    let position = applied.sensors.iter().position(|s| s.name == name);
+    match (position, old.sensor(name)) {
+        (Some(i), Some(before)) => applied.sensors[i] = before.clone(),
+        (Some(i), None) => {
+            let _added = applied.sensors.remove(i);
+        }
+        (None, Some(before)) => applied.sensors.push(before.clone()),
+        (None, None) => {}
+    }
+}
+
+// The states of the sensors of a publisher, by name, other than `sampling`, and `fault`,
+// which a sensor brought up again finds out for itself.
+fn sensor_states(
+    config: &NodeConfig,
+    publisher: &SensorPublisher,
+) -> BTreeMap<String, SensorState> {
+    let mut states = BTreeMap::new();
+    for sensor in &config.sensors {
+        let state = sensor.topic.as_ref().and_then(|topic| publisher.state(topic));
+        match state {
+            Some(SensorState::Sampling) | Some(SensorState::Fault) | None => {}
+            Some(state) => {
+                let _previous = states.insert(sensor.name.clone(), state);
+            }
+        }
+    }
+    states
+}
+
+// Put the sensors of a publisher back in the `states` they had, by name.
+fn restore_states(
+    config: &NodeConfig,
+    publisher: &mut SensorPublisher,
+    states: &BTreeMap<String, SensorState>,
+) {
+    for sensor in &config.sensors {
+        if let (Some(topic), Some(&state)) = (sensor.topic.as_ref(), states.get(&sensor.name)) {
+            if publisher.state(topic).is_none() {
+                continue;
+            }
+            if let Err(e) = publisher.set_state(topic, state) {
+                warn!("could not keep {} {}: {}", topic, state, e);
+            }
+        }
+    }
+}
+
+// Reschedule a sensor of a running publisher, moving it to its new topic.
+fn reschedule_sensor(
+    new: &NodeConfig,
+    publisher: &mut SensorPublisher,
+    before: &NodeSensorConfig,
+    after: &NodeSensorConfig,
+) -> Result<()> {
+    match (before.topic.as_ref(), after.topic.as_ref()) {
+        (Some(topic), Some(new_topic)) => {
+            let (schedule, sleep) = sample_schedule(after)?;
+            let _renamed = publisher.rename_sensor(topic, new_topic);
+            let _rescheduled = publisher.reschedule_sensor(new_topic, schedule, sleep);
+        }
+        (Some(topic), None) => {
+            let _removed = publisher.remove_sensor(topic);
+        }
+        (None, Some(new_topic)) => add_sensor(new, publisher, after, new_topic)?,
+        (None, None) => {}
+    }
+    Ok(())
+}
+
+// Apply the changes to the sensors, and settings, of a running publisher. The old settings
+// of those that fail to apply are put back into `applied`, and the first error is returned.
+fn update_publisher(
+    old: &NodeConfig,
+    new: &NodeConfig,
+    changes: &NodeChanges,
+    publisher: &mut SensorPublisher,
+    applied: &mut NodeConfig,
+) -> Result<()> {
+    let mut result = Ok(());
+    for name in &changes.rescheduled {
+        let (before, after) = (old.sensor(name).unwrap(), new.sensor(name).unwrap());
+        if let Err(e) = reschedule_sensor(new, publisher, before, after) {
+            keep_old_sensor(old, applied, name);
+            result = result.and(Err(e));
+        }
+    }
+    for name in changes.added.iter().chain(&changes.restarted) {
+        let sensor = new.sensor(name).unwrap();
+        if let Some(ref topic) = sensor.topic {
+            if let Err(e) = add_sensor(new, publisher, sensor, topic) {
+                keep_old_sensor(old, applied, name);
+                result = result.and(Err(e));
+            }
+        }
+    }
+    let config = new.publisher.as_ref().unwrap();
+    if changes.publisher_responder {
+        publisher.set_reload_requests(config.reload);
+        publisher.remove_responder();
+        if let Some(ref rep_url) = config.rep_url {
+            match publisher_responder(rep_url) {
+                Ok(socket) => publisher.set_responder(socket),
+                Err(e) => {
+                    // There is no responder now, and the next reload binds it again.
+                    if let Some(ref mut publisher) = applied.publisher {
+                        publisher.rep_url = None;
+                    }
+                    result = result.and(Err(e));
+                }
+            }
+        }
+    }
+    if changes.aggregate {
+        if config.aggregate.is_empty() {
+            publisher.remove_aggregator();
+        } else {
+            publisher.set_aggregator(Aggregator::new(&config.aggregate));
+        }
+    }
+    if changes.alarms {
+        if new.alarms.is_empty() {
+            publisher.remove_alarms();
+        } else {
+            match AlarmEngine::new(&new.alarms) {
+                Ok(alarms) => publisher.set_alarms(alarms),
+                Err(e) => {
+                    applied.alarms = old.alarms.clone();
+                    result = result.and(Err(e));
+                }
+            }
+        }
+    }
+    if changes.storage {
+        match measurement_log(new) {
+            Ok(Some(log)) => publisher.set_log(log),
+            Ok(None) => publisher.remove_log(),
+            Err(e) => {
+                applied.storage = old.storage.clone();
+                result = result.and(Err(e));
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connect_urls_of_bound_sockets() {
+        assert_eq!("tcp://localhost:5557", connect_url("tcp://*:5557"));
+        assert_eq!("tcp://localhost:5557", connect_url("tcp://0.0.0.0:5557"));
+        assert_eq!("tcp://10.0.0.2:5557", connect_url("tcp://10.0.0.2:5557"));
+        assert_eq!(
+            "ipc:///tmp/benita.ipc",
+            connect_url("ipc:///tmp/benita.ipc")
+        );
+    }
+
+    #[test]
+    fn topics_of_published_sensors() {
+        let config = NodeConfig::from_str(
+            r#"
+            name = "tank-a"
+
+            [[sensors]]
+            name = "ph1"
+            kind = "ph"
+            bus = "i2c1"
+            address = 99
+            topic = "ph-0123456789abcdef"
+
+            [[sensors]]
+            name = "ec1"
+            kind = "conductivity"
+            bus = "i2c1"
+            address = 100
+            "#,
+        )
+        .unwrap();
+        let node = Node::new(config);
+        assert_eq!(vec!["ph-0123456789abcdef"], node.topics());
+        // Nodes without a publisher publish nothing.
+        assert!(node.publisher().unwrap().is_none());
+    }
+
+    #[test]
+    fn report_responders_that_cannot_set_up_their_sensor() {
+        let config = NodeConfig::from_str(
+            r#"
+            name = "tank-a"
+
+            [[buses]]
+            name = "i2c1"
+            path = "/dev/benita-missing-i2c"
+
+            [[sensors]]
+            name = "ph1"
+            kind = "ph"
+            bus = "i2c1"
+            address = 99
+            rep_url = "inproc://ph1"
+            "#,
+        )
+        .unwrap();
+        let sensor = config.sensor("ph1").unwrap();
+        assert!(spawn_responder(&config, sensor, "inproc://ph1").is_err());
+    }
+
+    #[test]
+    fn override_the_settings_of_node_files() {
+        use std::fs::{self, File};
+        use std::io::Write;
+
+        let dir = ::std::env::temp_dir().join(format!("benita-node-{}", ::std::process::id()));
+        let _create = fs::create_dir_all(&dir);
+        let path = dir.join("node.toml");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(
+            br#"
+            name = "tank-a"
+
+            [[buses]]
+            name = "i2c1"
+            path = "/dev/i2c-1"
+
+            [[sensors]]
+            name = "ph1"
+            kind = "ph"
+            bus = "i2c1"
+            address = 99
+            "#,
+        )
+        .unwrap();
+
+        let overrides = NodeOverrides {
+            env_prefix: None,
+            settings: vec![
+                ("sensors.ph1.address".to_string(), "100".to_string()),
+                ("sensors.0.topic".to_string(), "ph-01".to_string()),
+            ],
+        };
+        let node = Node::with_overrides(&path, true, overrides.clone()).unwrap();
+        assert_eq!(100, node.config().sensors[0].address);
+        assert_eq!(vec!["ph-01"], node.topics());
+
+        // The merged settings are validated in strict mode
+        let mut out_of_range = overrides.clone();
+        out_of_range.settings[0].1 = "200".to_string();
+        assert!(out_of_range.config(&path, false).is_ok());
+        assert!(out_of_range.config(&path, true).is_err());
+        let _remove = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn leave_changes_that_fail_out_of_the_configuration() {
+        let mut node = Node::new(NodeConfig::from_str(r#"name = "tank-a""#).unwrap());
+        let config = NodeConfig::from_str(
+            r#"
+            name = "tank-a"
+
+            [[buses]]
+            name = "i2c1"
+            path = "/dev/benita-missing-i2c"
+
+            [[sensors]]
+            name = "ph1"
+            kind = "ph"
+            bus = "i2c1"
+            address = 99
+            rep_url = "inproc://ph1-apply"
+            "#,
+        )
+        .unwrap();
+        assert!(node.apply(config.clone()).is_err());
+        assert!(node.config().sensor("ph1").is_none());
+        assert_eq!(1, node.config().buses.len());
+        // The next reload tries again.
+        assert!(node.apply(config).is_err());
+        assert!(node.config().sensor("ph1").is_none());
+    }
+
+    #[test]
+    fn keep_the_states_of_sensors_brought_up_again() {
+        use network::publisher::{Sample, SampleSource};
+
+        struct Idle;
+
+        impl SampleSource for Idle {
+            fn sample(&self) -> Result<Sample> {
+                Err(ErrorKind::CommandRequest.into())
+            }
+            fn sleep(&self) -> Result<()> {
+                Ok(())
+            }
+        }
+
+        let config = NodeConfig::from_str(
+            r#"
+            name = "tank-a"
+
+            [[sensors]]
+            name = "ph1"
+            kind = "ph"
+            bus = "i2c1"
+            address = 99
+            topic = "ph-01"
+
+            [[sensors]]
+            name = "ph2"
+            kind = "ph"
+            bus = "i2c1"
+            address = 98
+            topic = "ph-02"
+            "#,
+        )
+        .unwrap();
+        let minute = Duration::from_secs(60);
+        let mut publisher = SensorPublisher::bind("inproc://node-states").unwrap();
+        publisher.add_sensor("ph-01", Idle, minute, false);
+        publisher.add_sensor("ph-02", Idle, minute, false);
+        publisher.set_state("ph-01", SensorState::Calibrating).unwrap();
+        let states = sensor_states(&config, &publisher);
+
+        assert!(publisher.remove_sensor("ph-01"));
+        publisher.add_sensor("ph-01", Idle, minute, false);
+        assert_eq!(Some(SensorState::Sampling), publisher.state("ph-01"));
+        restore_states(&config, &mut publisher, &states);
+        assert_eq!(Some(SensorState::Calibrating), publisher.state("ph-01"));
+        assert_eq!(Some(SensorState::Sampling), publisher.state("ph-02"));
+    }
+
+    #[test]
+    fn changes_between_configurations() {
+        let old = NodeConfig::from_str(
+            r#"
+            name = "tank-a"
+
+            [[buses]]
+            name = "i2c1"
+            path = "/dev/i2c-1"
+
+            [[sensors]]
+            name = "ph1"
+            kind = "ph"
+            bus = "i2c1"
+            address = 99
+            topic = "ph-0123456789abcdef"
+
+            [[sensors]]
+            name = "ec1"
+            kind = "conductivity"
+            bus = "i2c1"
+            address = 100
+            rep_url = "tcp://*:5556"
+
+            [[sensors]]
+            name = "temp1"
+            kind = "temperature"
+            bus = "i2c1"
+            address = 101
+            "#,
+        )
+        .unwrap();
+        let new = NodeConfig::from_str(
+            r#"
+            name = "tank-a"
+
+            [[buses]]
+            name = "i2c1"
+            path = "/dev/i2c-1"
+
+            [[sensors]]
+            name = "ph1"
+            kind = "ph"
+            bus = "i2c1"
+            address = 99
+            topic = "ph-0123456789abcdef"
+            schedule = { interval = 60000 }
+            options = { sleep = true }
+
+            [[sensors]]
+            name = "ec1"
+            kind = "conductivity"
+            bus = "i2c1"
+            address = 100
+            rep_url = "tcp://*:5560"
+
+            [[sensors]]
+            name = "ec2"
+            kind = "conductivity"
+            bus = "i2c1"
+            address = 102
+
+            [[alarms]]
+            name = "ph-range"
+            sensor = "ph-0123456789abcdef"
+            low = 6.5
+            high = 8.5
+            "#,
+        )
+        .unwrap();
+        let changes = NodeChanges::between(&old, &new);
+        assert_eq!(vec!["ec2"], changes.added);
+        assert_eq!(vec!["temp1"], changes.removed);
+        assert_eq!(vec!["ec1"], changes.restarted);
+        assert_eq!(vec!["ph1"], changes.rescheduled);
+        assert!(changes.alarms);
+        assert!(!changes.publisher);
+        assert!(!changes.storage);
+        assert!(NodeChanges::between(&new, &new).is_empty());
+
+        // Sensors without a responder, on a node without a publisher, touch no hardware.
+        let mut node = Node::new(old);
+        let mut without_ec1 = new.clone();
+        without_ec1.sensors.retain(|s| s.name != "ec1");
+        let changes = node.apply(without_ec1).unwrap();
+        assert_eq!(vec!["ec1", "temp1"], changes.removed);
+        assert_eq!(
+            vec!["ph1", "ec2"],
+            node.config()
+                .sensors
+                .iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<_>>()
+        );
+    }
+}
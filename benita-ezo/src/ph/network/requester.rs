@@ -5,6 +5,7 @@ use super::requests::*;
 
 use common_ezo::EzoChipAPI;
 use errors::*;
+use network::session::{OwnedRequest, SessionHeartbeat, SessionLock, SessionState, SessionUnlock};
 use network::{Endpoint, ReplyStatus, SocketRequest};
 
 use zmq::Socket;
@@ -12,6 +13,28 @@ use zmq::Socket;
 // Creates a client for network requests to the `PhResponder`.
 network_socket!(PhRequester, "Socket that communicates with the pH sensor.");
 
+impl PhRequester {
+    /// Send a request string, as understood by its responder, and return the debug form of
+    /// the typed reply. Requests that are not understood yield `ErrorKind::RequestParse`.
+    pub fn request(&self, req_str: &str) -> Result<String> {
+        request_typed_reply!(self, req_str, [
+            CalibrationHigh,
+            CalibrationLow,
+            CalibrationMid,
+            CalibrationState,
+            CompensationGet,
+            CompensationSet,
+            OwnedRequest,
+            Reading,
+            SessionHeartbeat,
+            SessionLock,
+            SessionState,
+            SessionUnlock,
+            Slope,
+        ])
+    }
+}
+
 impl EzoChipAPI for PhRequester {
     type SensorError = Error;
     type SensorReply = ReplyStatus;
@@ -2,10 +2,12 @@
 use super::super::device::PhSensor;
 use super::super::PhAPI;
 use super::replies::*;
+use super::requests::*;
 
 use common_ezo::EzoChipAPI;
 use errors::*;
-use network::{Endpoint, ReplyStatus};
+use network::session::CalibrationLock;
+use network::{Endpoint, ReplyStatus, SocketReply, SocketRequest};
 
 use zmq::Socket;
 
@@ -14,7 +16,41 @@ use zmq::Socket;
 network_sensor_socket! {
     PhResponder,
     PhSensor,
-    "Socket that responds to pH sensor commands."
+    "Socket that responds to pH sensor commands.",
+    session
+}
+
+// Requests that change the calibration of the sensor, and need its session lock.
+fn changes_calibration(req_str: &str) -> bool {
+    <CalibrationClear as SocketRequest>::from_str(req_str).is_ok()
+        || <CalibrationHigh as SocketRequest>::from_str(req_str).is_ok()
+        || <CalibrationLow as SocketRequest>::from_str(req_str).is_ok()
+        || <CalibrationMid as SocketRequest>::from_str(req_str).is_ok()
+        || <Factory as SocketRequest>::from_str(req_str).is_ok()
+        || <Import as SocketRequest>::from_str(req_str).is_ok()
+}
+
+impl PhResponder {
+    /// Evaluate a request, as sent by a `PhRequester`, and return the text form of its
+    /// reply. Requests that are not understood yield `ErrorKind::RequestParse`.
+    ///
+    /// Requests that change the calibration are only answered for the holder of the session
+    /// lock, see `benita::ezo::network::session`.
+    pub fn respond(&self, req_str: &str) -> Result<String> {
+        self.session
+            .respond(req_str, changes_calibration, |req_str| {
+                respond_to_requests!(self, req_str, {
+                    CalibrationHigh(value) => self.set_calibration_high(value),
+                    CalibrationLow(value) => self.set_calibration_low(value),
+                    CalibrationMid(value) => self.set_calibration_mid(value),
+                    CalibrationState => self.get_calibration_status(),
+                    CompensationGet => self.get_compensation(),
+                    CompensationSet(value) => self.set_compensation(value),
+                    Reading => self.get_reading(),
+                    Slope => self.get_slope(),
+                })
+            })
+    }
 }
 
 impl EzoChipAPI for PhResponder {
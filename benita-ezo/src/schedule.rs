@@ -0,0 +1,442 @@
+//! Sampling schedules of sensors.
+//!
+//! A sensor is sampled on a `Schedule`, which is one of:
+//!
+//! * A fixed interval, counted from the first sample, e.g. `{ interval = 10000 }`.
+//! * A fixed interval, aligned to the wall clock, e.g. `{ interval = 60000, align = true }`
+//!   samples on the minute.
+//! * A cron-like expression, in UTC, with an optional field for the seconds, e.g.
+//!   `{ cron = "0 */15 * * * *" }` samples every quarter of an hour.
+//!
+//! Cron expressions have five, or six, fields: `[second] minute hour day month weekday`.
+//! Every field is `*`, a value, a range like `1-5`, a list like `0,30`, or any of these
+//! with a step, like `*/15`. Weekdays are numbered from Sunday, as `0`, or `7`.
+//!
+//! A `Job` follows the schedule of a sensor. Readings take time, so a job starts every
+//! reading ahead of its scheduled time by the latency of the chip, and puts the chip to
+//! sleep between readings when it is idle long enough to make it worthwhile.
+use std::cmp;
+use std::str::FromStr;
+use std::time::Duration;
+
+use config::SensorKind;
+use errors::*;
+
+use chrono::{self, DateTime, Datelike, TimeZone, Timelike, Utc};
+
+/// Time a conductivity chip takes to take a reading, in milliseconds.
+pub const CONDUCTIVITY_LATENCY: u64 = 600;
+/// Time a pH chip takes to take a reading, in milliseconds.
+pub const PH_LATENCY: u64 = 900;
+/// Time a temperature chip takes to take a reading, in milliseconds.
+pub const TEMPERATURE_LATENCY: u64 = 600;
+/// Shortest idle time between readings that puts a chip to sleep automatically, in
+/// milliseconds.
+pub const SLEEP_THRESHOLD: u64 = 5_000;
+
+// Years to look ahead for a time matched by a cron expression, before giving up.
+const CRON_HORIZON: i32 = 8;
+
+/// The time the chip of a sensor takes to take a reading.
+pub fn reading_latency(kind: SensorKind) -> Duration {
+    let ms = match kind {
+        SensorKind::Conductivity => CONDUCTIVITY_LATENCY,
+        SensorKind::Ph => PH_LATENCY,
+        SensorKind::Temperature => TEMPERATURE_LATENCY,
+    };
+    Duration::from_millis(ms)
+}
+
+// A `std::time::Duration` as a `chrono::Duration`.
+fn span(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::max_value())
+}
+
+// The values matched by a field of a cron expression, between `min` and `max`, as bits.
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut bits = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(i) => (&part[..i], part[i + 1..].parse::<u32>().ok()?),
+            None => (part, 1),
+        };
+        let (first, last) = if range == "*" {
+            (min, max)
+        } else if let Some(i) = range.find('-') {
+            (
+                range[..i].parse::<u32>().ok()?,
+                range[i + 1..].parse::<u32>().ok()?,
+            )
+        } else {
+            let first = range.parse::<u32>().ok()?;
+            // A value with a step runs to the end of the field, e.g. `5/15`.
+            (first, if part.contains('/') { max } else { first })
+        };
+        if step == 0 || first < min || last > max || first > last {
+            return None;
+        }
+        let mut value = first;
+        while value <= last {
+            bits |= 1 << value;
+            value += step;
+        }
+    }
+    Some(bits)
+}
+
+/// A cron-like expression, in UTC.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cron {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // Whether days, or weekdays, are restricted, as opposed to `*`.
+    some_days: bool,
+    some_weekdays: bool,
+}
+
+impl Cron {
+    // Whether the expression matches the day of `time`. As with cron, when both days and
+    // weekdays are restricted, matching either is enough.
+    fn matches_day(&self, time: &DateTime<Utc>) -> bool {
+        let day = self.days & (1 << time.day()) != 0;
+        let weekday = self.weekdays & (1 << time.weekday().num_days_from_sunday()) != 0;
+        match (self.some_days, self.some_weekdays) {
+            (true, true) => day || weekday,
+            (true, false) => day,
+            (false, true) => weekday,
+            (false, false) => true,
+        }
+    }
+
+    /// The first time matched by the expression after `time`, if there is one within the
+    /// next few years.
+    pub fn next_after(&self, time: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let horizon = time.year() + CRON_HORIZON;
+        let mut t = Utc.timestamp(time.timestamp() + 1, 0);
+        while t.year() <= horizon {
+            if self.months & (1 << t.month()) == 0 {
+                t = if t.month() == 12 {
+                    Utc.ymd(t.year() + 1, 1, 1).and_hms(0, 0, 0)
+                } else {
+                    Utc.ymd(t.year(), t.month() + 1, 1).and_hms(0, 0, 0)
+                };
+            } else if !self.matches_day(&t) {
+                t = t.date().succ().and_hms(0, 0, 0);
+            } else if self.hours & (1 << t.hour()) == 0 {
+                t = t.date().and_hms(t.hour(), 0, 0) + chrono::Duration::hours(1);
+            } else if self.minutes & (1 << t.minute()) == 0 {
+                t = t.date().and_hms(t.hour(), t.minute(), 0) + chrono::Duration::minutes(1);
+            } else if self.seconds & (1 << t.second()) == 0 {
+                t = t + chrono::Duration::seconds(1);
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+impl FromStr for Cron {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Cron> {
+        let invalid = || ErrorKind::ConfigValue(format!("invalid cron expression {:?}", s));
+        let mut fields: Vec<&str> = s.split_whitespace().collect();
+        match fields.len() {
+            5 => fields.insert(0, "0"),
+            6 => {}
+            _ => return Err(invalid().into()),
+        }
+        let parse = |index: usize, min: u32, max: u32| parse_field(fields[index], min, max);
+        let parsed = (
+            parse(0, 0, 59),
+            parse(1, 0, 59),
+            parse(2, 0, 23),
+            parse(3, 1, 31),
+            parse(4, 1, 12),
+            parse(5, 0, 7),
+        );
+        match parsed {
+            (
+                Some(seconds),
+                Some(minutes),
+                Some(hours),
+                Some(days),
+                Some(months),
+                Some(weekdays),
+            ) => Ok(Cron {
+                seconds,
+                minutes,
+                hours,
+                days,
+                months,
+                // Sunday is both `0`, and `7`.
+                weekdays: (weekdays | weekdays >> 7) & 0x7f,
+                some_days: !fields[3].starts_with('*'),
+                some_weekdays: !fields[5].starts_with('*'),
+            }),
+            _ => Err(invalid().into()),
+        }
+    }
+}
+
+/// When a sensor is sampled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Every interval, counted from the first sample.
+    Every(Duration),
+    /// Every interval, on the wall-clock multiples of it, e.g. on the minute.
+    Aligned(Duration),
+    /// On the times matched by a cron expression.
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// The first scheduled time after `time`, if there is one.
+    pub fn next_after(&self, time: &DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match *self {
+            Schedule::Every(interval) => Some(*time + span(interval)),
+            Schedule::Aligned(interval) => {
+                let interval = span(interval).num_milliseconds();
+                if interval <= 0 {
+                    return Some(*time);
+                }
+                let ms = time.timestamp() * 1_000 + i64::from(time.timestamp_subsec_millis());
+                let next = (ms / interval + 1) * interval;
+                let nanos = (next % 1_000) as u32 * 1_000_000;
+                Some(Utc.timestamp(next / 1_000, nanos))
+            }
+            Schedule::Cron(ref cron) => cron.next_after(time),
+        }
+    }
+}
+
+/// Whether a chip is put to sleep between readings.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sleep {
+    /// Never.
+    Never,
+    /// After every reading.
+    Always,
+    /// When it is idle for at least `SLEEP_THRESHOLD` until the next reading.
+    Auto,
+}
+
+impl From<Option<bool>> for Sleep {
+    fn from(sleep: Option<bool>) -> Sleep {
+        match sleep {
+            Some(true) => Sleep::Always,
+            Some(false) => Sleep::Never,
+            None => Sleep::Auto,
+        }
+    }
+}
+
+/// The sampling of a sensor, on its schedule.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Job {
+    schedule: Schedule,
+    latency: Duration,
+    sleep: Sleep,
+    next: Option<DateTime<Utc>>,
+}
+
+impl Job {
+    /// A job for a chip that takes `latency` to take a reading. Readings on an interval
+    /// start right away, and the others at the first scheduled time after `now` that the
+    /// chip can make.
+    pub fn new(schedule: Schedule, latency: Duration, sleep: Sleep, now: &DateTime<Utc>) -> Job {
+        let mut job = Job {
+            schedule,
+            latency,
+            sleep,
+            next: None,
+        };
+        let ready = *now + span(latency);
+        job.next = match job.schedule {
+            Schedule::Every(_) => Some(ready),
+            _ => job.schedule.next_after(&ready),
+        };
+        job
+    }
+
+    /// The schedule of the job.
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    /// When the next reading is scheduled, if there is one.
+    pub fn next(&self) -> Option<DateTime<Utc>> {
+        self.next
+    }
+
+    /// When to start the next reading, so that it is taken at its scheduled time.
+    pub fn start_at(&self) -> Option<DateTime<Utc>> {
+        self.next.map(|next| next - span(self.latency))
+    }
+
+    /// Whether the next reading is due to start at `now`.
+    pub fn is_due(&self, now: &DateTime<Utc>) -> bool {
+        self.start_at().map_or(false, |start| start <= *now)
+    }
+
+    /// Move on to the reading after the one started at `now`. Readings that can no longer be
+    /// taken on time are skipped.
+    pub fn advance(&mut self, now: &DateTime<Utc>) {
+        let ready = *now + span(self.latency);
+        self.next = match self.schedule {
+            // Keep the cadence, unless we have fallen behind.
+            Schedule::Every(interval) => self.next.map(|next| {
+                let next = next + span(interval);
+                if next < ready {
+                    ready + span(interval)
+                } else {
+                    next
+                }
+            }),
+            _ => {
+                let after = cmp::max(self.next.unwrap_or(ready), ready);
+                self.schedule.next_after(&after)
+            }
+        };
+    }
+
+    /// Whether to put the chip to sleep after the reading started at `now`, once the job
+    /// has moved on to the next one.
+    pub fn sleeps_after(&self, now: &DateTime<Utc>) -> bool {
+        match self.sleep {
+            Sleep::Never => false,
+            Sleep::Always => true,
+            Sleep::Auto => match self.start_at() {
+                Some(start) => {
+                    let idle = start - (*now + span(self.latency));
+                    idle >= chrono::Duration::milliseconds(SLEEP_THRESHOLD as i64)
+                }
+                None => true,
+            },
+        }
+    }
+
+    /// Follow another schedule, and sleep setting, from `now`. A shorter interval takes
+    /// effect right away, and other schedules from their next scheduled time.
+    pub fn reschedule(&mut self, schedule: Schedule, sleep: Sleep, now: &DateTime<Utc>) {
+        let ready = *now + span(self.latency);
+        let first = schedule.next_after(&ready);
+        self.next = match schedule {
+            Schedule::Every(_) => match (self.next, first) {
+                (Some(next), Some(first)) => Some(cmp::min(next, first)),
+                (next, first) => next.or(first),
+            },
+            _ => first,
+        };
+        self.schedule = schedule;
+        self.sleep = sleep;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_cron_expressions() {
+        let cron: Cron = "*/15 8-17 * * 1-5".parse().unwrap();
+        assert_eq!(1, cron.seconds);
+        assert_eq!(1 | 1 << 15 | 1 << 30 | 1 << 45, cron.minutes);
+        assert_eq!(0b11_1111_1111 << 8, cron.hours);
+        assert_eq!(0b11_1110, cron.weekdays);
+        assert!(!cron.some_days);
+        assert!(cron.some_weekdays);
+
+        let sundays: Cron = "30 0 12 * * 7".parse().unwrap();
+        assert_eq!(1 << 30, sundays.seconds);
+        assert_eq!(1, sundays.weekdays);
+
+        for invalid in &[
+            "* * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "*/0 * * * *",
+            "a * * * *",
+        ] {
+            assert!(invalid.parse::<Cron>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn next_times_of_schedules() {
+        let time = at("2018-03-30T16:59:58.500Z");
+        let every = Schedule::Every(Duration::from_millis(10_000));
+        assert_eq!(
+            Some(at("2018-03-30T17:00:08.500Z")),
+            every.next_after(&time)
+        );
+        let aligned = Schedule::Aligned(Duration::from_millis(60_000));
+        assert_eq!(Some(at("2018-03-30T17:00:00Z")), aligned.next_after(&time));
+
+        // Weekdays, every quarter of an hour from 08:00 to 17:45. 2018-03-30 is a Friday.
+        let cron = Schedule::Cron("*/15 8-17 * * 1-5".parse().unwrap());
+        assert_eq!(Some(at("2018-03-30T17:00:00Z")), cron.next_after(&time));
+        let evening = at("2018-03-30T17:45:00Z");
+        assert_eq!(Some(at("2018-04-02T08:00:00Z")), cron.next_after(&evening));
+
+        let leap_day = Schedule::Cron("0 0 29 2 *".parse().unwrap());
+        assert_eq!(Some(at("2020-02-29T00:00:00Z")), leap_day.next_after(&time));
+        let never = Schedule::Cron("0 0 31 2 *".parse().unwrap());
+        assert_eq!(None, never.next_after(&time));
+    }
+
+    #[test]
+    fn jobs_start_ahead_by_the_reading_latency() {
+        let now = at("2018-03-30T16:59:30Z");
+        let latency = reading_latency(SensorKind::Ph);
+        let aligned = Schedule::Aligned(Duration::from_millis(60_000));
+        let mut job = Job::new(aligned, latency, Sleep::Auto, &now);
+        assert_eq!(Some(at("2018-03-30T17:00:00Z")), job.next());
+        assert_eq!(Some(at("2018-03-30T16:59:59.100Z")), job.start_at());
+        assert!(!job.is_due(&now));
+
+        let start = at("2018-03-30T16:59:59.100Z");
+        assert!(job.is_due(&start));
+        job.advance(&start);
+        assert_eq!(Some(at("2018-03-30T17:01:00Z")), job.next());
+        // Idle for almost a minute, so it sleeps.
+        assert!(job.sleeps_after(&start));
+
+        // Readings too late to be taken on time are skipped.
+        let late = at("2018-03-30T17:00:59.500Z");
+        job.advance(&late);
+        assert_eq!(Some(at("2018-03-30T17:02:00Z")), job.next());
+    }
+
+    #[test]
+    fn jobs_sleep_when_idle_long_enough() {
+        let now = at("2018-03-30T17:00:00Z");
+        let latency = reading_latency(SensorKind::Temperature);
+        let every = |ms| Schedule::Every(Duration::from_millis(ms));
+
+        let mut busy = Job::new(every(2_000), latency, Sleep::Auto, &now);
+        assert!(busy.is_due(&now));
+        busy.advance(&now);
+        assert!(!busy.sleeps_after(&now));
+
+        let mut idle = Job::new(every(10_000), latency, Sleep::Auto, &now);
+        idle.advance(&now);
+        assert!(idle.sleeps_after(&now));
+        idle.reschedule(every(10_000), Sleep::Never, &now);
+        assert!(!idle.sleeps_after(&now));
+
+        // A shorter interval takes effect right away.
+        idle.reschedule(every(1_000), Sleep::Always, &now);
+        assert_eq!(Some(at("2018-03-30T17:00:01.600Z")), idle.next());
+        assert!(idle.sleeps_after(&now));
+    }
+}
@@ -0,0 +1,219 @@
+//! Operating states of the sensors sampled by a node.
+//!
+//! Every sensor sampled by a `SensorPublisher` is in one of these states:
+//!
+//! * `sampling`, the default. Readings are published, stored, aggregated, and watched for
+//!   alarms.
+//! * `calibrating`, while the probe sits in a calibration solution, and `maintenance`, while
+//!   it is cleaned, or serviced. Readings are published on the `calibrating/`, or
+//!   `maintenance/`, topic of the sensor, and never stored, aggregated, or watched for alarms.
+//! * `fault`, after `FAULT_THRESHOLD` readings in a row fail. Readings are still attempted,
+//!   and the first one that succeeds brings the sensor back to `sampling`.
+//! * `sleeping`. The chip is put to sleep, and no readings are taken.
+//!
+//! Only these changes are allowed:
+//!
+//! | from          | to                                                |
+//! |---------------|---------------------------------------------------|
+//! | `sampling`    | `calibrating`, `maintenance`, `sleeping`, `fault` |
+//! | `calibrating` | `sampling`, `maintenance`                         |
+//! | `maintenance` | `sampling`, `calibrating`                         |
+//! | `sleeping`    | `sampling`, `calibrating`, `maintenance`          |
+//! | `fault`       | `sampling`, `maintenance`                         |
+//!
+//! Every change is published on the `state` topic:
+//!
+//! ```text
+//! state <sensor> <timestamp> <from> <to>
+//! ```
+use std::fmt;
+use std::str::FromStr;
+
+use errors::*;
+
+use chrono::{DateTime, Utc};
+
+/// Topic of the published state changes.
+pub const STATE_TOPIC: &str = "state";
+
+/// Failed readings in a row that put a sampling sensor in `fault`.
+pub const FAULT_THRESHOLD: u32 = 3;
+
+/// The operating state of a sensor.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SensorState {
+    /// Readings are data.
+    Sampling,
+    /// The probe is being calibrated.
+    Calibrating,
+    /// The probe is being cleaned, or serviced.
+    Maintenance,
+    /// Readings fail.
+    Fault,
+    /// The chip sleeps, and is not read.
+    Sleeping,
+}
+
+impl Default for SensorState {
+    fn default() -> SensorState {
+        SensorState::Sampling
+    }
+}
+
+impl SensorState {
+    /// Whether a sensor in this state may change to `next`. Staying in the same state is
+    /// always allowed.
+    pub fn can_change_to(self, next: SensorState) -> bool {
+        use self::SensorState::*;
+        match (self, next) {
+            (from, to) if from == to => true,
+            (Sampling, _) => true,
+            (Calibrating, Sampling) | (Calibrating, Maintenance) => true,
+            (Maintenance, Sampling) | (Maintenance, Calibrating) => true,
+            (Sleeping, Sampling) | (Sleeping, Calibrating) | (Sleeping, Maintenance) => true,
+            (Fault, Sampling) | (Fault, Maintenance) => true,
+            _ => false,
+        }
+    }
+
+    /// Change to `next`, if it is allowed.
+    pub fn change_to(self, next: SensorState) -> Result<SensorState> {
+        if self.can_change_to(next) {
+            Ok(next)
+        } else {
+            let reason = format!("{} to {}", self, next);
+            Err(ErrorKind::InvalidStateChange(reason).into())
+        }
+    }
+
+    /// Whether readings are taken in this state.
+    pub fn samples(self) -> bool {
+        self != SensorState::Sleeping
+    }
+
+    /// Whether readings taken in this state are data, to be stored, aggregated, and watched
+    /// for alarms.
+    pub fn records(self) -> bool {
+        self == SensorState::Sampling
+    }
+
+    /// The topic that readings of the sensor published on `topic` are published on, in this
+    /// state.
+    pub fn topic(self, topic: &str) -> String {
+        match self {
+            SensorState::Calibrating | SensorState::Maintenance => format!("{}/{}", self, topic),
+            _ => topic.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SensorState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            SensorState::Sampling => "sampling",
+            SensorState::Calibrating => "calibrating",
+            SensorState::Maintenance => "maintenance",
+            SensorState::Fault => "fault",
+            SensorState::Sleeping => "sleeping",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for SensorState {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SensorState> {
+        match s {
+            "sampling" => Ok(SensorState::Sampling),
+            "calibrating" | "calibration" => Ok(SensorState::Calibrating),
+            "maintenance" | "cleaning" => Ok(SensorState::Maintenance),
+            "fault" => Ok(SensorState::Fault),
+            "sleeping" => Ok(SensorState::Sleeping),
+            _ => Err(ErrorKind::InvalidStateChange(format!("unknown state {:?}", s)).into()),
+        }
+    }
+}
+
+/// A change in the state of a sensor, as published on the `state` topic.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateChange {
+    /// The topic of the sensor.
+    pub sensor: String,
+    /// When the state changed.
+    pub timestamp: DateTime<Utc>,
+    /// The state before the change.
+    pub from: SensorState,
+    /// The state after the change.
+    pub to: SensorState,
+}
+
+impl fmt::Display for StateChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {:?} {} {}",
+            STATE_TOPIC, self.sensor, self.timestamp, self.from, self.to
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn guarded_state_changes() {
+        use self::SensorState::*;
+        assert_eq!(Calibrating, Sampling.change_to(Calibrating).unwrap());
+        assert_eq!(Sampling, Calibrating.change_to(Sampling).unwrap());
+        assert_eq!(Sleeping, Sleeping.change_to(Sleeping).unwrap());
+        assert!(Calibrating.change_to(Sleeping).is_err());
+        assert!(Maintenance.change_to(Fault).is_err());
+        assert!(Sleeping.change_to(Fault).is_err());
+        assert!(Fault.change_to(Calibrating).is_err());
+        assert_eq!(
+            "invalid sensor state change: calibrating to sleeping",
+            Calibrating.change_to(Sleeping).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn readings_are_data_only_while_sampling() {
+        assert!(SensorState::Sampling.records());
+        assert!(!SensorState::Calibrating.records());
+        assert!(SensorState::Calibrating.samples());
+        assert!(!SensorState::Sleeping.samples());
+        assert_eq!("ph-01", SensorState::Sampling.topic("ph-01"));
+        assert_eq!("calibrating/ph-01", SensorState::Calibrating.topic("ph-01"));
+        assert_eq!("maintenance/ph-01", SensorState::Maintenance.topic("ph-01"));
+    }
+
+    #[test]
+    fn parse_and_format_states() {
+        for name in &[
+            "sampling",
+            "calibrating",
+            "maintenance",
+            "fault",
+            "sleeping",
+        ] {
+            let state: SensorState = name.parse().unwrap();
+            assert_eq!(*name, state.to_string());
+        }
+        assert_eq!(SensorState::Maintenance, "cleaning".parse().unwrap());
+        assert!("resting".parse::<SensorState>().is_err());
+
+        let change = StateChange {
+            sensor: "ph-01".to_string(),
+            timestamp: Utc.ymd(2017, 11, 18).and_hms(0, 0, 0),
+            from: SensorState::Sampling,
+            to: SensorState::Calibrating,
+        };
+        assert_eq!(
+            "state ph-01 2017-11-18T00:00:00Z sampling calibrating",
+            change.to_string()
+        );
+    }
+}
@@ -0,0 +1,816 @@
+//! Persistent, local, storage of measurements.
+//!
+//! A `MeasurementLog` appends measurements, in their 28-byte binary encoding, to segment
+//! files inside a directory. Segments are named `segment-<sequence>.log`, and the newest one
+//! is the only one written to. A new segment is started when the current one reaches its
+//! maximum size, or covers more time than its maximum age. The oldest segments are removed
+//! once there are too many of them, or once their data is older than the retention period.
+//! By default, a log keeps 30 days of measurements, in at most 1024 segments.
+//!
+//! When a node crashes in the middle of a write, the newest segment may end with a partial
+//! record. Opening the log truncates it back to the last complete record. Complete records
+//! that cannot be decoded, e.g. with an unknown sensor type, are skipped with a warning, so
+//! that one corrupt record does not make the rest of the log unreadable.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use errors::*;
+use measurement::{Measurement, RECORD_SIZE};
+
+use chrono::{self, DateTime, Utc};
+
+const SEGMENT_PREFIX: &str = "segment-";
+const SEGMENT_SUFFIX: &str = ".log";
+
+/// Rotation, and retention, limits for a `MeasurementLog`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogPolicy {
+    /// Maximum size of a segment, in bytes.
+    pub max_segment_size: u64,
+    /// Maximum time covered by a segment, from its oldest measurement.
+    pub max_segment_age: Option<Duration>,
+    /// Maximum number of segments to keep.
+    pub max_segments: Option<usize>,
+    /// How long to keep measurements, relative to the newest one appended, or to the clock if
+    /// that one is in the future.
+    pub retention: Option<Duration>,
+}
+
+impl Default for LogPolicy {
+    fn default() -> LogPolicy {
+        LogPolicy {
+            max_segment_size: 1024 * 1024,
+            max_segment_age: Some(Duration::from_secs(24 * 60 * 60)),
+            max_segments: Some(1024),
+            retention: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+        }
+    }
+}
+
+// A segment file, and the time range it covers.
+#[derive(Clone, Debug)]
+struct Segment {
+    sequence: u64,
+    path: PathBuf,
+    size: u64,
+    oldest: Option<DateTime<Utc>>,
+    newest: Option<DateTime<Utc>>,
+}
+
+impl Segment {
+    fn new(dir: &Path, sequence: u64) -> Segment {
+        let path = dir.join(format!(
+            "{}{:08}{}",
+            SEGMENT_PREFIX, sequence, SEGMENT_SUFFIX
+        ));
+        Segment {
+            sequence,
+            path,
+            size: 0,
+            oldest: None,
+            newest: None,
+        }
+    }
+
+    fn sequence_from_path(path: &Path) -> Option<u64> {
+        let name = path.file_name()?.to_str()?;
+        if !name.starts_with(SEGMENT_PREFIX) || !name.ends_with(SEGMENT_SUFFIX) {
+            return None;
+        }
+        name[SEGMENT_PREFIX.len()..name.len() - SEGMENT_SUFFIX.len()]
+            .parse()
+            .ok()
+    }
+
+    // The segment `sequence` in `dir`, with the time range of its records. A segment that
+    // does not exist is empty.
+    fn load(dir: &Path, sequence: u64) -> Result<Segment> {
+        let mut segment = Segment::new(dir, sequence);
+        if segment.path.exists() {
+            for m in segment.read()? {
+                segment.update(m.timestamp);
+            }
+            // Skipped records still take up space.
+            segment.size = fs::metadata(&segment.path)
+                .context(ErrorKind::LogStorage)?
+                .len();
+        }
+        Ok(segment)
+    }
+
+    fn update(&mut self, timestamp: DateTime<Utc>) {
+        self.size += RECORD_SIZE as u64;
+        if self.oldest.map_or(true, |t| timestamp < t) {
+            self.oldest = Some(timestamp);
+        }
+        if self.newest.map_or(true, |t| timestamp > t) {
+            self.newest = Some(timestamp);
+        }
+    }
+
+    // Does this segment hold any measurement in `[from, to)`?
+    fn overlaps(&self, from: &DateTime<Utc>, to: &DateTime<Utc>) -> bool {
+        match (self.oldest, self.newest) {
+            (Some(oldest), Some(newest)) => oldest < *to && newest >= *from,
+            _ => false,
+        }
+    }
+
+    // Read every complete record in the segment. A partial record at the end, from a write
+    // in progress, is ignored, and corrupt records are skipped.
+    fn read(&self) -> Result<Vec<Measurement>> {
+        let mut buf = Vec::new();
+        let _read = File::open(&self.path)
+            .and_then(|mut f| f.read_to_end(&mut buf))
+            .context(ErrorKind::LogStorage)?;
+        let mut measurements = Vec::with_capacity(buf.len() / RECORD_SIZE);
+        for (i, record) in buf.chunks(RECORD_SIZE).enumerate() {
+            if record.len() != RECORD_SIZE {
+                continue;
+            }
+            match Measurement::from_bytes(record) {
+                Ok(m) => measurements.push(m),
+                Err(e) => warn!(
+                    "skipping corrupt record at byte {} of {}: {}",
+                    i * RECORD_SIZE,
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+        Ok(measurements)
+    }
+}
+
+// The sequence numbers of the segments in `dir`, from oldest to newest.
+fn list_segments(dir: &Path) -> Result<Vec<u64>> {
+    let mut sequences = Vec::new();
+    for entry in fs::read_dir(dir).context(ErrorKind::LogStorage)? {
+        let entry = entry.context(ErrorKind::LogStorage)?;
+        if let Some(sequence) = Segment::sequence_from_path(&entry.path()) {
+            sequences.push(sequence);
+        }
+    }
+    sequences.sort();
+    Ok(sequences)
+}
+
+fn in_range(m: &Measurement, from: &DateTime<Utc>, to: &DateTime<Utc>) -> bool {
+    m.timestamp >= *from && m.timestamp < *to
+}
+
+fn to_chrono(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::max_value())
+}
+
+/// Append-only log of measurements, stored on the local filesystem.
+pub struct MeasurementLog {
+    dir: PathBuf,
+    policy: LogPolicy,
+    segments: Vec<Segment>,
+    file: File,
+}
+
+impl MeasurementLog {
+    /// Open the log stored in `dir`, creating it if needed.
+    ///
+    /// Partial records left at the end of the newest segment by a crash are removed.
+    pub fn open<P: AsRef<Path>>(dir: P, policy: LogPolicy) -> Result<MeasurementLog> {
+        let dir = dir.as_ref().to_path_buf();
+        let _create = fs::create_dir_all(&dir).context(ErrorKind::LogStorage)?;
+
+        let mut sequences = list_segments(&dir)?;
+        if sequences.is_empty() {
+            sequences.push(0);
+        }
+
+        let last = *sequences.last().unwrap();
+        let mut segments = Vec::with_capacity(sequences.len());
+        for sequence in sequences {
+            if sequence == last {
+                MeasurementLog::recover(&Segment::new(&dir, sequence).path)?;
+            }
+            segments.push(Segment::load(&dir, sequence)?);
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&segments.last().unwrap().path)
+            .context(ErrorKind::LogStorage)?;
+
+        Ok(MeasurementLog {
+            dir,
+            policy,
+            segments,
+            file,
+        })
+    }
+
+    // Truncate a segment to its last complete record.
+    fn recover(path: &Path) -> Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let file = OpenOptions::new()
+            .write(true)
+            .open(path)
+            .context(ErrorKind::LogStorage)?;
+        let len = file.metadata().context(ErrorKind::LogStorage)?.len();
+        let partial = len % RECORD_SIZE as u64;
+        if partial != 0 {
+            warn!(
+                "truncating {} bytes of a partial record from {}",
+                partial,
+                path.display()
+            );
+            let _truncate = file.set_len(len - partial).context(ErrorKind::LogStorage)?;
+            let _sync = file.sync_all().context(ErrorKind::LogStorage)?;
+        }
+        Ok(())
+    }
+
+    /// The directory that holds the log.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The rotation, and retention, limits of the log.
+    pub fn policy(&self) -> &LogPolicy {
+        &self.policy
+    }
+
+    /// The paths to the segment files, from oldest to newest.
+    pub fn segments(&self) -> Vec<PathBuf> {
+        self.segments.iter().map(|s| s.path.clone()).collect()
+    }
+
+    /// The oldest, and newest, timestamps stored in the log.
+    pub fn time_range(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let oldest = self.segments.iter().filter_map(|s| s.oldest).min()?;
+        let newest = self.segments.iter().filter_map(|s| s.newest).max()?;
+        Some((oldest, newest))
+    }
+
+    /// Append a measurement to the log.
+    pub fn append(&mut self, measurement: &Measurement) -> Result<()> {
+        let buf = measurement.to_bytes()?;
+        if self.should_rotate(measurement) {
+            let _rotate = self.rotate()?;
+        }
+        let _write = self.file.write_all(&buf).context(ErrorKind::LogStorage)?;
+        self.segments
+            .last_mut()
+            .unwrap()
+            .update(measurement.timestamp);
+        // A measurement from a clock gone wrong must not expire the whole log.
+        self.apply_retention(::std::cmp::min(measurement.timestamp, Utc::now()))
+    }
+
+    /// Flush appended measurements to the disk.
+    pub fn sync(&self) -> Result<()> {
+        let _sync = self.file.sync_data().context(ErrorKind::LogStorage)?;
+        Ok(())
+    }
+
+    fn should_rotate(&self, measurement: &Measurement) -> bool {
+        let current = self.segments.last().unwrap();
+        if current.size == 0 {
+            return false;
+        }
+        if current.size + RECORD_SIZE as u64 > self.policy.max_segment_size {
+            return true;
+        }
+        match (self.policy.max_segment_age, current.oldest) {
+            (Some(age), Some(oldest)) => measurement.timestamp - oldest >= to_chrono(age),
+            _ => false,
+        }
+    }
+
+    // Start a new segment.
+    fn rotate(&mut self) -> Result<()> {
+        let sequence = self.segments.last().unwrap().sequence + 1;
+        let segment = Segment::new(&self.dir, sequence);
+        debug!("rotating measurement log to {}", segment.path.display());
+        self.file = OpenOptions::new()
+            .create_new(true)
+            .append(true)
+            .open(&segment.path)
+            .context(ErrorKind::LogStorage)?;
+        self.segments.push(segment);
+        Ok(())
+    }
+
+    // Remove the oldest segments that are beyond the retention limits. The current segment is
+    // never removed.
+    fn apply_retention(&mut self, now: DateTime<Utc>) -> Result<()> {
+        while self.segments.len() > 1 {
+            let too_many = self
+                .policy
+                .max_segments
+                .map_or(false, |max| self.segments.len() > max);
+            let expired = match (self.policy.retention, self.segments[0].newest) {
+                (Some(retention), Some(newest)) => now - newest > to_chrono(retention),
+                _ => false,
+            };
+            if !too_many && !expired {
+                break;
+            }
+            let segment = self.segments.remove(0);
+            debug!("removing measurement log {}", segment.path.display());
+            let _remove = fs::remove_file(&segment.path).context(ErrorKind::LogStorage)?;
+        }
+        Ok(())
+    }
+
+    /// Read the measurements taken in `[from, to)`, in the order they were appended.
+    pub fn read_range(&self, from: &DateTime<Utc>, to: &DateTime<Utc>) -> Result<Vec<Measurement>> {
+        let mut measurements = Vec::new();
+        for segment in self.segments.iter().filter(|s| s.overlaps(from, to)) {
+            measurements.extend(
+                segment
+                    .read()?
+                    .into_iter()
+                    .filter(|m| in_range(m, from, to)),
+            );
+        }
+        Ok(measurements)
+    }
+
+    /// Read every measurement in the log.
+    pub fn read_all(&self) -> Result<Vec<Measurement>> {
+        let mut measurements = Vec::new();
+        for segment in &self.segments {
+            measurements.extend(segment.read()?);
+        }
+        Ok(measurements)
+    }
+}
+
+/// Read-only access to a measurement log, e.g. from another process than the one that
+/// appends to it.
+///
+/// The segments are listed again on every read, so that new, and removed, segments are seen.
+/// The time ranges of the segments that are no longer written to are kept, and shared by the
+/// clones of the reader.
+#[derive(Clone, Debug)]
+pub struct LogReader {
+    dir: PathBuf,
+    closed: Arc<Mutex<BTreeMap<u64, Segment>>>,
+}
+
+impl LogReader {
+    /// Create a reader for the log stored in `dir`.
+    pub fn new<P: AsRef<Path>>(dir: P) -> LogReader {
+        LogReader {
+            dir: dir.as_ref().to_path_buf(),
+            closed: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// The directory that holds the log.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Read the measurements taken in `[from, to)`, in the order they were appended.
+    pub fn read_range(&self, from: &DateTime<Utc>, to: &DateTime<Utc>) -> Result<Vec<Measurement>> {
+        let mut measurements = Vec::new();
+        for sequence in list_segments(&self.dir)? {
+            let segment = Segment::new(&self.dir, sequence);
+            // The segment may have been removed since it was listed.
+            let records = match segment.read() {
+                Ok(records) => records,
+                Err(_) if !segment.path.exists() => continue,
+                Err(e) => return Err(e),
+            };
+            measurements.extend(records.into_iter().filter(|m| in_range(m, from, to)));
+        }
+        Ok(measurements)
+    }
+
+    /// The cursor at the start of the first segment that can hold measurements taken in
+    /// `[from, to)`, or at the end of the log if there is none.
+    ///
+    /// Only the newest segment, which is still written to, is always read from the start.
+    pub fn seek(&self, from: &DateTime<Utc>, to: &DateTime<Utc>) -> Result<LogCursor> {
+        let sequences = list_segments(&self.dir)?;
+        let last = match sequences.last() {
+            Some(last) => *last,
+            None => return Ok(LogCursor::default()),
+        };
+        let mut closed = match self.closed.lock() {
+            Ok(closed) => closed,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        closed.retain(|sequence, _| sequences.binary_search(sequence).is_ok());
+        for sequence in sequences {
+            if sequence == last {
+                break;
+            }
+            if !closed.contains_key(&sequence) {
+                let segment = Segment::load(&self.dir, sequence)?;
+                let _old = closed.insert(sequence, segment);
+            }
+            if closed[&sequence].overlaps(from, to) {
+                return Ok(LogCursor {
+                    segment: sequence,
+                    offset: 0,
+                });
+            }
+        }
+        Ok(LogCursor {
+            segment: last,
+            offset: 0,
+        })
+    }
+
+    /// Read the records from `cursor` on, in the order they were appended, without reading
+    /// the records before it.
+    pub fn scan(&self, cursor: LogCursor) -> Result<LogScan> {
+        let mut sequences: Vec<u64> = list_segments(&self.dir)?
+            .into_iter()
+            .filter(|sequence| *sequence >= cursor.segment)
+            .collect();
+        sequences.reverse();
+        Ok(LogScan {
+            dir: self.dir.clone(),
+            sequences,
+            start: cursor,
+            current: None,
+        })
+    }
+}
+
+/// A position in a measurement log: a segment, and the offset of a record inside it.
+///
+/// Its text form is `<segment>:<offset>`. The default cursor is the start of the log.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LogCursor {
+    /// Sequence number of the segment.
+    pub segment: u64,
+    /// Offset of the record in the segment, in bytes.
+    pub offset: u64,
+}
+
+impl fmt::Display for LogCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.segment, self.offset)
+    }
+}
+
+impl ::std::str::FromStr for LogCursor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<LogCursor> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(segment), Some(offset)) => Ok(LogCursor {
+                segment: segment.parse::<u64>().context(ErrorKind::NumberParse)?,
+                offset: offset.parse::<u64>().context(ErrorKind::NumberParse)?,
+            }),
+            _ => Err(ErrorKind::NumberParse.into()),
+        }
+    }
+}
+
+/// Iterator over the measurements in a log, from a `LogCursor` on. Every measurement comes
+/// with the cursor of its record.
+///
+/// Like the other reads, a partial record at the end of a segment is ignored, corrupt
+/// records are skipped, and segments removed in the meantime are passed over.
+pub struct LogScan {
+    dir: PathBuf,
+    // Segments left to read, the next one last.
+    sequences: Vec<u64>,
+    start: LogCursor,
+    current: Option<(LogCursor, BufReader<File>)>,
+}
+
+impl LogScan {
+    // Open the next segment, at the start cursor if it is the first one. Returns `false`
+    // once there are no segments left.
+    fn open_next(&mut self) -> Result<bool> {
+        let sequence = match self.sequences.pop() {
+            Some(sequence) => sequence,
+            None => return Ok(false),
+        };
+        let segment = Segment::new(&self.dir, sequence);
+        let offset = if sequence == self.start.segment {
+            self.start.offset
+        } else {
+            0
+        };
+        let mut file = match File::open(&segment.path) {
+            Ok(file) => file,
+            // The segment has been removed since it was listed.
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => Err(e).context(ErrorKind::LogStorage)?,
+        };
+        let _offset = file
+            .seek(SeekFrom::Start(offset))
+            .context(ErrorKind::LogStorage)?;
+        let cursor = LogCursor {
+            segment: sequence,
+            offset,
+        };
+        self.current = Some((cursor, BufReader::new(file)));
+        Ok(true)
+    }
+}
+
+impl Iterator for LogScan {
+    type Item = Result<(LogCursor, Measurement)>;
+
+    fn next(&mut self) -> Option<Result<(LogCursor, Measurement)>> {
+        let mut record = [0; RECORD_SIZE];
+        loop {
+            if self.current.is_none() {
+                match self.open_next() {
+                    Ok(true) => continue,
+                    Ok(false) => return None,
+                    Err(e) => return Some(Err(e)),
+                }
+            }
+            let read = {
+                let (ref mut cursor, ref mut reader) = *self.current.as_mut().unwrap();
+                let at = *cursor;
+                cursor.offset += RECORD_SIZE as u64;
+                reader.read_exact(&mut record).map(|_| at)
+            };
+            let at = match read {
+                Ok(at) => at,
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    self.current = None;
+                    continue;
+                }
+                Err(e) => {
+                    self.current = None;
+                    return Some(Err(e).context(ErrorKind::LogStorage).map_err(Error::from));
+                }
+            };
+            match Measurement::from_bytes(&record) {
+                Ok(m) => return Some(Ok((at, m))),
+                Err(e) => warn!(
+                    "skipping corrupt record at byte {} of segment {}: {}",
+                    at.offset, at.segment, e
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use measurement::{SensorId, SensorType};
+    use std::env;
+
+    fn log_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("benita-store-{}-{}", name, ::std::process::id()));
+        let _remove = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn measurement(secs: i64) -> Measurement {
+        Measurement::new(
+            Utc.timestamp(1_500_000_000 + secs, 0),
+            SensorType::Temperature,
+            SensorId([1; 16]),
+            secs as f32,
+        )
+    }
+
+    #[test]
+    fn append_and_read_measurements_by_time_range() {
+        let dir = log_dir("range");
+        {
+            let mut log = MeasurementLog::open(&dir, LogPolicy::default()).unwrap();
+            for secs in 0..10 {
+                log.append(&measurement(secs)).unwrap();
+            }
+        }
+        let log = MeasurementLog::open(&dir, LogPolicy::default()).unwrap();
+        let read = log
+            .read_range(&measurement(3).timestamp, &measurement(6).timestamp)
+            .unwrap();
+        assert_eq!(vec![measurement(3), measurement(4), measurement(5)], read);
+        assert_eq!(10, log.read_all().unwrap().len());
+        assert_eq!(
+            Some((measurement(0).timestamp, measurement(9).timestamp)),
+            log.time_range()
+        );
+        let _remove = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rotate_segments_by_size_and_age() {
+        let dir = log_dir("rotate");
+        let policy = LogPolicy {
+            max_segment_size: 4 * RECORD_SIZE as u64,
+            max_segment_age: Some(Duration::from_secs(60)),
+            ..LogPolicy::default()
+        };
+        let mut log = MeasurementLog::open(&dir, policy).unwrap();
+        for secs in 0..10 {
+            log.append(&measurement(secs)).unwrap();
+        }
+        assert_eq!(3, log.segments().len());
+        log.append(&measurement(100)).unwrap();
+        log.append(&measurement(200)).unwrap();
+        assert_eq!(5, log.segments().len());
+        assert_eq!(12, log.read_all().unwrap().len());
+        let _remove = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn remove_segments_beyond_retention() {
+        let dir = log_dir("retention");
+        let policy = LogPolicy {
+            max_segment_size: 2 * RECORD_SIZE as u64,
+            max_segment_age: None,
+            max_segments: Some(3),
+            retention: None,
+        };
+        let mut log = MeasurementLog::open(&dir, policy).unwrap();
+        for secs in 0..10 {
+            log.append(&measurement(secs)).unwrap();
+        }
+        assert_eq!(3, log.segments().len());
+        assert_eq!(
+            Some((measurement(4).timestamp, measurement(9).timestamp)),
+            log.time_range()
+        );
+
+        let policy = LogPolicy {
+            max_segment_size: 2 * RECORD_SIZE as u64,
+            max_segment_age: None,
+            max_segments: None,
+            retention: Some(Duration::from_secs(100)),
+        };
+        let mut log = MeasurementLog::open(&dir, policy).unwrap();
+        log.append(&measurement(106)).unwrap();
+        log.append(&measurement(107)).unwrap();
+        assert_eq!(
+            Some((measurement(6).timestamp, measurement(107).timestamp)),
+            log.time_range()
+        );
+        let _remove = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn keep_segments_after_measurements_from_the_future() {
+        let dir = log_dir("future");
+        let policy = LogPolicy {
+            max_segment_size: 2 * RECORD_SIZE as u64,
+            max_segment_age: None,
+            max_segments: None,
+            retention: Some(Duration::from_secs(20 * 365 * 24 * 60 * 60)),
+        };
+        let mut log = MeasurementLog::open(&dir, policy).unwrap();
+        for secs in 0..4 {
+            log.append(&measurement(secs)).unwrap();
+        }
+        // Over 30 years after the others, and still in the future.
+        log.append(&measurement(1_000_000_000)).unwrap();
+        assert_eq!(3, log.segments().len());
+        assert_eq!(5, log.read_all().unwrap().len());
+        let _remove = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn truncate_partial_records_on_open() {
+        let dir = log_dir("recover");
+        let path = {
+            let mut log = MeasurementLog::open(&dir, LogPolicy::default()).unwrap();
+            log.append(&measurement(0)).unwrap();
+            log.append(&measurement(1)).unwrap();
+            log.segments().pop().unwrap()
+        };
+        // A crash in the middle of a write.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&measurement(2).to_bytes().unwrap()[..13])
+            .unwrap();
+
+        let mut log = MeasurementLog::open(&dir, LogPolicy::default()).unwrap();
+        assert_eq!(2 * RECORD_SIZE as u64, fs::metadata(&path).unwrap().len());
+        log.append(&measurement(3)).unwrap();
+        assert_eq!(
+            vec![measurement(0), measurement(1), measurement(3)],
+            log.read_all().unwrap()
+        );
+
+        // Readers ignore partial records from writes in progress.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&measurement(4).to_bytes().unwrap()[..13])
+            .unwrap();
+        let reader = LogReader::new(&dir);
+        let read = reader
+            .read_range(&measurement(0).timestamp, &measurement(10).timestamp)
+            .unwrap();
+        assert_eq!(3, read.len());
+        let _remove = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn scan_log_from_a_cursor() {
+        let dir = log_dir("scan");
+        let policy = LogPolicy {
+            max_segment_size: 3 * RECORD_SIZE as u64,
+            ..LogPolicy::default()
+        };
+        let mut log = MeasurementLog::open(&dir, policy).unwrap();
+        for secs in 0..8 {
+            log.append(&measurement(secs)).unwrap();
+        }
+        assert_eq!(3, log.segments().len());
+
+        let reader = LogReader::new(&dir);
+        let records: Vec<(LogCursor, Measurement)> = reader
+            .scan(LogCursor::default())
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(8, records.len());
+        let (cursor, m) = records[4];
+        assert_eq!(measurement(4), m);
+        assert_eq!(cursor, cursor.to_string().parse().unwrap());
+
+        // Resume from the middle of the second segment.
+        let resumed: Vec<Measurement> = reader
+            .scan(cursor)
+            .unwrap()
+            .map(|record| record.unwrap().1)
+            .collect();
+        assert_eq!((4..8).map(measurement).collect::<Vec<_>>(), resumed);
+        assert!("3".parse::<LogCursor>().is_err());
+        assert!("3:x".parse::<LogCursor>().is_err());
+        let _remove = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn seek_the_first_segment_of_a_time_range() {
+        let dir = log_dir("seek");
+        let policy = LogPolicy {
+            max_segment_size: 3 * RECORD_SIZE as u64,
+            ..LogPolicy::default()
+        };
+        let mut log = MeasurementLog::open(&dir, policy).unwrap();
+        for secs in 0..8 {
+            log.append(&measurement(secs)).unwrap();
+        }
+
+        let reader = LogReader::new(&dir);
+        let seek = |from: i64, to: i64| {
+            reader
+                .seek(&measurement(from).timestamp, &measurement(to).timestamp)
+                .unwrap()
+        };
+        assert_eq!(LogCursor::default(), seek(0, 10));
+        assert_eq!(LogCursor { segment: 1, offset: 0 }, seek(4, 10));
+        assert_eq!(LogCursor { segment: 1, offset: 0 }, seek(5, 6));
+        // The newest segment may still receive any measurement.
+        assert_eq!(LogCursor { segment: 2, offset: 0 }, seek(100, 200));
+
+        // Removed segments are forgotten.
+        let _remove = fs::remove_file(&log.segments()[0]);
+        assert_eq!(LogCursor { segment: 1, offset: 0 }, seek(0, 10));
+        let _remove = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn skip_corrupt_records_on_open() {
+        let dir = log_dir("corrupt");
+        let path = {
+            let mut log = MeasurementLog::open(&dir, LogPolicy::default()).unwrap();
+            for secs in 0..3 {
+                log.append(&measurement(secs)).unwrap();
+            }
+            log.segments().pop().unwrap()
+        };
+        // An unknown sensor type in the middle record.
+        let mut buf = fs::read(&path).unwrap();
+        buf[RECORD_SIZE + 4..RECORD_SIZE + 8].copy_from_slice(&[0xff; 4]);
+        fs::write(&path, &buf).unwrap();
+
+        let mut log = MeasurementLog::open(&dir, LogPolicy::default()).unwrap();
+        log.append(&measurement(3)).unwrap();
+        assert_eq!(
+            vec![measurement(0), measurement(2), measurement(3)],
+            log.read_all().unwrap()
+        );
+        assert_eq!(4 * RECORD_SIZE as u64, fs::metadata(&path).unwrap().len());
+        let reader = LogReader::new(&dir);
+        let read = reader
+            .read_range(&measurement(0).timestamp, &measurement(10).timestamp)
+            .unwrap();
+        assert_eq!(3, read.len());
+        let _remove = fs::remove_dir_all(&dir);
+    }
+}
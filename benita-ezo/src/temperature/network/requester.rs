@@ -15,6 +15,28 @@ network_socket!(
     "Socket that communicates with the pH sensor."
 );
 
+impl TemperatureRequester {
+    /// Send a request string, as understood by its responder, and return the debug form of
+    /// the typed reply. Requests that are not understood yield `ErrorKind::RequestParse`.
+    pub fn request(&self, req_str: &str) -> Result<String> {
+        request_typed_reply!(self, req_str, [
+            CalibrationState,
+            CalibrationTemperature,
+            DataloggerDisable,
+            DataloggerInterval,
+            DataloggerPeriod,
+            MemoryClear,
+            MemoryRecall,
+            MemoryRecallLast,
+            Reading,
+            ScaleCelsius,
+            ScaleFahrenheit,
+            ScaleKelvin,
+            ScaleState,
+        ])
+    }
+}
+
 impl EzoChipAPI for TemperatureRequester {
     type SensorError = Error;
     type SensorReply = ReplyStatus;
@@ -2,10 +2,11 @@
 use super::super::device::TemperatureSensor;
 use super::super::TemperatureAPI;
 use super::replies::*;
+use super::requests::*;
 
 use common_ezo::EzoChipAPI;
 use errors::*;
-use network::{Endpoint, ReplyStatus};
+use network::{Endpoint, ReplyStatus, SocketReply, SocketRequest};
 
 use zmq::Socket;
 
@@ -17,6 +18,28 @@ network_sensor_socket! {
     "Socket that responds to Temperature sensor commands."
 }
 
+impl TemperatureResponder {
+    /// Evaluate a request, as sent by a `TemperatureRequester`, and return the text form of
+    /// its reply. Requests that are not understood yield `ErrorKind::RequestParse`.
+    pub fn respond(&self, req_str: &str) -> Result<String> {
+        respond_to_requests!(self, req_str, {
+            CalibrationState => self.get_calibration_status(),
+            CalibrationTemperature(value) => self.set_calibration_temperature(value),
+            DataloggerDisable => self.set_data_logger_off(),
+            DataloggerInterval => self.get_data_logger_status(),
+            DataloggerPeriod(interval) => self.set_data_logger_interval(interval),
+            MemoryClear => self.set_memory_clear(),
+            MemoryRecall => self.get_memory_recall(),
+            MemoryRecallLast => self.get_memory_recall_last(),
+            Reading => self.get_reading(),
+            ScaleCelsius => self.set_scale_to_celsius(),
+            ScaleFahrenheit => self.set_scale_to_fahrenheit(),
+            ScaleKelvin => self.set_scale_to_kelvin(),
+            ScaleState => self.get_scale(),
+        })
+    }
+}
+
 impl EzoChipAPI for TemperatureResponder {
     type SensorError = Error;
     type SensorReply = ReplyStatus;
@@ -1,6 +1,8 @@
 //! Shared useful utility functions.
+use std::time::Duration;
+
 use super::errors::*;
-use zmq::{Context, Socket, SocketType};
+use zmq::{self, Context, Socket, SocketType};
 
 /// simple atof conversion.
 pub fn atof(s: &str) -> Result<f64> {
@@ -8,6 +10,35 @@ pub fn atof(s: &str) -> Result<f64> {
     Ok(_float)
 }
 
+// Whole milliseconds in `timeout`.
+fn millis(timeout: Duration) -> u64 {
+    timeout.as_secs() * 1_000 + u64::from(timeout.subsec_nanos() / 1_000_000)
+}
+
+/// Wait up to `timeout` for a message to arrive on `socket`. Returns `true` if one did.
+pub fn poll_readable(socket: &Socket, timeout: Duration) -> Result<bool> {
+    let mut items = [socket.as_poll_item(zmq::POLLIN)];
+    let _poll = zmq::poll(&mut items, millis(timeout) as i64).context(ErrorKind::SocketReceive)?;
+    Ok(items[0].is_readable())
+}
+
+/// create a REQUESTER socket connected to the specified URL, that waits up to `timeout` for
+/// every reply. Endpoints on the socket yield `ErrorKind::ServiceTimeout` once it runs out.
+///
+/// A `REQ` socket can not send again until it gets a reply, so it has to be replaced after
+/// a timeout. The socket does not linger, so that it can be dropped right away.
+pub fn create_timed_requester(context: &Context, url: &str, timeout: Duration) -> Result<Socket> {
+    let requester = context
+        .socket(SocketType::REQ)
+        .context(ErrorKind::SocketCreate)?;
+    let _timeout = requester
+        .set_rcvtimeo(millis(timeout) as i32)
+        .context(ErrorKind::SocketCreate)?;
+    let _linger = requester.set_linger(0).context(ErrorKind::SocketCreate)?;
+    let _connect = requester.connect(url).context(ErrorKind::SocketConnect)?;
+    Ok(requester)
+}
+
 /// create a REQUESTER socket bound to the specified URL.
 pub fn create_and_bind_requester(url: &str) -> Result<Socket> {
     // We start our ZMQ context.
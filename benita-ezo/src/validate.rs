@@ -0,0 +1,950 @@
+//! Strict validation of configuration files.
+//!
+//! Parsing a configuration ignores the keys it does not know, so a typo like `frontent_url`
+//! silently falls back to the default. Validation is opt-in, with
+//! `NodeConfig::from_file_strict`, or `ConfigLayers::set_strict`. It rejects unknown keys, and
+//! the settings that parse but make no sense, e.g.
+//!
+//! * I2C addresses out of the range of sensors, `0x08` to `0x77`, or shared by two sensors
+//!   of a bus.
+//! * URLs without a scheme supported by ZMQ, i.e. `tcp`, `ipc`, `inproc`, `pgm`, and `epgm`.
+//! * Sockets bound to the same endpoint.
+//! * Sampling intervals shorter than a reading takes, and schedules that do not parse.
+//!
+//! Every issue reports the file, line, and key it was found at, e.g.
+//!
+//! ```text
+//! node.toml:14: sensors[1].address: address 99 is already used by `ph1` on bus `i2c1`
+//! ```
+//!
+//! The keys of a node are known from `NodeConfig` itself, whose settings deny unknown
+//! fields. Parsing it without validation drops the keys it is refused for, with a warning.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use config::{NodeConfig, SensorKind, SensorOptions};
+use errors::*;
+
+use toml::value::Table;
+use toml::{self, Value};
+
+/// Schemes of the URLs supported by ZMQ sockets.
+pub const ZMQ_SCHEMES: &[&str] = &["tcp", "ipc", "inproc", "pgm", "epgm"];
+/// Lowest I2C address of a sensor. Lower addresses are reserved by the I2C specification.
+pub const MIN_I2C_ADDRESS: u16 = 0x08;
+/// Highest I2C address of a sensor. Higher addresses are reserved by the I2C specification.
+pub const MAX_I2C_ADDRESS: u16 = 0x77;
+/// Shortest sampling interval, in milliseconds. EZO chips take up to 900 ms per reading.
+pub const MIN_SAMPLE_INTERVAL: u64 = 1_000;
+
+// A key of a table, or an index of an array, in the path to a setting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Key {
+    Name(String),
+    Index(usize),
+}
+
+// The text form of the path to a setting, e.g. `sensors[1].address`.
+fn path_string(path: &[Key]) -> String {
+    let mut text = String::new();
+    for key in path {
+        match *key {
+            Key::Name(ref name) if text.is_empty() => text.push_str(name),
+            Key::Name(ref name) => {
+                text.push('.');
+                text.push_str(name);
+            }
+            Key::Index(index) => text.push_str(&format!("[{}]", index)),
+        }
+    }
+    text
+}
+
+/// A problem found in a configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Issue {
+    /// File of the configuration, if it was read from one.
+    pub file: Option<String>,
+    /// Line of the setting, or of its closest parent found in the file.
+    pub line: Option<usize>,
+    /// Path to the setting, e.g. `sensors[1].address`.
+    pub key: String,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.file, self.line) {
+            (&Some(ref file), Some(line)) => write!(f, "{}:{}: ", file, line)?,
+            (&Some(ref file), None) => write!(f, "{}: ", file)?,
+            (&None, Some(line)) => write!(f, "line {}: ", line)?,
+            (&None, None) => {}
+        }
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+// The keys of a table header, e.g. `["exporters", "destination"]` for
+// `[exporters.destination]`.
+fn header_keys(header: &str) -> Vec<String> {
+    header
+        .trim_matches(|c| c == '[' || c == ']' || c == ' ')
+        .split('.')
+        .map(|k| k.trim().trim_matches('"').to_string())
+        .collect()
+}
+
+// The path to the table of a header, with the index of the latest table of every array
+// of tables.
+fn header_path(keys: &[String], arrays: &BTreeMap<Vec<String>, usize>) -> Vec<Key> {
+    let mut path = Vec::new();
+    for end in 1..keys.len() + 1 {
+        path.push(Key::Name(keys[end - 1].clone()));
+        if let Some(count) = arrays.get(&keys[..end].to_vec()) {
+            path.push(Key::Index(count - 1));
+        }
+    }
+    path
+}
+
+// The line of the setting at `path` in `source`, or of its closest parent found, e.g. the
+// line of an inline table.
+fn line_of(source: &str, path: &[Key]) -> Option<usize> {
+    let mut arrays: BTreeMap<Vec<String>, usize> = BTreeMap::new();
+    let mut table: Vec<Key> = Vec::new();
+    let mut closest: Option<(usize, usize)> = None;
+    for (number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        let found = if line.starts_with("[[") {
+            let keys = header_keys(line);
+            *arrays.entry(keys.clone()).or_insert(0) += 1;
+            table = header_path(&keys, &arrays);
+            table.clone()
+        } else if line.starts_with('[') {
+            table = header_path(&header_keys(line), &arrays);
+            table.clone()
+        } else if line.starts_with('#') || !line.contains('=') {
+            continue;
+        } else {
+            let mut found = table.clone();
+            let key = line.split('=').next().unwrap();
+            for name in key.split('.') {
+                found.push(Key::Name(name.trim().trim_matches('"').to_string()));
+            }
+            found
+        };
+        if found.len() > path.len() || found[..] != path[..found.len()] {
+            continue;
+        }
+        if found.len() == path.len() {
+            return Some(number + 1);
+        }
+        if closest.map_or(true, |(depth, _)| found.len() > depth) {
+            closest = Some((found.len(), number + 1));
+        }
+    }
+    closest.map(|(_, number)| number)
+}
+
+// Number of single-character edits between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            let edit = ::std::cmp::min(previous[j + 1], current[j]) + 1;
+            current.push(::std::cmp::min(substitution, edit));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// The message for an unknown key, suggesting the closest known key, if any is close.
+fn unknown_key_message<'a, I>(key: &str, known: I) -> String
+where
+    I: Iterator<Item = &'a str>,
+{
+    let closest = known
+        .map(|k| (edit_distance(key, k), k))
+        .filter(|&(distance, _)| distance <= 2)
+        .min();
+    match closest {
+        Some((_, k)) => format!("unknown key `{}`, did you mean `{}`?", key, k),
+        None => format!("unknown key `{}`", key),
+    }
+}
+
+// The problem with a socket URL, if any.
+fn url_problem(url: &str) -> Option<String> {
+    let mut parts = url.splitn(2, "://");
+    let scheme = parts.next().unwrap();
+    let address = match parts.next() {
+        Some(address) => address,
+        None => return Some(format!("`{}` is not a URL", url)),
+    };
+    if !ZMQ_SCHEMES.contains(&scheme) {
+        return Some(format!(
+            "unsupported scheme `{}`, expected one of {}",
+            scheme,
+            ZMQ_SCHEMES.join(", ")
+        ));
+    }
+    if address.is_empty() {
+        return Some(format!("`{}` has no address", url));
+    }
+    if scheme == "tcp" {
+        let valid = match address.rfind(':') {
+            Some(colon) => colon > 0 && address[colon + 1..].parse::<u16>().is_ok(),
+            None => false,
+        };
+        if !valid {
+            return Some(format!("`{}` is not a `tcp://host:port` address", url));
+        }
+    }
+    None
+}
+
+// Whether sockets bound to `a`, and `b`, would conflict. TCP endpoints on the same port
+// conflict when their hosts are the same, or either is a wildcard.
+fn binds_conflict(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    let split = |url: &str| -> Option<(String, String)> {
+        if !url.starts_with("tcp://") {
+            return None;
+        }
+        let address = &url["tcp://".len()..];
+        address.rfind(':').map(|colon| {
+            (
+                address[..colon].to_string(),
+                address[colon + 1..].to_string(),
+            )
+        })
+    };
+    match (split(a), split(b)) {
+        (Some((host_a, port_a)), Some((host_b, port_b))) => {
+            let wildcard = |host: &str| host == "*" || host == "0.0.0.0";
+            port_a == port_b && (host_a == host_b || wildcard(&host_a) || wildcard(&host_b))
+        }
+        _ => false,
+    }
+}
+
+// The key refused by a `deny_unknown_fields` error, the path to its table, and the keys the
+// table knows, e.g. `rep_ulr`, `["sensors"]`, and `["name", "kind", ...]` for
+// "unknown field `rep_ulr`, expected one of `name`, `kind`, ... for key `sensors`".
+fn unknown_field(message: &str) -> Option<(String, Vec<String>, Vec<String>)> {
+    let prefix = "unknown field `";
+    if !message.starts_with(prefix) {
+        return None;
+    }
+    let rest = &message[prefix.len()..];
+    let end = match rest.find('`') {
+        Some(end) => end,
+        None => return None,
+    };
+    let key = rest[..end].to_string();
+    let rest = &rest[end + 1..];
+    let (expected, tables) = match rest.find(" for key `") {
+        Some(start) => {
+            let path = rest[start + " for key `".len()..].trim_right_matches('`');
+            let tables = path.split('.').map(|t| t.to_string()).collect();
+            (&rest[..start], tables)
+        }
+        None => (rest, Vec::new()),
+    };
+    let known = expected
+        .split('`')
+        .enumerate()
+        .filter(|&(i, _)| i % 2 == 1)
+        .map(|(_, k)| k.to_string())
+        .collect();
+    Some((key, tables, known))
+}
+
+// The path to `key`, in `table`, or in one of its inner tables. The errors of tagged enums,
+// like the `destination` of an exporter, name the key of the enum's table, not of their own.
+fn find_key(table: &Table, key: &str) -> Option<Vec<Key>> {
+    if table.contains_key(key) {
+        return Some(vec![Key::Name(key.to_string())]);
+    }
+    table
+        .iter()
+        .filter_map(|(name, value)| match *value {
+            Value::Table(ref inner) => find_key(inner, key).map(|found| {
+                let mut path = vec![Key::Name(name.clone())];
+                path.extend(found);
+                path
+            }),
+            _ => None,
+        })
+        .next()
+}
+
+// The path to `key`, in the table at the end of `tables`, or in the first table of an
+// array on the way with it.
+fn locate(table: &Table, tables: &[String], key: &str) -> Option<Vec<Key>> {
+    let (name, rest) = match tables.split_first() {
+        Some(split) => split,
+        None => return find_key(table, key),
+    };
+    let path = vec![Key::Name(name.clone())];
+    let found = match table.get(name) {
+        Some(&Value::Table(ref table)) => locate(table, rest, key),
+        Some(&Value::Array(ref items)) => items
+            .iter()
+            .enumerate()
+            .filter_map(|(index, item)| match *item {
+                Value::Table(ref table) => locate(table, rest, key).map(|found| {
+                    let mut path = vec![Key::Index(index)];
+                    path.extend(found);
+                    path
+                }),
+                _ => None,
+            })
+            .next(),
+        _ => None,
+    };
+    found.map(|found| path.into_iter().chain(found).collect())
+}
+
+// Remove the setting at `path`.
+fn remove_key(table: &mut Table, path: &[Key]) {
+    match (path.first(), path.get(1)) {
+        (Some(&Key::Name(ref name)), None) => {
+            let _removed = table.remove(name);
+        }
+        (Some(&Key::Name(ref name)), Some(&Key::Index(index))) => {
+            if let Some(&mut Value::Array(ref mut items)) = table.get_mut(name) {
+                if let Some(&mut Value::Table(ref mut table)) = items.get_mut(index) {
+                    remove_key(table, &path[2..]);
+                }
+            }
+        }
+        (Some(&Key::Name(ref name)), Some(_)) => {
+            if let Some(&mut Value::Table(ref mut table)) = table.get_mut(name) {
+                remove_key(table, &path[1..]);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Deserialize a node configuration from `table`, without the keys that `NodeConfig` refuses.
+// Returns the paths to those keys too, with the message for each of them.
+fn known_node_config(mut table: Table) -> Result<(NodeConfig, Vec<(Vec<Key>, String)>)> {
+    let mut unknown = Vec::new();
+    loop {
+        let e = match Value::Table(table.clone()).try_into::<NodeConfig>() {
+            Ok(config) => return Ok((config, unknown)),
+            Err(e) => e,
+        };
+        let found = unknown_field(&e.to_string()).and_then(|(key, tables, known)| {
+            let message = unknown_key_message(&key, known.iter().map(|k| k.as_str()));
+            locate(&table, &tables, &key).map(|path| (path, message))
+        });
+        match found {
+            Some((path, message)) => {
+                remove_key(&mut table, &path);
+                unknown.push((path, message));
+            }
+            None => return Err(e).context(ErrorKind::ConfigParse).map_err(Error::from),
+        }
+    }
+}
+
+/// Collects the issues of a configuration, read from `source`.
+pub struct Validator<'a> {
+    source: &'a str,
+    file: Option<String>,
+    issues: Vec<Issue>,
+}
+
+impl<'a> Validator<'a> {
+    /// Create a new validator for the configuration in `source`, read from `file`, if any.
+    pub fn new(source: &'a str, file: Option<&Path>) -> Validator<'a> {
+        Validator {
+            source,
+            file: file.map(|f| f.display().to_string()),
+            issues: Vec::new(),
+        }
+    }
+
+    /// The issues found so far.
+    pub fn issues(&self) -> &[Issue] {
+        &self.issues
+    }
+
+    /// `Ok` if no issues were found, or `ErrorKind::ConfigInvalid` with all of them.
+    pub fn result(&self) -> Result<()> {
+        if self.issues.is_empty() {
+            return Ok(());
+        }
+        let issues: Vec<String> = self.issues.iter().map(|i| i.to_string()).collect();
+        Err(ErrorKind::ConfigInvalid(issues.join("\n")).into())
+    }
+
+    fn report(&mut self, path: &[Key], message: String) {
+        let issue = Issue {
+            file: self.file.clone(),
+            line: line_of(self.source, path),
+            key: path_string(path),
+            message,
+        };
+        self.issues.push(issue);
+    }
+
+    /// Deserialize a node configuration from `table`, reporting the keys it does not know.
+    pub fn check_node_keys(&mut self, table: Table) -> Result<NodeConfig> {
+        let (config, unknown) = known_node_config(table)?;
+        for (path, message) in unknown {
+            self.report(&path, message);
+        }
+        Ok(config)
+    }
+
+    /// Report the keys of `layer` that are not in `known`, e.g. the settings of a file that
+    /// are not in the defaults of the configuration.
+    pub fn check_layer_keys(&mut self, layer: &Table, known: &Table) {
+        self.check_known_keys(layer, known, &mut Vec::new());
+    }
+
+    fn check_known_keys(&mut self, layer: &Table, known: &Table, path: &mut Vec<Key>) {
+        for (key, value) in layer {
+            path.push(Key::Name(key.clone()));
+            match (known.get(key), value) {
+                (None, _) => {
+                    let message = unknown_key_message(key, known.keys().map(|k| k.as_str()));
+                    self.report(path, message);
+                }
+                (Some(&Value::Table(ref known)), &Value::Table(ref layer)) => {
+                    self.check_known_keys(layer, known, path)
+                }
+                _ => {}
+            }
+            path.pop();
+        }
+    }
+
+    /// Report the settings of a node that make no sense.
+    pub fn check_node(&mut self, config: &NodeConfig) {
+        let mut buses: BTreeMap<&str, usize> = BTreeMap::new();
+        for (index, bus) in config.buses.iter().enumerate() {
+            if buses.insert(bus.name.as_str(), index).is_some() {
+                let path = [
+                    Key::Name("buses".into()),
+                    Key::Index(index),
+                    Key::Name("name".into()),
+                ];
+                self.report(&path, format!("bus `{}` is declared twice", bus.name));
+            }
+        }
+
+        let mut names: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut addresses: BTreeMap<(&str, u16), &str> = BTreeMap::new();
+        let mut binds: Vec<(Vec<Key>, &str)> = Vec::new();
+        for (index, sensor) in config.sensors.iter().enumerate() {
+            let at = |key: &str| {
+                vec![
+                    Key::Name("sensors".into()),
+                    Key::Index(index),
+                    Key::Name(key.into()),
+                ]
+            };
+            if names.insert(sensor.name.as_str(), index).is_some() {
+                self.report(
+                    &at("name"),
+                    format!("sensor `{}` is declared twice", sensor.name),
+                );
+            }
+            if !buses.contains_key(sensor.bus.as_str()) {
+                self.report(&at("bus"), format!("bus `{}` is not declared", sensor.bus));
+            }
+            if sensor.address < MIN_I2C_ADDRESS || sensor.address > MAX_I2C_ADDRESS {
+                let message = format!(
+                    "address {} is out of the I2C range {}..{}",
+                    sensor.address, MIN_I2C_ADDRESS, MAX_I2C_ADDRESS
+                );
+                self.report(&at("address"), message);
+            } else if let Some(other) =
+                addresses.insert((sensor.bus.as_str(), sensor.address), sensor.name.as_str())
+            {
+                let message = format!(
+                    "address {} is already used by `{}` on bus `{}`",
+                    sensor.address, other, sensor.bus
+                );
+                self.report(&at("address"), message);
+            }
+            if let Some(ref url) = sensor.rep_url {
+                binds.push((at("rep_url"), url.as_str()));
+            }
+            if let Some(ref schedule) = sensor.schedule {
+                let mut path = at("schedule");
+                match schedule.interval {
+                    Some(interval) if interval < MIN_SAMPLE_INTERVAL => {
+                        path.push(Key::Name("interval".into()));
+                        let message = format!(
+                            "interval of {} ms is shorter than the minimum of {} ms",
+                            interval, MIN_SAMPLE_INTERVAL
+                        );
+                        self.report(&path, message);
+                    }
+                    _ => {
+                        if let Err(e) = schedule.schedule() {
+                            if schedule.cron.is_some() {
+                                path.push(Key::Name("cron".into()));
+                            }
+                            self.report(&path, e.to_string());
+                        }
+                    }
+                }
+            }
+            self.check_options(sensor.kind, at("options"), &sensor.options);
+        }
+
+        if let Some(ref publisher) = config.publisher {
+            let at = |key: &str| vec![Key::Name("publisher".into()), Key::Name(key.into())];
+            binds.push((at("url"), publisher.url.as_str()));
+            if let Some(ref url) = publisher.rep_url {
+                binds.push((at("rep_url"), url.as_str()));
+            }
+        }
+        for (index, &(ref path, url)) in binds.iter().enumerate() {
+            if let Some(problem) = url_problem(url) {
+                self.report(path, problem);
+                continue;
+            }
+            let conflict = binds[..index]
+                .iter()
+                .find(|&&(_, other)| binds_conflict(url, other));
+            if let Some(&(ref other, _)) = conflict {
+                let message = format!("`{}` is already bound by {}", url, path_string(other));
+                self.report(path, message);
+            }
+        }
+    }
+
+    fn check_options(&mut self, kind: SensorKind, mut path: Vec<Key>, options: &SensorOptions) {
+        if let Some(ref output) = options.output {
+            path.push(Key::Name("output".into()));
+            if kind != SensorKind::Conductivity {
+                self.report(
+                    &path,
+                    "only conductivity sensors have output parameters".into(),
+                );
+            }
+            for param in output {
+                if !["EC", "TDS", "S", "SG"].contains(&param.as_str()) {
+                    let message = format!("unknown output parameter `{}`", param);
+                    self.report(&path, message);
+                }
+            }
+            path.pop();
+        }
+        if let Some(ref scale) = options.scale {
+            path.push(Key::Name("scale".into()));
+            if kind != SensorKind::Temperature {
+                self.report(&path, "only temperature sensors have a scale".into());
+            } else if !["celsius", "fahrenheit", "kelvin"].contains(&scale.as_str()) {
+                let message = format!("unknown scale `{}`", scale);
+                self.report(&path, message);
+            }
+            path.pop();
+        }
+    }
+}
+
+/// Parse a node configuration, read from `file`, if any, rejecting unknown keys, and the
+/// settings that make no sense.
+pub fn node_config(config_str: &str, file: Option<&Path>) -> Result<NodeConfig> {
+    let table: Table = toml::from_str(config_str).context(ErrorKind::ConfigParse)?;
+    let mut validator = Validator::new(config_str, file);
+    let config = validator.check_node_keys(table)?;
+    validator.check_node(&config);
+    validator.result()?;
+    Ok(config)
+}
+
+/// Parse a node configuration, ignoring the keys it does not know, with a warning.
+pub fn lenient_node_config(config_str: &str) -> Result<NodeConfig> {
+    let table: Table = toml::from_str(config_str).context(ErrorKind::ConfigParse)?;
+    let (config, unknown) = known_node_config(table)?;
+    for (path, message) in unknown {
+        warn!("ignoring {}: {}", path_string(&path), message);
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODE: &str = r#"
+        name = "tank-a"
+
+        [[buses]]
+        name = "i2c1"
+        path = "/dev/i2c-1"
+
+        [[sensors]]
+        name = "ph1"
+        kind = "ph"
+        bus = "i2c1"
+        address = 99
+        rep_url = "tcp://*:5558"
+
+        [[sensors]]
+        name = "rtd1"
+        kind = "temperature"
+        bus = "i2c1"
+        address = 102
+        schedule = { interval = 5000 }
+        options = { scale = "celsius" }
+
+        [publisher]
+        url = "tcp://*:5557"
+        "#;
+
+    #[test]
+    fn valid_node_configs_have_no_issues() {
+        let config = node_config(NODE, None).unwrap();
+        assert_eq!(2, config.sensors.len());
+    }
+
+    // Every setting of `NodeConfig` is in a struct literal below, so a new one does not
+    // compile until it is added to the file, which strict parsing accepts.
+    #[test]
+    fn strict_parsing_knows_every_setting() {
+        use aggregate::Window;
+        use alarm::{AlarmRule, Severity};
+        use config::{
+            BusConfig, ExporterConfig, InfluxDestination, NodePublisherConfig, NodeSensorConfig,
+            ScheduleConfig, StorageConfig,
+        };
+
+        let config_str = r#"
+            name = "tank-a"
+
+            [[buses]]
+            name = "i2c1"
+            path = "/dev/i2c-1"
+
+            [[sensors]]
+            name = "ec1"
+            kind = "conductivity"
+            bus = "i2c1"
+            address = 100
+            topic = "conductivity-01"
+            rep_url = "tcp://*:5558"
+            schedule = { interval = 60000, align = true }
+            options = { sleep = true, compensation = 25.0, output = ["EC", "TDS"] }
+
+            [[sensors]]
+            name = "rtd1"
+            kind = "temperature"
+            bus = "i2c1"
+            address = 102
+            schedule = { cron = "0 */15 * * * *" }
+            options = { scale = "kelvin" }
+
+            [publisher]
+            url = "tcp://*:5557"
+            rep_url = "tcp://*:5559"
+            aggregate = ["1h"]
+            reload = true
+
+            [storage]
+            path = "/var/lib/benita"
+            max_segment_size = 1048576
+            max_segment_age = 3600
+            max_segments = 24
+            retention = 86400
+
+            [[alarms]]
+            name = "ec-range"
+            sensor = "conductivity-01"
+            low = 100.0
+            high = 2000.0
+            rate = 50.0
+            hysteresis = 10.0
+            duration = 60
+            severity = "critical"
+
+            [[exporters]]
+            kind = "influx"
+            measurement = "benita"
+            site = "tank-a"
+            firmware = { conductivity-01 = "2.10" }
+            batch_size = 50
+            flush_interval = 5
+            retries = 2
+            destination = { kind = "file", path = "/var/lib/benita/influx", max_bytes = 1024, max_files = 3 }
+
+            [[exporters]]
+            kind = "influx"
+            destination = { kind = "udp", addr = "localhost:8089" }
+
+            [[exporters]]
+            kind = "influx"
+            destination = { kind = "http", url = "http://localhost:8086/write?db=benita" }
+
+            [[exporters]]
+            kind = "file"
+            path = "/var/lib/benita/measurements.csv"
+            format = "csv"
+            time_zone = "utc"
+            precision = 3
+            "#;
+        let mut firmware = BTreeMap::new();
+        firmware.insert("conductivity-01".to_string(), "2.10".to_string());
+        let influx = |destination| ExporterConfig::Influx {
+            measurement: None,
+            site: None,
+            firmware: BTreeMap::new(),
+            batch_size: None,
+            flush_interval: None,
+            retries: None,
+            destination,
+        };
+        let expected = NodeConfig {
+            name: "tank-a".to_string(),
+            buses: vec![BusConfig {
+                name: "i2c1".to_string(),
+                path: "/dev/i2c-1".into(),
+            }],
+            sensors: vec![
+                NodeSensorConfig {
+                    name: "ec1".to_string(),
+                    kind: SensorKind::Conductivity,
+                    bus: "i2c1".to_string(),
+                    address: 100,
+                    topic: Some("conductivity-01".to_string()),
+                    rep_url: Some("tcp://*:5558".to_string()),
+                    schedule: Some(ScheduleConfig {
+                        interval: Some(60_000),
+                        align: true,
+                        cron: None,
+                    }),
+                    options: SensorOptions {
+                        sleep: Some(true),
+                        compensation: Some(25.0),
+                        output: Some(vec!["EC".to_string(), "TDS".to_string()]),
+                        scale: None,
+                    },
+                },
+                NodeSensorConfig {
+                    name: "rtd1".to_string(),
+                    kind: SensorKind::Temperature,
+                    bus: "i2c1".to_string(),
+                    address: 102,
+                    topic: None,
+                    rep_url: None,
+                    schedule: Some(ScheduleConfig {
+                        interval: None,
+                        align: false,
+                        cron: Some("0 */15 * * * *".to_string()),
+                    }),
+                    options: SensorOptions {
+                        scale: Some("kelvin".to_string()),
+                        ..SensorOptions::default()
+                    },
+                },
+            ],
+            publisher: Some(NodePublisherConfig {
+                url: "tcp://*:5557".to_string(),
+                rep_url: Some("tcp://*:5559".to_string()),
+                aggregate: vec![Window::Hour],
+                reload: true,
+            }),
+            storage: Some(StorageConfig {
+                path: "/var/lib/benita".into(),
+                max_segment_size: Some(1_048_576),
+                max_segment_age: Some(3_600),
+                max_segments: Some(24),
+                retention: Some(86_400),
+            }),
+            alarms: vec![AlarmRule {
+                name: "ec-range".to_string(),
+                sensor: "conductivity-01".to_string(),
+                low: Some(100.0),
+                high: Some(2000.0),
+                rate: Some(50.0),
+                rate_window: None,
+                hysteresis: 10.0,
+                rate_hysteresis: 0.0,
+                duration: 60,
+                severity: Severity::Critical,
+            }],
+            exporters: vec![
+                ExporterConfig::Influx {
+                    measurement: Some("benita".to_string()),
+                    site: Some("tank-a".to_string()),
+                    firmware,
+                    batch_size: Some(50),
+                    flush_interval: Some(5),
+                    retries: Some(2),
+                    destination: InfluxDestination::File {
+                        path: "/var/lib/benita/influx".into(),
+                        max_bytes: Some(1024),
+                        max_files: Some(3),
+                    },
+                },
+                influx(InfluxDestination::Udp {
+                    addr: "localhost:8089".to_string(),
+                }),
+                influx(InfluxDestination::Http {
+                    url: "http://localhost:8086/write?db=benita".to_string(),
+                }),
+                ExporterConfig::File {
+                    path: "/var/lib/benita/measurements.csv".into(),
+                    format: Some("csv".to_string()),
+                    time_zone: Some("utc".to_string()),
+                    precision: Some(3),
+                },
+            ],
+        };
+        assert_eq!(expected, node_config(config_str, None).unwrap());
+    }
+
+    #[test]
+    fn unknown_keys_are_reported_at_their_line() {
+        let config_str = NODE.replace("rep_url = ", "rep_ulr = ");
+        let table: Table = toml::from_str(&config_str).unwrap();
+        let mut validator = Validator::new(&config_str, Some(Path::new("node.toml")));
+        let config = validator.check_node_keys(table).unwrap();
+        assert_eq!(None, config.sensors[0].rep_url);
+        assert_eq!(
+            &[Issue {
+                file: Some("node.toml".to_string()),
+                line: Some(13),
+                key: "sensors[0].rep_ulr".to_string(),
+                message: "unknown key `rep_ulr`, did you mean `rep_url`?".to_string(),
+            }],
+            validator.issues()
+        );
+
+        // Keys of inline tables are reported at the line of the table
+        let config_str = NODE.replace("{ scale =", "{ scael =");
+        let table: Table = toml::from_str(&config_str).unwrap();
+        let mut validator = Validator::new(&config_str, None);
+        let _config = validator.check_node_keys(table).unwrap();
+        assert_eq!(
+            "line 21: sensors[1].options.scael: unknown key `scael`, did you mean `scale`?",
+            validator.issues()[0].to_string()
+        );
+        assert!(node_config(&config_str, None).is_err());
+        let config = lenient_node_config(&config_str).unwrap();
+        assert_eq!(None, config.sensors[1].options.scale);
+
+        // Keys of the tables of tagged enums, and of every table of an array
+        let config_str = format!(
+            "{}\n[[exporters]]\nkind = \"influx\"\nsitte = \"a\"\n{}\n",
+            NODE.replace("rep_url = ", "rep_ulr = ").replace(
+                "address = 102",
+                "address = 102\n        rep_ulr = \"tcp://*:5559\""
+            ),
+            "destination = { kind = \"udp\", addr = \"localhost:8089\", port = 8089 }",
+        );
+        let table: Table = toml::from_str(&config_str).unwrap();
+        let mut validator = Validator::new(&config_str, None);
+        let _config = validator.check_node_keys(table).unwrap();
+        let mut keys: Vec<&str> = validator.issues().iter().map(|i| i.key.as_str()).collect();
+        keys.sort();
+        assert_eq!(
+            vec![
+                "exporters[0].destination.port",
+                "exporters[0].sitte",
+                "sensors[0].rep_ulr",
+                "sensors[1].rep_ulr",
+            ],
+            keys
+        );
+    }
+
+    #[test]
+    fn invalid_node_settings_are_reported() {
+        let config_str = NODE
+            .replace("address = 102", "address = 99")
+            .replace("interval = 5000", "interval = 100")
+            .replace("scale = \"celsius\"", "scale = \"rankine\"")
+            .replace("tcp://*:5557", "tcp://localhost:5558");
+        let config = NodeConfig::from_str(&config_str).unwrap();
+        let mut validator = Validator::new(&config_str, Some(Path::new("node.toml")));
+        validator.check_node(&config);
+        let issues: Vec<(Option<usize>, &str)> = validator
+            .issues()
+            .iter()
+            .map(|i| (i.line, i.key.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (Some(19), "sensors[1].address"),
+                (Some(20), "sensors[1].schedule.interval"),
+                (Some(21), "sensors[1].options.scale"),
+                (Some(24), "publisher.url"),
+            ],
+            issues
+        );
+        assert_eq!(
+            "node.toml:19: sensors[1].address: address 99 is already used by `ph1` on bus `i2c1`",
+            validator.issues()[0].to_string()
+        );
+        assert_eq!(
+            "`tcp://localhost:5558` is already bound by sensors[0].rep_url",
+            validator.issues()[3].message
+        );
+
+        let config_str = NODE
+            .replace("address = 102", "address = 120")
+            .replace("interval = 5000", "cron = \"*/15 * * *\"")
+            .replace(
+                "bus = \"i2c1\"\n        address = 99",
+                "bus = \"i2c0\"\n        address = 99",
+            )
+            .replace("tcp://*:5557", "http://localhost:5557");
+        let config = NodeConfig::from_str(&config_str).unwrap();
+        let mut validator = Validator::new(&config_str, None);
+        validator.check_node(&config);
+        let keys: Vec<&str> = validator.issues().iter().map(|i| i.key.as_str()).collect();
+        assert_eq!(
+            vec![
+                "sensors[0].bus",
+                "sensors[1].address",
+                "sensors[1].schedule.cron",
+                "publisher.url",
+            ],
+            keys
+        );
+    }
+
+    #[test]
+    fn socket_urls_with_zmq_schemes() {
+        assert_eq!(None, url_problem("tcp://*:5557"));
+        assert_eq!(None, url_problem("tcp://192.168.16.123:5558"));
+        assert_eq!(None, url_problem("ipc:///tmp/benita.ipc"));
+        assert!(url_problem("tcp://localhost").is_some());
+        assert!(url_problem("tcp://:5557").is_some());
+        assert!(url_problem("localhost:5557").is_some());
+        assert!(url_problem("udp://localhost:5557").is_some());
+    }
+
+    #[test]
+    fn conflicting_binds() {
+        assert!(binds_conflict("tcp://*:5557", "tcp://localhost:5557"));
+        assert!(binds_conflict("tcp://10.0.0.2:5557", "tcp://10.0.0.2:5557"));
+        assert!(!binds_conflict(
+            "tcp://10.0.0.2:5557",
+            "tcp://10.0.0.3:5557"
+        ));
+        assert!(!binds_conflict("tcp://*:5557", "tcp://*:5558"));
+        assert!(binds_conflict(
+            "ipc:///tmp/benita.ipc",
+            "ipc:///tmp/benita.ipc"
+        ));
+    }
+}
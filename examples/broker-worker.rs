@@ -0,0 +1,83 @@
+//! Register a sensor responder as a service with a broker.
+//!
+//! Requests received from the broker for `SERVICE` are forwarded to the responder
+//! at `REP_URL`, e.g. the one started by `conductivity-command-server`.
+extern crate benita;
+extern crate clap;
+extern crate failure;
+extern crate neuras;
+extern crate zmq;
+
+use benita::cli::is_url;
+use benita::ezo::errors::*;
+use benita::ezo::network::broker::BrokerWorker;
+use benita::ezo::network::ReplyStatus;
+use benita::ezo::utilities::create_and_connect_requester;
+
+use clap::{App, Arg};
+use failure::{Fail, ResultExt};
+use zmq::Socket;
+
+// Forward the request to the responder, and return its reply.
+fn forward(requester: &Socket, request: &str) -> Result<String> {
+    let _send = requester
+        .send(request.as_bytes(), 0)
+        .context(ErrorKind::SocketSend)?;
+    match requester.recv_string(0).context(ErrorKind::SocketReceive)? {
+        Ok(reply) => Ok(reply),
+        Err(_) => Err(ErrorKind::ResponseParse.into()),
+    }
+}
+
+fn parse_cli_arguments() -> Result<()> {
+    let matches = App::new("benita-broker-worker")
+        .version("0.1.0")
+        .author("Joaquin R. <globojorro@gmail.com>")
+        .about("Benita IoT. Offers a sensor responder as a broker service.")
+        .arg(
+            Arg::with_name("BROKER_URL")
+                .help("Sets the url of the broker")
+                .takes_value(true)
+                .validator(is_url)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("SERVICE")
+                .help("Sets the service name, e.g. `tank3/ph`")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("REP_URL")
+                .help("Sets the url of the sensor responder")
+                .takes_value(true)
+                .validator(is_url)
+                .required(true),
+        )
+        .get_matches();
+
+    let broker_url = matches.value_of("BROKER_URL").unwrap();
+    let service = matches.value_of("SERVICE").unwrap();
+    let rep_url = matches.value_of("REP_URL").unwrap();
+
+    let requester = create_and_connect_requester(rep_url)?;
+    let mut worker = BrokerWorker::new(broker_url, &[service])?;
+
+    let _run = worker.run(|_service, request| match forward(&requester, request) {
+        Ok(reply) => reply,
+        Err(_) => format!("{:?}", ReplyStatus::Err),
+    })?;
+
+    // Never reach this line...
+    Ok(())
+}
+
+fn main() {
+    if let Err(ref e) = parse_cli_arguments() {
+        println!("error: {:?}", e.cause());
+        // The backtrace is not always generated. Try to run this example
+        // with `RUST_BACKTRACE=1`.
+        println!("backtrace: {:?}", e.backtrace());
+        ::std::process::exit(1);
+    }
+}
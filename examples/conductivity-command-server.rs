@@ -1,6 +1,18 @@
-//! Allows for remote command of the EC EZO chip, exposing a limited API.
+//! Allows for remote command of the EC EZO chip, through its responder.
 //!
-//! This server binds to the `REP_URL` argument, expected from the command line.
+//! This server binds to the `REP_URL` argument, or to `tcp://*:5556` by default.
+//! With `--store DIR`, it also answers `history` requests from the measurement log in `DIR`.
+//!
+//! Settings are layered: the defaults, then the `--config` file, then the
+//! `BENITA_SOCKET_EC_*` and `BENITA_SENSOR_EC_*` environment variables, then the
+//! command-line arguments. `--print-config` prints the effective settings, e.g.
+//!
+//! ```toml
+//! url = "tcp://*:5556"
+//! socket_connection = "bind"
+//! path = "/dev/i2c-1"
+//! address = 100
+//! ```
 extern crate benita;
 extern crate chrono;
 extern crate clap;
@@ -11,25 +23,27 @@ extern crate log;
 extern crate neuras;
 extern crate zmq;
 
-use std::fmt;
-use std::path::PathBuf;
 use std::result;
 
 use benita::cli::is_url;
-use benita::ezo::conductivity::device::commands::Command;
 use benita::ezo::conductivity::device::ConductivitySensor;
-use benita::ezo::conductivity::network::requests::*;
 use benita::ezo::conductivity::network::ConductivityResponder;
-use benita::ezo::config::{ConnectionType, SensorConfig, SocketConfig};
-use benita::ezo::network::{Endpoint, ReplyStatus, SocketRequest};
+use benita::ezo::config::{to_toml, ConfigLayers, ConnectionType, SensorConfig, SocketConfig};
+use benita::ezo::network::history::HistoryService;
+use benita::ezo::node::serve_requests;
+use benita::ezo::store::LogReader;
 use benita::ezo::utilities::*;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use failure::{Error, ResultExt};
 use zmq::Socket;
 
 type Result<T> = result::Result<T, Error>;
 
+const DEFAULT_URL: &'static str = "tcp://*:5556";
+const DEFAULT_I2C: &'static str = "/dev/i2c-1";
+const DEFAULT_ADDRESS: u16 = 100;
+
 // Configure and start logger.
 fn start_logger() -> Result<()> {
     let _logger = fern::Dispatch::new()
@@ -52,76 +66,42 @@ fn start_logger() -> Result<()> {
     Ok(())
 }
 
+// Return the socket, and sensor, settings: the defaults, then the `--config` file, then the
+// environment variables, then the command-line arguments.
+fn configs_from_layers(matches: &ArgMatches) -> Result<(SocketConfig, SensorConfig)> {
+    let socket_defaults = SocketConfig {
+        url: DEFAULT_URL.to_string(),
+        socket_connection: ConnectionType::Bind,
+    };
+    let mut socket_layers = ConfigLayers::new(&socket_defaults)?;
+    let mut sensor_layers = ConfigLayers::new(&SensorConfig::new(DEFAULT_I2C, DEFAULT_ADDRESS))?;
+    if let Some(path) = matches.value_of("config") {
+        socket_layers.merge_file(path)?;
+        sensor_layers.merge_file(path)?;
+    }
+    socket_layers.merge_env("BENITA_SOCKET_EC")?;
+    sensor_layers.merge_env("BENITA_SENSOR_EC")?;
+    if let Some(url) = matches.value_of("URL") {
+        socket_layers.set("url", url)?;
+    }
+    if let Some(path) = matches.value_of("I2C") {
+        sensor_layers.set("path", path)?;
+    }
+    if let Some(address) = matches.value_of("ADDRESS") {
+        sensor_layers.set("address", address)?;
+    }
+    Ok((socket_layers.build()?, sensor_layers.build()?))
+}
+
 // Return a `Socket` from a `SocketConfig`
 fn socket_from_config(cfg: &SocketConfig) -> Result<Socket> {
     let socket = match cfg.socket_connection {
-        ConnectionType::Bind => create_and_bind_responder(cfg.url)?,
-        ConnectionType::Connect => create_and_connect_responder(cfg.url)?,
+        ConnectionType::Bind => create_and_bind_responder(&cfg.url)?,
+        ConnectionType::Connect => create_and_connect_responder(&cfg.url)?,
     };
     Ok(socket)
 }
 
-// Evaluate a conductivity command using the given responder. Returns a String.
-fn eval<T: Command>(responder: &ConductivityResponder, cmd: T) -> Result<String>
-where
-    <T as Command>::Response: fmt::Debug,
-{
-    let reply = match cmd.run(&mut responder.sensor.i2cdev.borrow_mut()) {
-        Ok(rep) => format!("{:?}", rep),
-        Err(_) => format!("{:?}", ReplyStatus::Err),
-    };
-    Ok(reply)
-}
-
-// Match and evaluate commands.
-//
-// NOTE: It uses a custom subset of the `ConducivityAPI`.
-fn match_and_eval(s: &str, e: &ConductivityResponder) -> Result<String> {
-    match s {
-        a if CalibrationState::from_str(a).is_ok() => eval(e, CalibrationState::from_str(a)?),
-        a if CompensationGet::from_str(a).is_ok() => eval(e, CompensationGet::from_str(a)?),
-        a if CompensationSet::from_str(a).is_ok() => eval(e, CompensationSet::from_str(a)?),
-        a if DeviceInformation::from_str(a).is_ok() => eval(e, DeviceInformation::from_str(a)?),
-        a if LedOff::from_str(a).is_ok() => eval(e, LedOff::from_str(a)?),
-        a if LedOn::from_str(a).is_ok() => eval(e, LedOn::from_str(a)?),
-        a if LedState::from_str(a).is_ok() => eval(e, LedState::from_str(a)?),
-        a if Export::from_str(a).is_ok() => eval(e, Export::from_str(a)?),
-        a if ExportInfo::from_str(a).is_ok() => eval(e, ExportInfo::from_str(a)?),
-        a if Import::from_str(a).is_ok() => eval(e, Import::from_str(a)?),
-        a if OutputEnableConductivity::from_str(a).is_ok() => {
-            eval(e, OutputEnableConductivity::from_str(a)?)
-        }
-        a if OutputEnableTds::from_str(a).is_ok() => eval(e, OutputEnableTds::from_str(a)?),
-        a if OutputEnableSalinity::from_str(a).is_ok() => {
-            eval(e, OutputEnableSalinity::from_str(a)?)
-        }
-        a if OutputEnableSpecificGravity::from_str(a).is_ok() => {
-            eval(e, OutputEnableSpecificGravity::from_str(a)?)
-        }
-        a if OutputDisableConductivity::from_str(a).is_ok() => {
-            eval(e, OutputDisableConductivity::from_str(a)?)
-        }
-        a if OutputDisableTds::from_str(a).is_ok() => eval(e, OutputDisableTds::from_str(a)?),
-        a if OutputDisableSalinity::from_str(a).is_ok() => {
-            eval(e, OutputDisableSalinity::from_str(a)?)
-        }
-        a if OutputDisableSpecificGravity::from_str(a).is_ok() => {
-            eval(e, OutputDisableSpecificGravity::from_str(a)?)
-        }
-        a if OutputState::from_str(a).is_ok() => eval(e, OutputState::from_str(a)?),
-        a if ProtocolLockDisable::from_str(a).is_ok() => eval(e, ProtocolLockDisable::from_str(a)?),
-        a if ProtocolLockEnable::from_str(a).is_ok() => eval(e, ProtocolLockEnable::from_str(a)?),
-        a if ProtocolLockState::from_str(a).is_ok() => eval(e, ProtocolLockState::from_str(a)?),
-        a if Reading::from_str(a).is_ok() => eval(e, Reading::from_str(a)?),
-        a if Sleep::from_str(a).is_ok() => eval(e, Sleep::from_str(a)?),
-        a if Status::from_str(a).is_ok() => eval(e, Status::from_str(a)?),
-        _ => {
-            error!("bad sensor command");
-            Ok(format!("{:?}", ReplyStatus::Err))
-        }
-    }
-}
-
 // Parse the command-line arguments and execute.
 fn evaluate_command_line() -> Result<()> {
     // Match the command-line arguments from std::io and start the service.
@@ -133,38 +113,47 @@ fn evaluate_command_line() -> Result<()> {
             Arg::with_name("URL")
                 .help("Sets the url for the response server")
                 .takes_value(true)
-                .validator(is_url)
-                .required(true),
+                .validator(is_url),
         )
         .arg(
             Arg::with_name("I2C")
                 .help("Sets the path for the I2C sensor.")
-                .takes_value(true)
-                .required(true),
+                .takes_value(true),
         )
         .arg(
             Arg::with_name("ADDRESS")
                 .help("Sets the I2C sensor address.")
-                .takes_value(true)
-                .required(true),
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("Sets a custom config file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("print-config")
+                .long("print-config")
+                .help("Prints the effective settings, and exits"),
+        )
+        .arg(
+            Arg::with_name("store")
+                .short("s")
+                .long("store")
+                .value_name("DIR")
+                .help("Answers history requests from the measurement log in DIR")
+                .takes_value(true),
         )
         .get_matches();
 
-    // socket configuration from args.
-    let socket_cfg = SocketConfig {
-        socket_connection: ConnectionType::Bind,
-        url: matches.value_of("URL").unwrap(),
-    };
-
-    // sensor configuration from args.
-    let sensor_cfg = SensorConfig {
-        address: matches
-            .value_of("ADDRESS")
-            .unwrap()
-            .parse::<u16>()
-            .context("Bad Address")?,
-        path: PathBuf::from(matches.value_of("I2C").unwrap()),
-    };
+    // socket, and sensor, configuration from the layered settings.
+    let (socket_cfg, sensor_cfg) = configs_from_layers(&matches)?;
+    if matches.is_present("print-config") {
+        print!("{}{}", to_toml(&socket_cfg)?, to_toml(&sensor_cfg)?);
+        return Ok(());
+    }
 
     // initialize the sensor.
     let sensor = ConductivitySensor::from_config(sensor_cfg)?;
@@ -175,17 +164,20 @@ fn evaluate_command_line() -> Result<()> {
     // initialize the responder with the sensor and socket.
     let responder = ConductivityResponder::new(socket, sensor)?;
 
-    // the main loop, it will run for as long as the program runs.
-    loop {
-        let req_str = &responder.recv()?;
-        info!("REQ: {}", &req_str);
-        let call: String = match_and_eval(&req_str, &responder)?;
-        info!("REP: {}", &call);
-        let _reply = &responder.send(call.as_bytes())?;
-    }
-
-    // Never reach this line...
-    // Ok(())
+    // answer history requests from the measurement log, if there is one.
+    let history = matches
+        .value_of("store")
+        .map(|dir| HistoryService::new(LogReader::new(dir)));
+
+    // answer every request through the responder, which guards the calibration with its
+    // session lock, for as long as the program runs.
+    serve_requests(&responder, |req_str| {
+        match history.as_ref().and_then(|h| h.respond(req_str)) {
+            Some(reply) => Ok(reply),
+            None => responder.respond(req_str),
+        }
+    })?;
+    Ok(())
 }
 
 // Main program. Starts logger, then evaluates args from stdin.
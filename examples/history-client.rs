@@ -0,0 +1,120 @@
+//! Fetch the measurements stored on a sensor node, and print them.
+//!
+//! The node must answer `history` requests, e.g. a command server started with `--store`.
+//! With `--window`, the statistics for every window are printed instead, e.g. daily
+//! summaries with `--window 1d`.
+extern crate benita;
+extern crate chrono;
+extern crate clap;
+extern crate failure;
+extern crate neuras;
+extern crate zmq;
+
+use std::result;
+
+use benita::cli::is_url;
+use benita::ezo::aggregate::{aggregate, Window};
+use benita::ezo::network::history::{fetch_history, HistoryRequest, HistoryRequester};
+
+use chrono::{DateTime, Duration, Utc};
+use clap::{App, Arg};
+use failure::{Error, ResultExt};
+use zmq::{Context, REQ};
+
+type Result<T> = result::Result<T, Error>;
+
+fn parse_cli_arguments() -> Result<()> {
+    let matches = App::new("benita-history-client")
+        .version("0.1.0")
+        .author("Joaquin R. <globojorro@gmail.com>")
+        .about("Benita IoT. Fetches the measurements stored on a node.")
+        .arg(
+            Arg::with_name("REP_URL")
+                .help("Sets the url for the REP server")
+                .takes_value(true)
+                .validator(is_url)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("SENSOR")
+                .help("Sets the sensor topic, e.g. ph-0123456789abcdef")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("from")
+                .long("from")
+                .value_name("TIMESTAMP")
+                .help("Sets the start of the time range (default: a day ago)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("to")
+                .long("to")
+                .value_name("TIMESTAMP")
+                .help("Sets the end of the time range (default: now)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("step")
+                .long("step")
+                .value_name("SECONDS")
+                .help("Returns at most one measurement every SECONDS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("window")
+                .short("w")
+                .long("window")
+                .value_name("WINDOW")
+                .help("Prints the statistics for every window")
+                .possible_values(&["1m", "15m", "1h", "1d"])
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let to = match matches.value_of("to") {
+        Some(t) => t.parse::<DateTime<Utc>>().context("Bad timestamp")?,
+        None => Utc::now(),
+    };
+    let from = match matches.value_of("from") {
+        Some(t) => t.parse::<DateTime<Utc>>().context("Bad timestamp")?,
+        None => to - Duration::days(1),
+    };
+    let mut request = HistoryRequest::new(matches.value_of("SENSOR").unwrap(), from, to)?;
+    if let Some(step) = matches.value_of("step") {
+        request.step = Some(step.parse::<u64>().context("Bad step")?);
+    }
+
+    let context = Context::new();
+    let socket = context.socket(REQ)?;
+    let _connect = socket.connect(matches.value_of("REP_URL").unwrap())?;
+    let requester = HistoryRequester::new(socket)?;
+
+    let measurements = fetch_history(&requester, &request)?;
+    match matches.value_of("window") {
+        Some(w) => {
+            let window = w.parse::<Window>()?;
+            for stats in aggregate(&measurements, window) {
+                println!("{}", stats);
+            }
+        }
+        None => {
+            for m in measurements {
+                println!("{}", m);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(ref e) = parse_cli_arguments() {
+        println!("error: {:?}", e.cause());
+        // The backtrace is not always generated. Try to run this example
+        // with `RUST_BACKTRACE=1`.
+        let backtrace = e.backtrace();
+        println!("backtrace: {:?}", backtrace);
+        ::std::process::exit(1);
+    }
+}
@@ -0,0 +1,100 @@
+//! Expose sensor command servers as JSON resources over HTTP.
+//!
+//! The gateway, and its sensors, are set up with a configuration file:
+//!
+//! ```toml
+//! http_addr = "0.0.0.0:8080"
+//! sub_url = "tcp://192.168.16.123:5556"
+//!
+//! [[sensors]]
+//! name = "ph1"
+//! kind = "ph"
+//! url = "tcp://192.168.16.123:5558"
+//!
+//! [[sensors]]
+//! name = "rtd1"
+//! kind = "temperature"
+//! url = "tcp://192.168.16.123:5557"
+//! ```
+//!
+//! so that, e.g. `curl http://localhost:8080/sensors/ph1/reading` takes a pH reading, and
+//! `curl http://localhost:8080/metrics` returns the metrics of every sensor for Prometheus.
+//! Browsers can follow the readings, and alarms, of `sub_url` with a WebSocket on e.g.
+//! `ws://localhost:8080/stream?topic=ph&topic=alarm`.
+extern crate benita;
+extern crate chrono;
+extern crate clap;
+extern crate failure;
+extern crate fern;
+#[macro_use]
+extern crate log;
+extern crate neuras;
+extern crate zmq;
+
+use std::fs::File;
+use std::io::Read;
+
+use benita::ezo::config::GatewayConfig;
+use benita::ezo::errors::*;
+use benita::ezo::network::http::HttpGateway;
+use clap::{App, Arg};
+use failure::{Fail, ResultExt};
+
+// Configure and start logger.
+fn start_logger() -> Result<()> {
+    let _logger = fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "{}[{}][{}] {}",
+                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+                record.target(),
+                record.level(),
+                message
+            ))
+        })
+        .level(log::LogLevelFilter::Info)
+        .chain(std::io::stdout())
+        .apply()
+        .context(ErrorKind::RunTime("Could not setup logging".to_string()))?;
+    Ok(())
+}
+
+fn parse_cli_arguments() -> Result<()> {
+    let matches = App::new("benita-http-gateway")
+        .version("0.1.0")
+        .author("Joaquin R. <globojorro@gmail.com>")
+        .about("Benita IoT. HTTP/JSON gateway for sensor command servers.")
+        .arg(
+            Arg::with_name("CONFIG")
+                .help("Sets the gateway config file")
+                .takes_value(true)
+                .required(true),
+        )
+        .get_matches();
+
+    let mut input = String::new();
+    let _read = File::open(matches.value_of("CONFIG").unwrap())
+        .and_then(|mut f| f.read_to_string(&mut input))
+        .context(ErrorKind::ConfigParse)?;
+    let config = GatewayConfig::from_str(&input)?;
+    let gateway = HttpGateway::from_config(&config)?;
+
+    info!("Serving HTTP on {}", gateway.local_addr()?);
+    gateway.run()
+}
+
+fn run_code() -> Result<()> {
+    // Initialize logging.
+    let _log = start_logger()?;
+    parse_cli_arguments()
+}
+
+fn main() {
+    if let Err(ref e) = run_code() {
+        println!("error: {:?}", e.cause());
+        // The backtrace is not always generated. Try to run this example
+        // with `RUST_BACKTRACE=1`.
+        println!("backtrace: {:?}", e.backtrace());
+        ::std::process::exit(1);
+    }
+}
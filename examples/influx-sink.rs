@@ -0,0 +1,105 @@
+//! Write the measurements of a sensor publisher as InfluxDB line protocol.
+//!
+//! The sink is set up with a configuration file:
+//!
+//! ```toml
+//! sub_url = "tcp://192.168.16.123:5556"
+//! site = "tank-a"
+//! batch_size = 100
+//! flush_interval = 10
+//! retries = 3
+//!
+//! [firmware]
+//! 0123456789abcdef = "2.10"
+//!
+//! [destination]
+//! kind = "http"
+//! url = "http://localhost:8086/write?db=benita"
+//! ```
+//!
+//! Lines can also be sent to a UDP listener, with `kind = "udp"` and e.g.
+//! `addr = "localhost:8089"`, or appended to a rotating file, with `kind = "file"`,
+//! `path`, `max_bytes`, and `max_files`. A local listener, e.g. `nc -lu 8089`, shows the
+//! lines as they are written.
+extern crate benita;
+extern crate chrono;
+extern crate clap;
+extern crate failure;
+extern crate fern;
+#[macro_use]
+extern crate log;
+
+use std::fs::File;
+use std::io::Read;
+use std::time::Duration;
+
+use benita::ezo::config::InfluxConfig;
+use benita::ezo::errors::*;
+use benita::ezo::influx::InfluxSink;
+use benita::ezo::measurement::SensorType;
+use benita::ezo::network::subscriber::Subscriber;
+use clap::{App, Arg};
+use failure::{Fail, ResultExt};
+
+// Configure and start logger.
+fn start_logger() -> Result<()> {
+    let _logger = fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "{}[{}][{}] {}",
+                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+                record.target(),
+                record.level(),
+                message
+            ))
+        })
+        .level(log::LogLevelFilter::Info)
+        .chain(std::io::stdout())
+        .apply()
+        .context(ErrorKind::RunTime("Could not setup logging".to_string()))?;
+    Ok(())
+}
+
+fn parse_cli_arguments() -> Result<()> {
+    let matches = App::new("benita-influx-sink")
+        .version("0.1.0")
+        .author("Joaquin R. <globojorro@gmail.com>")
+        .about("Benita IoT. Writes published measurements as InfluxDB line protocol.")
+        .arg(
+            Arg::with_name("CONFIG")
+                .help("Sets the sink config file")
+                .takes_value(true)
+                .required(true),
+        )
+        .get_matches();
+
+    let mut input = String::new();
+    let _read = File::open(matches.value_of("CONFIG").unwrap())
+        .and_then(|mut f| f.read_to_string(&mut input))
+        .context(ErrorKind::ConfigParse)?;
+    let config = InfluxConfig::from_str(&input)?;
+    let mut sink = InfluxSink::from_config(&config)?;
+
+    let topics: Vec<&str> = SensorType::all().iter().map(|t| t.name()).collect();
+    let mut subscriber = Subscriber::connect(config.sub_url, &topics)?;
+    subscriber.set_reconnect_timeout(Some(Duration::from_secs(60)));
+
+    info!("Writing publications from {}", config.sub_url);
+    sink.run(subscriber)
+}
+
+fn run_code() -> Result<()> {
+    // Initialize logging.
+    let _log = start_logger()?;
+    parse_cli_arguments()
+}
+
+fn main() {
+    if let Err(ref e) = run_code() {
+        println!("error: {:?}", e.cause());
+        // The backtrace is not always generated. Try to run this example
+        // with `RUST_BACKTRACE=1`.
+        println!("backtrace: {:?}", e.backtrace());
+        ::std::process::exit(1);
+    }
+}
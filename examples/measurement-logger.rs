@@ -0,0 +1,131 @@
+//! Subscribe to sensor publications, and store them in a local measurement log.
+//!
+//! This keeps the readings of nodes whose publishers do not store their own samples, e.g.
+//! on a gateway that outlives network outages between the nodes and the central server.
+extern crate benita;
+extern crate chrono;
+extern crate clap;
+extern crate failure;
+extern crate fern;
+#[macro_use]
+extern crate log;
+extern crate neuras;
+extern crate zmq;
+
+use std::time::Duration;
+
+use benita::cli::is_url;
+use benita::ezo::errors::*;
+use benita::ezo::network::subscriber::Subscriber;
+use benita::ezo::store::{LogPolicy, MeasurementLog};
+use clap::{App, Arg};
+use failure::{Fail, ResultExt};
+
+// Configure and start logger.
+fn start_logger() -> Result<()> {
+    let _logger = fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "{}[{}][{}] {}",
+                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+                record.target(),
+                record.level(),
+                message
+            ))
+        })
+        .level(log::LogLevelFilter::Info)
+        .chain(std::io::stdout())
+        .apply()
+        .context(ErrorKind::RunTime("Could not setup logging".to_string()))?;
+    Ok(())
+}
+
+fn parse_cli_arguments() -> Result<()> {
+    let matches = App::new("benita-measurement-logger")
+        .version("0.1.0")
+        .author("Joaquin R. <globojorro@gmail.com>")
+        .about("Benita IoT. Local storage of published sensor readings.")
+        .arg(
+            Arg::with_name("SUB_URL")
+                .help("Sets the url of the publication server")
+                .takes_value(true)
+                .validator(is_url)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("DIR")
+                .help("Sets the directory for the measurement log")
+                .takes_value(true)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("topic")
+                .short("t")
+                .long("topic")
+                .value_name("PREFIX")
+                .help("Subscribes to the topics with this prefix (default: all)")
+                .takes_value(true)
+                .multiple(true),
+        )
+        .arg(
+            Arg::with_name("max-segments")
+                .long("max-segments")
+                .value_name("COUNT")
+                .help("Sets the number of log segments to keep")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("retention")
+                .long("retention")
+                .value_name("SECONDS")
+                .help("Sets how long to keep measurements")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let mut policy = LogPolicy::default();
+    if let Some(n) = matches.value_of("max-segments") {
+        policy.max_segments = Some(n.parse::<usize>().context(ErrorKind::NumberParse)?);
+    }
+    if let Some(secs) = matches.value_of("retention") {
+        let secs = secs.parse::<u64>().context(ErrorKind::NumberParse)?;
+        policy.retention = Some(Duration::from_secs(secs));
+    }
+
+    let url = matches.value_of("SUB_URL").unwrap();
+    let topics: Vec<&str> = match matches.values_of("topic") {
+        Some(topics) => topics.collect(),
+        None => vec![""],
+    };
+    let mut log = MeasurementLog::open(matches.value_of("DIR").unwrap(), policy)?;
+    let mut subscriber = Subscriber::connect(url, &topics)?;
+    subscriber.set_reconnect_timeout(Some(Duration::from_secs(60)));
+
+    info!("Storing publications from {}", &url);
+    loop {
+        match subscriber.recv_measurements() {
+            Ok(measurements) => {
+                for m in measurements {
+                    let _append = log.append(&m)?;
+                }
+            }
+            Err(e) => warn!("skipping publication: {}", e),
+        }
+    }
+}
+
+fn run_code() -> Result<()> {
+    // Initialize logging.
+    let _log = start_logger()?;
+    parse_cli_arguments()
+}
+
+fn main() {
+    if let Err(ref e) = run_code() {
+        println!("error: {:?}", e.cause());
+        // The backtrace is not always generated. Try to run this example
+        // with `RUST_BACKTRACE=1`.
+        println!("backtrace: {:?}", e.backtrace());
+        ::std::process::exit(1);
+    }
+}
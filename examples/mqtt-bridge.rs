@@ -0,0 +1,101 @@
+//! Bridge sensor publications, and command servers, to an MQTT broker.
+//!
+//! The bridge is set up with a configuration file:
+//!
+//! ```toml
+//! broker_addr = "localhost:1883"
+//! client_id = "benita-node"
+//! topic_prefix = "benita"
+//! qos = 1
+//! retain = true
+//! sub_url = "tcp://192.168.16.123:5556"
+//! discovery_prefix = "homeassistant"
+//!
+//! [[sensors]]
+//! name = "ph1"
+//! kind = "ph"
+//! url = "tcp://192.168.16.123:5558"
+//! topic = "ph-0123456789abcdef"
+//! ```
+//!
+//! so that measurements are published on e.g. `benita/ph-0123456789abcdef`, and, e.g.
+//! `mosquitto_pub -t benita/ph1/command -m read` takes a pH reading, which is published on
+//! `benita/ph1/response`. With a `discovery_prefix`, the sensors with a `topic` are
+//! announced to Home Assistant.
+extern crate benita;
+extern crate chrono;
+extern crate clap;
+extern crate failure;
+extern crate fern;
+#[macro_use]
+extern crate log;
+extern crate neuras;
+extern crate zmq;
+
+use std::fs::File;
+use std::io::Read;
+
+use benita::ezo::config::MqttConfig;
+use benita::ezo::errors::*;
+use benita::ezo::network::mqtt::MqttBridge;
+use clap::{App, Arg};
+use failure::{Fail, ResultExt};
+
+// Configure and start logger.
+fn start_logger() -> Result<()> {
+    let _logger = fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "{}[{}][{}] {}",
+                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+                record.target(),
+                record.level(),
+                message
+            ))
+        })
+        .level(log::LogLevelFilter::Info)
+        .chain(std::io::stdout())
+        .apply()
+        .context(ErrorKind::RunTime("Could not setup logging".to_string()))?;
+    Ok(())
+}
+
+fn parse_cli_arguments() -> Result<()> {
+    let matches = App::new("benita-mqtt-bridge")
+        .version("0.1.0")
+        .author("Joaquin R. <globojorro@gmail.com>")
+        .about("Benita IoT. MQTT bridge for sensor publishers and command servers.")
+        .arg(
+            Arg::with_name("CONFIG")
+                .help("Sets the bridge config file")
+                .takes_value(true)
+                .required(true),
+        )
+        .get_matches();
+
+    let mut input = String::new();
+    let _read = File::open(matches.value_of("CONFIG").unwrap())
+        .and_then(|mut f| f.read_to_string(&mut input))
+        .context(ErrorKind::ConfigParse)?;
+    let config = MqttConfig::from_str(&input)?;
+    let mut bridge = MqttBridge::from_config(&config)?;
+
+    info!("Bridging to the MQTT broker at {}", config.broker_addr);
+    bridge.run()
+}
+
+fn run_code() -> Result<()> {
+    // Initialize logging.
+    let _log = start_logger()?;
+    parse_cli_arguments()
+}
+
+fn main() {
+    if let Err(ref e) = run_code() {
+        println!("error: {:?}", e.cause());
+        // The backtrace is not always generated. Try to run this example
+        // with `RUST_BACKTRACE=1`.
+        println!("backtrace: {:?}", e.backtrace());
+        ::std::process::exit(1);
+    }
+}
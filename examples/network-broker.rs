@@ -0,0 +1,95 @@
+//! Broker between sensor service clients and the sensor nodes that serve them.
+//!
+//! Sensor nodes register their services with the broker (see the `broker-worker`
+//! example), and clients send requests to the broker by service name.
+extern crate benita;
+extern crate chrono;
+extern crate clap;
+extern crate failure;
+extern crate fern;
+#[macro_use]
+extern crate log;
+extern crate neuras;
+extern crate zmq;
+
+use std::time::Duration;
+
+use benita::cli::is_url;
+use benita::ezo::errors::*;
+use benita::ezo::network::broker::Broker;
+use clap::{App, Arg};
+use failure::{Fail, ResultExt};
+
+// Configure and start logger.
+fn start_logger() -> Result<()> {
+    let _logger = fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "{}[{}][{}] {}",
+                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+                record.target(),
+                record.level(),
+                message
+            ))
+        })
+        .level(log::LogLevelFilter::Debug)
+        .chain(std::io::stdout())
+        .chain(fern::log_file("broker.log").context(ErrorKind::RunTime("failed to open log file".to_string()))?)
+        .apply()
+        .context(ErrorKind::RunTime("Could not setup logging".to_string()))?;
+    Ok(())
+}
+
+fn parse_cli_arguments() -> Result<()> {
+    let matches = App::new("benita-broker")
+        .version("0.1.0")
+        .author("Joaquin R. <globojorro@gmail.com>")
+        .about("Benita IoT. Broker for sensor services.")
+        .arg(
+            Arg::with_name("URL")
+                .help("Sets the url for the broker")
+                .takes_value(true)
+                .validator(is_url)
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .short("t")
+                .long("timeout")
+                .value_name("SECONDS")
+                .help("Sets how long requests wait for a worker")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let url = matches.value_of("URL").unwrap();
+    let mut broker = Broker::bind(url)?;
+
+    if let Some(t) = matches.value_of("timeout") {
+        let secs = t.parse::<u64>().context(ErrorKind::NumberParse)?;
+        broker.set_request_timeout(Duration::from_secs(secs));
+    }
+
+    info!("Broker now serving at: {}", &url);
+    let _run = broker.run()?;
+
+    // Never reach this line...
+    Ok(())
+}
+
+fn run_code() -> Result<()> {
+    // Initialize logging.
+    let _log = start_logger()?;
+    info!("Starting network-broker");
+    parse_cli_arguments()
+}
+
+fn main() {
+    if let Err(ref e) = run_code() {
+        println!("error: {:?}", e.cause());
+        // The backtrace is not always generated. Try to run this example
+        // with `RUST_BACKTRACE=1`.
+        println!("backtrace: {:?}", e.backtrace());
+        ::std::process::exit(1);
+    }
+}
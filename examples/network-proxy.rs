@@ -1,5 +1,16 @@
 //! Proxy between networks for PUB services.
 //!
+//! Settings are layered: the defaults, then the `--config` file, then the
+//! `BENITA_PROXY_*` environment variables, then the command-line arguments.
+//! `--strict` rejects unknown settings in the file. `--print-config` prints the effective
+//! settings, e.g.
+//!
+//! ```toml
+//! backend_url = "tcp://*:5561"
+//! backend_connection = "bind"
+//! frontend_url = "tcp://*:5562"
+//! frontend_connection = "bind"
+//! ```
 extern crate benita;
 extern crate chrono;
 extern crate clap;
@@ -10,15 +21,15 @@ extern crate log;
 extern crate neuras;
 extern crate zmq;
 
-use std::fs::File;
-use std::io::Read;
-
-use benita::ezo::config::ProxyConfig;
+use benita::ezo::config::{to_toml, ConfigLayers, ConnectionType, ProxyConfig};
 use benita::ezo::errors::*;
 use clap::{App, Arg};
 use failure::{Fail, ResultExt};
 use zmq::{Context, XPUB, XSUB, proxy};
 
+const DEFAULT_BACKEND_URL: &'static str = "tcp://*:5561";
+const DEFAULT_FRONTEND_URL: &'static str = "tcp://*:5562";
+
 // Configure and start logger.
 fn start_logger() -> Result<()> {
     let _logger = fern::Dispatch::new()
@@ -59,9 +70,7 @@ fn parse_cli_arguments() -> Result<()> {
                 .value_name("BACKEND_URL")
                 .help("Sets the url for the backend server")
                 .takes_value(true)
-                .index(1)
-                .required(true)
-                .conflicts_with_all(&["config"]),
+                .index(1),
         )
         .arg(
             Arg::with_name("frontend-url")
@@ -70,9 +79,17 @@ fn parse_cli_arguments() -> Result<()> {
                 .value_name("FRONTEND_URL")
                 .help("Sets the url for the frontend server")
                 .takes_value(true)
-                .required(true)
-                .index(2)
-                .conflicts_with_all(&["config"]),
+                .index(2),
+        )
+        .arg(
+            Arg::with_name("strict")
+                .long("strict")
+                .help("Rejects unknown settings in the config file"),
+        )
+        .arg(
+            Arg::with_name("print-config")
+                .long("print-config")
+                .help("Prints the effective settings, and exits"),
         )
         .arg(
             Arg::with_name("debug")
@@ -82,26 +99,37 @@ fn parse_cli_arguments() -> Result<()> {
         )
         .get_matches();
 
-    let mut input = String::new();
-    let mut config = ProxyConfig::default();
+    let defaults = ProxyConfig {
+        backend_url: DEFAULT_BACKEND_URL.to_string(),
+        backend_connection: ConnectionType::Bind,
+        frontend_url: DEFAULT_FRONTEND_URL.to_string(),
+        frontend_connection: ConnectionType::Bind,
+    };
+    let mut layers = ConfigLayers::new(&defaults)?;
+    layers.set_strict(matches.is_present("strict"));
 
     if let Some(c) = matches.value_of("config") {
-        let _read = File::open(&c)
-            .and_then(|mut f| f.read_to_string(&mut input))
-            .unwrap();
-
-        config = ProxyConfig::from_str(&input)?;
-    } else {
-        if let Some(frontend) = matches.value_of("frontend-url") {
-            config.frontend_url = frontend;
-        }
-
-        if let Some(backend) = matches.value_of("backend-url") {
-            config.backend_url = backend;
-        }
+        layers.merge_file(c)?;
+    }
+
+    layers.merge_env("BENITA_PROXY")?;
+
+    if let Some(frontend) = matches.value_of("frontend-url") {
+        layers.set("frontend_url", frontend)?;
+    }
+
+    if let Some(backend) = matches.value_of("backend-url") {
+        layers.set("backend_url", backend)?;
+    }
+
+    let config: ProxyConfig = layers.build()?;
+
+    if matches.is_present("print-config") {
+        print!("{}", to_toml(&config)?);
+        return Ok(());
     }
 
-    let _run = run_proxy(config.backend_url, config.frontend_url)?;
+    let _run = run_proxy(&config.backend_url, &config.frontend_url)?;
 
     // Never reach this line...
     Ok(())
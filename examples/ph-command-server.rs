@@ -1,6 +1,18 @@
-//! Allows for remote command of the PH EZO chip, exposing a limited API.
+//! Allows for remote command of the PH EZO chip, through its responder.
 //!
-//! This server binds to the `REP_URL` argument, expected from the command line.
+//! This server binds to the `REP_URL` argument, or to `tcp://*:5558` by default.
+//! With `--store DIR`, it also answers `history` requests from the measurement log in `DIR`.
+//!
+//! Settings are layered: the defaults, then the `--config` file, then the
+//! `BENITA_SOCKET_PH_*` and `BENITA_SENSOR_PH_*` environment variables, then the
+//! command-line arguments. `--print-config` prints the effective settings, e.g.
+//!
+//! ```toml
+//! url = "tcp://*:5558"
+//! socket_connection = "bind"
+//! path = "/dev/i2c-1"
+//! address = 99
+//! ```
 extern crate benita;
 extern crate chrono;
 extern crate clap;
@@ -11,26 +23,27 @@ extern crate log;
 extern crate neuras;
 extern crate zmq;
 
-use std::path::PathBuf;
 use std::result;
 
 use benita::cli::is_url;
-use benita::ezo::common_ezo::EzoChipAPI;
-use benita::ezo::config::{ConnectionType, SensorConfig, SocketConfig};
-use benita::ezo::errors::{Error as EzoError};
-use benita::ezo::network::{Endpoint, ReplyStatus};
-use benita::ezo::ph::command::*;
+use benita::ezo::config::{to_toml, ConfigLayers, ConnectionType, SensorConfig, SocketConfig};
+use benita::ezo::network::history::HistoryService;
+use benita::ezo::node::serve_requests;
 use benita::ezo::ph::device::PhSensor;
 use benita::ezo::ph::network::PhResponder;
-use benita::ezo::ph::PhAPI;
+use benita::ezo::store::LogReader;
 use benita::ezo::utilities::*;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use failure::{Error, ResultExt};
 use zmq::Socket;
 
 type Result<T> = result::Result<T, Error>;
 
+const DEFAULT_URL: &'static str = "tcp://*:5558";
+const DEFAULT_I2C: &'static str = "/dev/i2c-1";
+const DEFAULT_ADDRESS: u16 = 99;
+
 // Configure and start logger.
 fn start_logger() -> Result<()> {
     let _logger = fern::Dispatch::new()
@@ -51,79 +64,42 @@ fn start_logger() -> Result<()> {
     Ok(())
 }
 
+// Return the socket, and sensor, settings: the defaults, then the `--config` file, then the
+// environment variables, then the command-line arguments.
+fn configs_from_layers(matches: &ArgMatches) -> Result<(SocketConfig, SensorConfig)> {
+    let socket_defaults = SocketConfig {
+        url: DEFAULT_URL.to_string(),
+        socket_connection: ConnectionType::Bind,
+    };
+    let mut socket_layers = ConfigLayers::new(&socket_defaults)?;
+    let mut sensor_layers = ConfigLayers::new(&SensorConfig::new(DEFAULT_I2C, DEFAULT_ADDRESS))?;
+    if let Some(path) = matches.value_of("config") {
+        socket_layers.merge_file(path)?;
+        sensor_layers.merge_file(path)?;
+    }
+    socket_layers.merge_env("BENITA_SOCKET_PH")?;
+    sensor_layers.merge_env("BENITA_SENSOR_PH")?;
+    if let Some(url) = matches.value_of("URL") {
+        socket_layers.set("url", url)?;
+    }
+    if let Some(path) = matches.value_of("I2C") {
+        sensor_layers.set("path", path)?;
+    }
+    if let Some(address) = matches.value_of("ADDRESS") {
+        sensor_layers.set("address", address)?;
+    }
+    Ok((socket_layers.build()?, sensor_layers.build()?))
+}
+
 // Return a `Socket` from a `SocketConfig`
 fn socket_from_config(cfg: &SocketConfig) -> Result<Socket> {
     let socket = match cfg.socket_connection {
-        ConnectionType::Bind => create_and_bind_responder(cfg.url)?,
-        ConnectionType::Connect => create_and_connect_responder(cfg.url)?,
+        ConnectionType::Bind => create_and_bind_responder(&cfg.url)?,
+        ConnectionType::Connect => create_and_connect_responder(&cfg.url)?,
     };
     Ok(socket)
 }
 
-// Return 'err' string, and log it
-fn return_error(e: EzoError) -> String {
-    error!("ph sensor error: {}", e);
-    format!("{:?}", ReplyStatus::Err)
-}
-
-// Match and evaluate commands
-fn match_and_eval(s: &str, e: &PhResponder) -> Result<String> {
-    match s {
-        a if <CalibrationState as I2CCommand>::from_str(a).is_ok() => {
-            let _req = <CalibrationState as I2CCommand>::from_str(s)?;
-            let reply = match e.get_calibration_status() {
-                Ok(rep) => format!("{:?}", rep),
-                Err(e) => return_error(e.into()),
-            };
-            Ok(reply)
-        }
-        a if <CompensationSet as I2CCommand>::from_str(a).is_ok() => {
-            let _req = <CompensationSet as I2CCommand>::from_str(s)?;
-            let reply = match e.set_compensation(_req.0) {
-                Ok(rep) => format!("{:?}", rep),
-                Err(e) => return_error(e),
-            };
-            Ok(reply)
-        }
-        a if <DeviceInformation as I2CCommand>::from_str(a).is_ok() => {
-            let _req = <DeviceInformation as I2CCommand>::from_str(s)?;
-            let reply = match e.get_device_info() {
-                Ok(rep) => format!("{:?}", rep),
-                Err(e) => return_error(e),
-            };
-            Ok(reply)
-        }
-        a if <Reading as I2CCommand>::from_str(a).is_ok() => {
-            let _req = <Reading as I2CCommand>::from_str(s)?;
-            let reply = match e.get_reading() {
-                Ok(rep) => format!("{:?}", rep),
-                Err(e) => return_error(e),
-            };
-            Ok(reply)
-        }
-        a if <Sleep as I2CCommand>::from_str(a).is_ok() => {
-            let _req = <Sleep as I2CCommand>::from_str(s)?;
-            let reply = match e.set_sleep() {
-                Ok(rep) => format!("{:?}", rep),
-                Err(e) => return_error(e),
-            };
-            Ok(reply)
-        }
-        a if <Status as I2CCommand>::from_str(a).is_ok() => {
-            let _req = <Status as I2CCommand>::from_str(s)?;
-            let reply = match e.get_device_status() {
-                Ok(rep) => format!("{:?}", rep),
-                Err(e) => return_error(e),
-            };
-            Ok(reply)
-        }
-        _ => {
-            error!("bad sensor command");
-            Ok(format!("{:?}", ReplyStatus::Err))
-        }
-    }
-}
-
 // Parse the command-line arguments and execute.
 fn evaluate_command_line() -> Result<()> {
     let matches = App::new("benita-ph-network-service")
@@ -134,38 +110,47 @@ fn evaluate_command_line() -> Result<()> {
             Arg::with_name("URL")
                 .help("Sets the url for the response server")
                 .takes_value(true)
-                .validator(is_url)
-                .required(true),
+                .validator(is_url),
         )
         .arg(
             Arg::with_name("I2C")
                 .help("Sets the path for the I2C sensor.")
-                .takes_value(true)
-                .required(true),
+                .takes_value(true),
         )
         .arg(
             Arg::with_name("ADDRESS")
                 .help("Sets the I2C sensor address.")
-                .takes_value(true)
-                .required(true),
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("Sets a custom config file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("print-config")
+                .long("print-config")
+                .help("Prints the effective settings, and exits"),
+        )
+        .arg(
+            Arg::with_name("store")
+                .short("s")
+                .long("store")
+                .value_name("DIR")
+                .help("Answers history requests from the measurement log in DIR")
+                .takes_value(true),
         )
         .get_matches();
 
-    // socket configuration from args.
-    let socket_cfg = SocketConfig {
-        socket_connection: ConnectionType::Bind,
-        url: matches.value_of("URL").unwrap(),
-    };
-
-    // sensor configuration from args.
-    let sensor_cfg = SensorConfig {
-        address: matches
-            .value_of("ADDRESS")
-            .unwrap()
-            .parse::<u16>()
-            .context("Bad Address")?,
-        path: PathBuf::from(matches.value_of("I2C").unwrap()),
-    };
+    // socket, and sensor, configuration from the layered settings.
+    let (socket_cfg, sensor_cfg) = configs_from_layers(&matches)?;
+    if matches.is_present("print-config") {
+        print!("{}{}", to_toml(&socket_cfg)?, to_toml(&sensor_cfg)?);
+        return Ok(());
+    }
 
     // initialize the sensor.
     let sensor = PhSensor::from_config(sensor_cfg)?;
@@ -176,17 +161,20 @@ fn evaluate_command_line() -> Result<()> {
     // initialize the responder with the sensor and socket.
     let responder = PhResponder::new(socket, sensor)?;
 
-    // the main loop, it will run for as long as the program runs.
-    loop {
-        let req_str = &responder.recv()?;
-        info!("REQ: {}", &req_str);
-        let call: String = match_and_eval(&req_str, &responder)?;
-        info!("REP: {}", &call);
-        let _reply = &responder.send(call.as_bytes())?;
-    }
-
-    // Never reach this line...
-    // Ok(())
+    // answer history requests from the measurement log, if there is one.
+    let history = matches
+        .value_of("store")
+        .map(|dir| HistoryService::new(LogReader::new(dir)));
+
+    // answer every request through the responder, which guards the calibration with its
+    // session lock, for as long as the program runs.
+    serve_requests(&responder, |req_str| {
+        match history.as_ref().and_then(|h| h.respond(req_str)) {
+            Some(reply) => Ok(reply),
+            None => responder.respond(req_str),
+        }
+    })?;
+    Ok(())
 }
 
 // Main program. Starts logger, then evaluates args from stdin.
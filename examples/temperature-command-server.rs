@@ -1,6 +1,18 @@
 //! Allows for remote command of the RTD EZO chip, exposing a limited API.
 //!
-//! This server binds to the `REP_URL` argument, expected from the command line.
+//! This server binds to the `REP_URL` argument, or to `tcp://*:5559` by default.
+//! With `--store DIR`, it also answers `history` requests from the measurement log in `DIR`.
+//!
+//! Settings are layered: the defaults, then the `--config` file, then the
+//! `BENITA_SOCKET_TEMPERATURE_*` and `BENITA_SENSOR_TEMPERATURE_*` environment variables,
+//! then the command-line arguments. `--print-config` prints the effective settings, e.g.
+//!
+//! ```toml
+//! url = "tcp://*:5559"
+//! socket_connection = "bind"
+//! path = "/dev/i2c-1"
+//! address = 101
+//! ```
 extern crate benita;
 extern crate chrono;
 extern crate clap;
@@ -11,26 +23,31 @@ extern crate log;
 extern crate neuras;
 extern crate zmq;
 
-use std::path::PathBuf;
 use std::result;
 
 use benita::cli::is_url;
 use benita::ezo::common_ezo::EzoChipAPI;
-use benita::ezo::config::{ConnectionType, SensorConfig, SocketConfig};
+use benita::ezo::config::{to_toml, ConfigLayers, ConnectionType, SensorConfig, SocketConfig};
 use benita::ezo::errors::{Error as EzoError};
 use benita::ezo::network::{Endpoint, ReplyStatus};
+use benita::ezo::network::history::HistoryService;
 use benita::ezo::temperature::command::*;
 use benita::ezo::temperature::device::TemperatureSensor;
 use benita::ezo::temperature::network::TemperatureResponder;
 use benita::ezo::temperature::TemperatureAPI;
+use benita::ezo::store::LogReader;
 use benita::ezo::utilities::*;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use failure::{Error, ResultExt};
 use zmq::Socket;
 
 type Result<T> = result::Result<T, Error>;
 
+const DEFAULT_URL: &'static str = "tcp://*:5559";
+const DEFAULT_I2C: &'static str = "/dev/i2c-1";
+const DEFAULT_ADDRESS: u16 = 101;
+
 // Configure and start logger.
 fn start_logger() -> Result<()> {
     let _logger = fern::Dispatch::new()
@@ -51,11 +68,38 @@ fn start_logger() -> Result<()> {
     Ok(())
 }
 
+// Return the socket, and sensor, settings: the defaults, then the `--config` file, then the
+// environment variables, then the command-line arguments.
+fn configs_from_layers(matches: &ArgMatches) -> Result<(SocketConfig, SensorConfig)> {
+    let socket_defaults = SocketConfig {
+        url: DEFAULT_URL.to_string(),
+        socket_connection: ConnectionType::Bind,
+    };
+    let mut socket_layers = ConfigLayers::new(&socket_defaults)?;
+    let mut sensor_layers = ConfigLayers::new(&SensorConfig::new(DEFAULT_I2C, DEFAULT_ADDRESS))?;
+    if let Some(path) = matches.value_of("config") {
+        socket_layers.merge_file(path)?;
+        sensor_layers.merge_file(path)?;
+    }
+    socket_layers.merge_env("BENITA_SOCKET_TEMPERATURE")?;
+    sensor_layers.merge_env("BENITA_SENSOR_TEMPERATURE")?;
+    if let Some(url) = matches.value_of("URL") {
+        socket_layers.set("url", url)?;
+    }
+    if let Some(path) = matches.value_of("I2C") {
+        sensor_layers.set("path", path)?;
+    }
+    if let Some(address) = matches.value_of("ADDRESS") {
+        sensor_layers.set("address", address)?;
+    }
+    Ok((socket_layers.build()?, sensor_layers.build()?))
+}
+
 // Return a `Socket` from a `SocketConfig`
 fn socket_from_config(cfg: &SocketConfig) -> Result<Socket> {
     let socket = match cfg.socket_connection {
-        ConnectionType::Bind => create_and_bind_responder(cfg.url)?,
-        ConnectionType::Connect => create_and_connect_responder(cfg.url)?,
+        ConnectionType::Bind => create_and_bind_responder(&cfg.url)?,
+        ConnectionType::Connect => create_and_connect_responder(&cfg.url)?,
     };
     Ok(socket)
 }
@@ -127,38 +171,47 @@ fn evaluate_command_line() -> Result<()> {
             Arg::with_name("URL")
                 .help("Sets the url for the response server")
                 .takes_value(true)
-                .validator(is_url)
-                .required(true),
+                .validator(is_url),
         )
         .arg(
             Arg::with_name("I2C")
                 .help("Sets the path for the I2C sensor.")
-                .takes_value(true)
-                .required(true),
+                .takes_value(true),
         )
         .arg(
             Arg::with_name("ADDRESS")
                 .help("Sets the I2C sensor address.")
-                .takes_value(true)
-                .required(true),
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("Sets a custom config file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("print-config")
+                .long("print-config")
+                .help("Prints the effective settings, and exits"),
+        )
+        .arg(
+            Arg::with_name("store")
+                .short("s")
+                .long("store")
+                .value_name("DIR")
+                .help("Answers history requests from the measurement log in DIR")
+                .takes_value(true),
         )
         .get_matches();
 
-    // socket configuration from args.
-    let socket_cfg = SocketConfig {
-        socket_connection: ConnectionType::Bind,
-        url: matches.value_of("URL").unwrap(),
-    };
-
-    // sensor configuration from args.
-    let sensor_cfg = SensorConfig {
-        address: matches
-            .value_of("ADDRESS")
-            .unwrap()
-            .parse::<u16>()
-            .context("Bad Address")?,
-        path: PathBuf::from(matches.value_of("I2C").unwrap()),
-    };
+    // socket, and sensor, configuration from the layered settings.
+    let (socket_cfg, sensor_cfg) = configs_from_layers(&matches)?;
+    if matches.is_present("print-config") {
+        print!("{}{}", to_toml(&socket_cfg)?, to_toml(&sensor_cfg)?);
+        return Ok(());
+    }
 
     // initialize the sensor.
     let sensor = TemperatureSensor::from_config(sensor_cfg)?;
@@ -169,11 +222,19 @@ fn evaluate_command_line() -> Result<()> {
     // initialize the responder with the sensor and socket.
     let responder = TemperatureResponder::new(socket, sensor)?;
 
+    // answer history requests from the measurement log, if there is one.
+    let history = matches
+        .value_of("store")
+        .map(|dir| HistoryService::new(LogReader::new(dir)));
+
     // the main loop, it will run for as long as the program runs.
     loop {
         let req_str = &responder.recv()?;
         info!("REQ: {}", &req_str);
-        let call: String = match_and_eval(&req_str, &responder)?;
+        let call: String = match history.as_ref().and_then(|h| h.respond(&req_str)) {
+            Some(reply) => reply,
+            None => match_and_eval(&req_str, &responder)?,
+        };
         info!("REP: {}", &call);
         let _reply = &responder.send(call.as_bytes())?;
     }
@@ -2,6 +2,19 @@
 //! data with a UUID as the topic.
 //!
 //! This server binds to the `PUB_URL` argument, expected from the command line.
+//! Alternatively, several sensors may be published by using a configuration file:
+//!
+//! ```toml
+//! pub_url = "tcp://*:5557"
+//!
+//! [[sensors]]
+//! kind = "temperature"
+//! path = "/dev/i2c-1"
+//! address = 101
+//! topic = "temperature-0123456789abcdef"
+//! interval = 10000
+//! sleep = true
+//! ```
 extern crate benita;
 extern crate chrono;
 extern crate clap;
@@ -9,22 +22,22 @@ extern crate failure;
 extern crate neuras;
 extern crate zmq;
 
-use std::thread;
-use std::time::Duration;
+use std::fs::File;
+use std::io::Read;
 use std::result;
+use std::time::Duration;
 
-use benita::ezo::common_ezo::EzoChipAPI;
+use benita::ezo::config::PublisherConfig;
+use benita::ezo::network::publisher::{SensorPublisher, TemperatureSource};
 use benita::ezo::temperature::device::TemperatureSensor;
-use benita::ezo::temperature::TemperatureAPI;
 
-use chrono::{DateTime, Utc};
 use clap::{App, Arg};
 use failure::{Error, ResultExt};
-use zmq::{Context, PUB};
 
 const I2C_BUS_ID: u8 = 1;
 const EZO_SENSOR_ADDR: u16 = 101; // could be specified as 0x65
 const PUB_CHANNEL: &'static str = "temperature-0123456789abcdef";
+const PUB_INTERVAL: u64 = 10_000;
 
 type Result<T> = result::Result<T, Error>;
 
@@ -33,6 +46,14 @@ fn parse_cli_arguments() -> Result<()> {
         .version("0.1.0")
         .author("Joaquin R. <globojorro@gmail.com>")
         .about("Benita IoT. A publication service for temperature data.")
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .help("Sets a custom config file")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("pub-url")
                 .short("p")
@@ -41,53 +62,44 @@ fn parse_cli_arguments() -> Result<()> {
                 .help("Sets the url for the publication server")
                 .takes_value(true)
                 .index(1)
-                .required(true),
+                .required_unless("config")
+                .conflicts_with_all(&["config"]),
         )
         .get_matches();
 
-    let mut pub_url = String::new();
-
-    if let Some(c) = matches.value_of("pub-url") {
-        pub_url = String::from(c);
-    }
-
-    run(&pub_url)?;
+    let mut publisher = match matches.value_of("config") {
+        Some(c) => {
+            let mut input = String::new();
+            let _read = File::open(&c)
+                .and_then(|mut f| f.read_to_string(&mut input))
+                .context("Could not read config file")?;
+            let config = PublisherConfig::from_str(&input)?;
+            SensorPublisher::from_config(&config)?
+        }
+        None => {
+            let pub_url = matches.value_of("pub-url").unwrap();
+            let device_path = format!("/dev/i2c-{}", I2C_BUS_ID);
+            let rtd_sensor = TemperatureSensor::new(&device_path, EZO_SENSOR_ADDR)
+                .context("Could not open I2C device")?;
+            let mut publisher =
+                SensorPublisher::bind(pub_url).context("Publisher could not be started")?;
+            // The chip is put to sleep right after every reading.
+            publisher.add_sensor(
+                PUB_CHANNEL,
+                TemperatureSource(rtd_sensor),
+                Duration::from_millis(PUB_INTERVAL),
+                true,
+            );
+            publisher
+        }
+    };
+
+    publisher.run()?;
 
     // Never reach this line...
     Ok(())
 }
 
-fn run(pub_url: &str) -> Result<()> {
-    let device_path = format!("/dev/i2c-{}", I2C_BUS_ID);
-    let rtd_sensor = TemperatureSensor::new(&device_path, EZO_SENSOR_ADDR)
-        .context("Could not open I2C device")?;
-    let context = Context::new();
-    let publisher = context.socket(PUB)?;
-
-    let _bind = publisher.bind(pub_url).context("Publisher could not be started")?;
-
-    loop {
-        // We query the current temperature state of the sensor chip.
-        let scale = rtd_sensor.get_scale()?;
-
-        // We take a temperature reading (around 600ms).
-        let temperature = rtd_sensor.get_reading()?;
-
-        // We immediately put the chip to sleep.
-        let _sleep = rtd_sensor.set_sleep()?;
-
-        // We print out the result with the current ISO datetime.
-        let dt: DateTime<Utc> = Utc::now();
-        let update = format!("{} {:?} {} {:?}", PUB_CHANNEL, dt, temperature, scale);
-        publisher.send(&update.as_bytes(), 0).unwrap();
-        println!("{}", &update);
-
-        // put the thread to sleep for 10_000 - 900 ms = 9_100 ms.
-        // The real delay will depend on your system's characteristics.
-        thread::sleep(Duration::from_millis(9_100));
-    }
-}
-
 fn main() {
     if let Err(ref e) = parse_cli_arguments() {
         println!("error: {:?}", e.cause());
@@ -7,24 +7,19 @@ extern crate failure;
 extern crate neuras;
 extern crate zmq;
 
-use std::thread;
-use std::time::Duration;
 use std::result;
+use std::time::Duration;
 
 use benita::errors::ErrorKind;
-use chrono::{DateTime, Local};
+use benita::ezo::network::subscriber::Subscriber;
+use chrono::Local;
 use clap::{App, Arg};
 use failure::Error;
-use zmq::{Context, SUB};
 
 const SUB_CHANNEL: &'static str = "temperature-0123456789abcdef";
 
 type Result<T> = result::Result<T, Error>;
 
-fn atof(s: &str) -> f64 {
-    s.parse().unwrap()
-}
-
 fn parse_cli_arguments() -> Result<()> {
     let matches = App::new("benita-subscriber")
         .version("0.1.0")
@@ -74,83 +69,53 @@ fn parse_cli_arguments() -> Result<()> {
     Ok(())
 }
 
-fn parse_sub_str(sub_str: &str) -> Result<(String, DateTime<Local>, f64, String)> {
-    let mut split = sub_str.split(' ');
-
-    // The first string is the UUID of the message source.
-    let uuid = match split.next() {
-        Some(_uuid) => _uuid.to_string(),
-        _ => {
-            println!("No valid UUID found");
-            return Err(ErrorKind::ResponseParse.into());
-        }
-    };
-
-    let dt = match split.next() {
-        Some(date_n_time) => date_n_time.parse::<DateTime<Local>>().unwrap(),
-        _ => {
-            println!("NO valid date-time found");
-            return Err(ErrorKind::ResponseParse.into());
-        }
-    };
-
-    let temperature = match split.next() {
-        Some(temp) => atof(&temp),
-        _ => {
-            println!("NO valid date-time found");
-            return Err(ErrorKind::ResponseParse.into());
-        }
-    };
-
-    let scale = match split.next() {
-        Some(_scale) => _scale.to_string(),
-        _ => {
-            println!("NO valid temperature scale found");
-            return Err(ErrorKind::ResponseParse.into());
-        }
-    };
-
-    Ok((uuid, dt, temperature, scale))
-}
-
 fn run_subscriber(pub_url: &str, channel: &str) -> Result<()> {
     println!("Collecting updates from weather server...");
 
-    let context = Context::new();
-    let subscriber = context.socket(SUB)?;
-    let _connect = subscriber.connect(pub_url)?;
-
-    let _subscribe = subscriber.set_subscribe(channel.as_bytes())?;
+    let mut subscriber = Subscriber::connect(pub_url, &[channel])?;
+    subscriber.set_reconnect_timeout(Some(Duration::from_secs(60)));
 
     let mut samples = 0;
     let mut total_temp = 0f64;
 
     // Reactor-type loop, it will run as long as the current program runs.
     loop {
-        let sub_str = subscriber.recv_string(0).unwrap().unwrap();
-
-        let (uuid, dt, temperature, scale) = parse_sub_str(&sub_str)?;
+        let publication = match subscriber.recv() {
+            Ok(publication) => publication,
+            Err(e) => {
+                println!("skipping message: {}", e);
+                continue;
+            }
+        };
+        let dt = publication.timestamp.with_timezone(&Local);
+        let temperature = match publication.value() {
+            Some(temperature) => temperature,
+            None => {
+                println!("skipping message without a value");
+                continue;
+            }
+        };
         println!(
             "{} {} {}",
             dt.format("%F %T %z").to_string(),
             temperature,
-            scale
+            &publication.unit
         );
 
         total_temp += temperature;
 
         if samples == 6 {
             let avg = total_temp / 6.0;
-            println!("UUID: {} AVG: {:.*} {}", uuid, 3, avg, scale);
+            println!(
+                "UUID: {} AVG: {:.*} {}",
+                &publication.topic, 3, avg, &publication.unit
+            );
 
             total_temp = 0f64;
             samples = 1;
         } else {
             samples += 1;
         }
-
-        // No work left, so we sleep.
-        thread::sleep(Duration::from_millis(1));
     }
 }
 
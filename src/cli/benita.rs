@@ -1,49 +1,256 @@
 //! Top-level command parsers for common networked sensor services.
+use std::io::{self, BufRead, Write};
+use std::time::Duration;
+
+use cli::conductivity::{
+    run_conductivity_client, run_conductivity_sensor, ConductivityClientApp, ConductivitySensorApp,
+};
+use cli::export::{export_cli_parser, run_export};
+use cli::ph::{run_ph_client, run_ph_sensor, PhClientApp, PhSensorApp};
+use cli::shell::{run_shell, shell_cli_parser};
+use cli::temperature::{
+    run_temperature_client, run_temperature_sensor, TemperatureClientApp, TemperatureSensorApp,
+};
 use errors::*;
+use ezo::conductivity::ConductivitySensor;
+use ezo::config::SensorKind;
+use ezo::network::conductivity::{ConductivityRequester, ConductivityResponder};
+use ezo::network::ph::{PhRequester, PhResponder};
+use ezo::network::publisher::{ConductivitySource, PhSource, SensorPublisher, TemperatureSource};
+use ezo::network::subscriber::Subscriber;
+use ezo::network::temperature::{TemperatureRequester, TemperatureResponder};
+use ezo::node::{serve_requests, Node, NodeOverrides};
+use ezo::ph::PhSensor;
+use ezo::temperature::TemperatureSensor;
+use utilities::{create_and_bind_responder, create_and_connect_timed_requester, send_request};
 
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
-/// A parser for `benita` sensor network sub-commands.
-pub fn sensor_network_cli_parser(matches: &ArgMatches) -> Result<()> {
-    let _netcmd = match matches.subcommand() {
-        ("client", Some(_client_matches)) => {
-            let _subcmd = match _client_matches.subcommand() {
-                ("req", Some(_rep_matches)) => {
-                    println!("REQ!");
-                }
-                ("sub", Some(_sub_matches)) => {
-                    println!("SUB!");
-                }
-                _ => unreachable!(),
-            };
+/// Interval between the readings republished by `server pub`, unless given, in
+/// milliseconds.
+pub const DEFAULT_PUB_INTERVAL: u64 = 10_000;
+
+// Start a responder for the sensor on the given I2C device, and address.
+fn start_responder(kind: &SensorKind, matches: &ArgMatches) -> Result<()> {
+    let path = matches.value_of("i2cdev").unwrap();
+    let address = matches
+        .value_of("device_address")
+        .unwrap()
+        .parse::<u16>()
+        .context(ErrorKind::AddressParse)?;
+    let url = matches.value_of("url").unwrap();
+    let socket = create_and_bind_responder(url)?;
+    info!(
+        "Answering requests for the sensor at {}:{} on {}",
+        path, address, url
+    );
+    let served = match *kind {
+        SensorKind::Conductivity => {
+            let sensor =
+                ConductivitySensor::new(path, address).context(ErrorKind::SensorTrouble)?;
+            let responder =
+                ConductivityResponder::new(socket, sensor).context(ErrorKind::SocketCreate)?;
+            serve_requests(&responder, |req| responder.respond(req))
         }
-        ("server", Some(_server_matches)) => {
-            let _subcmd = match _server_matches.subcommand() {
-                ("rep", Some(_rep_matches)) => {
-                    println!("REP!");
-                }
-                ("pub", Some(_sub_matches)) => {
-                    println!("PUB!");
+        SensorKind::Ph => {
+            let sensor = PhSensor::new(path, address).context(ErrorKind::SensorTrouble)?;
+            let responder = PhResponder::new(socket, sensor).context(ErrorKind::SocketCreate)?;
+            serve_requests(&responder, |req| responder.respond(req))
+        }
+        SensorKind::Temperature => {
+            let sensor = TemperatureSensor::new(path, address).context(ErrorKind::SensorTrouble)?;
+            let responder =
+                TemperatureResponder::new(socket, sensor).context(ErrorKind::SocketCreate)?;
+            serve_requests(&responder, |req| responder.respond(req))
+        }
+    };
+    let _serve = served.context(ErrorKind::Neurotic)?;
+    Ok(())
+}
+
+// Start a publisher for the readings of the responder at `rep-url`.
+fn start_publisher(kind: &SensorKind, matches: &ArgMatches) -> Result<()> {
+    let rep_url = matches.value_of("rep-url").unwrap();
+    let pub_url = matches.value_of("pub-url").unwrap();
+    let channel = matches.value_of("channel").unwrap();
+    let interval = match matches.value_of("interval") {
+        Some(ms) => ms.parse::<u64>().context(ErrorKind::NumberParse)?,
+        None => DEFAULT_PUB_INTERVAL,
+    };
+    let interval = Duration::from_millis(interval);
+    // Readings that time out are skipped, and the next ones are requested on time.
+    let socket = create_and_connect_timed_requester(rep_url)?;
+    let _relaxed = socket
+        .set_req_relaxed(true)
+        .context(ErrorKind::SocketCreate)?;
+    let _correlate = socket
+        .set_req_correlate(true)
+        .context(ErrorKind::SocketCreate)?;
+    let mut publisher = SensorPublisher::bind(pub_url).context(ErrorKind::SocketBind)?;
+    match *kind {
+        SensorKind::Conductivity => {
+            let requester = ConductivityRequester::new(socket).context(ErrorKind::SocketCreate)?;
+            publisher.add_sensor(channel, ConductivitySource(requester), interval, false);
+        }
+        SensorKind::Ph => {
+            let requester = PhRequester::new(socket).context(ErrorKind::SocketCreate)?;
+            publisher.add_sensor(channel, PhSource(requester), interval, false);
+        }
+        SensorKind::Temperature => {
+            let requester = TemperatureRequester::new(socket).context(ErrorKind::SocketCreate)?;
+            publisher.add_sensor(channel, TemperatureSource(requester), interval, false);
+        }
+    }
+    info!(
+        "Publishing the readings from {} on {}, as {}",
+        rep_url, pub_url, channel
+    );
+    let _run = publisher.run().context(ErrorKind::SocketSend)?;
+    Ok(())
+}
+
+// Send the request given in the command-line, or every line read from stdin, and print
+// their replies. A request from stdin that times out is reported, and the next one is sent
+// from a new requester.
+fn start_requester(matches: &ArgMatches) -> Result<()> {
+    let url = matches.value_of("url").unwrap();
+    let mut socket = create_and_connect_timed_requester(url)?;
+    if let Some(words) = matches.values_of("request") {
+        let request = words.collect::<Vec<&str>>().join(" ");
+        println!("{}", send_request(&socket, &request)?);
+        return Ok(());
+    }
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    loop {
+        print!("> ");
+        let _flush = stdout.flush().context(ErrorKind::SocketSend)?;
+        let mut line = String::new();
+        let read = stdin
+            .lock()
+            .read_line(&mut line)
+            .context(ErrorKind::CommandParse)?;
+        match line.trim() {
+            _ if read == 0 => return Ok(()),
+            "" => continue,
+            "exit" | "quit" => return Ok(()),
+            request => match send_request(&socket, request) {
+                Ok(reply) => println!("{}", reply),
+                Err(ref e) if e.kind() == ErrorKind::ServiceTimeout => {
+                    println!("error: {}", e);
+                    socket = create_and_connect_timed_requester(url)?;
                 }
-                _ => unreachable!(),
-            };
+                Err(e) => return Err(e),
+            },
+        }
+    }
+}
+
+// Print the readings published on `channel`, for as long as the program runs.
+fn start_subscriber(matches: &ArgMatches) -> Result<()> {
+    let url = matches.value_of("url").unwrap();
+    let channel = matches.value_of("channel").unwrap();
+    let mut subscriber = Subscriber::connect(url, &[channel]).context(ErrorKind::SocketConnect)?;
+    loop {
+        match subscriber.recv() {
+            Ok(publication) => println!("{}", publication),
+            Err(e) => warn!("skipping publication: {}", e),
         }
+    }
+}
+
+/// A parser for `benita` sensor network sub-commands.
+pub fn sensor_network_cli_parser(kind: &SensorKind, matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        ("client", Some(client_matches)) => match client_matches.subcommand() {
+            ("req", Some(req_matches)) => start_requester(req_matches),
+            ("sub", Some(sub_matches)) => start_subscriber(sub_matches),
+            _ => unreachable!(),
+        },
+        ("server", Some(server_matches)) => match server_matches.subcommand() {
+            ("rep", Some(rep_matches)) => start_responder(kind, rep_matches),
+            ("pub", Some(pub_matches)) => start_publisher(kind, pub_matches),
+            _ => unreachable!(),
+        },
         _ => unreachable!(),
+    }
+}
+
+// Execute a sensor command, and print its reply.
+fn run_sensor_command(kind: &SensorKind, local: bool, matches: &ArgMatches) -> Result<()> {
+    let reply = match (kind, local) {
+        (&SensorKind::Conductivity, false) => run_conductivity_client(matches)?,
+        (&SensorKind::Conductivity, true) => run_conductivity_sensor(matches)?,
+        (&SensorKind::Ph, false) => run_ph_client(matches)?,
+        (&SensorKind::Ph, true) => run_ph_sensor(matches)?,
+        (&SensorKind::Temperature, false) => run_temperature_client(matches)?,
+        (&SensorKind::Temperature, true) => run_temperature_sensor(matches)?,
     };
+    println!("{}", reply);
     Ok(())
 }
 
 /// A parser for `benita` sensor sub-commands.
-pub fn sensor_cli_parser(matches: &ArgMatches) -> Result<()> {
-    let _parse_matches = match matches.subcommand() {
-        ("network", Some(net_matches)) => {
-            let _netcmd = sensor_network_cli_parser(net_matches)?;
-        }
+pub fn sensor_cli_parser(kind: &SensorKind, matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        ("client", Some(client_matches)) => run_sensor_command(kind, false, client_matches),
+        ("network", Some(net_matches)) => sensor_network_cli_parser(kind, net_matches),
+        ("sensor", Some(sensor_matches)) => run_sensor_command(kind, true, sensor_matches),
         _ => unreachable!(),
+    }
+}
+
+/// Prefix of the environment variables that override the settings of `benita run` nodes,
+/// e.g. `BENITA_SENSORS_PH1_ADDRESS`.
+pub const NODE_ENV_PREFIX: &str = "BENITA";
+
+// Bring up the node declared by the `--config` file, with the settings overridden by
+// `BENITA_*` variables, and `--set` flags, and run it.
+fn run_node(matches: &ArgMatches) -> Result<()> {
+    let path = matches.value_of("config").unwrap();
+    let strict = matches.is_present("strict");
+    let mut overrides = NodeOverrides {
+        env_prefix: Some(NODE_ENV_PREFIX.to_string()),
+        settings: Vec::new(),
     };
+    if let Some(settings) = matches.values_of("set") {
+        for setting in settings {
+            let mut parts = setting.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => overrides
+                    .settings
+                    .push((key.to_string(), value.to_string())),
+                _ => return Err(ErrorKind::ConfigParse.into()),
+            }
+        }
+    }
+    if matches.is_present("print-config") {
+        let layers = overrides.layers(path).context(ErrorKind::ConfigParse)?;
+        print!("{}", layers.to_toml().context(ErrorKind::ConfigParse)?);
+        return Ok(());
+    }
+    let mut node = Node::with_overrides(path, strict, overrides).context(ErrorKind::ConfigParse)?;
+    let _run = node.run().context(ErrorKind::Neurotic)?;
     Ok(())
 }
 
+/// Run the `benita` command-line application, with the matches of `benita_cli_parser`.
+pub fn benita_cli(matches: &ArgMatches) -> Result<()> {
+    match matches.subcommand() {
+        ("conductivity", Some(sensor_matches)) => {
+            sensor_cli_parser(&SensorKind::Conductivity, sensor_matches)
+        }
+        ("export", Some(export_matches)) => run_export(export_matches),
+        ("ph", Some(sensor_matches)) => sensor_cli_parser(&SensorKind::Ph, sensor_matches),
+        ("run", Some(run_matches)) => run_node(run_matches),
+        ("shell", Some(shell_matches)) => run_shell(shell_matches),
+        ("temperature", Some(sensor_matches)) => {
+            sensor_cli_parser(&SensorKind::Temperature, sensor_matches)
+        }
+        _ => unreachable!(),
+    }
+}
+
 /// `benita` command-line application for sensor and network services.
 pub fn benita_cli_parser<'a, 'b>() -> App<'a, 'b> {
     // Network Client subcommands
@@ -59,6 +266,12 @@ pub fn benita_cli_parser<'a, 'b>() -> App<'a, 'b> {
                         .help("URL to make requests to")
                         .required(true)
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("request")
+                        .help("request to send, e.g. `read` (default: read requests from stdin)")
+                        .multiple(true)
+                        .takes_value(true),
                 ),
         )
         .subcommand(
@@ -127,6 +340,14 @@ pub fn benita_cli_parser<'a, 'b>() -> App<'a, 'b> {
                         .help("channel for the publishing")
                         .required(true)
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .short("i")
+                        .long("interval")
+                        .value_name("MILLISECONDS")
+                        .help("time between readings (default: 10000)")
+                        .takes_value(true),
                 ),
         );
 
@@ -141,24 +362,65 @@ pub fn benita_cli_parser<'a, 'b>() -> App<'a, 'b> {
         .version("0.1.0")
         .author("Joaquin R. <globojorro@gmail.com>")
         .about("Command-line interface for managing benita services.")
+        .settings(&[AppSettings::SubcommandRequiredElseHelp])
         .subcommand(
             SubCommand::with_name("conductivity")
                 .about("Control the conductivity sensor")
                 .settings(&[AppSettings::SubcommandRequiredElseHelp])
-                .subcommand(network_cmd.clone()),
+                .subcommand(ConductivityClientApp::new())
+                .subcommand(network_cmd.clone())
+                .subcommand(ConductivitySensorApp::new()),
         )
         .subcommand(
             SubCommand::with_name("temperature")
                 .about("Control the temperature sensor")
                 .settings(&[AppSettings::SubcommandRequiredElseHelp])
-                .subcommand(network_cmd.clone()),
+                .subcommand(TemperatureClientApp::new())
+                .subcommand(network_cmd.clone())
+                .subcommand(TemperatureSensorApp::new()),
         )
         .subcommand(
             SubCommand::with_name("ph")
                 .about("Control the pH sensor")
                 .settings(&[AppSettings::SubcommandRequiredElseHelp])
-                .subcommand(network_cmd.clone()),
+                .subcommand(PhClientApp::new())
+                .subcommand(network_cmd.clone())
+                .subcommand(PhSensorApp::new()),
         )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Bring up a whole sensor node, as declared by its config file")
+                .arg(
+                    Arg::with_name("config")
+                        .short("c")
+                        .long("config")
+                        .value_name("FILE")
+                        .help("Node config file, e.g. `node.toml`")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("set")
+                        .long("set")
+                        .value_name("KEY=VALUE")
+                        .help("Overrides a setting, e.g. `sensors.ph1.address=100`")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("strict")
+                        .long("strict")
+                        .help("Rejects unknown keys, and settings that make no sense"),
+                )
+                .arg(
+                    Arg::with_name("print-config")
+                        .long("print-config")
+                        .help("Prints the effective settings, and exits"),
+                ),
+        )
+        .subcommand(shell_cli_parser())
+        .subcommand(export_cli_parser())
 }
 
 /// `benita-calibrated-service` command-line application for networked sensor services.
@@ -202,3 +464,136 @@ pub fn benita_calibrated_service_cli_parser<'a, 'b>() -> App<'a, 'b> {
                 .help("Turn debugging information on"),
         )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sensor_network_commands() {
+        let matches = benita_cli_parser()
+            .get_matches_from_safe(vec![
+                "benita",
+                "ph",
+                "network",
+                "client",
+                "req",
+                "tcp://localhost:5558",
+                "calibration-mid",
+                "7.00",
+            ])
+            .unwrap();
+        let req_matches = matches
+            .subcommand_matches("ph")
+            .and_then(|m| m.subcommand_matches("network"))
+            .and_then(|m| m.subcommand_matches("client"))
+            .and_then(|m| m.subcommand_matches("req"))
+            .unwrap();
+        assert_eq!(Some("tcp://localhost:5558"), req_matches.value_of("url"));
+        let request: Vec<&str> = req_matches.values_of("request").unwrap().collect();
+        assert_eq!(vec!["calibration-mid", "7.00"], request);
+
+        let matches = benita_cli_parser()
+            .get_matches_from_safe(vec![
+                "benita",
+                "temperature",
+                "network",
+                "server",
+                "pub",
+                "tcp://localhost:5558",
+                "tcp://*:5557",
+                "temperature-0123456789abcdef",
+                "--interval",
+                "5000",
+            ])
+            .unwrap();
+        let pub_matches = matches
+            .subcommand_matches("temperature")
+            .and_then(|m| m.subcommand_matches("network"))
+            .and_then(|m| m.subcommand_matches("server"))
+            .and_then(|m| m.subcommand_matches("pub"))
+            .unwrap();
+        assert_eq!(Some("5000"), pub_matches.value_of("interval"));
+
+        // Responders need a device, and an address.
+        assert!(benita_cli_parser()
+            .get_matches_from_safe(vec![
+                "benita",
+                "conductivity",
+                "network",
+                "server",
+                "rep",
+                "/dev/i2c-1",
+            ])
+            .is_err());
+    }
+
+    #[test]
+    fn parse_sensor_commands() {
+        let matches = benita_cli_parser()
+            .get_matches_from_safe(vec![
+                "benita",
+                "conductivity",
+                "client",
+                "tcp://localhost:5558",
+                "output",
+                "tds",
+                "off",
+            ])
+            .unwrap();
+        let client_matches = matches
+            .subcommand_matches("conductivity")
+            .and_then(|m| m.subcommand_matches("client"))
+            .unwrap();
+        assert_eq!(Some("tcp://localhost:5558"), client_matches.value_of("URL"));
+        assert_eq!(Some("output"), client_matches.subcommand_name());
+
+        let matches = benita_cli_parser()
+            .get_matches_from_safe(vec![
+                "benita",
+                "ph",
+                "sensor",
+                "/dev/i2c-1",
+                "99",
+                "compensation",
+                "set",
+                "25.0",
+            ])
+            .unwrap();
+        let sensor_matches = matches
+            .subcommand_matches("ph")
+            .and_then(|m| m.subcommand_matches("sensor"))
+            .unwrap();
+        assert_eq!(Some("99"), sensor_matches.value_of("ADDRESS"));
+        assert_eq!(Some("compensation"), sensor_matches.subcommand_name());
+
+        let matches = benita_cli_parser()
+            .get_matches_from_safe(vec!["benita", "shell", "tcp://localhost:5558", "-s", "ph"])
+            .unwrap();
+        let shell_matches = matches.subcommand_matches("shell").unwrap();
+        assert_eq!(Some("ph"), shell_matches.value_of("sensor"));
+        assert!(benita_cli_parser()
+            .get_matches_from_safe(vec!["benita", "shell", "tcp://localhost:5558", "-s", "orp"])
+            .is_err());
+    }
+
+    #[test]
+    fn parse_run_command() {
+        let matches = benita_cli_parser()
+            .get_matches_from_safe(vec!["benita", "run", "--config", "node.toml"])
+            .unwrap();
+        let run_matches = matches.subcommand_matches("run").unwrap();
+        assert_eq!(Some("node.toml"), run_matches.value_of("config"));
+        assert!(!run_matches.is_present("strict"));
+        let matches = benita_cli_parser()
+            .get_matches_from_safe(vec!["benita", "run", "-c", "node.toml", "--strict"])
+            .unwrap();
+        assert!(matches
+            .subcommand_matches("run")
+            .unwrap()
+            .is_present("strict"));
+        assert!(benita_cli_parser()
+            .get_matches_from_safe(vec!["benita", "run"])
+            .is_err());
+    }
+}
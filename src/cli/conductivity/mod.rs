@@ -1,10 +1,21 @@
 //! Command-line parsers for `Conductivity` services.
 pub mod subcommands;
 
-use cli::shared::is_url;
+use cli::shared::{float_value, i2c_args, i2c_values, is_url, reply_string, run_chip_command};
+use errors::*;
+use ezo::common_ezo::EzoChipAPI;
+use ezo::conductivity::{ConductivityAPI, ConductivitySensor};
+use ezo::errors::Error as EzoError;
+use ezo::network::conductivity::requests::{
+    CalibrationClear, CalibrationDry, CalibrationHigh, CalibrationLow, CalibrationOnePoint,
+};
+use ezo::network::conductivity::ConductivityRequester;
+use ezo::network::session::SessionRequester;
+use ezo::network::{ReplyStatus, SocketRequest};
 use self::subcommands::*;
+use utilities::create_and_connect_timed_requester;
 
-use clap::{App, AppSettings, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
 /// Main command-line interface.
 pub struct ConductivityApp;
@@ -26,6 +37,7 @@ impl ConductivitySocketApp {
         ConductivityApp::new()
             .subcommand(ConductivityServerApp::new())
             .subcommand(ConductivityClientApp::new())
+            .subcommand(ConductivitySensorApp::new())
     }
 }
 
@@ -107,6 +119,37 @@ impl ConductivityClientApp {
                     .validator(is_url)
                     .conflicts_with_all(&["config"]),
             )
+            .arg(
+                Arg::with_name("session")
+                    .long("session")
+                    .value_name("TOKEN")
+                    .help("Sends calibration commands in the session of TOKEN, see `session-lock`")
+                    .takes_value(true),
+            )
+            .subcommands(vec![
+                ConductivityCalibrationCommand::new(),
+                ConductivityCompensationCommand::new(),
+                ConductivityOutputParamsCommand::new(),
+                ConductivityProbeTypeCommand::new(),
+                ConductivityDeviceCommand::new(),
+                ConductivityFindCommand::new(),
+                ConductivityLedCommand::new(),
+                ConductivityProtocolLockCommand::new(),
+                ConductivityReadCommand::new(),
+                ConductivitySleepCommand::new(),
+            ])
+    }
+}
+
+/// Conductivity Sensor command-line interface, for a sensor on a local I2C bus.
+pub struct ConductivitySensorApp;
+
+impl ConductivitySensorApp {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("sensor")
+            .about("Local I2C sensor")
+            .settings(&[AppSettings::DisableHelpSubcommand])
+            .args(&i2c_args())
             .subcommands(vec![
                 ConductivityCalibrationCommand::new(),
                 ConductivityCompensationCommand::new(),
@@ -122,6 +165,108 @@ impl ConductivityClientApp {
     }
 }
 
+/// Executes the conductivity command in `matches`, on anything that implements the
+/// `ConductivityAPI`, and returns the text form of its reply.
+pub fn run_conductivity_command<T>(sensor: &T, matches: &ArgMatches) -> Result<String>
+where
+    T: ConductivityAPI<Error = EzoError, DefaultReply = ReplyStatus>
+        + EzoChipAPI<SensorError = EzoError, SensorReply = ReplyStatus>,
+{
+    if let Some(reply) = run_chip_command(sensor, matches)? {
+        return Ok(reply);
+    }
+    match matches.subcommand() {
+        ("calibration", Some(m)) => match m.subcommand() {
+            ("status", _) => reply_string(sensor.get_calibration_status()),
+            ("clear", _) => reply_string(sensor.set_calibration_clear()),
+            ("dry", _) => reply_string(sensor.set_calibration_dry()),
+            ("high", Some(cal)) => {
+                reply_string(sensor.set_calibration_high(float_value(cal, "CAL")?))
+            }
+            ("low", Some(cal)) => {
+                reply_string(sensor.set_calibration_low(float_value(cal, "CAL")?))
+            }
+            ("single", Some(cal)) => {
+                reply_string(sensor.set_calibration_single(float_value(cal, "CAL")?))
+            }
+            _ => unreachable!(),
+        },
+        ("compensation", Some(m)) => match m.subcommand() {
+            ("set", Some(temp)) => {
+                reply_string(sensor.set_compensation(float_value(temp, "VALUE")?))
+            }
+            _ => reply_string(sensor.get_compensation()),
+        },
+        ("output", Some(m)) => {
+            let on = |param: &ArgMatches| param.subcommand_name() == Some("on");
+            match m.subcommand() {
+                ("ec", Some(p)) if on(p) => reply_string(sensor.set_output_conductivity_on()),
+                ("ec", _) => reply_string(sensor.set_output_conductivity_off()),
+                ("salinity", Some(p)) if on(p) => reply_string(sensor.set_output_salinity_on()),
+                ("salinity", _) => reply_string(sensor.set_output_salinity_off()),
+                ("sg", Some(p)) if on(p) => reply_string(sensor.set_output_specific_gravity_on()),
+                ("sg", _) => reply_string(sensor.set_output_specific_gravity_off()),
+                ("tds", Some(p)) if on(p) => reply_string(sensor.set_output_tds_on()),
+                ("tds", _) => reply_string(sensor.set_output_tds_off()),
+                _ => reply_string(sensor.get_output_params()),
+            }
+        }
+        ("probe-type", Some(m)) => match m.value_of("probe").unwrap() {
+            "0.1" => reply_string(sensor.set_probe_type_point_one()),
+            "1.0" => reply_string(sensor.set_probe_type_one()),
+            "10.0" => reply_string(sensor.set_probe_type_ten()),
+            _ => reply_string(sensor.get_probe_type_status()),
+        },
+        ("read", _) => reply_string(sensor.get_reading()),
+        _ => Err(ErrorKind::CommandParse.into()),
+    }
+}
+
+// The request of a command that changes the calibration, and needs the session lock of the
+// sensor, if `matches` has one.
+fn calibration_request(matches: &ArgMatches) -> Result<Option<String>> {
+    let request = match matches.subcommand() {
+        ("calibration", Some(m)) => match m.subcommand() {
+            ("clear", _) => SocketRequest::to_string(&CalibrationClear),
+            ("dry", _) => SocketRequest::to_string(&CalibrationDry),
+            ("high", Some(cal)) => {
+                SocketRequest::to_string(&CalibrationHigh(float_value(cal, "CAL")?))
+            }
+            ("low", Some(cal)) => {
+                SocketRequest::to_string(&CalibrationLow(float_value(cal, "CAL")?))
+            }
+            ("single", Some(cal)) => {
+                SocketRequest::to_string(&CalibrationOnePoint(float_value(cal, "CAL")?))
+            }
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(request))
+}
+
+/// Executes the command in the matches of `ConductivityClientApp`, through a
+/// `ConductivityRequester`. With `--session`, calibration commands are sent in the session
+/// of its token, through a `SessionRequester`.
+pub fn run_conductivity_client(matches: &ArgMatches) -> Result<String> {
+    let socket = create_and_connect_timed_requester(matches.value_of("URL").unwrap())?;
+    let session = matches.value_of("session");
+    if let (Some(token), Some(request)) = (session, calibration_request(matches)?) {
+        let requester = SessionRequester::new(socket).context(ErrorKind::SocketCreate)?;
+        return reply_string(requester.send_as(token, &request));
+    }
+    let requester = ConductivityRequester::new(socket).context(ErrorKind::SocketCreate)?;
+    run_conductivity_command(&requester, matches)
+}
+
+/// Executes the command in the matches of `ConductivitySensorApp`, on the local
+/// `ConductivitySensor`.
+pub fn run_conductivity_sensor(matches: &ArgMatches) -> Result<String> {
+    let (path, address) = i2c_values(matches)?;
+    let sensor = ConductivitySensor::new(path, address).context(ErrorKind::SensorTrouble)?;
+    run_conductivity_command(&sensor, matches)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -161,6 +306,33 @@ mod tests {
         assert!(matches.is_ok());
     }
 
+    #[test]
+    fn parsing_client_calibration_commands_in_a_session() {
+        let cli_app = ConductivitySocketApp::new();
+        let arg_vec = vec![
+            "conductivity",
+            "client",
+            "ipc://server",
+            "--session",
+            "0123456789abcdef",
+            "calibration",
+            "dry",
+        ];
+        let matches = cli_app.get_matches_from_safe(arg_vec).unwrap();
+        let client_matches = matches.subcommand_matches("client").unwrap();
+        assert_eq!(Some("0123456789abcdef"), client_matches.value_of("session"));
+        assert_eq!(
+            Some("calibration-dry".to_string()),
+            calibration_request(client_matches).unwrap()
+        );
+
+        let cli_app = ConductivitySocketApp::new();
+        let arg_vec = vec!["conductivity", "client", "ipc://server", "--session", "0123", "read"];
+        let matches = cli_app.get_matches_from_safe(arg_vec).unwrap();
+        let client_matches = matches.subcommand_matches("client").unwrap();
+        assert_eq!(None, calibration_request(client_matches).unwrap());
+    }
+
     #[test]
     fn parsing_invalid_client_cli_input_yields_err() {
         let mut cli_app = ConductivitySocketApp::new();
@@ -183,6 +355,28 @@ mod tests {
     }
 
     // Tests for the command-line app.
+    #[test]
+    fn parsing_valid_sensor_cli_input() {
+        let cli_app = ConductivitySocketApp::new();
+        let arg_vec = vec!["conductivity", "sensor", "/dev/i2c-1", "100", "output", "tds", "on"];
+        let matches = cli_app.get_matches_from_safe(arg_vec).unwrap();
+        let sensor_matches = matches.subcommand_matches("sensor").unwrap();
+        assert_eq!(("/dev/i2c-1", 100), i2c_values(sensor_matches).unwrap());
+    }
+
+    #[test]
+    fn parsing_invalid_sensor_cli_input_yields_err() {
+        let mut cli_app = ConductivitySocketApp::new();
+
+        let arg_vec = vec!["conductivity", "sensor", "/dev/i2c-1", "read"];
+        let matches = cli_app.get_matches_from_safe_borrow(arg_vec);
+        assert!(matches.is_err());
+
+        let arg_vec = vec!["conductivity", "sensor", "/dev/i2c-1", "100", "output", "tds"];
+        let matches = cli_app.get_matches_from_safe_borrow(arg_vec);
+        assert!(matches.is_err());
+    }
+
     #[test]
     fn parsing_valid_command_line_input() {
         let mut cli_app = ConductivityCommandApp::new();
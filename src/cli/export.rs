@@ -0,0 +1,258 @@
+//! Export of stored, or live, measurements as CSV, or JSON lines.
+//!
+//! `benita export --store <DIR>` writes the measurements of a measurement log, over a time
+//! range, to stdout, e.g.
+//!
+//! ```text
+//! benita export --format jsonl --store /var/lib/benita --from 2017-11-18T00:00:00Z
+//! ```
+//!
+//! and `benita export --sub <URL>` writes the measurements published on a PUB socket, for as
+//! long as the program runs, e.g.
+//!
+//! ```text
+//! benita export --columns timestamp,EC,TDS --tz -06:00 --sub tcp://192.168.16.123:5556
+//! ```
+//!
+//! Conductivity readings are split into a column for each of the chip's output parameters,
+//! set with `--output-params`, unless the columns are given with `--columns`.
+use std::io;
+use std::time::Duration;
+
+use cli::is_url;
+use errors::*;
+use ezo::errors::{ErrorKind as EzoErrorKind, Result as EzoResult};
+use ezo::export::{export_range, Column, ExportOptions, Exporter};
+use ezo::measurement::SensorType;
+use ezo::network::subscriber::Subscriber;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+// The export settings of the command-line arguments.
+fn export_options(matches: &ArgMatches) -> EzoResult<ExportOptions> {
+    let columns = match matches.value_of("columns") {
+        Some(columns) => Column::parse_list(columns)?,
+        None => Column::defaults(matches.value_of("output-params").unwrap())?,
+    };
+    let precision = match matches.value_of("precision") {
+        Some(p) => Some(p.parse::<usize>().context(EzoErrorKind::InvalidExportOption(
+            "the precision is not a number".to_string(),
+        ))?),
+        None => None,
+    };
+    Ok(ExportOptions {
+        format: matches.value_of("format").unwrap().parse()?,
+        columns,
+        time_zone: matches.value_of("tz").unwrap().parse()?,
+        precision,
+    })
+}
+
+// The value of a timestamp argument, e.g. `2017-11-18T00:00:00Z`.
+fn timestamp_value(matches: &ArgMatches, name: &str) -> EzoResult<Option<DateTime<Utc>>> {
+    match matches.value_of(name) {
+        Some(t) => {
+            let timestamp = t
+                .parse::<DateTime<Utc>>()
+                .context(EzoErrorKind::InvalidExportOption(format!(
+                    "bad timestamp {:?}",
+                    t
+                )))?;
+            Ok(Some(timestamp))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Export the measurements of a measurement log, or of a publisher, with the matches of
+/// `export_cli_parser`.
+pub fn run_export(matches: &ArgMatches) -> Result<()> {
+    let options = export_options(matches).context(ErrorKind::MeasurementExport)?;
+    let topics: Vec<&str> = match matches.values_of("topic") {
+        Some(topics) => topics.collect(),
+        None => Vec::new(),
+    };
+    let stdout = io::stdout();
+    let mut exporter = Exporter::new(stdout.lock(), options);
+
+    if let Some(dir) = matches.value_of("store") {
+        let from = match timestamp_value(matches, "from").context(ErrorKind::MeasurementExport)? {
+            Some(from) => from,
+            None => DateTime::<Utc>::from_utc(NaiveDateTime::from_timestamp(0, 0), Utc),
+        };
+        let to = match timestamp_value(matches, "to").context(ErrorKind::MeasurementExport)? {
+            Some(to) => to,
+            None => Utc::now(),
+        };
+        let rows = export_range(dir, &from, &to, &topics, &mut exporter)
+            .context(ErrorKind::MeasurementExport)?;
+        info!("Exported {} rows", rows);
+        return Ok(());
+    }
+    let url = matches.value_of("sub").unwrap();
+    let topics = if topics.is_empty() {
+        SensorType::all().iter().map(|t| t.name()).collect()
+    } else {
+        topics
+    };
+    let mut subscriber = Subscriber::connect(url, &topics).context(ErrorKind::SocketConnect)?;
+    subscriber.set_reconnect_timeout(Some(Duration::from_secs(60)));
+    info!("Exporting publications from {}", url);
+    let _follow = exporter
+        .follow(&mut subscriber)
+        .context(ErrorKind::MeasurementExport)?;
+    Ok(())
+}
+
+/// `benita export` sub-command, for stored, or live, measurements.
+pub fn export_cli_parser<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("export")
+        .about("export stored, or live, measurements as CSV or JSON lines")
+        .arg(
+            Arg::with_name("store")
+                .long("store")
+                .value_name("DIR")
+                .help("Exports the measurements stored in the measurement log in DIR")
+                .takes_value(true)
+                .required_unless("sub")
+                .conflicts_with("sub"),
+        )
+        .arg(
+            Arg::with_name("sub")
+                .long("sub")
+                .value_name("SUB_URL")
+                .help("Exports the measurements published at SUB_URL, as they arrive")
+                .takes_value(true)
+                .validator(is_url),
+        )
+        .arg(
+            Arg::with_name("from")
+                .long("from")
+                .value_name("TIMESTAMP")
+                .help("Sets the start of the stored range (default: the first measurement)")
+                .takes_value(true)
+                .requires("store"),
+        )
+        .arg(
+            Arg::with_name("to")
+                .long("to")
+                .value_name("TIMESTAMP")
+                .help("Sets the end of the stored range (default: now)")
+                .takes_value(true)
+                .requires("store"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Sets the export format")
+                .possible_values(&["csv", "jsonl"])
+                .default_value("csv")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("columns")
+                .short("c")
+                .long("columns")
+                .value_name("COLUMNS")
+                .help("Sets the columns, e.g. timestamp,sensor,type,EC,TDS,S,SG,pH,temperature")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output-params")
+                .long("output-params")
+                .value_name("PARAMS")
+                .help("Sets the output parameters of the conductivity sensors")
+                .default_value("EC,TDS,S,SG")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tz")
+                .long("tz")
+                .value_name("TIME_ZONE")
+                .help("Sets the time zone of timestamps: utc, local, or an offset like -06:00")
+                .default_value("utc")
+                .allow_hyphen_values(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("precision")
+                .short("p")
+                .long("precision")
+                .value_name("DECIMALS")
+                .help("Sets the number of decimals of the values")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("topic")
+                .short("t")
+                .long("topic")
+                .value_name("TOPIC")
+                .help("Exports the measurements of these topics only, e.g. ph-")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ezo::export::{Format, TimeZone};
+
+    #[test]
+    fn parse_export_options() {
+        let matches = export_cli_parser()
+            .get_matches_from_safe(vec![
+                "export",
+                "--store",
+                "/var/lib/benita",
+                "--from",
+                "2017-11-18T00:00:00Z",
+                "--format",
+                "jsonl",
+                "--columns",
+                "timestamp,EC,TDS",
+                "-p",
+                "2",
+            ])
+            .unwrap();
+        assert_eq!(Some("/var/lib/benita"), matches.value_of("store"));
+        let options = export_options(&matches).unwrap();
+        assert_eq!(Format::JsonLines, options.format);
+        assert_eq!(
+            vec![
+                Column::Timestamp,
+                Column::Field(SensorType::Conductivity),
+                Column::Field(SensorType::TotalDissolvedSolids),
+            ],
+            options.columns
+        );
+        assert_eq!(TimeZone::Utc, options.time_zone);
+        assert_eq!(Some(2), options.precision);
+        assert!(timestamp_value(&matches, "from").unwrap().is_some());
+        assert_eq!(None, timestamp_value(&matches, "to").unwrap());
+
+        let matches = export_cli_parser()
+            .get_matches_from_safe(vec!["export", "--sub", "tcp://localhost:5557"])
+            .unwrap();
+        assert_eq!(Some("tcp://localhost:5557"), matches.value_of("sub"));
+        assert_eq!(Format::Csv, export_options(&matches).unwrap().format);
+
+        for args in &[
+            vec!["export"],
+            vec!["export", "--store", "logs", "--sub", "tcp://localhost:5557"],
+            vec!["export", "--sub", "tcp://localhost:5557", "--from", "2017-11-18T00:00:00Z"],
+        ] {
+            assert!(export_cli_parser()
+                .get_matches_from_safe(args.clone())
+                .is_err());
+        }
+        let matches = export_cli_parser()
+            .get_matches_from_safe(vec!["export", "--store", "logs", "--to", "yesterday"])
+            .unwrap();
+        assert!(timestamp_value(&matches, "to").is_err());
+    }
+}
@@ -1,20 +1,10 @@
 //! Command-line interpreter items.
-use utilities::atof;
+pub mod benita;
+pub mod conductivity;
+pub mod export;
+pub mod ph;
+pub mod shared;
+pub mod shell;
+pub mod temperature;
 
-use url::Url;
-
-/// Validator function for URLs.
-pub fn is_url(v: String) -> ::std::result::Result<(), String> {
-    match Url::parse(&v) {
-        Ok(_) => Ok(()),
-        _ => Err("Invalid URL".to_string()),
-    }
-}
-
-/// Validator function for floating point numbers.
-pub fn is_float(v: String) -> ::std::result::Result<(), String> {
-    match atof(&v) {
-        Ok(_) => Ok(()),
-        _ => Err("The value is not numeric.".to_string()),
-    }
-}
+pub use self::shared::{is_float, is_url};
@@ -1,10 +1,21 @@
 //! Command-line parsers for `pH` services.
 pub mod subcommands;
 
-use cli::shared::is_url;
+use cli::shared::{float_value, i2c_args, i2c_values, is_url, reply_string, run_chip_command};
+use errors::*;
+use ezo::common_ezo::EzoChipAPI;
+use ezo::errors::Error as EzoError;
+use ezo::network::ph::requests::{
+    CalibrationClear, CalibrationHigh, CalibrationLow, CalibrationMid,
+};
+use ezo::network::ph::PhRequester;
+use ezo::network::session::SessionRequester;
+use ezo::network::{ReplyStatus, SocketRequest};
+use ezo::ph::{PhAPI, PhSensor};
 use self::subcommands::*;
+use utilities::create_and_connect_timed_requester;
 
-use clap::{App, AppSettings, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
 /// Main command-line interface.
 pub struct PhApp;
@@ -17,6 +28,7 @@ impl PhApp {
             .settings(&[AppSettings::DisableHelpSubcommand])
             .subcommand(PhServerApp::new())
             .subcommand(PhClientApp::new())
+            .subcommand(PhSensorApp::new())
     }
 }
 
@@ -76,6 +88,13 @@ impl PhClientApp {
                     .validator(is_url)
                     .conflicts_with_all(&["config"]),
             )
+            .arg(
+                Arg::with_name("session")
+                    .long("session")
+                    .value_name("TOKEN")
+                    .help("Sends calibration commands in the session of TOKEN, see `session-lock`")
+                    .takes_value(true),
+            )
             .subcommands(vec![
                 PhCalibrationCommand::new(),
                 PhCompensationCommand::new(),
@@ -89,6 +108,27 @@ impl PhClientApp {
     }
 }
 
+/// pH Sensor command-line interface, for a sensor on a local I2C bus.
+pub struct PhSensorApp;
+
+impl PhSensorApp {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("sensor")
+            .about("Local I2C sensor")
+            .settings(&[AppSettings::DisableHelpSubcommand])
+            .args(&i2c_args())
+            .subcommands(vec![
+                PhCalibrationCommand::new(),
+                PhCompensationCommand::new(),
+                PhDeviceCommand::new(),
+                PhFindCommand::new(),
+                PhLedCommand::new(),
+                PhProtocolLockCommand::new(),
+                PhReadCommand::new(),
+                PhSleepCommand::new(),
+            ])
+    }
+}
 
 /// Simple command-line interface.
 pub struct PhCommandApp;
@@ -110,6 +150,85 @@ impl PhCommandApp {
     }
 }
 
+/// Executes the pH command in `matches`, on anything that implements the `PhAPI`, and
+/// returns the text form of its reply.
+pub fn run_ph_command<T>(sensor: &T, matches: &ArgMatches) -> Result<String>
+where
+    T: PhAPI<Error = EzoError, DefaultReply = ReplyStatus>
+        + EzoChipAPI<SensorError = EzoError, SensorReply = ReplyStatus>,
+{
+    if let Some(reply) = run_chip_command(sensor, matches)? {
+        return Ok(reply);
+    }
+    match matches.subcommand() {
+        ("calibration", Some(m)) => match m.subcommand() {
+            ("status", _) => reply_string(sensor.get_calibration_status()),
+            ("clear", _) => reply_string(sensor.set_calibration_clear()),
+            ("high", Some(cal)) => {
+                reply_string(sensor.set_calibration_high(float_value(cal, "CAL")?))
+            }
+            ("mid", Some(cal)) => {
+                reply_string(sensor.set_calibration_mid(float_value(cal, "CAL")?))
+            }
+            ("low", Some(cal)) => {
+                reply_string(sensor.set_calibration_low(float_value(cal, "CAL")?))
+            }
+            _ => unreachable!(),
+        },
+        ("compensation", Some(m)) => match m.subcommand() {
+            ("set", Some(temp)) => {
+                reply_string(sensor.set_compensation(float_value(temp, "TEMP")?))
+            }
+            _ => reply_string(sensor.get_compensation()),
+        },
+        ("read", _) => reply_string(sensor.get_reading()),
+        _ => Err(ErrorKind::CommandParse.into()),
+    }
+}
+
+// The request of a command that changes the calibration, and needs the session lock of the
+// sensor, if `matches` has one.
+fn calibration_request(matches: &ArgMatches) -> Result<Option<String>> {
+    let request = match matches.subcommand() {
+        ("calibration", Some(m)) => match m.subcommand() {
+            ("clear", _) => SocketRequest::to_string(&CalibrationClear),
+            ("high", Some(cal)) => {
+                SocketRequest::to_string(&CalibrationHigh(float_value(cal, "CAL")?))
+            }
+            ("mid", Some(cal)) => {
+                SocketRequest::to_string(&CalibrationMid(float_value(cal, "CAL")?))
+            }
+            ("low", Some(cal)) => {
+                SocketRequest::to_string(&CalibrationLow(float_value(cal, "CAL")?))
+            }
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(request))
+}
+
+/// Executes the command in the matches of `PhClientApp`, through a `PhRequester`. With
+/// `--session`, calibration commands are sent in the session of its token, through a
+/// `SessionRequester`.
+pub fn run_ph_client(matches: &ArgMatches) -> Result<String> {
+    let socket = create_and_connect_timed_requester(matches.value_of("URL").unwrap())?;
+    let session = matches.value_of("session");
+    if let (Some(token), Some(request)) = (session, calibration_request(matches)?) {
+        let requester = SessionRequester::new(socket).context(ErrorKind::SocketCreate)?;
+        return reply_string(requester.send_as(token, &request));
+    }
+    let requester = PhRequester::new(socket).context(ErrorKind::SocketCreate)?;
+    run_ph_command(&requester, matches)
+}
+
+/// Executes the command in the matches of `PhSensorApp`, on the local `PhSensor`.
+pub fn run_ph_sensor(matches: &ArgMatches) -> Result<String> {
+    let (path, address) = i2c_values(matches)?;
+    let sensor = PhSensor::new(path, address).context(ErrorKind::SensorTrouble)?;
+    run_ph_command(&sensor, matches)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -148,6 +267,34 @@ mod tests {
         assert!(matches.is_ok());
     }
 
+    #[test]
+    fn parsing_client_calibration_commands_in_a_session() {
+        let cli_app = PhApp::new();
+        let arg_vec = vec![
+            "ph",
+            "client",
+            "ipc://server",
+            "--session",
+            "0123456789abcdef",
+            "calibration",
+            "mid",
+            "7.00",
+        ];
+        let matches = cli_app.get_matches_from_safe(arg_vec).unwrap();
+        let client_matches = matches.subcommand_matches("client").unwrap();
+        assert_eq!(Some("0123456789abcdef"), client_matches.value_of("session"));
+        assert_eq!(
+            Some("calibration-mid 7.000".to_string()),
+            calibration_request(client_matches).unwrap()
+        );
+
+        let cli_app = PhApp::new();
+        let arg_vec = vec!["ph", "client", "ipc://server", "--session", "0123", "read"];
+        let matches = cli_app.get_matches_from_safe(arg_vec).unwrap();
+        let client_matches = matches.subcommand_matches("client").unwrap();
+        assert_eq!(None, calibration_request(client_matches).unwrap());
+    }
+
     #[test]
     fn parsing_invalid_client_cli_input_yields_err() {
         let mut cli_app = PhApp::new();
@@ -170,6 +317,28 @@ mod tests {
     }
 
     // Tests for the command-line app.
+    #[test]
+    fn parsing_valid_sensor_cli_input() {
+        let cli_app = PhApp::new();
+        let arg_vec = vec!["ph", "sensor", "/dev/i2c-1", "99", "calibration", "mid", "7.00"];
+        let matches = cli_app.get_matches_from_safe(arg_vec).unwrap();
+        let sensor_matches = matches.subcommand_matches("sensor").unwrap();
+        assert_eq!(("/dev/i2c-1", 99), i2c_values(sensor_matches).unwrap());
+    }
+
+    #[test]
+    fn parsing_invalid_sensor_cli_input_yields_err() {
+        let mut cli_app = PhApp::new();
+
+        let arg_vec = vec!["ph", "sensor", "/dev/i2c-1", "read"];
+        let matches = cli_app.get_matches_from_safe_borrow(arg_vec);
+        assert!(matches.is_err());
+
+        let arg_vec = vec!["ph", "sensor", "/dev/i2c-1", "99", "calibration", "mid"];
+        let matches = cli_app.get_matches_from_safe_borrow(arg_vec);
+        assert!(matches.is_err());
+    }
+
     #[test]
     fn parsing_valid_command_line_input() {
         let mut cli_app = PhCommandApp::new();
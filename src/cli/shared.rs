@@ -0,0 +1,304 @@
+//! Command-line items shared by every sensor.
+use std::fmt::Display;
+
+use errors::*;
+use ezo::common_ezo::EzoChipAPI;
+use ezo::errors::{Error as EzoError, ErrorKind as EzoErrorKind, Result as EzoResult};
+use ezo::network::ReplyStatus;
+use utilities::atof;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use url::Url;
+
+/// Validator function for URLs.
+pub fn is_url(v: String) -> ::std::result::Result<(), String> {
+    match Url::parse(&v) {
+        Ok(_) => Ok(()),
+        _ => Err("Invalid URL".to_string()),
+    }
+}
+
+/// Validator function for floating point numbers.
+pub fn is_float(v: String) -> ::std::result::Result<(), String> {
+    match atof(&v) {
+        Ok(_) => Ok(()),
+        _ => Err("The value is not numeric.".to_string()),
+    }
+}
+
+/// Validator function for I2C addresses.
+pub fn is_address(v: String) -> ::std::result::Result<(), String> {
+    match v.parse::<u16>() {
+        Ok(_) => Ok(()),
+        _ => Err("Invalid I2C address".to_string()),
+    }
+}
+
+/// Parses the `clear` subcommand.
+pub struct ClearSubcommand;
+
+impl ClearSubcommand {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("clear")
+            .about("Clear the current settings.")
+            .settings(&[AppSettings::DisableHelpSubcommand])
+    }
+}
+
+/// Parses the `get` subcommand.
+pub struct GetSubcommand;
+
+impl GetSubcommand {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("get")
+            .about("Get the current value.")
+            .settings(&[AppSettings::DisableHelpSubcommand])
+    }
+}
+
+/// Parses the `off` subcommand.
+pub struct OffSubcommand;
+
+impl OffSubcommand {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("off")
+            .about("Set it off.")
+            .settings(&[AppSettings::DisableHelpSubcommand])
+    }
+}
+
+/// Parses the `on` subcommand.
+pub struct OnSubcommand;
+
+impl OnSubcommand {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("on")
+            .about("Set it on.")
+            .settings(&[AppSettings::DisableHelpSubcommand])
+    }
+}
+
+/// Parses the `set VALUE` subcommand, for numeric values.
+pub struct SetSubcommand;
+
+impl SetSubcommand {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("set")
+            .about("Set a new value.")
+            .settings(&[AppSettings::DisableHelpSubcommand])
+            .arg(
+                Arg::with_name("VALUE")
+                    .help("Numeric value up to 3 decimals.")
+                    .takes_value(true)
+                    .validator(is_float)
+                    .required(true),
+            )
+    }
+}
+
+/// Parses the `status` subcommand.
+pub struct StatusSubcommand;
+
+impl StatusSubcommand {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("status")
+            .about("Get the current status.")
+            .settings(&[AppSettings::DisableHelpSubcommand])
+    }
+}
+
+/// Parses the command for getting the sensor information, or status.
+pub struct DeviceCommand;
+
+impl DeviceCommand {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("device")
+            .about("Device information, and status.")
+            .settings(&[
+                AppSettings::DisableHelpSubcommand,
+                AppSettings::SubcommandRequired,
+            ])
+            .subcommand(
+                SubCommand::with_name("info")
+                    .about("Get the device model, and firmware version.")
+                    .settings(&[AppSettings::DisableHelpSubcommand]),
+            )
+            .subcommand(StatusSubcommand::new())
+    }
+}
+
+/// Parses the command for enabling "Find" mode on the sensor.
+pub struct FindCommand;
+
+impl FindCommand {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("find")
+            .about("Find the sensor, by blinking its LED.")
+            .settings(&[AppSettings::DisableHelpSubcommand])
+    }
+}
+
+/// Parses the command for setting the LED on or off on the sensor.
+pub struct LedCommand;
+
+impl LedCommand {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("led")
+            .about("Sensor LED.")
+            .settings(&[
+                AppSettings::DisableHelpSubcommand,
+                AppSettings::SubcommandRequired,
+            ])
+            .subcommand(OffSubcommand::new())
+            .subcommand(OnSubcommand::new())
+            .subcommand(StatusSubcommand::new())
+    }
+}
+
+/// Parses the command for setting the protocol lock on or off on the sensor.
+pub struct ProtocolLockCommand;
+
+impl ProtocolLockCommand {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("protocol-lock")
+            .about("Lock the sensor to I2C mode.")
+            .settings(&[
+                AppSettings::DisableHelpSubcommand,
+                AppSettings::SubcommandRequired,
+            ])
+            .subcommand(OffSubcommand::new())
+            .subcommand(OnSubcommand::new())
+            .subcommand(StatusSubcommand::new())
+    }
+}
+
+/// Parses the command for taking a reading from the sensor.
+pub struct ReadCommand;
+
+impl ReadCommand {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("read")
+            .about("Take a reading.")
+            .settings(&[AppSettings::DisableHelpSubcommand])
+    }
+}
+
+/// Parses the command for putting the sensor to sleep (low-power mode).
+pub struct SleepCommand;
+
+impl SleepCommand {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("sleep")
+            .about("Put the sensor to sleep (low-power mode).")
+            .settings(&[AppSettings::DisableHelpSubcommand])
+    }
+}
+
+/// Parses the positional I2C device path, and address, of a local sensor.
+pub fn i2c_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("I2CDEV")
+            .help("Path to the i2cdev bus, e.g. `/dev/i2c-1`.")
+            .takes_value(true)
+            .index(1)
+            .required(true),
+        Arg::with_name("ADDRESS")
+            .help("I2C device address.")
+            .takes_value(true)
+            .index(2)
+            .required(true)
+            .validator(is_address),
+    ]
+}
+
+/// The I2C device path, and address, given by `i2c_args`.
+pub fn i2c_values<'a>(matches: &'a ArgMatches) -> Result<(&'a str, u16)> {
+    let path = matches.value_of("I2CDEV").unwrap();
+    let address = matches
+        .value_of("ADDRESS")
+        .unwrap()
+        .parse::<u16>()
+        .context(ErrorKind::AddressParse)?;
+    Ok((path, address))
+}
+
+/// The numeric value of the argument `name`.
+pub fn float_value(matches: &ArgMatches, name: &str) -> Result<f64> {
+    atof(matches.value_of(name).unwrap())
+}
+
+/// The text form of a typed reply from the sensor. Replies that time out yield
+/// `ErrorKind::ServiceTimeout`.
+pub fn reply_string<R: Display>(reply: EzoResult<R>) -> Result<String> {
+    let kind = match reply {
+        Err(ref e) if e.kind() == EzoErrorKind::ServiceTimeout => ErrorKind::ServiceTimeout,
+        _ => ErrorKind::CommandRequest,
+    };
+    let reply = reply.context(kind)?;
+    Ok(format!("{}", reply))
+}
+
+// The subcommand of `on|off|status` commands.
+fn switch<'a>(matches: &'a ArgMatches) -> &'a str {
+    matches.subcommand_name().unwrap()
+}
+
+/// Executes the `device`, `find`, `led`, `protocol-lock`, and `sleep` commands, which every
+/// sensor understands, and returns the text form of their reply. Returns `None` for any
+/// other command.
+pub fn run_chip_command<T>(sensor: &T, matches: &ArgMatches) -> Result<Option<String>>
+where
+    T: EzoChipAPI<SensorError = EzoError, SensorReply = ReplyStatus>,
+{
+    let reply = match matches.subcommand() {
+        ("device", Some(m)) => match switch(m) {
+            "info" => reply_string(sensor.get_device_info()),
+            _ => reply_string(sensor.get_device_status()),
+        },
+        ("find", _) => reply_string(sensor.set_find_mode()),
+        ("led", Some(m)) => match switch(m) {
+            "on" => reply_string(sensor.set_led_on()),
+            "off" => reply_string(sensor.set_led_off()),
+            _ => reply_string(sensor.get_led_status()),
+        },
+        ("protocol-lock", Some(m)) => match switch(m) {
+            "on" => reply_string(sensor.set_protocol_lock_on()),
+            "off" => reply_string(sensor.set_protocol_lock_off()),
+            _ => reply_string(sensor.get_protocol_lock_status()),
+        },
+        ("sleep", _) => reply_string(sensor.set_sleep()),
+        _ => return Ok(None),
+    };
+    reply.map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_valid_i2c_args() {
+        let cli_app = App::new("sensor").args(&i2c_args());
+        let matches = cli_app
+            .get_matches_from_safe(vec!["sensor", "/dev/i2c-1", "99"])
+            .unwrap();
+        assert_eq!(("/dev/i2c-1", 99), i2c_values(&matches).unwrap());
+
+        let cli_app = App::new("sensor").args(&i2c_args());
+        let matches = cli_app.get_matches_from_safe(vec!["sensor", "/dev/i2c-1", "x63"]);
+        assert!(matches.is_err());
+    }
+
+    #[test]
+    fn parsing_set_subcommand() {
+        let mut cli_app = App::new("compensation").subcommand(SetSubcommand::new());
+        let matches = cli_app
+            .get_matches_from_safe_borrow(vec!["compensation", "set", "25.5"])
+            .unwrap();
+        let set_matches = matches.subcommand_matches("set").unwrap();
+        assert_eq!(25.5, float_value(set_matches, "VALUE").unwrap());
+
+        let matches = cli_app.get_matches_from_safe_borrow(vec!["compensation", "set", "x"]);
+        assert!(matches.is_err());
+    }
+}
@@ -0,0 +1,405 @@
+//! Interactive shell for the requests of a networked sensor.
+//!
+//! `benita shell <url>` connects a requester to the responder at `url`, and sends it the
+//! requests typed at the prompt, e.g. `calibration-mid 7.00`, showing their typed replies,
+//! or errors. Replies that take longer than `REPLY_TIMEOUT_MS` are reported as errors, and
+//! the requester is replaced. Request names are completed with `TAB`, and `help [REQUEST]`
+//! describes them. Lines are kept in a history file across sessions.
+use std::env;
+use std::path::PathBuf;
+
+use errors::*;
+use ezo::common_ezo::response::DeviceInfo;
+use ezo::config::SensorKind;
+use ezo::errors::{ErrorKind as EzoErrorKind, Result as EzoResult};
+use ezo::network::conductivity::ConductivityRequester;
+use ezo::network::mqtt::discovery::Device;
+use ezo::network::ph::PhRequester;
+use ezo::network::temperature::TemperatureRequester;
+use ezo::network::SocketReply;
+use utilities::{create_and_connect_timed_requester, send_request};
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::Editor;
+use zmq::Socket;
+
+/// Name of the history file, in the home directory, unless given.
+pub const HISTORY_FILE: &str = ".benita_history";
+
+/// Commands understood by the shell itself.
+pub const SHELL_COMMANDS: &[&str] = &["exit", "help", "quit"];
+
+/// A request understood by a sensor, as typed in the shell.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RequestHelp {
+    pub name: &'static str,
+    /// The arguments of the request, if any, e.g. `VALUE`.
+    pub args: &'static str,
+    pub help: &'static str,
+}
+
+macro_rules! request_help {
+    ( $( $name:expr, $args:expr => $help:expr ; )* ) => {
+        &[ $( RequestHelp { name: $name, args: $args, help: $help } ),* ]
+    };
+}
+
+/// Requests understood by every sensor.
+pub const COMMON_REQUESTS: &[RequestHelp] = request_help! {
+    "calibration-clear", "" => "Clear the calibration settings.";
+    "device-address", "ADDRESS" => "Change the I2C address of the sensor.";
+    "device-info", "" => "Get the device model, and firmware version.";
+    "export", "" => "Export the next calibration line.";
+    "export-info", "" => "Get the number of calibration lines to export.";
+    "factory", "" => "Reset the sensor to its factory settings.";
+    "find", "" => "Blink the LED, to find the sensor.";
+    "import", "LINE" => "Import a calibration line.";
+    "led-off", "" => "Turn the LED off.";
+    "led-on", "" => "Turn the LED on.";
+    "led-status", "" => "Get the LED status.";
+    "protocol-lock-off", "" => "Unlock the sensor from I2C mode.";
+    "protocol-lock-on", "" => "Lock the sensor to I2C mode.";
+    "protocol-lock-status", "" => "Get the protocol lock status.";
+    "sleep", "" => "Put the sensor to sleep (low-power mode).";
+    "status", "" => "Get the device status, and voltage.";
+};
+
+/// Requests understood by conductivity sensors.
+pub const CONDUCTIVITY_REQUESTS: &[RequestHelp] = request_help! {
+    "calibration-dry", "" => "Set the calibration dry-point.";
+    "calibration-high", "VALUE" => "Set the calibration high-point, in µS/cm.";
+    "calibration-low", "VALUE" => "Set the calibration low-point, in µS/cm.";
+    "calibration-onepoint", "VALUE" => "Set the single-point calibration, in µS/cm.";
+    "calibration-status", "" => "Get the calibration status.";
+    "compensation-get", "" => "Get the compensation temperature.";
+    "compensation-set", "VALUE" => "Set the compensation temperature, in °C.";
+    "output-conductivity-off", "" => "Leave the conductivity out of readings.";
+    "output-conductivity-on", "" => "Include the conductivity in readings.";
+    "output-salinity-off", "" => "Leave the salinity out of readings.";
+    "output-salinity-on", "" => "Include the salinity in readings.";
+    "output-sg-off", "" => "Leave the specific gravity out of readings.";
+    "output-sg-on", "" => "Include the specific gravity in readings.";
+    "output-status", "" => "Get the parameters included in readings.";
+    "output-tds-off", "" => "Leave the total dissolved solids out of readings.";
+    "output-tds-on", "" => "Include the total dissolved solids in readings.";
+    "probe-type-0.1", "" => "Set the probe type to K 0.1.";
+    "probe-type-1.0", "" => "Set the probe type to K 1.0.";
+    "probe-type-10", "" => "Set the probe type to K 10.";
+    "probe-type-status", "" => "Get the probe type.";
+    "read", "" => "Take a reading.";
+};
+
+/// Requests understood by pH sensors.
+pub const PH_REQUESTS: &[RequestHelp] = request_help! {
+    "calibration-high", "VALUE" => "Set the calibration high-point, in pH.";
+    "calibration-low", "VALUE" => "Set the calibration low-point, in pH.";
+    "calibration-mid", "VALUE" => "Set the calibration mid-point, in pH.";
+    "calibration-status", "" => "Get the calibration status.";
+    "compensation-get", "" => "Get the compensation temperature.";
+    "compensation-set", "VALUE" => "Set the compensation temperature, in °C.";
+    "read", "" => "Take a reading.";
+    "slope", "" => "Get the slope of the probe.";
+};
+
+/// Session requests, understood by sensors whose calibration is guarded by a lock.
+pub const SESSION_REQUESTS: &[RequestHelp] = request_help! {
+    "as", "TOKEN REQUEST" => "Send a request in the session of TOKEN.";
+    "session-heartbeat", "TOKEN" => "Keep the session lock of TOKEN.";
+    "session-lock", "OWNER [SECS]" => "Lock the calibration for OWNER, kept SECS without heartbeats, and get its token.";
+    "session-status", "" => "Get the owner of the session lock.";
+    "session-unlock", "TOKEN" => "Release the session lock of TOKEN.";
+};
+
+/// Requests understood by temperature sensors.
+pub const TEMPERATURE_REQUESTS: &[RequestHelp] = request_help! {
+    "calibration-set", "VALUE" => "Calibrate the sensor to a temperature, on its scale.";
+    "calibration-status", "" => "Get the calibration status.";
+    "datalogger-off", "" => "Stop the data logger.";
+    "datalogger-set", "INTERVAL" => "Store a reading every INTERVAL, in tens of seconds.";
+    "datalogger-status", "" => "Get the data logger interval.";
+    "memory-clear", "" => "Clear the stored readings.";
+    "memory-recall", "" => "Recall the stored readings.";
+    "memory-recall-last", "" => "Recall the last stored reading.";
+    "read", "" => "Take a reading.";
+    "scale-celsius", "" => "Read temperatures in degrees Celsius.";
+    "scale-fahrenheit", "" => "Read temperatures in degrees Fahrenheit.";
+    "scale-kelvin", "" => "Read temperatures in Kelvin.";
+    "scale-status", "" => "Get the temperature scale.";
+};
+
+/// The requests understood by sensors of `kind`, sorted by name.
+pub fn requests(kind: SensorKind) -> Vec<RequestHelp> {
+    let mut requests = COMMON_REQUESTS.to_vec();
+    requests.extend_from_slice(match kind {
+        SensorKind::Conductivity => CONDUCTIVITY_REQUESTS,
+        SensorKind::Ph => PH_REQUESTS,
+        SensorKind::Temperature => TEMPERATURE_REQUESTS,
+    });
+    if kind != SensorKind::Temperature {
+        requests.extend_from_slice(SESSION_REQUESTS);
+    }
+    requests.sort_by_key(|r| r.name);
+    requests
+}
+
+/// The `help` text for every request of sensors of `kind`, or for the request `name`.
+pub fn help_text(kind: SensorKind, name: Option<&str>) -> Option<String> {
+    let lines: Vec<String> = requests(kind)
+        .iter()
+        .filter(|r| name.map_or(true, |n| r.name == n))
+        .map(|r| {
+            let usage = match r.args {
+                "" => r.name.to_string(),
+                args => format!("{} {}", r.name, args),
+            };
+            format!("{:<28}{}", usage, r.help)
+        })
+        .collect();
+    if lines.is_empty() {
+        return None;
+    }
+    Some(lines.join("\n"))
+}
+
+/// Completions for the word before `pos`, in `line`, as the position where the word starts,
+/// and the candidates that replace it. Only the first word, or the word after `help`, is
+/// completed.
+pub fn complete_line(names: &[&str], line: &str, pos: usize) -> (usize, Vec<String>) {
+    let head = &line[..pos];
+    let start = head.rfind(' ').map_or(0, |i| i + 1);
+    let before = head[..start].split_whitespace().collect::<Vec<&str>>();
+    let candidates: Vec<&&str> = match before.len() {
+        0 => names.iter().chain(SHELL_COMMANDS.iter()).collect(),
+        1 if before[0] == "help" => names.iter().collect(),
+        _ => Vec::new(),
+    };
+    let prefix = &head[start..];
+    let mut completions: Vec<String> = candidates
+        .into_iter()
+        .filter(|c| c.starts_with(prefix))
+        .map(|c| c.to_string())
+        .collect();
+    completions.sort();
+    (start, completions)
+}
+
+// Completes request names, and shell commands.
+struct RequestCompleter {
+    names: Vec<&'static str>,
+}
+
+impl Completer for RequestCompleter {
+    fn complete(&self, line: &str, pos: usize) -> ::rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete_line(&self.names, line, pos))
+    }
+}
+
+// A requester for a sensor of any kind.
+enum ShellRequester {
+    Conductivity(ConductivityRequester),
+    Ph(PhRequester),
+    Temperature(TemperatureRequester),
+}
+
+impl ShellRequester {
+    fn new(kind: SensorKind, socket: Socket) -> EzoResult<ShellRequester> {
+        Ok(match kind {
+            SensorKind::Conductivity => {
+                ShellRequester::Conductivity(ConductivityRequester::new(socket)?)
+            }
+            SensorKind::Ph => ShellRequester::Ph(PhRequester::new(socket)?),
+            SensorKind::Temperature => {
+                ShellRequester::Temperature(TemperatureRequester::new(socket)?)
+            }
+        })
+    }
+
+    fn request(&self, req_str: &str) -> EzoResult<String> {
+        match *self {
+            ShellRequester::Conductivity(ref requester) => requester.request(req_str),
+            ShellRequester::Ph(ref requester) => requester.request(req_str),
+            ShellRequester::Temperature(ref requester) => requester.request(req_str),
+        }
+    }
+}
+
+/// The kind of sensor with the given device model, as in its `DeviceInfo`.
+pub fn sensor_kind(model: &str) -> Result<SensorKind> {
+    match model {
+        "EC" => Ok(SensorKind::Conductivity),
+        "pH" => Ok(SensorKind::Ph),
+        "RTD" => Ok(SensorKind::Temperature),
+        _ => Err(ErrorKind::ResponseParse.into()),
+    }
+}
+
+// Ask the responder on `socket` for its device model.
+fn detect_sensor_kind(socket: &Socket) -> Result<SensorKind> {
+    let reply = send_request(socket, "device-info")?;
+    let info = <DeviceInfo as SocketReply>::from_str(&reply).context(ErrorKind::ResponseParse)?;
+    let device = Device::parse(&format!("{}", info)).context(ErrorKind::ResponseParse)?;
+    sensor_kind(&device.model)
+}
+
+// The history file given in the command-line, or the one in the home directory.
+fn history_path(matches: &ArgMatches) -> Option<PathBuf> {
+    match matches.value_of("history") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE)),
+    }
+}
+
+/// Run the shell, with the matches of `shell_cli_parser`. A request that times out is
+/// reported, and the next one is sent from a new requester.
+pub fn run_shell(matches: &ArgMatches) -> Result<()> {
+    let url = matches.value_of("url").unwrap();
+    let socket = create_and_connect_timed_requester(url)?;
+    let kind = match matches.value_of("sensor") {
+        Some("conductivity") => SensorKind::Conductivity,
+        Some("ph") => SensorKind::Ph,
+        Some("temperature") => SensorKind::Temperature,
+        _ => detect_sensor_kind(&socket)?,
+    };
+    let mut requester = ShellRequester::new(kind, socket).context(ErrorKind::SocketCreate)?;
+
+    let mut editor = Editor::<RequestCompleter>::new();
+    editor.set_completer(Some(RequestCompleter {
+        names: requests(kind).iter().map(|r| r.name).collect(),
+    }));
+    let history = history_path(matches);
+    if let Some(ref path) = history {
+        // There is no history before the first session.
+        let _load = editor.load_history(path);
+    }
+    println!(
+        "Connected to {:?} sensor at {}. Type `help` for its requests, or `exit`.",
+        kind, url
+    );
+    let prompt = format!("{}> ", url);
+    loop {
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Eof) | Err(ReadlineError::Interrupted) => break,
+            Err(e) => {
+                error!("could not read line: {}", e);
+                return Err(ErrorKind::CommandParse.into());
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line);
+        let mut words = line.splitn(2, ' ');
+        match (words.next(), words.next()) {
+            (Some("exit"), None) | (Some("quit"), None) => break,
+            (Some("help"), name) => match help_text(kind, name.map(|n| n.trim())) {
+                Some(text) => println!("{}", text),
+                None => println!("error: unknown request {:?}", name.unwrap_or("")),
+            },
+            _ => match requester.request(line) {
+                Ok(reply) => println!("{}", reply),
+                Err(e) => {
+                    println!("error: {}", e);
+                    if e.kind() == EzoErrorKind::ServiceTimeout {
+                        let socket = create_and_connect_timed_requester(url)?;
+                        requester =
+                            ShellRequester::new(kind, socket).context(ErrorKind::SocketCreate)?;
+                    }
+                }
+            },
+        }
+    }
+    if let Some(ref path) = history {
+        if let Err(e) = editor.save_history(path) {
+            warn!("could not save the history to {}: {}", path.display(), e);
+        }
+    }
+    Ok(())
+}
+
+/// `benita shell` sub-command, for the interactive shell.
+pub fn shell_cli_parser<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("shell")
+        .about("interactive shell for the requests of a networked sensor")
+        .settings(&[AppSettings::ArgRequiredElseHelp])
+        .arg(
+            Arg::with_name("url")
+                .help("URL of the sensor's responder")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sensor")
+                .short("s")
+                .long("sensor")
+                .value_name("KIND")
+                .help("kind of sensor (default: asked to the sensor)")
+                .possible_values(&["conductivity", "ph", "temperature"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("history")
+                .long("history")
+                .value_name("FILE")
+                .help("history file (default: ~/.benita_history)")
+                .takes_value(true),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn complete_request_names() {
+        let names = ["calibration-high", "calibration-low", "read"];
+        assert_eq!(
+            (
+                0,
+                vec![
+                    "calibration-high".to_string(),
+                    "calibration-low".to_string()
+                ]
+            ),
+            complete_line(&names, "cal", 3)
+        );
+        assert_eq!(
+            (0, vec!["exit".to_string()]),
+            complete_line(&names, "ex", 2)
+        );
+        assert_eq!(
+            (5, vec!["read".to_string()]),
+            complete_line(&names, "help re", 7)
+        );
+        // Arguments are not completed.
+        assert_eq!(
+            (17, Vec::<String>::new()),
+            complete_line(&names, "calibration-high 7", 18)
+        );
+    }
+
+    #[test]
+    fn requests_of_sensor_kinds() {
+        let names: Vec<&str> = requests(SensorKind::Temperature)
+            .iter()
+            .map(|r| r.name)
+            .collect();
+        assert!(names.contains(&"scale-kelvin"));
+        assert!(names.contains(&"led-on"));
+        assert!(!names.contains(&"output-tds-on"));
+        assert!(!names.contains(&"session-lock"));
+
+        assert_eq!(
+            Some("calibration-mid VALUE       Set the calibration mid-point, in pH.".to_string()),
+            help_text(SensorKind::Ph, Some("calibration-mid"))
+        );
+        assert_eq!(None, help_text(SensorKind::Ph, Some("scale-kelvin")));
+        assert!(help_text(SensorKind::Conductivity, Some("session-lock")).is_some());
+
+        assert_eq!(SensorKind::Temperature, sensor_kind("RTD").unwrap());
+        assert!(sensor_kind("ORP").is_err());
+    }
+}
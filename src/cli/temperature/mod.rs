@@ -1,10 +1,17 @@
 //! Command-line parsers for `Temperature` services.
 pub mod subcommands;
 
-use cli::shared::is_url;
+use cli::shared::{float_value, i2c_args, i2c_values, is_url, reply_string, run_chip_command};
+use errors::*;
+use ezo::common_ezo::EzoChipAPI;
+use ezo::errors::Error as EzoError;
+use ezo::network::temperature::TemperatureRequester;
+use ezo::network::ReplyStatus;
+use ezo::temperature::{TemperatureAPI, TemperatureSensor};
 use self::subcommands::*;
+use utilities::create_and_connect_timed_requester;
 
-use clap::{App, AppSettings, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
 /// Main command-line interface.
 pub struct TemperatureApp;
@@ -17,6 +24,7 @@ impl TemperatureApp {
             .settings(&[AppSettings::DisableHelpSubcommand])
             .subcommand(TemperatureServerApp::new())
             .subcommand(TemperatureClientApp::new())
+            .subcommand(TemperatureSensorApp::new())
     }
 }
 
@@ -107,6 +115,67 @@ impl TemperatureCommandApp {
     }
 }
 
+/// Temperature Sensor command-line interface, for a sensor on a local I2C bus.
+pub struct TemperatureSensorApp;
+
+impl TemperatureSensorApp {
+    pub fn new<'a, 'b>() -> App<'a, 'b> {
+        SubCommand::with_name("sensor")
+            .about("Local I2C sensor")
+            .settings(&[AppSettings::DisableHelpSubcommand])
+            .args(&i2c_args())
+            .subcommands(vec![
+                TemperatureCalibrationCommand::new(),
+                TemperatureDeviceCommand::new(),
+                TemperatureFindCommand::new(),
+                TemperatureLedCommand::new(),
+                TemperatureProtocolLockCommand::new(),
+                TemperatureReadCommand::new(),
+                TemperatureSleepCommand::new(),
+            ])
+    }
+}
+
+/// Executes the temperature command in `matches`, on anything that implements the
+/// `TemperatureAPI`, and returns the text form of its reply.
+pub fn run_temperature_command<T>(sensor: &T, matches: &ArgMatches) -> Result<String>
+where
+    T: TemperatureAPI<Error = EzoError, DefaultReply = ReplyStatus>
+        + EzoChipAPI<SensorError = EzoError, SensorReply = ReplyStatus>,
+{
+    if let Some(reply) = run_chip_command(sensor, matches)? {
+        return Ok(reply);
+    }
+    match matches.subcommand() {
+        ("calibration", Some(m)) => match m.subcommand() {
+            ("status", _) => reply_string(sensor.get_calibration_status()),
+            ("clear", _) => reply_string(sensor.set_calibration_clear()),
+            ("set", Some(cal)) => {
+                reply_string(sensor.set_calibration_temperature(float_value(cal, "CAL")?))
+            }
+            _ => unreachable!(),
+        },
+        ("read", _) => reply_string(sensor.get_reading()),
+        _ => Err(ErrorKind::CommandParse.into()),
+    }
+}
+
+/// Executes the command in the matches of `TemperatureClientApp`, through a
+/// `TemperatureRequester`.
+pub fn run_temperature_client(matches: &ArgMatches) -> Result<String> {
+    let socket = create_and_connect_timed_requester(matches.value_of("URL").unwrap())?;
+    let requester = TemperatureRequester::new(socket).context(ErrorKind::SocketCreate)?;
+    run_temperature_command(&requester, matches)
+}
+
+/// Executes the command in the matches of `TemperatureSensorApp`, on the local
+/// `TemperatureSensor`.
+pub fn run_temperature_sensor(matches: &ArgMatches) -> Result<String> {
+    let (path, address) = i2c_values(matches)?;
+    let sensor = TemperatureSensor::new(path, address).context(ErrorKind::SensorTrouble)?;
+    run_temperature_command(&sensor, matches)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -167,6 +236,28 @@ mod tests {
     }
 
     // Tests for the command-line app.
+    #[test]
+    fn parsing_valid_sensor_cli_input() {
+        let cli_app = TemperatureApp::new();
+        let arg_vec = vec!["temperature", "sensor", "/dev/i2c-1", "102", "read"];
+        let matches = cli_app.get_matches_from_safe(arg_vec).unwrap();
+        let sensor_matches = matches.subcommand_matches("sensor").unwrap();
+        assert_eq!(("/dev/i2c-1", 102), i2c_values(sensor_matches).unwrap());
+    }
+
+    #[test]
+    fn parsing_invalid_sensor_cli_input_yields_err() {
+        let mut cli_app = TemperatureApp::new();
+
+        let arg_vec = vec!["temperature", "sensor", "/dev/i2c-1", "read"];
+        let matches = cli_app.get_matches_from_safe_borrow(arg_vec);
+        assert!(matches.is_err());
+
+        let arg_vec = vec!["temperature", "sensor", "/dev/i2c-1", "102", "led", "dim"];
+        let matches = cli_app.get_matches_from_safe_borrow(arg_vec);
+        assert!(matches.is_err());
+    }
+
     #[test]
     fn parsing_valid_command_line_input() {
         let mut cli_app = TemperatureCommandApp::new();
@@ -26,6 +26,10 @@ pub enum ErrorKind {
     CommandResponse,
     #[fail(display = "could not parse configuration file")]
     ConfigParse,
+    #[fail(display = "could not setup logging")]
+    LoggerSetup,
+    #[fail(display = "could not export measurements")]
+    MeasurementExport,
     #[fail(display = "socket could not bind to the network URL")]
     SocketBind,
     #[fail(display = "the socket couldn't be created")]
@@ -46,6 +50,8 @@ pub enum ErrorKind {
     ResponseParse,
     #[fail(display = "trouble with the sensor")]
     SensorTrouble,
+    #[fail(display = "the requested service did not reply in time")]
+    ServiceTimeout,
 }
 
 impl Fail for Error {
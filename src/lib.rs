@@ -14,7 +14,10 @@ extern crate chrono;
 extern crate clap;
 #[macro_use]
 extern crate failure;
+#[macro_use]
+extern crate log;
 extern crate neuras;
+extern crate rustyline;
 extern crate url;
 extern crate zmq;
 
@@ -0,0 +1,69 @@
+//! `benita` command-line application.
+//!
+//! Every sensor has network services, e.g. a responder for the pH sensor at address 99 on
+//! the first I2C bus, and a publisher for its readings:
+//!
+//! ```text
+//! benita ph network server rep /dev/i2c-1 99 tcp://*:5558
+//! benita ph network server pub tcp://localhost:5558 tcp://*:5557 ph-0123456789abcdef
+//! ```
+//!
+//! and clients for them, which send requests, one-shot or from stdin, and print the readings
+//! published on a channel:
+//!
+//! ```text
+//! benita ph network client req tcp://localhost:5558 read
+//! benita ph network client sub tcp://localhost:5557 ph-0123456789abcdef
+//! ```
+//!
+//! Stored, or published, measurements are exported as CSV, or JSON lines, e.g.
+//!
+//! ```text
+//! benita export --format jsonl --store /var/lib/benita --from 2017-11-18T00:00:00Z
+//! ```
+extern crate benita;
+extern crate chrono;
+extern crate failure;
+extern crate fern;
+extern crate log;
+
+use benita::cli::benita::{benita_cli, benita_cli_parser};
+use benita::errors::*;
+use failure::Fail;
+
+// Configure and start logger. Logs go to stderr, to keep them apart from replies, and
+// readings.
+fn start_logger() -> Result<()> {
+    let _logger = fern::Dispatch::new()
+        .format(|out, message, record| {
+            out.finish(format_args!(
+                "{}[{}][{}] {}",
+                chrono::Local::now().format("[%Y-%m-%d][%H:%M:%S]"),
+                record.target(),
+                record.level(),
+                message
+            ))
+        })
+        .level(log::LogLevelFilter::Info)
+        .chain(std::io::stderr())
+        .apply()
+        .context(ErrorKind::LoggerSetup)?;
+    Ok(())
+}
+
+fn run_code() -> Result<()> {
+    let matches = benita_cli_parser().get_matches();
+    // Initialize logging.
+    let _log = start_logger()?;
+    benita_cli(&matches)
+}
+
+fn main() {
+    if let Err(ref e) = run_code() {
+        eprintln!("error: {}", e);
+        if let Some(cause) = e.cause() {
+            eprintln!("caused by: {}", cause);
+        }
+        ::std::process::exit(1);
+    }
+}
@@ -1,6 +1,12 @@
 //! Shared useful utility functions.
+use std::time::Duration;
+
 use super::errors::*;
-use zmq::{Context, Socket, SocketType};
+use ezo::utilities::create_timed_requester;
+use zmq::{self, Context, Socket, SocketType};
+
+/// Time that requesters wait for every reply, in milliseconds.
+pub const REPLY_TIMEOUT_MS: u64 = 5_000;
 
 /// simple atof conversion.
 pub fn atof(s: &str) -> Result<f64> {
@@ -34,6 +40,29 @@ pub fn create_and_connect_requester(url: &str) -> Result<Socket> {
     Ok(requester)
 }
 
+/// create a REQUESTER socket connected to the specified URL, that waits up to
+/// `REPLY_TIMEOUT_MS` for every reply. Requests sent from it yield `ErrorKind::ServiceTimeout`
+/// once it runs out, and the socket has to be replaced.
+pub fn create_and_connect_timed_requester(url: &str) -> Result<Socket> {
+    let timeout = Duration::from_millis(REPLY_TIMEOUT_MS);
+    let requester =
+        create_timed_requester(&Context::new(), url, timeout).context(ErrorKind::SocketConnect)?;
+    Ok(requester)
+}
+
+/// Send a request, and return its reply.
+pub fn send_request(socket: &Socket, request: &str) -> Result<String> {
+    let _send = socket
+        .send(request.as_bytes(), 0)
+        .context(ErrorKind::SocketSend)?;
+    match socket.recv_string(0) {
+        Ok(Ok(reply)) => Ok(reply),
+        Ok(Err(_)) => Err(ErrorKind::ResponseParse.into()),
+        Err(zmq::Error::EAGAIN) => Err(ErrorKind::ServiceTimeout.into()),
+        Err(e) => Err(e).context(ErrorKind::SocketReceive).map_err(Error::from),
+    }
+}
+
 /// create a RESPONDER socket bound to the specified URL.
 pub fn create_and_bind_responder(url: &str) -> Result<Socket> {
     // We start our ZMQ context.